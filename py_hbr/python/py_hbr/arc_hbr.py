@@ -0,0 +1,31 @@
+## Functions for computing the ARC-HBR bleeding risk score from
+## patient features, using the same criteria as the Rust code.
+##
+
+from py_hbr import _lib_name
+import pyarrow
+
+
+def arc_hbr_from_features(features):
+    """
+    Compute the ARC-HBR score and the status of each criterion from a
+    table of patient features, with one row per index event.
+
+    The features table can be a pyarrow Table or a pandas dataframe,
+    and can contain the columns age, egfr (mL/min/1.73m2), haemoglobin
+    (g/L), platelets (10^9/L), and the boolean columns
+    oral_anticoagulant, nsaids_or_steroids, bleeding_within_6_months,
+    bleeding_within_12_months, cancer, liver_cirrhosis and
+    ischaemic_stroke. Missing columns and null values are treated as
+    missing data.
+
+    The result is a pyarrow Table with one row per row of the features,
+    and columns "score" (1 for each major and 0.5 for each minor
+    criterion), "is_hbr" (whether the score is at least 1), and one
+    column per criterion (e.g. "anaemia"), which is "major", "minor",
+    "not_met" or "missing".
+    """
+    if not hasattr(features, "__arrow_c_stream__"):
+        features = pyarrow.Table.from_pandas(features, preserve_index=False)
+    table = _lib_name.rust_arc_hbr_from_features(features)
+    return pyarrow.table(table)
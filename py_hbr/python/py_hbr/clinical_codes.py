@@ -4,7 +4,7 @@
 
 from py_hbr import _lib_name
 import os
-import pandas
+import pyarrow
 
 
 def get_groups_in_codes_file(codes_file_path):
@@ -37,7 +37,68 @@ def get_codes_in_group(codes_file_path, group):
             f"code group '{group}' is not present in codes file '{codes_file_path}'"
         )
 
-    return pandas.DataFrame(_lib_name.rust_get_codes_in_group(codes_file_path, group))
+    table = _lib_name.rust_get_codes_in_group(codes_file_path, group)
+    return pyarrow.table(table).to_pandas()
+
+def get_group_summaries(codes_file_path, max_examples=3):
+    """
+    Get a pandas dataframe summarising the groups defined in a codes
+    file, for checking the group definitions at a glance.
+
+    The dataframe has one row per group (sorted by name), and three
+    columns: "group", for the group name; "n_codes", for the number
+    of codes in the group; and "example_codes", for a list of the
+    first few codes in the group (at most max_examples).
+    """
+    if not os.path.exists(codes_file_path):
+        raise ValueError(f"The codes file '{codes_file_path}' does not exist")
+
+    table = _lib_name.rust_get_group_summaries(codes_file_path, max_examples)
+    return pyarrow.table(table).to_pandas()
+
+
+def validate_codes_file(codes_file_path, allowed_groups=None, snake_case=True):
+    """
+    Check a codes file for problems that would make codes silently fail
+    to match, or put them in the wrong group (e.g. overlapping index
+    ranges, excludes of undefined groups, or codes that appear twice).
+    Group names must be snake_case (unless snake_case is False), and
+    must be in allowed_groups if it is given.
+
+    Returns a list with one dictionary per problem, with keys "kind"
+    (e.g. "overlapping_categories"), "location" (the category or group
+    with the problem), "field" (e.g. "index" or "exclude") and "problem"
+    (a description). The list is empty if the codes file is valid, so
+    it can be used in a test, e.g. assert validate_codes_file(path) == [].
+    """
+    if not os.path.exists(codes_file_path):
+        raise ValueError(f"The codes file '{codes_file_path}' does not exist")
+
+    allowed_groups = None if allowed_groups is None else list(allowed_groups)
+    return _lib_name.rust_validate_codes_file(codes_file_path, allowed_groups, snake_case)
+
+
+def annotate_groups(episodes, codes_file_path, code_columns):
+    """
+    Add a boolean column for each group in a codes file to a table of
+    episodes, which is True if any of the codes in the code_columns
+    (e.g. the primary and secondary diagnosis columns) is in the group.
+
+    The episodes can be a pyarrow Table or a pandas dataframe, and the
+    result is a pyarrow Table with the original columns followed by
+    one column per group (named after the group). Call once with the
+    diagnosis columns and an ICD-10 codes file, and again with the
+    procedure columns and an OPCS-4 codes file, to flag both kinds of
+    group. Codes that do not match anything in the codes file are not
+    in any group.
+    """
+    if not os.path.exists(codes_file_path):
+        raise ValueError(f"The codes file '{codes_file_path}' does not exist")
+
+    if not hasattr(episodes, "__arrow_c_stream__"):
+        episodes = pyarrow.Table.from_pandas(episodes, preserve_index=False)
+    table = _lib_name.rust_annotate_groups(episodes, codes_file_path, list(code_columns))
+    return pyarrow.table(table)
 
 class ClinicalCode:
     def __init__(self, name, docs):
@@ -49,15 +110,36 @@ class ClinicalCode:
         self.docs = docs
 
 class ClinicalCodeParser:
-    def __init__(self, diagnosis_codes_file_path, procedure_codes_file_path):
+    def __init__(self, diagnosis_codes_file_path=None, procedure_codes_file_path=None):
         """
         Create a new code parser for diagnosis and procedure codes using
-        the code trees provided in the two file paths
+        the code trees provided in the two file paths. If both paths are
+        None, the ICD-10 (diagnosis) and OPCS-4 (procedure) codes files
+        built into the package are used. If only one path is given, only
+        that kind of code can be parsed, and passing the other kind to
+        any of the methods raises a ValueError.
         """
         self._parser = _lib_name.RustClinicalCodeParser(
             diagnosis_codes_file_path, procedure_codes_file_path
         )
 
+    @classmethod
+    def from_yaml(cls, diagnosis_codes=None, procedure_codes=None):
+        """
+        Create a new code parser from codes files held in memory instead
+        of on the filesystem. Each argument can be the contents of a
+        codes file (as str or bytes), or a file-like object to read it
+        from, e.g. importlib.resources.files(package).joinpath("icd10.yaml").open().
+        As for the constructor, the built-in codes files are used if both
+        arguments are None, and only one kind of code can be parsed if
+        only one argument is given.
+        """
+        parser = cls.__new__(cls)
+        parser._parser = _lib_name.RustClinicalCodeParser.from_yaml(
+            diagnosis_codes, procedure_codes
+        )
+        return parser
+
     def find_exact(self, code, diagnosis_or_procedure):
         '''
         Find an exact match for the diagnosis or procedure code
@@ -69,5 +151,70 @@ class ClinicalCodeParser:
         code = self._parser.find_exact_diagnosis(code, diagnosis_or_procedure)
         return ClinicalCode(code[0], code[1])
 
+    def groups_for_code(self, code, diagnosis_or_procedure):
+        '''
+        Get the list of code groups (e.g. "bleeding_al_ani") that contain
+        the diagnosis or procedure code, or raise a ValueError if the code
+        does not match anything in the code tree.
+        '''
+        return self._parser.groups_for_code(code, diagnosis_or_procedure)
+
+    def random_code_from_group(self, group, seed, n, diagnosis_or_procedure="diagnosis"):
+        '''
+        Get a list of n codes (e.g. ["I21.0", "I21.4", ...]) picked at
+        random from a code group. The same seed always gives the same
+        list of codes, so this can be used to make deterministic test
+        data (e.g. synthetic episode tables).
+        '''
+        return self._parser.random_code_from_group(group, seed, n, diagnosis_or_procedure)
+
+    def children(self, category, diagnosis_or_procedure="diagnosis"):
+        '''
+        Get the sub-categories and codes directly under a category
+        (e.g. "I20-I25"), in tree order, as a list of (ClinicalCode,
+        is_category) tuples, where is_category is True if the child has
+        children of its own. Pass "" to get the top level of the tree.
+        Raises a ValueError if the category is not in the code tree.
+        '''
+        children = self._parser.children(category, diagnosis_or_procedure)
+        return [(ClinicalCode(name, docs), is_category) for name, docs, is_category in children]
+
+    def docs(self, code, diagnosis_or_procedure="diagnosis"):
+        '''
+        Get the description of a code or category (e.g. a chapter or
+        block), or raise a ValueError if it is not in the code tree.
+        '''
+        return self._parser.docs(code, diagnosis_or_procedure)
+
+    def ancestors(self, code, diagnosis_or_procedure="diagnosis"):
+        '''
+        Get the list of categories (as ClinicalCode objects) containing
+        a code, starting with the immediate parent and going up to the
+        top of the code tree (e.g. I21, I20-I25, IX, ICD-10 for I21.9).
+        Raises a ValueError if the code is not in the code tree.
+        '''
+        ancestors = self._parser.ancestors(code, diagnosis_or_procedure)
+        return [ClinicalCode(name, docs) for name, docs in ancestors]
+
+    def parse_column(self, codes, diagnosis_or_procedure, parallel=False, chunk_size=100_000):
+        '''
+        Parse a whole column of diagnosis or procedure codes (a pyarrow
+        array, numpy array, pandas Series or list of strings), which is
+        much faster than calling find_exact for each code. Returns a
+        pyarrow Table with one row per code, and columns "name" and
+        "docs" (the matched code, or null if there is no exact match),
+        "groups" (the list of groups containing the code) and "valid"
+        (whether an exact match was found).
+
+        Pass parallel=True to split a large column into chunks of
+        chunk_size codes and parse them on all the cores of the machine.
+        The result is the same, but the parser does not remember the
+        codes parsed this way.
+        '''
+        if hasattr(codes, "to_numpy"):
+            codes = pyarrow.array(codes.to_numpy(), type=pyarrow.string())
+        table = self._parser.parse_column(codes, diagnosis_or_procedure, parallel, chunk_size)
+        return pyarrow.table(table)
+
 
 
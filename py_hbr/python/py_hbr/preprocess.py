@@ -0,0 +1,28 @@
+## Functions for converting raw data tables (e.g. blood test results)
+## into the structured records used in the models.
+##
+
+from py_hbr import _lib_name
+import pyarrow
+
+
+def measurements_from_pathology_blood(pathology_blood):
+    """
+    Convert a table of blood test results in the format of the HIC
+    pathology_blood table into a tidy table of measurements, using
+    the same rules as the Rust preprocessing.
+
+    The pathology_blood table can be a pyarrow Table or a pandas
+    dataframe, and must contain the columns subject, order_name,
+    test_name, test_result, test_result_unit, sample_collected_date_time
+    and result_available_date_time. The result is a pyarrow Table with
+    one row per test result, and columns "subject", "measurement" (the
+    test name), "value", "unit", "date" (when the sample was collected)
+    and "flags". The value is null if the result could not be converted
+    (e.g. because it has an unexpected unit), and flags contains the
+    reason (flags is null for results that were converted).
+    """
+    if not hasattr(pathology_blood, "__arrow_c_stream__"):
+        pathology_blood = pyarrow.Table.from_pandas(pathology_blood, preserve_index=False)
+    table = _lib_name.rust_measurements_from_pathology_blood(pathology_blood)
+    return pyarrow.table(table)
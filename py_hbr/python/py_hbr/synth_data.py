@@ -0,0 +1,69 @@
+## Functions for generating seeded synthetic data, for testing the
+## data preprocessing and models without access to real data.
+##
+
+from py_hbr import _lib_name
+import json
+
+
+def generate_synthetic_dataset(spec, output_dir):
+    """
+    Generate a synthetic dataset and write each table to a file (named
+    after the table, e.g. pathology_blood.parquet) in output_dir,
+    which is created if it does not exist. The tables are generated and
+    written in Rust, so large tables are never held in Python memory.
+
+    The spec is a dictionary (or a YAML or JSON string) containing the
+    global seed and the tables to generate, for example:
+
+        {"seed": 42, "tables": [{"table": "pathology_blood", "num_rows": 100000}]}
+
+    The subjects in all the tables are picked from the same pool of
+    patients, so the tables can be joined on the subject column. The
+    size of the pool can be set using "num_patients" (the default is
+    50000), and the "patients" table contains their demographics.
+    The files are parquet unless "format" is set to "csv" or "ndjson".
+    Very large tables can be generated and written in chunks of rows,
+    so that they do not need to fit in memory, by setting "chunk_size".
+    The diagnoses in the "episodes" table are picked according to the
+    table's "diagnosis_frequencies" (a dictionary mapping ICD-10 codes
+    to frequencies, e.g. counts from hospital episode statistics) if
+    it is given, instead of uniformly at random.
+    The event times in a table are spread over its "date_window" (e.g.
+    {"start": "2015-01-01T00:00:00", "end": "2020-01-01T00:00:00"}),
+    and the time between the start and end of each event (such as
+    sample collection and result) is between the "min_minutes" and
+    "max_minutes" of its "duration". The times are naive local times,
+    unless "timezone_aware" is true, when they are timezone-aware
+    (Europe/London) timestamps instead.
+    Setting "corruption" (e.g. {"trailing_whitespace": 0.1, "non_numeric":
+    0.05}) injects defects into the tables, for testing data cleaning,
+    and its "duplicates" and "near_duplicates" rates add copies of rows
+    (near-duplicates have slightly shifted times), for testing
+    deduplication.
+
+    The tables are made by version "generator_version" of the
+    generators (1 if it is not given), so the same spec always produces
+    the same data, even after the generators have changed. A
+    manifest.json file recording the spec and the row count and hash
+    of each file is also written (see verify_synthetic_dataset). Returns
+    the list of paths of the files written, and raises a ValueError if
+    the spec is not valid.
+    """
+    if isinstance(spec, dict):
+        spec = json.dumps(spec)
+    return _lib_name.rust_generate_synthetic_dataset(spec, str(output_dir))
+
+
+def verify_synthetic_dataset(output_dir):
+    """
+    Check a synthetic dataset written by generate_synthetic_dataset
+    against the manifest.json in output_dir. The files must not have
+    changed, and generating the tables again from the spec in the
+    manifest must give exactly the same files (so that changes to the
+    generators which break reproducibility are detected).
+
+    Returns the list of paths of the files verified, and raises a
+    ValueError describing the problems if the dataset is not verified.
+    """
+    return _lib_name.rust_verify_synthetic_dataset(str(output_dir))
@@ -0,0 +1,123 @@
+//! Evaluating the ARC-HBR score from patient features
+//!
+//! The criteria are evaluated using the same function as the Rust
+//! pipeline (see rust_hbr::patient::arc_hbr::ArcHbrFeatures), so
+//! Python and Rust agree on the thresholds and on how missing data is
+//! handled.
+
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, AsArray, BooleanBuilder, Float64Builder, StringBuilder};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Float64Type, UInt32Type};
+use pyo3::{exceptions::PyValueError, prelude::*};
+use rust_hbr::patient::arc_hbr::{arc_hbr_score, ArcHbrCriterion, ArcHbrFeatures};
+
+use crate::table::{self, ArrowTable};
+
+/// Compute the ARC-HBR score and criteria for each row of a table of
+/// patient features.
+///
+/// The table can be anything that implements __arrow_c_stream__ (e.g.
+/// a pyarrow Table), with one row per index event and the columns
+/// (see ArcHbrFeatures for the definitions):
+/// * age: the age at the index event
+/// * egfr, haemoglobin, platelets: the baseline blood test results,
+///   in mL/min/1.73m2, g/L and 10*9/L
+/// * oral_anticoagulant, nsaids_or_steroids: whether the drugs are
+///   prescribed at the index event
+/// * bleeding_within_6_months, bleeding_within_12_months: whether
+///   there was a bleed in the 6 or 12 months before the index event
+/// * cancer, liver_cirrhosis, ischaemic_stroke: whether the conditions
+///   have been coded (see ArcHbrFeatures)
+///
+/// Null values are missing, and so are columns that are not in the
+/// table, so a criterion is Missing if its features are not available.
+///
+/// The result is a table (see ArrowTable) with one row per row of the
+/// input, and the columns:
+/// * score: the ARC-HBR score (1 for each major and 0.5 for each minor
+///   criterion)
+/// * is_hbr: whether the score is at least 1
+/// * one column for each criterion (e.g. oral_anticoagulant),
+///   containing major, minor, not_met or missing
+///
+/// Raises ValueError if the table cannot be read, or a column cannot be
+/// converted to the right type.
+///
+#[pyfunction]
+pub fn rust_arc_hbr_from_features(features: &PyAny) -> PyResult<ArrowTable> {
+    let batch = table::record_batch(features)?;
+    let num_rows = batch.num_rows();
+    let column = |name: &str, data_type: &DataType| {
+        batch
+            .column_by_name(name)
+            .map(|column| cast(column, data_type))
+            .transpose()
+            .map_err(|err| PyValueError::new_err(format!("Failed to read column '{name}': {err}")))
+    };
+    let floats = |name: &str| -> PyResult<Vec<Option<f64>>> {
+        Ok(match column(name, &DataType::Float64)? {
+            Some(column) => column.as_primitive::<Float64Type>().iter().collect(),
+            None => vec![None; num_rows],
+        })
+    };
+    let flags = |name: &str| -> PyResult<Vec<Option<bool>>> {
+        Ok(match column(name, &DataType::Boolean)? {
+            Some(column) => column.as_boolean().iter().collect(),
+            None => vec![None; num_rows],
+        })
+    };
+    let age: Vec<Option<u32>> = match column("age", &DataType::UInt32)? {
+        Some(column) => column.as_primitive::<UInt32Type>().iter().collect(),
+        None => vec![None; num_rows],
+    };
+    let egfr = floats("egfr")?;
+    let haemoglobin = floats("haemoglobin")?;
+    let platelets = floats("platelets")?;
+    let oral_anticoagulant = flags("oral_anticoagulant")?;
+    let nsaids_or_steroids = flags("nsaids_or_steroids")?;
+    let bleeding_within_6_months = flags("bleeding_within_6_months")?;
+    let bleeding_within_12_months = flags("bleeding_within_12_months")?;
+    let cancer = flags("cancer")?;
+    let liver_cirrhosis = flags("liver_cirrhosis")?;
+    let ischaemic_stroke = flags("ischaemic_stroke")?;
+
+    let mut score = Float64Builder::with_capacity(num_rows);
+    let mut is_hbr = BooleanBuilder::with_capacity(num_rows);
+    let mut statuses: Vec<_> = ArcHbrCriterion::ALL
+        .iter()
+        .map(|_| StringBuilder::new())
+        .collect();
+    for row in 0..num_rows {
+        let features = ArcHbrFeatures {
+            age: age[row],
+            egfr: egfr[row],
+            haemoglobin: haemoglobin[row],
+            platelets: platelets[row],
+            oral_anticoagulant: oral_anticoagulant[row],
+            nsaids_or_steroids: nsaids_or_steroids[row],
+            bleeding_within_6_months: bleeding_within_6_months[row],
+            bleeding_within_12_months: bleeding_within_12_months[row],
+            cancer: cancer[row],
+            liver_cirrhosis: liver_cirrhosis[row],
+            ischaemic_stroke: ischaemic_stroke[row],
+        };
+        let criteria = features.criteria();
+        let row_score = arc_hbr_score(&criteria);
+        score.append_value(row_score);
+        is_hbr.append_value(row_score >= 1.0);
+        for ((_, status), builder) in criteria.iter().zip(statuses.iter_mut()) {
+            builder.append_value(status.name());
+        }
+    }
+
+    let mut columns = vec![
+        ("score", Arc::new(score.finish()) as ArrayRef),
+        ("is_hbr", Arc::new(is_hbr.finish())),
+    ];
+    for (criterion, mut builder) in ArcHbrCriterion::ALL.iter().zip(statuses) {
+        columns.push((criterion.name(), Arc::new(builder.finish())));
+    }
+    ArrowTable::from_columns(columns)
+}
@@ -1,43 +1,155 @@
 //! The main Rust-language interface layer between rust_hbr (the Rust crate)
 //! and py_hbr (the Python package).
 
-use pyo3::{exceptions::PyValueError, prelude::*};
-use rust_hbr::{clinical_code::ClinicalCodeStore, clinical_code_tree::ClinicalCodeTree};
+use pyo3::{
+    exceptions::{PyKeyError, PyTypeError, PyValueError},
+    prelude::*,
+};
+use rand::seq::SliceRandom;
+use rust_hbr::{
+    clinical_code::{ClinicalCodeRef, ClinicalCodeStore},
+    clinical_code_tree::{
+        ClinicalCodeTree, CodeParserCache, GroupNameRules, GroupNamingConvention, TreeDiagnostic,
+    },
+    seeded_rng::make_rng,
+    HbrError,
+};
 use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow::array::{
+    Array, ArrayRef, BooleanBuilder, ListBuilder, StringArray, StringBuilder, UInt64Array,
+};
+
+mod arc_hbr;
+mod measurements;
+mod parse;
+mod synth;
+mod table;
+
+use parse::ParsedColumnBuilder;
+use table::ArrowTable;
+
+/// Convert an error from rust_hbr into a Python exception. Unknown
+/// code groups raise KeyError; everything else raises ValueError.
+fn to_py_err(err: HbrError) -> PyErr {
+    match err {
+        HbrError::UnknownGroup(_) => PyKeyError::new_err(err.to_string()),
+        _ => PyValueError::new_err(err.to_string()),
+    }
+}
+
+/// Open and parse a codes file, raising a Python exception
+/// if the file cannot be opened or parsed.
+fn code_tree_from_file(codes_file_path: &str) -> PyResult<ClinicalCodeTree> {
+    code_tree_from_file_unchecked(codes_file_path)?.map_err(to_py_err)
+}
+
+/// Open and parse a codes file, raising a Python exception if the
+/// file cannot be opened, but returning the error if it cannot be
+/// parsed (so that the caller can look at the problems found).
+fn code_tree_from_file_unchecked(
+    codes_file_path: &str,
+) -> PyResult<Result<ClinicalCodeTree, HbrError>> {
+    let f = std::fs::File::open(codes_file_path).map_err(|err| {
+        PyValueError::new_err(format!("Failed to open codes file {codes_file_path}: {err}"))
+    })?;
+    Ok(ClinicalCodeTree::from_reader(f))
+}
+
+/// Load the diagnosis and procedure code trees using load. If
+/// neither codes file is given, the ICD-10 and OPCS-4 code trees
+/// embedded in rust_hbr are used (see ClinicalCodeTree::icd10_default);
+/// otherwise only the kinds of code that are given are loaded.
+fn load_code_trees<T>(
+    diagnosis_codes: Option<T>,
+    procedure_codes: Option<T>,
+    load: impl Fn(T) -> PyResult<ClinicalCodeTree>,
+) -> PyResult<(Option<ClinicalCodeTree>, Option<ClinicalCodeTree>)> {
+    if diagnosis_codes.is_none() && procedure_codes.is_none() {
+        let diagnosis_code_tree = ClinicalCodeTree::icd10_default().map_err(to_py_err)?;
+        let procedure_code_tree = ClinicalCodeTree::opcs4_default().map_err(to_py_err)?;
+        return Ok((Some(diagnosis_code_tree), Some(procedure_code_tree)));
+    }
+    Ok((
+        diagnosis_codes.map(&load).transpose()?,
+        procedure_codes.map(&load).transpose()?,
+    ))
+}
+
+/// Parse a codes file held in memory, as a YAML str or bytes,
+/// or read from a file-like object (anything with a read() method
+/// returning str or bytes, e.g. from importlib.resources). Raises
+/// TypeError for anything else.
+fn code_tree_from_yaml(codes: &PyAny) -> PyResult<ClinicalCodeTree> {
+    let codes = if codes.hasattr("read")? {
+        codes.call_method0("read")?
+    } else {
+        codes
+    };
+    let yaml: &[u8] = if let Ok(text) = codes.extract::<&str>() {
+        text.as_bytes()
+    } else if let Ok(bytes) = codes.extract::<&[u8]>() {
+        bytes
+    } else {
+        return Err(PyTypeError::new_err(
+            "Expected the codes file as str or bytes, or a file-like object",
+        ));
+    };
+    ClinicalCodeTree::from_reader(yaml).map_err(to_py_err)
+}
+
+/// Get the name and docs of a code in the store
+fn name_and_docs(code_store: &ClinicalCodeStore, code_ref: &ClinicalCodeRef) -> (String, String) {
+    let clinical_code = code_store
+        .clinical_code_from(code_ref)
+        .expect("Clinical code should be present");
+    (clinical_code.name().clone(), clinical_code.docs().clone())
+}
 
 /// Class for parsing diagnosis and procedure codes by searching
 /// for them in a codes file. Used to check code validity and
-/// also retrieve documentation for the code.
+/// also retrieve documentation for the code. The result of parsing
+/// each code string is cached, so repeated codes are only looked
+/// up once.
 #[pyclass]
 struct RustClinicalCodeParser {
-    code_store: ClinicalCodeStore,
-    diagnosis_code_tree: ClinicalCodeTree,
-    procedure_code_tree: ClinicalCodeTree,
+    /// None if only a procedure codes file was given
+    diagnosis_parser: Option<CodeParserCache>,
+    /// None if only a diagnosis codes file was given
+    procedure_parser: Option<CodeParserCache>,
 }
 
 #[pymethods]
 impl RustClinicalCodeParser {
+    /// If both codes file paths are None, the ICD-10 (for diagnoses)
+    /// and OPCS-4 (for procedures) codes files built into the library
+    /// are used. If only one path is given, only that kind of code
+    /// can be parsed, and passing the other kind to any of the
+    /// methods raises ValueError.
     #[new]
-    fn new(diagnosis_codes_file_path: &str, procedure_codes_file_path: &str) -> PyResult<Self> {
-        let diagnosis_code_tree = if let Ok(f) = std::fs::File::open(diagnosis_codes_file_path) {
-            ClinicalCodeTree::from_reader(f)
-        } else {
-            return Err(PyValueError::new_err("Failed to open diagnosis codes file"));
-        };
-
-        let procedure_code_tree = if let Ok(f) = std::fs::File::open(procedure_codes_file_path) {
-            ClinicalCodeTree::from_reader(f)
-        } else {
-            return Err(PyValueError::new_err("Failed to open procedure codes file"));
-        };
-
-        let mut code_store = ClinicalCodeStore::new();
+    #[pyo3(signature = (diagnosis_codes_file_path=None, procedure_codes_file_path=None))]
+    fn new(
+        diagnosis_codes_file_path: Option<&str>,
+        procedure_codes_file_path: Option<&str>,
+    ) -> PyResult<Self> {
+        let (diagnosis_code_tree, procedure_code_tree) = load_code_trees(
+            diagnosis_codes_file_path,
+            procedure_codes_file_path,
+            code_tree_from_file,
+        )?;
+        Ok(Self::from_trees(diagnosis_code_tree, procedure_code_tree))
+    }
 
-        Ok(Self {
-            code_store,
-            diagnosis_code_tree,
-            procedure_code_tree,
-        })
+    /// Make a parser from codes files held in memory (see
+    /// code_tree_from_yaml) instead of file paths. As for new, the
+    /// built-in codes files are used if both arguments are None.
+    #[staticmethod]
+    #[pyo3(signature = (diagnosis_codes=None, procedure_codes=None))]
+    fn from_yaml(diagnosis_codes: Option<&PyAny>, procedure_codes: Option<&PyAny>) -> PyResult<Self> {
+        let (diagnosis_code_tree, procedure_code_tree) =
+            load_code_trees(diagnosis_codes, procedure_codes, code_tree_from_yaml)?;
+        Ok(Self::from_trees(diagnosis_code_tree, procedure_code_tree))
     }
 
     /// Find an exact match for the provided diagnosis or procedure code 
@@ -47,55 +159,205 @@ impl RustClinicalCodeParser {
     /// to determine which tree to use. Throws a python error if you pass
     /// any other string.
     fn find_exact_diagnosis(&mut self, code: &str, diagnosis_or_procedure: &str) -> PyResult<(String, String)> {
-        let code_tree = if diagnosis_or_procedure == "diagnosis" {
-            &self.diagnosis_code_tree
-        } else if diagnosis_or_procedure == "procedure" {
-            &self.procedure_code_tree
+        let parser = self.parser(diagnosis_or_procedure)?;
+        let code_ref = parser.find_exact(code).map_err(to_py_err)?.code();
+        Ok(name_and_docs(parser.code_store(), &code_ref))
+    }
+
+    /// Get the sorted list of groups that contain a diagnosis or
+    /// procedure code (pass "diagnosis" or "procedure" as the kind).
+    /// Raises ValueError if there is no exact match for the code.
+    fn groups_for_code(&mut self, code: &str, kind: &str) -> PyResult<Vec<String>> {
+        let code_match = self.parser(kind)?.find_exact(code).map_err(to_py_err)?;
+        let mut groups: Vec<String> = code_match.groups().iter().cloned().collect();
+        groups.sort();
+        Ok(groups)
+    }
+
+    /// Pick n codes at random (with replacement) from a group of
+    /// diagnosis or procedure codes (pass "diagnosis" or "procedure"
+    /// as the kind), returning the code names. The same seed always
+    /// gives the same codes. Raises KeyError if the group is not
+    /// defined, or ValueError if the group is empty.
+    #[pyo3(signature = (group, seed, n, kind="diagnosis"))]
+    fn random_code_from_group(
+        &mut self,
+        group: &str,
+        seed: u64,
+        n: usize,
+        kind: &str,
+    ) -> PyResult<Vec<String>> {
+        let parser = self.parser(kind)?;
+        let codes = parser
+            .codes_in_group(&group.to_string())
+            .map_err(to_py_err)?;
+        if codes.is_empty() {
+            return Err(to_py_err(HbrError::EmptyGroup(group.to_string())));
+        }
+
+        let mut rng = make_rng(seed, "random_code_from_group");
+        let code_store = parser.code_store();
+        Ok((0..n)
+            .map(|_| {
+                let code_ref = codes.choose(&mut rng).expect("Group is not empty");
+                code_store
+                    .clinical_code_from(code_ref)
+                    .expect("Clinical code should be present")
+                    .name()
+                    .clone()
+            })
+            .collect())
+    }
+
+    /// Get the sub-categories and codes directly under a category of
+    /// the diagnosis or procedure tree (pass "diagnosis" or "procedure"
+    /// as the kind), in tree order, as a list of (name, docs,
+    /// is_category) tuples. Pass an empty string to get the top level
+    /// of the tree. The list is empty for a code. Raises ValueError if
+    /// the category is not in the tree.
+    #[pyo3(signature = (category, kind="diagnosis"))]
+    fn children(&mut self, category: &str, kind: &str) -> PyResult<Vec<(String, String, bool)>> {
+        let parser = self.parser(kind)?;
+        let (code_tree, code_store) = parser.tree_and_store();
+        let children = code_tree
+            .children_of(category, code_store)
+            .map_err(to_py_err)?;
+        Ok(children
+            .into_iter()
+            .map(|(code_ref, is_category)| {
+                let (name, docs) = name_and_docs(parser.code_store(), &code_ref);
+                (name, docs, is_category)
+            })
+            .collect())
+    }
+
+    /// Get the description of a code or category in the diagnosis or
+    /// procedure tree (pass "diagnosis" or "procedure" as the kind).
+    /// Raises ValueError if the name is not in the tree.
+    #[pyo3(signature = (code, kind="diagnosis"))]
+    fn docs(&mut self, code: &str, kind: &str) -> PyResult<String> {
+        let parser = self.parser(kind)?;
+        Ok(parser.code_tree().docs_of(code).map_err(to_py_err)?.clone())
+    }
+
+    /// Get the categories containing a code or category in the
+    /// diagnosis or procedure tree (pass "diagnosis" or "procedure" as
+    /// the kind), from the immediate parent up to the top of the tree,
+    /// as a list of (name, docs) tuples. Raises ValueError if the code
+    /// is not in the tree.
+    #[pyo3(signature = (code, kind="diagnosis"))]
+    fn ancestors(&mut self, code: &str, kind: &str) -> PyResult<Vec<(String, String)>> {
+        let parser = self.parser(kind)?;
+        let (code_tree, code_store) = parser.tree_and_store();
+        let ancestors = code_tree.ancestors(code, code_store).map_err(to_py_err)?;
+        Ok(ancestors
+            .iter()
+            .map(|code_ref| name_and_docs(parser.code_store(), code_ref))
+            .collect())
+    }
+
+    /// Parse a whole column of diagnosis or procedure codes (pass
+    /// "diagnosis" or "procedure" as the kind), looking for an exact
+    /// match for each code as in find_exact_diagnosis. The codes can
+    /// be an Arrow string array (e.g. a pyarrow Array) or a sequence
+    /// of strings (e.g. a list or numpy array), and may contain None.
+    ///
+    /// The result is a table (see ArrowTable) with one row per code
+    /// and the columns:
+    /// * name: the name of the matched code (null if not valid)
+    /// * docs: the description of the matched code (null if not valid)
+    /// * groups: the sorted list of groups containing the code (empty
+    ///   if not valid)
+    /// * valid: whether an exact match was found for the code
+    ///
+    /// If parallel is true, the column is split into chunks of
+    /// chunk_size codes which are parsed in parallel (see
+    /// parse::parse_chunks), without holding the GIL. This is much
+    /// faster for large columns on a multi-core machine, but does not
+    /// use or add to the cache of parsed codes.
+    #[pyo3(signature = (codes, kind, parallel=false, chunk_size=100_000))]
+    fn parse_column(
+        &mut self,
+        py: Python<'_>,
+        codes: &PyAny,
+        kind: &str,
+        parallel: bool,
+        chunk_size: usize,
+    ) -> PyResult<ArrowTable> {
+        let codes = table::string_array(codes)?;
+        let parser = self.parser(kind)?;
+
+        let batch = if parallel {
+            let code_tree = parser.code_tree();
+            py.allow_threads(|| parse::parse_chunks(code_tree, &codes, chunk_size))
         } else {
-            return Err(PyValueError::new_err(format!(
-                "Must pass one of 'diagnosis' or 'procedure', not '{diagnosis_or_procedure}'"
-            )))
+            let mut builder = ParsedColumnBuilder::new();
+            for code in codes.iter() {
+                let code_ref = code.and_then(|code| parser.find_exact(code).ok().map(|m| m.code()));
+                match code_ref {
+                    Some(code_ref) => builder.append_match(parser.code_store(), &code_ref),
+                    None => builder.append_invalid(),
+                }
+            }
+            builder.finish()
         };
-        if let Ok(matched_code_ref) = code_tree
-            .find_exact(code.to_string(), &mut self.code_store)
-        {
-            let matched_code = self
-                .code_store
-                .clinical_code_from(&matched_code_ref)
-                .expect("If code was matched, expected code ref to be valid");
-            Ok((
-                matched_code.name().to_string(),
-                matched_code.docs().to_string(),
-            ))
-        } else {
-            Err(PyValueError::new_err(format!(
-                "No match for {code} found in diagnosis tree"
-            )))
+        let batch = batch.map_err(|err| PyValueError::new_err(err.to_string()))?;
+        Ok(ArrowTable::from_batch(batch))
+    }
+}
+
+impl RustClinicalCodeParser {
+    /// Make a parser using the diagnosis and procedure code trees
+    /// (either of which may be missing)
+    fn from_trees(
+        diagnosis_code_tree: Option<ClinicalCodeTree>,
+        procedure_code_tree: Option<ClinicalCodeTree>,
+    ) -> Self {
+        Self {
+            diagnosis_parser: diagnosis_code_tree.map(CodeParserCache::new),
+            procedure_parser: procedure_code_tree.map(CodeParserCache::new),
         }
     }
+
+    /// Get the parser for "diagnosis" or "procedure" codes, or raise
+    /// ValueError for any other kind, or if no codes file was given
+    /// for that kind of code
+    fn parser(&mut self, kind: &str) -> PyResult<&mut CodeParserCache> {
+        let parser = match kind {
+            "diagnosis" => &mut self.diagnosis_parser,
+            "procedure" => &mut self.procedure_parser,
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "Must pass one of 'diagnosis' or 'procedure', not '{kind}'"
+                )))
+            }
+        };
+        parser.as_mut().ok_or_else(|| {
+            PyValueError::new_err(format!(
+                "Cannot parse {kind} codes, because no {kind} codes file was given"
+            ))
+        })
+    }
 }
 
 /// Get the clinical codes in a particular code group defined
 /// in a codes file.
 ///
-/// The result is a named list (intended as a dataframe) with the
-/// columns:
+/// The result is a table (see ArrowTable) with the columns:
 /// * name: the name of the code in the group (e.g. A01.0)
 /// * docs: the description of the code
 ///
-/// TODO: figure out a good way to handle errors.
+/// Raises KeyError if the group is not defined in the codes file,
+/// or ValueError if the codes file cannot be read.
 ///
-/// @export
 #[pyfunction]
-fn rust_get_codes_in_group(codes_file_path: &str, group: &str) -> HashMap<String, Vec<String>> {
-    let f = std::fs::File::open(codes_file_path).expect("Failed to open codes file");
-
-    let code_tree = ClinicalCodeTree::from_reader(f);
+fn rust_get_codes_in_group(codes_file_path: &str, group: &str) -> PyResult<ArrowTable> {
+    let code_tree = code_tree_from_file(codes_file_path)?;
     let mut code_store = ClinicalCodeStore::new();
 
     let clinical_code_refs = code_tree
         .codes_in_group(&String::from(group), &mut code_store)
-        .expect("Should succeed, code is present");
+        .map_err(to_py_err)?;
 
     let mut name = Vec::new();
     let mut docs = Vec::new();
@@ -107,10 +369,7 @@ fn rust_get_codes_in_group(codes_file_path: &str, group: &str) -> HashMap<String
         docs.push(clinical_code.docs().clone());
     }
 
-    let mut code_list = HashMap::new();
-    code_list.insert(format!("name"), name);
-    code_list.insert(format!("docs"), docs);
-    code_list
+    ArrowTable::from_string_columns(vec![("name", name), ("docs", docs)])
 }
 
 /// Get the code groups defined in a codes file
@@ -120,11 +379,195 @@ fn rust_get_codes_in_group(codes_file_path: &str, group: &str) -> HashMap<String
 /// all the code groups using rust_get_codes_in_group.
 ///
 #[pyfunction]
-fn rust_get_groups_in_codes_file(codes_file_path: &str) -> Vec<String> {
-    let f = std::fs::File::open(codes_file_path).expect("Failed to open codes file");
-    let code_tree = ClinicalCodeTree::from_reader(f);
+fn rust_get_groups_in_codes_file(codes_file_path: &str) -> PyResult<Vec<String>> {
+    let code_tree = code_tree_from_file(codes_file_path)?;
     // get the code groups and return here
-    code_tree.groups().iter().cloned().collect()
+    Ok(code_tree.groups().iter().cloned().collect())
+}
+
+/// Summarise the code groups defined in a codes file
+///
+/// The result is a table (see ArrowTable) with one row per group,
+/// sorted by group name, and the columns:
+/// * group: the name of the group
+/// * n_codes: the number of codes in the group
+/// * example_codes: the first max_examples codes in the group
+///
+/// Raises ValueError if the codes file cannot be read.
+///
+#[pyfunction]
+#[pyo3(signature = (codes_file_path, max_examples=3))]
+fn rust_get_group_summaries(codes_file_path: &str, max_examples: usize) -> PyResult<ArrowTable> {
+    let code_tree = code_tree_from_file(codes_file_path)?;
+    let summaries = code_tree.group_summaries(max_examples);
+
+    let group = StringArray::from_iter_values(summaries.iter().map(|summary| &summary.group));
+    let n_codes = UInt64Array::from_iter_values(summaries.iter().map(|summary| summary.n_codes as u64));
+    let mut example_codes = ListBuilder::new(StringBuilder::new());
+    for summary in &summaries {
+        example_codes.append_value(summary.example_codes.iter().map(Some));
+    }
+    ArrowTable::from_columns(vec![
+        ("group", Arc::new(group) as ArrayRef),
+        ("n_codes", Arc::new(n_codes)),
+        ("example_codes", Arc::new(example_codes.finish())),
+    ])
+}
+
+/// Add a column for each code group in a codes file to a table of
+/// episodes (e.g. from HES), showing whether any of the codes in the
+/// row is in the group.
+///
+/// The table can be anything that implements __arrow_c_stream__
+/// (e.g. a pyarrow Table), and code_columns names the columns that
+/// hold codes of the kind in the codes file (e.g. the primary and
+/// secondary diagnosis columns for an ICD-10 codes file). Codes that
+/// do not match anything in the tree, and missing codes, are not in
+/// any group.
+///
+/// The result is a table (see ArrowTable) with the columns of the
+/// original table followed by one boolean column per group, named
+/// after the group and in sorted order. Raises KeyError if a code
+/// column is not in the table, or ValueError if a group has the same
+/// name as a column of the table or the codes file cannot be read.
+///
+#[pyfunction]
+fn rust_annotate_groups(
+    table: &PyAny,
+    codes_file_path: &str,
+    code_columns: Vec<&str>,
+) -> PyResult<ArrowTable> {
+    let batch = table::record_batch(table)?;
+    let mut parser = CodeParserCache::new(code_tree_from_file(codes_file_path)?);
+
+    let mut groups: Vec<String> = parser.code_tree().groups().iter().cloned().collect();
+    groups.sort();
+    if let Some(group) = groups
+        .iter()
+        .find(|group| batch.column_by_name(group).is_some())
+    {
+        return Err(PyValueError::new_err(format!(
+            "Group '{group}' has the same name as a column of the table"
+        )));
+    }
+
+    let code_columns = code_columns
+        .into_iter()
+        .map(|name| {
+            let column = batch
+                .column_by_name(name)
+                .ok_or_else(|| PyKeyError::new_err(format!("No column '{name}' in the table")))?;
+            table::as_strings(column)
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+
+    let mut flags: Vec<BooleanBuilder> = groups
+        .iter()
+        .map(|_| BooleanBuilder::with_capacity(batch.num_rows()))
+        .collect();
+    for row in 0..batch.num_rows() {
+        let mut in_group = vec![false; groups.len()];
+        for column in &code_columns {
+            if column.is_null(row) {
+                continue;
+            }
+            if let Ok(code_match) = parser.find_exact(column.value(row)) {
+                for (flag, group) in in_group.iter_mut().zip(&groups) {
+                    *flag |= code_match.in_group(group);
+                }
+            }
+        }
+        for (builder, flag) in flags.iter_mut().zip(in_group) {
+            builder.append_value(flag);
+        }
+    }
+
+    let new_columns = groups
+        .iter()
+        .zip(flags.iter_mut())
+        .map(|(group, builder)| (group.as_str(), Arc::new(builder.finish()) as ArrayRef))
+        .collect();
+    ArrowTable::with_new_columns(&batch, new_columns)
+}
+
+/// Convert a problem found in a codes file into a dictionary with
+/// the keys:
+/// * kind: the kind of problem (e.g. overlapping_categories)
+/// * location: the name of the category or group with the problem
+/// * field: the field of the category or group that has the problem
+///   (index, exclude, name or groups)
+/// * problem: a description of the problem
+fn diagnostic_dict(diagnostic: &TreeDiagnostic) -> HashMap<&'static str, String> {
+    let (kind, location, field) = match diagnostic {
+        TreeDiagnostic::ReversedIndex { category } => ("reversed_index", category, "index"),
+        TreeDiagnostic::IndexLengthMismatch { category } => {
+            ("index_length_mismatch", category, "index")
+        }
+        TreeDiagnostic::OverlappingCategories { first, .. } => {
+            ("overlapping_categories", first, "index")
+        }
+        TreeDiagnostic::OutsideParentRange { category, .. } => {
+            ("outside_parent_range", category, "index")
+        }
+        TreeDiagnostic::UnknownExcludeGroup { category, .. } => {
+            ("unknown_exclude_group", category, "exclude")
+        }
+        TreeDiagnostic::DuplicateCode { name, .. } => ("duplicate_code", name, "name"),
+        TreeDiagnostic::GroupNameConvention { group } => ("group_name_convention", group, "groups"),
+        TreeDiagnostic::GroupNotAllowed { group } => ("group_not_allowed", group, "groups"),
+    };
+    HashMap::from([
+        ("kind", kind.to_string()),
+        ("location", location.clone()),
+        ("field", field.to_string()),
+        ("problem", diagnostic.to_string()),
+    ])
+}
+
+/// Check a codes file for problems that would make codes silently
+/// fail to match, or put them in the wrong groups (see
+/// ClinicalCodeTree::validate and validate_group_names).
+///
+/// Group names must be snake_case (unless snake_case is false), and
+/// must be in allowed_groups if it is given. Returns a list with one
+/// dictionary per problem (see diagnostic_dict), which is empty if
+/// the codes file is valid. If the indices in the file are malformed,
+/// only those problems are returned, because the rest of the checks
+/// need a code tree that can be loaded.
+///
+/// Raises ValueError if the codes file cannot be read.
+///
+#[pyfunction]
+#[pyo3(signature = (codes_file_path, allowed_groups=None, snake_case=true))]
+fn rust_validate_codes_file(
+    codes_file_path: &str,
+    allowed_groups: Option<Vec<String>>,
+    snake_case: bool,
+) -> PyResult<Vec<HashMap<&'static str, String>>> {
+    let code_tree = match code_tree_from_file_unchecked(codes_file_path)? {
+        Ok(code_tree) => code_tree,
+        Err(HbrError::InvalidIndex(diagnostics)) => {
+            return Ok(diagnostics.iter().map(diagnostic_dict).collect())
+        }
+        Err(err) => return Err(to_py_err(err)),
+    };
+
+    let mut rules = GroupNameRules::new();
+    if snake_case {
+        rules = rules.with_convention(GroupNamingConvention::SnakeCase);
+    }
+    if let Some(allowed_groups) = allowed_groups {
+        rules = rules.with_allowed_groups(&allowed_groups);
+    }
+
+    let mut diagnostics = code_tree.validate();
+    for diagnostic in code_tree.validate_group_names(&rules) {
+        // Undefined excluded groups are found by both checks
+        if !diagnostics.contains(&diagnostic) {
+            diagnostics.push(diagnostic);
+        }
+    }
+    Ok(diagnostics.iter().map(diagnostic_dict).collect())
 }
 
 /// A Python module implemented in Rust.
@@ -133,6 +576,17 @@ fn rust_get_groups_in_codes_file(codes_file_path: &str) -> Vec<String> {
 fn my_lib_name(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(rust_get_codes_in_group, m)?)?;
     m.add_function(wrap_pyfunction!(rust_get_groups_in_codes_file, m)?)?;
+    m.add_function(wrap_pyfunction!(rust_get_group_summaries, m)?)?;
+    m.add_function(wrap_pyfunction!(rust_annotate_groups, m)?)?;
+    m.add_function(wrap_pyfunction!(rust_validate_codes_file, m)?)?;
+    m.add_function(wrap_pyfunction!(
+        measurements::rust_measurements_from_pathology_blood,
+        m
+    )?)?;
+    m.add_function(wrap_pyfunction!(arc_hbr::rust_arc_hbr_from_features, m)?)?;
+    m.add_function(wrap_pyfunction!(synth::rust_generate_synthetic_dataset, m)?)?;
+    m.add_function(wrap_pyfunction!(synth::rust_verify_synthetic_dataset, m)?)?;
     m.add_class::<RustClinicalCodeParser>()?;
+    m.add_class::<ArrowTable>()?;
     Ok(())
 }
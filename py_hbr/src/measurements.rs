@@ -0,0 +1,134 @@
+//! Converting raw blood test results into measurements
+//!
+//! Each row of the raw table is converted using the same function
+//! as the Rust preprocessing (e.g. measurement_from_pathology_blood),
+//! so Python and Rust pipelines agree on which results are valid.
+
+use std::sync::Arc;
+
+use arrow::array::{Array, ArrayRef, Float64Builder, StringBuilder};
+use pyo3::{exceptions::PyKeyError, prelude::*};
+use rust_hbr::{
+    patient::measurements::MeasurementValue, preprocess::measurement_from_pathology_blood,
+    units::Analyte,
+};
+
+use crate::table::{self, ArrowTable};
+
+/// The columns of the pathology_blood table, in the order of the
+/// arguments of measurement_from_pathology_blood
+const PATHOLOGY_BLOOD_COLUMNS: [&str; 6] = [
+    "order_name",
+    "test_name",
+    "sample_collected_date_time",
+    "result_available_date_time",
+    "test_result",
+    "test_result_unit",
+];
+
+/// Convert the rows of a HIC pathology_blood table into measurements.
+///
+/// The table can be anything that implements __arrow_c_stream__ (e.g.
+/// a pyarrow Table), and must have a subject column as well as the
+/// columns read by measurement_from_pathology_blood (order_name,
+/// test_name, test_result, test_result_unit, sample_collected_date_time
+/// and result_available_date_time). Columns that are not strings
+/// (e.g. timestamps) are converted to strings first.
+///
+/// The result is a table (see ArrowTable) with one row per row of the
+/// input, and the columns:
+/// * subject: the subject column of the input
+/// * measurement: the name of the test (e.g. Platelets)
+/// * value: the numerical value of the measurement (null if the row
+///   could not be converted)
+/// * unit: the unit of the value, which is the canonical unit of the
+///   test (see rust_hbr::units) if the row was converted, or the unit
+///   of the test result otherwise
+/// * date: the sample_collected_date_time column of the input
+/// * flags: the reason the row could not be converted into a
+///   measurement (e.g. an unexpected unit), or null if it was converted
+///
+/// Raises KeyError if a column is missing, or ValueError if the table
+/// cannot be read.
+///
+#[pyfunction]
+pub fn rust_measurements_from_pathology_blood(pathology_blood: &PyAny) -> PyResult<ArrowTable> {
+    let batch = table::record_batch(pathology_blood)?;
+    let column = |name: &str| {
+        batch
+            .column_by_name(name)
+            .cloned()
+            .ok_or_else(|| PyKeyError::new_err(format!("No column '{name}' in the table")))
+    };
+    let subject = column("subject")?;
+    let date = column("sample_collected_date_time")?;
+    let columns = PATHOLOGY_BLOOD_COLUMNS
+        .iter()
+        .map(|name| table::as_strings(&column(name)?))
+        .collect::<PyResult<Vec<_>>>()?;
+    let test_name = &columns[1];
+    let test_result_unit = &columns[5];
+
+    let mut value = Float64Builder::with_capacity(batch.num_rows());
+    let mut unit = StringBuilder::new();
+    let mut flags = StringBuilder::new();
+    for row in 0..batch.num_rows() {
+        let raw_unit = test_result_unit
+            .is_valid(row)
+            .then(|| test_result_unit.value(row));
+        let missing = PATHOLOGY_BLOOD_COLUMNS
+            .iter()
+            .zip(&columns)
+            .find(|(_, column)| column.is_null(row));
+        if let Some((name, _)) = missing {
+            value.append_null();
+            unit.append_option(raw_unit);
+            flags.append_value(format!("missing {name}"));
+            continue;
+        }
+
+        let [order_name, test_name, sample_collected, result_available, test_result, test_result_unit] =
+            [0, 1, 2, 3, 4, 5].map(|n| columns[n].value(row).to_string());
+        let canonical_unit =
+            Analyte::from_test_name(&test_name).map(|analyte| analyte.canonical_unit());
+        let measurement = measurement_from_pathology_blood(
+            order_name,
+            test_name,
+            sample_collected,
+            result_available,
+            test_result,
+            test_result_unit,
+        );
+        match measurement.map(|measurement| measurement.value) {
+            Ok(MeasurementValue::Integer(integer)) => {
+                value.append_value(integer as f64);
+                unit.append_option(canonical_unit);
+                flags.append_null();
+            }
+            Ok(MeasurementValue::Real(real)) => {
+                value.append_value(real);
+                unit.append_option(canonical_unit);
+                flags.append_null();
+            }
+            Ok(MeasurementValue::String(string)) => {
+                value.append_null();
+                unit.append_option(canonical_unit);
+                flags.append_value(format!("non-numerical value {string}"));
+            }
+            Err(err) => {
+                value.append_null();
+                unit.append_option(raw_unit);
+                flags.append_value(err.to_string());
+            }
+        }
+    }
+
+    ArrowTable::from_columns(vec![
+        ("subject", subject),
+        ("measurement", Arc::new(test_name.clone()) as ArrayRef),
+        ("value", Arc::new(value.finish())),
+        ("unit", Arc::new(unit.finish())),
+        ("date", date),
+        ("flags", Arc::new(flags.finish())),
+    ])
+}
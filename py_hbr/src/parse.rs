@@ -0,0 +1,133 @@
+//! Parsing whole columns of codes
+//!
+//! The result of parsing a column is a table with one row per code
+//! (see RustClinicalCodeParser::parse_column), built up one row at a
+//! time by ParsedColumnBuilder. Large columns can be parsed in
+//! parallel using parse_chunks, which splits the column into chunks
+//! and parses each chunk on a separate thread.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow::array::{Array, ArrayRef, BooleanBuilder, ListBuilder, StringArray, StringBuilder};
+use arrow::compute::concat_batches;
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use rayon::prelude::*;
+use rust_hbr::{
+    clinical_code::{ClinicalCodeRef, ClinicalCodeStore},
+    clinical_code_tree::ClinicalCodeTree,
+};
+
+/// Builds the columns of the table returned by parse_column:
+/// * name: the name of the matched code (null if not valid)
+/// * docs: the description of the matched code (null if not valid)
+/// * groups: the sorted list of groups containing the code (empty
+///   if not valid)
+/// * valid: whether an exact match was found for the code
+pub struct ParsedColumnBuilder {
+    name: StringBuilder,
+    docs: StringBuilder,
+    groups: ListBuilder<StringBuilder>,
+    valid: BooleanBuilder,
+}
+
+impl ParsedColumnBuilder {
+    pub fn new() -> Self {
+        Self {
+            name: StringBuilder::new(),
+            docs: StringBuilder::new(),
+            groups: ListBuilder::new(StringBuilder::new()),
+            valid: BooleanBuilder::new(),
+        }
+    }
+
+    /// Add a row for a code that was matched using find_exact
+    /// (so its groups are recorded in the code store)
+    pub fn append_match(&mut self, code_store: &ClinicalCodeStore, code_ref: &ClinicalCodeRef) {
+        let matched_code = code_store
+            .clinical_code_from(code_ref)
+            .expect("If code was matched, expected code ref to be valid");
+        self.name.append_value(matched_code.name());
+        self.docs.append_value(matched_code.docs());
+        let mut code_groups: Vec<&String> = code_store
+            .groups_of(code_ref)
+            .expect("Groups are recorded when a code is matched")
+            .iter()
+            .collect();
+        code_groups.sort();
+        for group in code_groups {
+            self.groups.values().append_value(group);
+        }
+        self.groups.append(true);
+        self.valid.append_value(true);
+    }
+
+    /// Add a row for a code that was not matched (or was missing)
+    pub fn append_invalid(&mut self) {
+        self.name.append_null();
+        self.docs.append_null();
+        self.groups.append(true);
+        self.valid.append_value(false);
+    }
+
+    /// Make the table from the rows added so far
+    pub fn finish(mut self) -> Result<RecordBatch, ArrowError> {
+        RecordBatch::try_from_iter([
+            ("name", Arc::new(self.name.finish()) as ArrayRef),
+            ("docs", Arc::new(self.docs.finish())),
+            ("groups", Arc::new(self.groups.finish())),
+            ("valid", Arc::new(self.valid.finish())),
+        ])
+    }
+}
+
+/// Parse one chunk of a column, using a code store that belongs
+/// to the chunk. Each distinct code string is only looked up once.
+fn parse_chunk(code_tree: &ClinicalCodeTree, codes: &StringArray) -> Result<RecordBatch, ArrowError> {
+    let mut code_store = ClinicalCodeStore::new();
+    let mut matches: HashMap<&str, Option<ClinicalCodeRef>> = HashMap::new();
+    let mut builder = ParsedColumnBuilder::new();
+    for code in codes.iter() {
+        let code_ref = code.and_then(|code| {
+            *matches.entry(code).or_insert_with(|| {
+                let code_match = code_tree.find_exact(code.to_string(), &mut code_store);
+                code_match.ok().map(|code_match| code_match.code())
+            })
+        });
+        match code_ref {
+            Some(code_ref) => builder.append_match(&code_store, &code_ref),
+            None => builder.append_invalid(),
+        }
+    }
+    builder.finish()
+}
+
+/// Parse a column of codes in chunks of chunk_size codes, using a
+/// thread for each chunk (from the rayon thread pool), and join the
+/// results into one table in the original order.
+///
+/// ClinicalCodeRefs from one store cannot be used with another, so
+/// each chunk has its own code store, and the matches are converted
+/// to names and docs before the chunks are joined. This means the
+/// result is the same as parsing the whole column in one go.
+pub fn parse_chunks(
+    code_tree: &ClinicalCodeTree,
+    codes: &StringArray,
+    chunk_size: usize,
+) -> Result<RecordBatch, ArrowError> {
+    let chunk_size = chunk_size.max(1);
+    let batches = (0..codes.len())
+        .step_by(chunk_size)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|offset| {
+            let length = chunk_size.min(codes.len() - offset);
+            parse_chunk(code_tree, &codes.slice(offset, length))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    match batches.first() {
+        Some(batch) => concat_batches(&batch.schema(), &batches),
+        None => ParsedColumnBuilder::new().finish(),
+    }
+}
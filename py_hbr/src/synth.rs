@@ -0,0 +1,65 @@
+//! Synthetic data generation
+//!
+//! Synthetic datasets are generated and written to files entirely in
+//! Rust (see rust_hbr::synth_dataset), so large tables never need to
+//! be held in Python memory.
+
+use std::path::Path;
+
+use pyo3::prelude::*;
+use rust_hbr::manifest::verify_dataset;
+use rust_hbr::synth_dataset::{generate_dataset, DatasetSpec};
+
+use crate::to_py_err;
+
+/// Generate the synthetic tables listed in a dataset spec, writing
+/// each one to a file (named after the table, in the format given by
+/// the spec, which is parquet by default) in output_dir.
+///
+/// The spec is a YAML or JSON string with the global seed and the
+/// list of tables to generate (see DatasetSpec). The GIL is released
+/// while the tables are generated. Returns the paths of the files
+/// written, in the same order as the tables in the spec.
+///
+/// Raises ValueError if the spec is not valid, or if the files
+/// cannot be written.
+///
+#[pyfunction]
+pub fn rust_generate_synthetic_dataset(
+    py: Python<'_>,
+    spec: &str,
+    output_dir: &str,
+) -> PyResult<Vec<String>> {
+    let spec = DatasetSpec::from_reader(spec.as_bytes()).map_err(to_py_err)?;
+    let paths = py
+        .allow_threads(|| generate_dataset(&spec, output_dir))
+        .map_err(to_py_err)?;
+    Ok(paths
+        .iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Check a synthetic dataset in output_dir against its manifest (see
+/// rust_hbr::manifest::verify_dataset). The GIL is released while the
+/// tables are generated again. Returns the paths of the files that
+/// were verified.
+///
+/// Raises ValueError if the dataset does not match its manifest.
+///
+#[pyfunction]
+pub fn rust_verify_synthetic_dataset(py: Python<'_>, output_dir: &str) -> PyResult<Vec<String>> {
+    let manifest = py
+        .allow_threads(|| verify_dataset(output_dir))
+        .map_err(to_py_err)?;
+    Ok(manifest
+        .files
+        .iter()
+        .map(|file| {
+            Path::new(output_dir)
+                .join(&file.file_name)
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect())
+}
@@ -0,0 +1,166 @@
+//! Tables returned to Python as Arrow data
+//!
+//! Tabular results are built as an Arrow RecordBatch and returned
+//! wrapped in ArrowTable, which implements the Arrow PyCapsule
+//! interface (__arrow_c_stream__). Python libraries that support the
+//! interface (pyarrow.table(), polars.DataFrame(), etc.) can then read
+//! the columns directly, without converting each value to a Python
+//! object first. Columns passed from Python are read in the same way
+//! where possible (see string_array and record_batch).
+
+use std::ffi::CString;
+use std::sync::Arc;
+
+use arrow::array::{make_array, ArrayRef, AsArray, StringArray};
+use arrow::compute::{cast, concat_batches};
+use arrow::datatypes::DataType;
+use arrow::ffi::{from_ffi, FFI_ArrowArray, FFI_ArrowSchema};
+use arrow::ffi_stream::{ArrowArrayStreamReader, FFI_ArrowArrayStream};
+use arrow::record_batch::{RecordBatch, RecordBatchIterator, RecordBatchReader};
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyCapsule};
+
+/// Check that a capsule from the Arrow PyCapsule interface has the
+/// expected name, and return a pointer to its contents
+fn capsule_pointer(capsule: &PyCapsule, name: &str) -> PyResult<*mut std::ffi::c_void> {
+    match capsule.name()? {
+        Some(capsule_name) if capsule_name.to_bytes() == name.as_bytes() => Ok(capsule.pointer()),
+        _ => Err(PyValueError::new_err(format!("Expected an {name} capsule"))),
+    }
+}
+
+/// Read a column of strings passed from Python, either as an Arrow
+/// array (anything implementing __arrow_c_array__, such as a pyarrow
+/// Array) or as a sequence of strings (such as a list or a numpy
+/// array). Missing values (None or null) are null in the result.
+pub fn string_array(column: &PyAny) -> PyResult<StringArray> {
+    if !column.hasattr("__arrow_c_array__")? {
+        let strings: Vec<Option<String>> = column.extract()?;
+        return Ok(StringArray::from(strings));
+    }
+
+    let (schema, array): (&PyCapsule, &PyCapsule) =
+        column.call_method0("__arrow_c_array__")?.extract()?;
+    let schema = capsule_pointer(schema, "arrow_schema")? as *const FFI_ArrowSchema;
+    let array = capsule_pointer(array, "arrow_array")? as *mut FFI_ArrowArray;
+    // Safety: the capsules hold an ArrowSchema and ArrowArray (checked
+    // by name above). The array is moved out of its capsule (leaving it
+    // released), and the schema is only borrowed.
+    let data = unsafe { from_ffi(FFI_ArrowArray::from_raw(array), &*schema) }
+        .map_err(|err| PyValueError::new_err(err.to_string()))?;
+    as_strings(&make_array(data))
+}
+
+/// Convert an Arrow column to strings (e.g. from large strings or
+/// a dictionary-encoded column), or raise ValueError if the column
+/// cannot be converted
+pub fn as_strings(column: &ArrayRef) -> PyResult<StringArray> {
+    let strings = cast(column, &DataType::Utf8)
+        .map_err(|err| PyValueError::new_err(format!("Expected a column of strings: {err}")))?;
+    Ok(strings.as_string::<i32>().clone())
+}
+
+/// Read a table passed from Python (anything implementing
+/// __arrow_c_stream__, such as a pyarrow Table or a polars
+/// DataFrame) into a single record batch.
+pub fn record_batch(table: &PyAny) -> PyResult<RecordBatch> {
+    let stream: &PyCapsule = table.call_method0("__arrow_c_stream__")?.extract()?;
+    let stream = capsule_pointer(stream, "arrow_array_stream")? as *mut FFI_ArrowArrayStream;
+    // Safety: the capsule holds an ArrowArrayStream (checked by name
+    // above), which is moved out of the capsule (leaving it released)
+    let stream = unsafe { FFI_ArrowArrayStream::from_raw(stream) };
+    let reader = ArrowArrayStreamReader::try_new(stream)
+        .map_err(|err| PyValueError::new_err(err.to_string()))?;
+    let schema = reader.schema();
+    let batches = reader
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| PyValueError::new_err(err.to_string()))?;
+    concat_batches(&schema, &batches).map_err(|err| PyValueError::new_err(err.to_string()))
+}
+
+/// A table of results, which can be converted to a pyarrow Table,
+/// a polars DataFrame, or anything else that reads Arrow data
+/// using the PyCapsule interface (e.g. pyarrow.table(result))
+#[pyclass]
+pub struct ArrowTable {
+    batch: RecordBatch,
+}
+
+impl ArrowTable {
+    /// Make a table from a record batch
+    pub fn from_batch(batch: RecordBatch) -> Self {
+        Self { batch }
+    }
+
+    /// Make a table from named columns, which must all have the
+    /// same length
+    pub fn from_columns(columns: Vec<(&str, ArrayRef)>) -> PyResult<Self> {
+        let batch = RecordBatch::try_from_iter(columns)
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        Ok(Self { batch })
+    }
+
+    /// Make a table from the columns of a record batch followed
+    /// by some new columns
+    pub fn with_new_columns(batch: &RecordBatch, columns: Vec<(&str, ArrayRef)>) -> PyResult<Self> {
+        let schema = batch.schema();
+        let existing = schema
+            .fields()
+            .iter()
+            .zip(batch.columns())
+            .map(|(field, column)| (field.name().as_str(), column.clone()));
+        Self::from_columns(existing.chain(columns).collect())
+    }
+
+    /// Make a table of string columns
+    pub fn from_string_columns(columns: Vec<(&str, Vec<String>)>) -> PyResult<Self> {
+        Self::from_columns(
+            columns
+                .into_iter()
+                .map(|(name, column)| {
+                    let array: ArrayRef = Arc::new(StringArray::from(column));
+                    (name, array)
+                })
+                .collect(),
+        )
+    }
+}
+
+#[pymethods]
+impl ArrowTable {
+    /// Export the schema of the table (Arrow PyCapsule interface)
+    fn __arrow_c_schema__<'py>(&self, py: Python<'py>) -> PyResult<&'py PyCapsule> {
+        let schema = FFI_ArrowSchema::try_from(self.batch.schema().as_ref())
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        PyCapsule::new(py, schema, Some(CString::new("arrow_schema").unwrap()))
+    }
+
+    /// Export the table as a stream of record batches (Arrow
+    /// PyCapsule interface). Casting to a requested schema is
+    /// not supported, so requested_schema is ignored.
+    #[pyo3(signature = (requested_schema=None))]
+    fn __arrow_c_stream__<'py>(
+        &self,
+        py: Python<'py>,
+        requested_schema: Option<PyObject>,
+    ) -> PyResult<&'py PyCapsule> {
+        let _ = requested_schema;
+        let batches = RecordBatchIterator::new(vec![Ok(self.batch.clone())], self.batch.schema());
+        let stream = FFI_ArrowArrayStream::new(Box::new(batches));
+        PyCapsule::new(py, stream, Some(CString::new("arrow_array_stream").unwrap()))
+    }
+
+    /// Get the number of rows in the table
+    fn __len__(&self) -> usize {
+        self.batch.num_rows()
+    }
+
+    /// Get the names of the columns of the table
+    fn column_names(&self) -> Vec<String> {
+        self.batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect()
+    }
+}
@@ -8,8 +8,111 @@
 //! any extra conversion to more usable types has been performed).
 //! 
 
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::thread::LocalKey;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use extendr_api::prelude::*;
-use rust_hbr::{clinical_code::ClinicalCodeStore, clinical_code_tree::ClinicalCodeTree};
+use polars::prelude::{DataFrame, DataType, PolarsError, Series, TimeUnit};
+use rand::seq::SliceRandom;
+use rust_hbr::{
+    clinical_code::{ClinicalCodeRef, ClinicalCodeStore},
+    clinical_code_tree::{ClinicalCodeTree, CodeParserCache},
+    patient::{
+        summary::{summarise_patients, EpisodeRecord, LabResult, PatientSummary},
+        PatientStore,
+    },
+    preprocess::measurement_from_pathology_blood,
+    seeded_rng::make_rng,
+    synth_dataset::{SyntheticTable, TableSpec},
+    GeneratorVersion, HbrError, PatientRegistry, DEFAULT_NUM_PATIENTS,
+};
+
+thread_local! {
+    /// Parser for the ICD-10 codes file embedded in rust_hbr. R only
+    /// calls into the package from one thread, so the parser (and the
+    /// codes it has already looked up) is kept between calls.
+    static DIAGNOSIS_PARSER: RefCell<Option<CodeParserCache>> = const { RefCell::new(None) };
+
+    /// Parser for the OPCS-4 codes file embedded in rust_hbr
+    static PROCEDURE_PARSER: RefCell<Option<CodeParserCache>> = const { RefCell::new(None) };
+}
+
+/// Convert an error from rust_hbr into an R error (raised as an R
+/// condition when the function returns to R). The codes file is
+/// added to the message for errors that depend on which codes file
+/// was used (e.g. an unknown group).
+fn to_r_error(err: HbrError, codes_file_path: &str) -> Error {
+    match err {
+        HbrError::UnknownGroup(group) => Error::Other(format!(
+            "Code group '{group}' is not defined in codes file '{codes_file_path}'"
+        )),
+        HbrError::EmptyGroup(group) => Error::Other(format!(
+            "Code group '{group}' does not contain any codes in codes file '{codes_file_path}'"
+        )),
+        HbrError::CodesFile(err) => Error::Other(format!(
+            "Failed to parse codes file '{codes_file_path}': {err}"
+        )),
+        err => Error::Other(err.to_string()),
+    }
+}
+
+/// Get the name and description of a code in the code store. Raises
+/// an R error if the code is not in the store (which is a bug, because
+/// code refs are only obtained by adding codes to the store).
+fn name_and_docs(
+    code_store: &ClinicalCodeStore,
+    code_ref: &ClinicalCodeRef,
+) -> Result<(String, String)> {
+    let clinical_code = code_store.clinical_code_from(code_ref).ok_or_else(|| {
+        Error::Other(String::from("Clinical code is missing from the code store"))
+    })?;
+    Ok((clinical_code.name().clone(), clinical_code.docs().clone()))
+}
+
+/// Call f with the parser for "diagnosis" (ICD-10) or "procedure"
+/// (OPCS-4) codes, which is created from the codes file embedded in
+/// rust_hbr the first time it is used. Raises an R error for any
+/// other kind.
+fn with_default_parser<T>(
+    kind: &str,
+    f: impl FnOnce(&mut CodeParserCache) -> Result<T>,
+) -> Result<T> {
+    type DefaultTree = fn() -> std::result::Result<ClinicalCodeTree, HbrError>;
+    let (parser, default_tree): (&'static LocalKey<_>, DefaultTree) = match kind {
+        "diagnosis" => (&DIAGNOSIS_PARSER, ClinicalCodeTree::icd10_default),
+        "procedure" => (&PROCEDURE_PARSER, ClinicalCodeTree::opcs4_default),
+        _ => {
+            return Err(Error::Other(format!(
+                "Must pass one of 'diagnosis' or 'procedure', not '{kind}'"
+            )))
+        }
+    };
+    parser.with(|parser| {
+        let mut parser = parser.borrow_mut();
+        if let Some(parser) = parser.as_mut() {
+            return f(parser);
+        }
+        let code_tree = default_tree().map_err(|err| Error::Other(err.to_string()))?;
+        f(parser.insert(CodeParserCache::new(code_tree)))
+    })
+}
+
+/// Open and parse a codes file. Errors are converted to an R
+/// error (raised when the function returns to R), instead of
+/// panicking.
+fn code_tree_from_file(codes_file_path: &str) -> Result<ClinicalCodeTree> {
+    let f = std::fs::File::open(codes_file_path).map_err(|err| match err.kind() {
+        std::io::ErrorKind::NotFound => {
+            Error::Other(format!("The codes file '{codes_file_path}' does not exist"))
+        }
+        _ => Error::Other(format!(
+            "Failed to open codes file '{codes_file_path}': {err}"
+        )),
+    })?;
+    ClinicalCodeTree::from_reader(f).map_err(|err| to_r_error(err, codes_file_path))
+}
 
 /// Get the clinical codes in a particular code group defined
 /// in a codes file.
@@ -19,28 +122,25 @@ use rust_hbr::{clinical_code::ClinicalCodeStore, clinical_code_tree::ClinicalCod
 /// * name: the name of the code in the group (e.g. A01.0)
 /// * docs: the description of the code 
 /// 
-/// TODO: figure out a good way to handle errors.
+/// Raises an R error if the codes file cannot be read, or if the
+/// group is not defined in the codes file.
 /// 
 /// @export
 #[extendr]
-fn rust_get_codes_in_group(codes_file_path: &str, group: &str) -> List {
-    let f = std::fs::File::open(codes_file_path).expect("Failed to open codes file");
-
-    let code_tree = ClinicalCodeTree::from_reader(f);
+fn rust_get_codes_in_group(codes_file_path: &str, group: &str) -> Result<List> {
+    let code_tree = code_tree_from_file(codes_file_path)?;
     let mut code_store = ClinicalCodeStore::new();
 
     let clinical_code_refs = code_tree
         .codes_in_group(&String::from(group), &mut code_store)
-        .expect("Should succeed, code is present");
+        .map_err(|err| to_r_error(err, codes_file_path))?;
 
     let mut name = Vec::new();
     let mut docs = Vec::new();
     for code_ref in clinical_code_refs {
-        let clinical_code = code_store
-            .clinical_code_from(&code_ref)
-            .expect("Clinical code should be present");
-        name.push(clinical_code.name().clone());
-        docs.push(clinical_code.docs().clone());
+        let (code_name, code_docs) = name_and_docs(&code_store, &code_ref)?;
+        name.push(code_name);
+        docs.push(code_docs);
     }
 
     // Don't be fooled here -- interpret this as
@@ -48,7 +148,7 @@ fn rust_get_codes_in_group(codes_file_path: &str, group: &str) -> List {
     // {"name": name, "docs": docs}; in R, the
     // lvalues are strings, but they are "unquoted"
     // (they are not variables).
-    list!(name = name, docs = docs)
+    Ok(list!(name = name, docs = docs))
 }
 
 /// Get the code groups defined in a codes file
@@ -58,11 +158,667 @@ fn rust_get_codes_in_group(codes_file_path: &str, group: &str) -> List {
 /// all the code groups using rust_get_codes_in_group.
 /// 
 #[extendr]
-fn rust_get_groups_in_codes_file(codes_file_path: &str) -> Vec<String> {
-    let f = std::fs::File::open(codes_file_path).expect("Failed to open codes file");
-    let code_tree = ClinicalCodeTree::from_reader(f);
+fn rust_get_groups_in_codes_file(codes_file_path: &str) -> Result<Vec<String>> {
+    let code_tree = code_tree_from_file(codes_file_path)?;
     // get the code groups and return here
-    code_tree.groups().iter().cloned().collect()
+    Ok(code_tree.groups().iter().cloned().collect())
+}
+
+/// Get the clinical codes in every code group defined in a codes
+/// file, in one long table.
+///
+/// The result is a named list (intended as a dataframe) with one
+/// row for each code in each group (so a code in two groups appears
+/// twice), sorted by group, and the columns:
+/// * group: the name of the code group
+/// * name: the name of the code in the group (e.g. A01.0)
+/// * docs: the description of the code
+///
+/// Raises an R error if the codes file cannot be read.
+///
+/// @export
+#[extendr]
+fn rust_get_all_groups(codes_file_path: &str) -> Result<List> {
+    let code_tree = code_tree_from_file(codes_file_path)?;
+    let mut code_store = ClinicalCodeStore::new();
+
+    let mut groups: Vec<&String> = code_tree.groups().iter().collect();
+    groups.sort();
+
+    let mut group_column = Vec::new();
+    let mut name = Vec::new();
+    let mut docs = Vec::new();
+    for group in groups {
+        let clinical_code_refs = code_tree
+            .codes_in_group(group, &mut code_store)
+            .map_err(|err| to_r_error(err, codes_file_path))?;
+        for code_ref in clinical_code_refs {
+            let (code_name, code_docs) = name_and_docs(&code_store, &code_ref)?;
+            group_column.push(group.clone());
+            name.push(code_name);
+            docs.push(code_docs);
+        }
+    }
+
+    Ok(list!(group = group_column, name = name, docs = docs))
+}
+
+/// Summarise the contents of a codes file, for checking which edition
+/// or variant of the codes file was loaded.
+///
+/// The result is a named list with the elements:
+/// * n_codes: the number of codes in the codes file
+/// * n_categories: the number of categories that are not codes (at
+///   any level, including the chapters)
+/// * n_chapters: the number of chapters (e.g. 22 for ICD-10)
+/// * group_sizes: a named integer vector (sorted by name) with the
+///   number of codes in each group
+///
+/// Raises an R error if the codes file cannot be read.
+///
+/// @export
+#[extendr]
+fn rust_codes_file_summary(codes_file_path: &str) -> Result<List> {
+    let code_tree = code_tree_from_file(codes_file_path)?;
+    let summary = code_tree.summary();
+
+    let mut group_sizes: Vec<(&String, &usize)> = summary.group_sizes.iter().collect();
+    group_sizes.sort();
+    let (groups, sizes): (Vec<&String>, Vec<i32>) = group_sizes
+        .into_iter()
+        .map(|(group, size)| (group, *size as i32))
+        .unzip();
+    let mut group_sizes = Robj::from(sizes);
+    group_sizes.set_names(groups.iter().map(|group| group.as_str()))?;
+
+    Ok(list!(
+        n_codes = summary.n_codes as i32,
+        n_categories = summary.n_categories as i32,
+        n_chapters = summary.n_chapters as i32,
+        group_sizes = group_sizes
+    ))
+}
+
+/// Find a diagnosis (ICD-10) or procedure (OPCS-4) code, using the
+/// codes files embedded in the package. Pass "diagnosis" or
+/// "procedure" as the kind.
+///
+/// The result is a named character vector c(name = ..., docs = ...)
+/// containing the name of the matched code (e.g. I21.0) and its
+/// description. If there is no exact match for the code, both
+/// elements are NA, and the reason is in the "reason" attribute.
+///
+/// Raises an R error if kind is not "diagnosis" or "procedure".
+///
+/// @export
+#[extendr]
+fn rust_find_exact(code: &str, kind: &str) -> Result<Robj> {
+    with_default_parser(kind, |parser| {
+        let (name_and_docs, reason) =
+            match parser.find_exact(code).map(|code_match| code_match.code()) {
+                Ok(code_ref) => {
+                    let (name, docs) = name_and_docs(parser.code_store(), &code_ref)?;
+                    (vec![Some(name), Some(docs)], None)
+                }
+                Err(err) => (vec![None, None], Some(err.to_string())),
+            };
+        let mut result = Robj::from(name_and_docs);
+        result.set_names(["name", "docs"])?;
+        if let Some(reason) = reason {
+            result.set_attrib("reason", reason)?;
+        }
+        Ok(result)
+    })
+}
+
+/// Get the groups that contain a diagnosis (ICD-10) or procedure
+/// (OPCS-4) code, using the codes files embedded in the package.
+/// Pass "diagnosis" or "procedure" as the kind.
+///
+/// Returns a sorted character vector of group names, which is empty
+/// if the code is valid but not in any group. Raises an R error if
+/// there is no exact match for the code, or if kind is not
+/// "diagnosis" or "procedure".
+///
+/// @export
+#[extendr]
+fn rust_groups_for_code(code: &str, kind: &str) -> Result<Vec<String>> {
+    with_default_parser(kind, |parser| {
+        let code_match = parser
+            .find_exact(code)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        let mut groups: Vec<String> = code_match.groups().iter().cloned().collect();
+        groups.sort();
+        Ok(groups)
+    })
+}
+
+/// Pick n codes at random (with replacement) from a group of diagnosis
+/// (ICD-10) or procedure (OPCS-4) codes, using the codes files embedded
+/// in the package. Pass "diagnosis" or "procedure" as the kind.
+///
+/// Returns a character vector of code names. The same seed always
+/// gives the same codes, which are also the same as the codes from
+/// random_code_from_group in py_hbr. Raises an R error if the group
+/// is not defined or is empty.
+///
+/// @export
+#[extendr]
+fn rust_sample_codes(group: &str, n: usize, seed: u64, kind: &str) -> Result<Vec<String>> {
+    with_default_parser(kind, |parser| {
+        let codes = parser
+            .codes_in_group(&group.to_string())
+            .map_err(|err| Error::Other(err.to_string()))?;
+        if codes.is_empty() {
+            return Err(Error::Other(
+                HbrError::EmptyGroup(group.to_string()).to_string(),
+            ));
+        }
+
+        // Same id as py_hbr, so that both give the same codes
+        let mut rng = make_rng(seed, "random_code_from_group");
+        (0..n)
+            .map(|_| {
+                let code_ref = codes.choose(&mut rng).expect("Group is not empty");
+                let (name, _) = name_and_docs(parser.code_store(), code_ref)?;
+                Ok(name)
+            })
+            .collect()
+    })
+}
+
+/// Check a character vector of diagnosis (ICD-10) or procedure
+/// (OPCS-4) codes, using the codes files embedded in the package.
+/// Pass "diagnosis" or "procedure" as the kind.
+///
+/// All the codes are parsed in Rust (each distinct code is only
+/// looked up once), so this is much faster than checking codes one
+/// at a time from R. The result is a named list (intended as a
+/// dataframe) with one row per code, and the columns:
+/// * code: the code that was checked
+/// * valid: whether an exact match was found for the code
+/// * name: the name of the matched code (NA if not valid)
+/// * docs: the description of the matched code (NA if not valid)
+/// * groups: a list of the (sorted) groups containing each code
+///   (empty if not valid)
+///
+/// Missing codes (NA) are not valid. Raises an R error if kind is
+/// not "diagnosis" or "procedure".
+///
+/// @export
+#[extendr]
+fn rust_check_codes(codes: Strings, kind: &str) -> Result<List> {
+    with_default_parser(kind, |parser| {
+        let mut valid = Vec::with_capacity(codes.len());
+        let mut name = Vec::with_capacity(codes.len());
+        let mut docs = Vec::with_capacity(codes.len());
+        let mut groups = Vec::with_capacity(codes.len());
+        for code in codes.iter() {
+            let code_match = if code.is_na() {
+                None
+            } else {
+                parser.find_exact(code.as_str()).ok().map(|code_match| {
+                    let mut code_groups: Vec<String> =
+                        code_match.groups().iter().cloned().collect();
+                    code_groups.sort();
+                    (code_match.code(), code_groups)
+                })
+            };
+            match code_match {
+                Some((code_ref, code_groups)) => {
+                    let (code_name, code_docs) = name_and_docs(parser.code_store(), &code_ref)?;
+                    valid.push(true);
+                    name.push(Some(code_name));
+                    docs.push(Some(code_docs));
+                    groups.push(code_groups);
+                }
+                None => {
+                    valid.push(false);
+                    name.push(None);
+                    docs.push(None);
+                    groups.push(Vec::new());
+                }
+            }
+        }
+        let groups = List::from_values(groups);
+        Ok(list!(
+            code = codes,
+            valid = valid,
+            name = name,
+            docs = docs,
+            groups = groups
+        ))
+    })
+}
+
+/// Flag which code groups in a codes file contain any of the codes in
+/// each row of a table of episodes (e.g. from HES).
+///
+/// code_columns is a list of character vectors of the same length,
+/// holding the codes of the kind in the codes file (e.g. the primary
+/// and secondary diagnosis columns for an ICD-10 codes file). Codes
+/// that do not match anything in the tree, and missing codes (NA),
+/// are not in any group.
+///
+/// The result is a named list (intended to be added as columns to the
+/// table of episodes) with one logical vector per group, named after
+/// the group and in sorted order, which is TRUE for rows where any of
+/// the codes is in the group. Raises an R error if the codes file
+/// cannot be read, or if the code columns are not character vectors
+/// of the same length.
+///
+/// @export
+#[extendr]
+fn rust_annotate_groups(code_columns: List, codes_file_path: &str) -> Result<List> {
+    let mut parser = CodeParserCache::new(code_tree_from_file(codes_file_path)?);
+
+    let mut groups: Vec<String> = parser.code_tree().groups().iter().cloned().collect();
+    groups.sort();
+
+    let code_columns = code_columns
+        .values()
+        .map(Strings::try_from)
+        .collect::<Result<Vec<_>>>()?;
+    let num_rows = code_columns.first().map_or(0, |column| column.len());
+    if code_columns.iter().any(|column| column.len() != num_rows) {
+        return Err(Error::Other(String::from(
+            "The code columns must all have the same length",
+        )));
+    }
+
+    let mut flags = vec![vec![false; num_rows]; groups.len()];
+    for column in &code_columns {
+        for (row, code) in column.iter().enumerate() {
+            if code.is_na() {
+                continue;
+            }
+            if let Ok(code_match) = parser.find_exact(code.as_str()) {
+                for (group_flags, group) in flags.iter_mut().zip(&groups) {
+                    group_flags[row] |= code_match.in_group(group);
+                }
+            }
+        }
+    }
+
+    List::from_names_and_values(groups, flags)
+}
+
+/// Get a character column from a named list (e.g. a dataframe), with
+/// None for missing values (NA). Raises an R error if the column is
+/// not present or is not a character vector.
+fn string_column(table: &List, name: &str) -> Result<Vec<Option<String>>> {
+    let column = Strings::try_from(table.dollar(name)?)
+        .map_err(|_| Error::Other(format!("Expected a character column '{name}'")))?;
+    Ok(column
+        .iter()
+        .map(|value| (!value.is_na()).then(|| value.as_str().to_string()))
+        .collect())
+}
+
+/// Get a date column (in seconds since the epoch, as stored in a
+/// POSIXct vector) from a named list, with None for missing values.
+/// Raises an R error if the column is not present or is not numeric.
+fn date_column(table: &List, name: &str) -> Result<Vec<Option<DateTime<Utc>>>> {
+    let column = table
+        .dollar(name)?
+        .as_real_vector()
+        .ok_or_else(|| Error::Other(format!("Expected a numeric (POSIXct) column '{name}'")))?;
+    Ok(column.into_iter().map(date_from_seconds).collect())
+}
+
+/// Convert a time in seconds since the epoch (e.g. from a POSIXct
+/// vector) into a date, or None if the value is missing (NA)
+fn date_from_seconds(seconds: f64) -> Option<DateTime<Utc>> {
+    if seconds.is_nan() {
+        return None;
+    }
+    Utc.timestamp_millis_opt((seconds * 1000.0).round() as i64)
+        .single()
+}
+
+/// Raise an R error if the columns of a table do not all have the
+/// same length as the first column
+fn check_lengths(table_name: &str, lengths: &[usize]) -> Result<()> {
+    match lengths.split_first() {
+        Some((first, rest)) if rest.iter().any(|length| length != first) => Err(Error::Other(
+            format!("The columns of {table_name} must all have the same length"),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Read the episodes passed to rust_patient_summaries. Missing codes
+/// are left out, and a missing end date is taken to be the start date.
+fn episode_records(episodes: &List) -> Result<Vec<EpisodeRecord>> {
+    let patient_id = string_column(episodes, "patient_id")?;
+    let spell_id = string_column(episodes, "spell_id")?;
+    let start = date_column(episodes, "start")?;
+    let end = date_column(episodes, "end")?;
+    let code_columns = |name: &str| -> Result<Vec<Vec<Option<String>>>> {
+        let columns = List::try_from(episodes.dollar(name)?)?;
+        columns
+            .values()
+            .map(|column| {
+                let column = Strings::try_from(column).map_err(|_| {
+                    Error::Other(format!(
+                        "Expected the {name} columns to be character vectors"
+                    ))
+                })?;
+                Ok(column
+                    .iter()
+                    .map(|value| (!value.is_na()).then(|| value.as_str().to_string()))
+                    .collect())
+            })
+            .collect()
+    };
+    let diagnoses = code_columns("diagnoses")?;
+    let procedures = code_columns("procedures")?;
+
+    let mut lengths = vec![patient_id.len(), spell_id.len(), start.len(), end.len()];
+    lengths.extend(diagnoses.iter().chain(&procedures).map(Vec::len));
+    check_lengths("episodes", &lengths)?;
+
+    let codes_in_row = |columns: &Vec<Vec<Option<String>>>, row: usize| -> Vec<String> {
+        columns
+            .iter()
+            .filter_map(|column| column[row].clone())
+            .collect()
+    };
+    (0..patient_id.len())
+        .map(|row| {
+            let missing = |column: &str| {
+                Error::Other(format!("The {column} of episode {} is missing", row + 1))
+            };
+            let start = start[row].ok_or_else(|| missing("start"))?;
+            Ok(EpisodeRecord {
+                patient_id: patient_id[row]
+                    .clone()
+                    .ok_or_else(|| missing("patient_id"))?,
+                spell_id: spell_id[row].clone().ok_or_else(|| missing("spell_id"))?,
+                start,
+                end: end[row].unwrap_or(start),
+                diagnoses: codes_in_row(&diagnoses, row),
+                procedures: codes_in_row(&procedures, row),
+            })
+        })
+        .collect()
+}
+
+/// Read the blood test results passed to rust_patient_summaries,
+/// keeping the rows that can be converted into measurements (see
+/// measurement_from_pathology_blood) and that have a sample date
+fn lab_results(labs: &List) -> Result<Vec<LabResult>> {
+    let subject = string_column(labs, "subject")?;
+    let order_name = string_column(labs, "order_name")?;
+    let test_name = string_column(labs, "test_name")?;
+    let test_result = string_column(labs, "test_result")?;
+    let test_result_unit = string_column(labs, "test_result_unit")?;
+    let sample_collected = date_column(labs, "sample_collected_date_time")?;
+    let result_available = date_column(labs, "result_available_date_time")?;
+    check_lengths(
+        "labs",
+        &[
+            subject.len(),
+            order_name.len(),
+            test_name.len(),
+            test_result.len(),
+            test_result_unit.len(),
+            sample_collected.len(),
+            result_available.len(),
+        ],
+    )?;
+
+    let mut lab_results = Vec::new();
+    for row in 0..subject.len() {
+        let (
+            Some(subject),
+            Some(order_name),
+            Some(test_name),
+            Some(test_result),
+            Some(test_result_unit),
+            Some(sample_collected),
+        ) = (
+            &subject[row],
+            &order_name[row],
+            &test_name[row],
+            &test_result[row],
+            &test_result_unit[row],
+            sample_collected[row],
+        )
+        else {
+            continue;
+        };
+        let result_available = result_available[row];
+        let measurement = measurement_from_pathology_blood(
+            order_name.clone(),
+            test_name.clone(),
+            sample_collected.to_rfc3339(),
+            result_available
+                .map(|date| date.to_rfc3339())
+                .unwrap_or_default(),
+            test_result.clone(),
+            test_result_unit.clone(),
+        );
+        if let Ok(mut measurement) = measurement {
+            measurement.measurement_date = Some(sample_collected);
+            measurement.measurement_available = result_available;
+            lab_results.push(LabResult {
+                patient_id: subject.clone(),
+                test_name: test_name.clone(),
+                measurement,
+            });
+        }
+    }
+    Ok(lab_results)
+}
+
+/// Convert the patient summaries into a named list of columns (see
+/// rust_patient_summaries)
+fn summaries_to_list(summaries: &[PatientSummary], groups: &[String]) -> Result<List> {
+    let mut names = vec![
+        String::from("patient_id"),
+        String::from("n_spells"),
+        String::from("n_spells_in_lookback"),
+    ];
+    let mut values = vec![
+        Robj::from(
+            summaries
+                .iter()
+                .map(|summary| summary.patient_id.clone())
+                .collect::<Vec<_>>(),
+        ),
+        Robj::from(
+            summaries
+                .iter()
+                .map(|summary| summary.n_spells as i32)
+                .collect::<Vec<_>>(),
+        ),
+        Robj::from(
+            summaries
+                .iter()
+                .map(|summary| summary.n_spells_in_lookback as i32)
+                .collect::<Vec<_>>(),
+        ),
+    ];
+    for group in groups {
+        names.push(group.clone());
+        let counts: Vec<i32> = summaries
+            .iter()
+            .map(|summary| summary.group_counts[group] as i32)
+            .collect();
+        values.push(Robj::from(counts));
+    }
+    let test_names: BTreeSet<&String> = summaries
+        .iter()
+        .flat_map(|summary| summary.latest_measurements.keys())
+        .collect();
+    for test_name in test_names {
+        names.push(format!("latest_{}", test_name.to_lowercase()));
+        let latest: Vec<Option<f64>> = summaries
+            .iter()
+            .map(|summary| summary.latest_measurements.get(test_name).copied())
+            .collect();
+        values.push(Robj::from(latest));
+    }
+    List::from_names_and_values(names, values)
+}
+
+/// Make patients from a table of episodes (e.g. HES), and summarise
+/// each one over a lookback window before an index date, using the
+/// codes files embedded in the package.
+///
+/// episodes is a named list with the columns patient_id and spell_id
+/// (character), start and end (POSIXct, in seconds), and diagnoses and
+/// procedures, which are each a list of the character columns holding
+/// the codes (primary code first). labs is a pathology_blood table
+/// (see rust_make_pathology_blood), with the subject column holding
+/// the patient id. index_date is in seconds (POSIXct), and the window
+/// covers the lookback_days days before (but not including) it.
+///
+/// The result is a named list (intended as a dataframe) with one row
+/// per patient, sorted by patient_id, and the columns:
+/// * patient_id: the patient
+/// * n_spells: the total number of spells
+/// * n_spells_in_lookback: the number of spells that started in the
+///   lookback window
+/// * one integer column per code group (named after the group) with
+///   the number of spells in the window containing a code in the group
+/// * latest_<test>: the most recent result for each blood test (e.g.
+///   latest_platelets) in the window, or NA if there were none
+///
+/// Codes that are not valid, and blood test results that cannot be
+/// converted into measurements, are left out. Raises an R error if a
+/// column is missing or has the wrong type.
+///
+/// @export
+#[extendr]
+fn rust_patient_summaries(
+    episodes: List,
+    labs: List,
+    index_date: f64,
+    lookback_days: f64,
+) -> Result<List> {
+    let episode_records = episode_records(&episodes)?;
+    let lab_results = lab_results(&labs)?;
+    let index_date = date_from_seconds(index_date)
+        .ok_or_else(|| Error::Other(String::from("The index_date must not be missing")))?;
+    let lookback = Duration::milliseconds((lookback_days * 86_400_000.0).round() as i64);
+
+    with_default_parser("diagnosis", |diagnosis_parser| {
+        with_default_parser("procedure", |procedure_parser| {
+            let patient_store =
+                PatientStore::from_episodes(&episode_records, diagnosis_parser, procedure_parser);
+            let mut groups: Vec<String> = diagnosis_parser
+                .code_tree()
+                .groups()
+                .iter()
+                .chain(procedure_parser.code_tree().groups())
+                .cloned()
+                .collect();
+            groups.sort();
+            groups.dedup();
+            let summaries = summarise_patients(
+                &patient_store,
+                &lab_results,
+                index_date,
+                lookback,
+                &groups,
+                diagnosis_parser.code_store(),
+                procedure_parser.code_store(),
+            );
+            summaries_to_list(&summaries, &groups)
+        })
+    })
+}
+
+/// Convert a column of a (synthetic) dataframe to an R vector. String
+/// columns become character vectors, numerical columns become numeric
+/// vectors, and timestamps become POSIXct (in UTC). Null values are NA.
+fn series_to_robj(series: &Series) -> Result<Robj> {
+    let polars_err = |err: PolarsError| Error::Other(err.to_string());
+    let robj = match series.dtype() {
+        DataType::Utf8 => {
+            let values: Vec<Option<String>> = series
+                .utf8()
+                .map_err(polars_err)?
+                .into_iter()
+                .map(|value| value.map(String::from))
+                .collect();
+            Robj::from(values)
+        }
+        DataType::Boolean => {
+            let values: Vec<Option<bool>> =
+                series.bool().map_err(polars_err)?.into_iter().collect();
+            Robj::from(values)
+        }
+        DataType::Datetime(time_unit, _) => {
+            let per_second = match time_unit {
+                TimeUnit::Nanoseconds => 1e9,
+                TimeUnit::Microseconds => 1e6,
+                TimeUnit::Milliseconds => 1e3,
+            };
+            let seconds: Vec<Option<f64>> = series
+                .cast(&DataType::Int64)
+                .map_err(polars_err)?
+                .i64()
+                .map_err(polars_err)?
+                .into_iter()
+                .map(|value| value.map(|value| value as f64 / per_second))
+                .collect();
+            let mut robj = Robj::from(seconds);
+            robj.set_class(["POSIXct", "POSIXt"])?;
+            robj.set_attrib("tzone", "UTC")?;
+            robj
+        }
+        dtype if dtype.is_numeric() => {
+            let values: Vec<Option<f64>> = series
+                .cast(&DataType::Float64)
+                .map_err(polars_err)?
+                .f64()
+                .map_err(polars_err)?
+                .into_iter()
+                .collect();
+            Robj::from(values)
+        }
+        dtype => {
+            return Err(Error::Other(format!(
+                "Cannot convert column {} of type {dtype} to R",
+                series.name()
+            )))
+        }
+    };
+    Ok(robj)
+}
+
+/// Convert a (synthetic) dataframe to a named list of columns (see
+/// series_to_robj), which can be converted to a dataframe in R
+fn dataframe_to_list(df: &DataFrame) -> Result<List> {
+    let names: Vec<&str> = df.get_column_names();
+    let values = df
+        .get_columns()
+        .iter()
+        .map(series_to_robj)
+        .collect::<Result<Vec<_>>>()?;
+    List::from_names_and_values(names, values)
+}
+
+/// Make the synthetic pathology_blood table (blood test results),
+/// with num_rows rows generated from the global seed.
+///
+/// The table is the same as the one generated by py_hbr (and the
+/// synthetic dataset files) for the same seed, the default number of
+/// patients and the default (first) version of the generators, so R
+/// and Python code can be tested against identical data. The result is a named list
+/// (intended as a dataframe) of the columns of the table. Timestamp
+/// columns are POSIXct (in UTC).
+///
+/// @export
+#[extendr]
+fn rust_make_pathology_blood(seed: u64, num_rows: usize) -> Result<List> {
+    let registry = PatientRegistry::new(seed, DEFAULT_NUM_PATIENTS);
+    let table = TableSpec::new(SyntheticTable::PathologyBlood, num_rows)
+        .make(&registry, GeneratorVersion::default());
+    dataframe_to_list(&table)
 }
 
 // Macro to generate exports.
@@ -72,4 +828,13 @@ extendr_module! {
     mod rhbr;
     fn rust_get_codes_in_group;
     fn rust_get_groups_in_codes_file;
+    fn rust_get_all_groups;
+    fn rust_codes_file_summary;
+    fn rust_find_exact;
+    fn rust_groups_for_code;
+    fn rust_sample_codes;
+    fn rust_check_codes;
+    fn rust_annotate_groups;
+    fn rust_patient_summaries;
+    fn rust_make_pathology_blood;
 }
@@ -9,7 +9,7 @@ fn main() {
         std::fs::File::open("..\\codes_editor\\icd10_example.yaml").expect("Failed to open file");
 
     // Should execute without panic
-    let code_tree = ClinicalCodeTree::from_reader(f);
+    let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
 
     let mut code_store = ClinicalCodeStore::new();
 
@@ -23,7 +23,9 @@ fn main() {
 
     // Get a few random clinical codes from the code tree
     let mut rng = make_rng(321, "code_gen_id");
-    let code1 = code_tree.random_clinical_code(&mut rng, &mut code_store);
+    let code1 = code_tree
+        .random_clinical_code(&mut rng, &mut code_store)
+        .expect("Should be able to pick a valid code");
     println!("An arbitrary random code:");
     printcode!(code1, code_store);
 
@@ -39,7 +41,8 @@ fn main() {
     // Parse a code
     if let Ok(matched_code) = code_tree.find_exact(format!("A00.0"), &mut code_store) {
         print!("Matched code: ");
-        printcode!(matched_code, code_store);
+        printcode!(matched_code.code(), code_store);
+        println!("In groups: {:?}", matched_code.groups());
     } else {
         println!("Failed to match code");
     }
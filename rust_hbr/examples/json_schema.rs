@@ -0,0 +1,19 @@
+//! Print the JSON schemas for the main data structures. Pass the
+//! name of a schema (e.g. patient) to print only that one.
+//!
+
+use rust_hbr::schema::{json_schema_string, SCHEMA_NAMES};
+
+fn main() {
+    let names: Vec<String> = match std::env::args().nth(1) {
+        Some(name) => vec![name],
+        None => SCHEMA_NAMES.iter().map(|name| name.to_string()).collect(),
+    };
+
+    for name in names {
+        match json_schema_string(&name) {
+            Some(schema) => println!("{schema}"),
+            None => println!("No schema called '{name}'"),
+        }
+    }
+}
@@ -1,25 +1,28 @@
 use datafusion::prelude::*;
-use rust_hbr::{load_record_batch, save_record_batch, make_pathology_blood};
-use polars::prelude::*;
+use rust_hbr::synth_dataset::{SyntheticTable, TableSpec};
+use rust_hbr::{load_record_batch, save_record_batch, GeneratorVersion, PatientRegistry, DEFAULT_NUM_PATIENTS};
 
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
 
-    let df = make_pathology_blood("pathology_blood", 0, 100);
+    let registry = PatientRegistry::new(0, DEFAULT_NUM_PATIENTS);
+    let table_spec = TableSpec::new(SyntheticTable::PathologyBlood, 100);
+    let df = table_spec.make(&registry, GeneratorVersion::LATEST);
     println!{"{df}"};
 
+    // The same table as an Arrow record batch
+    let batch = table_spec.make_record_batch(&registry, GeneratorVersion::LATEST);
+    save_record_batch("example.parquet", batch);
 
-    //save_record_batch("example.parquet", batch);
+    let batch = load_record_batch("example.parquet");
 
-    //let batch = load_record_batch("example.parquet");
-
-   /*  let ctx = SessionContext::new();
+    let ctx = SessionContext::new();
     let df = ctx
         .read_batch(batch)
         .expect("Failed to convert batch to dataframe");
 
-    df.show().await?; */
+    df.show().await?;
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -4,7 +4,10 @@ use std::collections::HashMap;
 
 use polars::prelude::*;
 use rust_hbr::{
-    make_pathology_blood, patient::Patient, preprocess::measurement_from_pathology_blood,
+    make_pathology_blood, make_pathology_blood_lazy,
+    patient::Patient,
+    EventTiming, GeneratorVersion, PatientRegistry, DEFAULT_NUM_PATIENTS, DEFAULT_PROCESSING_TIME,
+    preprocess::{measurement_from_pathology_blood, pathology_blood_measurements_lazy},
 };
 
 /// Get a string column from a polars dataframe. Panics if
@@ -33,7 +36,9 @@ fn main() {
     //let mut patients = HashMap::new();
 
     // Make synthetic blood test results
-    let df = make_pathology_blood("pathology_blood", 0, 100);
+    let registry = PatientRegistry::new(0, DEFAULT_NUM_PATIENTS);
+    let timing = EventTiming::with_duration(DEFAULT_PROCESSING_TIME);
+    let df = make_pathology_blood("pathology_blood", &registry, GeneratorVersion::LATEST, timing, 100);
 
     // Get the columns of interest
     // let subject = get_utf8_column(&df, "subject").into_iter();
@@ -60,19 +65,14 @@ fn main() {
     //     );
     // }
 
-    // let df_reduced = df
-    // .lazy()
-    //     .select([
-    //         "subject",
-    //         "order_name",
-    //         "test_name",
-    //         "test_result",
-    //         "test_result_unit",
-    //         "sample_collected_date_time",
-    //         "result_available_date_time",
-    //     ])
-    //     .collect()
-    //     .unwrap();
+    // Select the columns needed for measurements lazily, so that the
+    // selection is fused with generating the synthetic table
+    let pathology_blood =
+        make_pathology_blood_lazy("pathology_blood", &registry, GeneratorVersion::LATEST, timing, 100)
+            .unwrap();
+    let df_reduced = pathology_blood_measurements_lazy(pathology_blood)
+        .collect()
+        .unwrap();
 
-    // println!("{}", df_reduced);
+    println!("{}", df_reduced);
 }
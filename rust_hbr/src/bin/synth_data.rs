@@ -0,0 +1,275 @@
+//! Command line program for generating synthetic datasets
+//!
+//! List the tables that can be generated:
+//!
+//! ```text
+//! synth-data list
+//! ```
+//!
+//! Print the columns of a table:
+//!
+//! ```text
+//! synth-data schema pathology_blood
+//! ```
+//!
+//! Generate one or more tables as parquet files in a directory (the
+//! number of rows can be written in scientific notation):
+//!
+//! ```text
+//! synth-data generate --table pathology_blood --rows 1e6 --seed 42 --out data/
+//! ```
+//!
+//! Use --format csv or --format ndjson to write CSV or NDJSON files
+//! instead of parquet, and --chunk-size to generate and write very large
+//! tables a chunk of rows at a time (e.g. --chunk-size 1e6). Use
+//! --corruption-rate to inject defects (such as trailing whitespace or
+//! placeholder dates) into the tables for testing ingestion,
+//! --duplicate-rate to add exact and near-duplicate rows for testing
+//! deduplication, and --diagnosis-frequencies with a YAML file mapping
+//! ICD-10 codes to frequencies to give the episodes table realistic
+//! diagnosis prevalences. Use --timezone-aware to write the event times
+//! as timezone-aware (Europe/London) timestamps instead of naive local
+//! times. New datasets use the latest version of the generators, unless
+//! an older one is picked with --generator-version. Or generate the
+//! tables in a dataset spec file (see synth_dataset):
+//!
+//! ```text
+//! synth-data generate --spec dataset.yaml --out data/
+//! ```
+//!
+//! A manifest (manifest.json) is written with the tables. Check that the
+//! files have not changed, and that the current version of the
+//! generators still reproduces them:
+//!
+//! ```text
+//! synth-data verify data/
+//! ```
+//!
+
+use std::fs::File;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand};
+use rust_hbr::corruption::CorruptionSpec;
+use rust_hbr::manifest::verify_dataset;
+use rust_hbr::synth_dataset::{
+    generate_dataset, DatasetSpec, OutputFormat, SyntheticTable, TableSpec,
+};
+use rust_hbr::{CodeFrequencies, GeneratorVersion, DEFAULT_NUM_PATIENTS};
+
+#[derive(Parser)]
+#[command(name = "synth-data", about = "Generate seeded synthetic data tables")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the tables that can be generated
+    List,
+    /// Print the column names and types of a table
+    Schema {
+        /// The name of the table (see list)
+        #[arg(value_parser = parse_table)]
+        table: SyntheticTable,
+    },
+    /// Generate tables and write them to files
+    Generate {
+        /// A dataset spec file (YAML or JSON) listing the tables to
+        /// generate. Cannot be used with --table.
+        #[arg(
+            long,
+            conflicts_with_all = [
+                "table",
+                "rows",
+                "seed",
+                "patients",
+                "format",
+                "chunk_size",
+                "corruption_rate",
+                "duplicate_rate",
+                "diagnosis_frequencies",
+                "timezone_aware",
+                "generator_version"
+            ]
+        )]
+        spec: Option<PathBuf>,
+        /// A table to generate (can be given more than once)
+        #[arg(long, value_parser = parse_table, required_unless_present = "spec")]
+        table: Vec<SyntheticTable>,
+        /// The number of rows in each table (e.g. 1000 or 1e6)
+        #[arg(long, value_parser = parse_num_rows, default_value = "1000")]
+        rows: usize,
+        /// The global seed for all the tables
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// The number of patients shared by the tables
+        #[arg(long, default_value_t = DEFAULT_NUM_PATIENTS)]
+        patients: usize,
+        /// The file format (parquet, csv or ndjson)
+        #[arg(long, value_parser = parse_format, default_value = "parquet")]
+        format: OutputFormat,
+        /// Generate and write each table in chunks of this many rows
+        /// (e.g. 1e6), so that the memory used does not depend on the
+        /// number of rows
+        #[arg(long, value_parser = parse_num_rows)]
+        chunk_size: Option<usize>,
+        /// Inject every kind of defect into the tables (apart from the
+        /// subject column) at this rate, between 0 and 1
+        #[arg(long)]
+        corruption_rate: Option<f64>,
+        /// Follow rows with an exact copy at this rate, and with a copy
+        /// with slightly shifted timestamps at the same rate (between 0
+        /// and 1)
+        #[arg(long)]
+        duplicate_rate: Option<f64>,
+        /// A YAML (or JSON) file mapping ICD-10 codes to their
+        /// frequencies, used to pick the primary diagnoses in the
+        /// episodes table (instead of picking them uniformly)
+        #[arg(long)]
+        diagnosis_frequencies: Option<PathBuf>,
+        /// Write the event times (e.g. when blood samples are collected)
+        /// as timezone-aware timestamps in Europe/London, instead of
+        /// naive local times
+        #[arg(long)]
+        timezone_aware: bool,
+        /// The version of the generators (the latest if not given), so
+        /// that a dataset written by an older version can be reproduced
+        #[arg(long, value_parser = parse_generator_version)]
+        generator_version: Option<GeneratorVersion>,
+        /// The directory to write the files to (created if it does
+        /// not exist)
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Check a generated dataset against its manifest
+    Verify {
+        /// The directory containing the dataset and its manifest
+        dir: PathBuf,
+    },
+}
+
+fn parse_table(name: &str) -> Result<SyntheticTable, String> {
+    SyntheticTable::from_name(name)
+        .ok_or_else(|| format!("no table called '{name}' (use list to see the tables)"))
+}
+
+fn parse_format(name: &str) -> Result<OutputFormat, String> {
+    OutputFormat::from_name(name)
+        .ok_or_else(|| format!("no format called '{name}' (use parquet, csv or ndjson)"))
+}
+
+fn parse_generator_version(number: &str) -> Result<GeneratorVersion, String> {
+    let number: u32 = number
+        .parse()
+        .map_err(|_| format!("'{number}' is not a version number"))?;
+    GeneratorVersion::try_from(number).map_err(|err| err.to_string())
+}
+
+/// Parse a number of rows, allowing scientific notation (e.g. 1e6)
+/// as long as the result is a whole number
+fn parse_num_rows(num_rows: &str) -> Result<usize, String> {
+    if let Ok(num_rows) = num_rows.parse::<usize>() {
+        return Ok(num_rows);
+    }
+    match num_rows.parse::<f64>() {
+        Ok(value) if value >= 0.0 && value.fract() == 0.0 && value <= usize::MAX as f64 => {
+            Ok(value as usize)
+        }
+        _ => Err(format!("'{num_rows}' is not a whole number of rows")),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    match Cli::parse().command {
+        Command::List => {
+            for table in SyntheticTable::ALL {
+                println!("{}", table.name());
+            }
+        }
+        Command::Schema { table } => {
+            for field in table.schema().iter_fields() {
+                println!("{}: {}", field.name(), field.data_type());
+            }
+        }
+        Command::Generate {
+            spec,
+            table,
+            rows,
+            seed,
+            patients,
+            format,
+            chunk_size,
+            corruption_rate,
+            duplicate_rate,
+            diagnosis_frequencies,
+            timezone_aware,
+            generator_version,
+            out,
+        } => {
+            let spec = match spec {
+                Some(path) => {
+                    let file = File::open(&path)
+                        .with_context(|| format!("could not open {}", path.display()))?;
+                    DatasetSpec::from_reader(file)?
+                }
+                None => {
+                    let mut tables: Vec<_> = table
+                        .into_iter()
+                        .map(|table| TableSpec {
+                            // Only the tables with event times have any
+                            // timestamps to convert
+                            timezone_aware: timezone_aware && table.default_timing().is_some(),
+                            ..TableSpec::new(table, rows)
+                        })
+                        .collect();
+                    if let Some(path) = diagnosis_frequencies {
+                        let file = File::open(&path)
+                            .with_context(|| format!("could not open {}", path.display()))?;
+                        let frequencies = CodeFrequencies::from_reader(file)?;
+                        let Some(episodes) = tables
+                            .iter_mut()
+                            .find(|table_spec| table_spec.table == SyntheticTable::Episodes)
+                        else {
+                            bail!("--diagnosis-frequencies can only be used with --table episodes");
+                        };
+                        episodes.diagnosis_frequencies = Some(frequencies);
+                    }
+                    let corruption = match (corruption_rate, duplicate_rate) {
+                        (None, None) => None,
+                        _ => Some(CorruptionSpec {
+                            duplicates: duplicate_rate.unwrap_or(0.0),
+                            near_duplicates: duplicate_rate.unwrap_or(0.0),
+                            ..corruption_rate
+                                .map(CorruptionSpec::uniform)
+                                .unwrap_or_default()
+                        }),
+                    };
+                    let spec = DatasetSpec {
+                        seed,
+                        num_patients: patients,
+                        tables,
+                        format,
+                        chunk_size,
+                        corruption,
+                        generator_version: generator_version.unwrap_or(GeneratorVersion::LATEST),
+                    };
+                    spec.validate()?;
+                    spec
+                }
+            };
+            for path in generate_dataset(&spec, &out)? {
+                println!("{}", path.display());
+            }
+        }
+        Command::Verify { dir } => {
+            let manifest = verify_dataset(&dir)?;
+            for file in &manifest.files {
+                println!("{}: {} rows, ok", file.file_name, file.num_rows);
+            }
+        }
+    }
+    Ok(())
+}
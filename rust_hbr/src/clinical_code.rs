@@ -8,27 +8,93 @@
 //! Two type wrappers DiagnosisCode and ProcedureCode are provided to allow programs
 //! to distinguish ICD-10 and OPCS-4 codes.
 
-use bimap::BiMap;
+use hashbrown::hash_map::RawEntryMut;
+use hashbrown::HashMap;
+use rustc_hash::FxHasher;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
 
 use crate::clinical_code_tree::Categories;
+use crate::error::HbrError;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The first bytes of every saved code store (see ClinicalCodeStore::save)
+const STORE_MAGIC: &[u8; 8] = b"HBRSTORE";
+
+/// The version of the saved code store format. This must be increased
+/// whenever StoredCode changes.
+const STORE_VERSION: u32 = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Debug)]
 pub struct DiagnosisCode(ClinicalCodeRef);
 
-#[derive(Serialize, Deserialize, Debug)]
+impl DiagnosisCode {
+    /// Wrap a reference to an ICD-10 code
+    pub fn from(clinical_code_ref: ClinicalCodeRef) -> Self {
+        Self(clinical_code_ref)
+    }
+
+    /// Get the underlying clinical code reference
+    pub fn clinical_code_ref(&self) -> ClinicalCodeRef {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Debug)]
 pub struct ProcedureCode(ClinicalCodeRef);
 
+impl ProcedureCode {
+    /// Wrap a reference to an OPCS-4 code
+    pub fn from(clinical_code_ref: ClinicalCodeRef) -> Self {
+        Self(clinical_code_ref)
+    }
+
+    /// Get the underlying clinical code reference
+    pub fn clinical_code_ref(&self) -> ClinicalCodeRef {
+        self.0
+    }
+}
+
 /// Stores the data for a clinical code (an ICD-10 or OPCS-4 code), which
 /// comprises the code itself, the description, and the list of groups containing
 /// the code. This struct is not passed around in the program -- it is stored in
 /// a ClinicalCodeStore, and references are passed around instead.
-#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+///
+/// Two codes are the same if they have the same name and description (the
+/// short description and synonyms are extra information about the code, and
+/// are not compared).
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ClinicalCode {
     /// The name of the code, e.g. I22.1
     name: String,
     /// The descriptions of the code
     docs: String,
+    /// A compact description of the code, for displaying
+    /// in places where the full description is too long
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    short_docs: Option<String>,
+    /// Alternative clinical terms for the code
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    synonyms: Vec<String>,
+}
+
+impl PartialEq for ClinicalCode {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.docs == other.docs
+    }
+}
+
+impl Eq for ClinicalCode {}
+
+impl Hash for ClinicalCode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.docs.hash(state);
+    }
 }
 
 impl ClinicalCode {
@@ -38,13 +104,36 @@ impl ClinicalCode {
         Self {
             name,
             docs,
+            short_docs: None,
+            synonyms: Vec::new(),
             //groups: Vec::new(),
         }
     }
 
+    /// Create a new clinical code that also has a short description
+    /// and synonyms
+    pub fn with_synonyms(
+        name: String,
+        docs: String,
+        short_docs: Option<String>,
+        synonyms: Vec<String>,
+    ) -> Self {
+        Self {
+            name,
+            docs,
+            short_docs,
+            synonyms,
+        }
+    }
+
     /// Make a clinical code from a category/code node in a clinical code tree
     pub fn from(category: &Categories) -> Self {
-        let clinical_code = Self::new(category.name().clone(), category.docs().clone());
+        let clinical_code = Self::with_synonyms(
+            category.name().clone(),
+            category.docs().clone(),
+            category.short_docs().cloned(),
+            category.synonyms().clone(),
+        );
         //clinical_code.groups = category.groups.clone();
         clinical_code
     }
@@ -57,27 +146,43 @@ impl ClinicalCode {
         &self.docs
     }
 
+    /// Get the short description of the code if there is one
+    pub fn short_docs(&self) -> Option<&String> {
+        self.short_docs.as_ref()
+    }
+
+    /// Get the short description of the code, or the full
+    /// description if there is no short description
+    pub fn short_docs_or_docs(&self) -> &String {
+        self.short_docs.as_ref().unwrap_or(&self.docs)
+    }
+
+    /// Get the alternative clinical terms for the code
+    pub fn synonyms(&self) -> &Vec<String> {
+        &self.synonyms
+    }
+
 }
 
 /// An opaque reference to a clinical code, which can be used to obtain information
 /// about the code from a ClinicalCodeStore. 
 /// 
-/// Using a type instead of a raw u64 to make it clear what it is for. The struct is
+/// Using a type instead of a raw u32 to make it clear what it is for. The struct is
 /// supposed to be cheap to copy -- avoid adding to it.
-#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Debug)]
 pub struct ClinicalCodeRef {
-    id: u64,
+    id: u32,
 }
 
 impl ClinicalCodeRef {
     /// Create a new reference to a clinical code from an id. The purpose of these
     /// functions is to avoid letting the user modify the id after creation.
-    pub fn from(id: u64) -> Self {
+    pub fn from(id: u32) -> Self {
         Self { id }
     }
 
     /// Get the raw id
-    pub fn id(&self) -> u64 {
+    pub fn id(&self) -> u32 {
         self.id
     }
 }
@@ -110,6 +215,135 @@ macro_rules! name {
     }
 }
 
+/// The short description and synonyms of a code in the
+/// ClinicalCodeStore. Most codes have neither, so these are
+/// only stored for the codes that do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CodeExtras {
+    short_docs: Option<String>,
+    synonyms: Vec<String>,
+}
+
+/// Returned by ClinicalCodeView::synonyms for codes with no synonyms
+static NO_SYNONYMS: Vec<String> = Vec::new();
+
+/// A clinical code in a ClinicalCodeStore, returned by
+/// ClinicalCodeStore::clinical_code_from. It has the same accessors
+/// as ClinicalCode, but borrows the strings from the store instead of
+/// owning them (use to_clinical_code to get an owned copy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClinicalCodeView<'a> {
+    name: &'a String,
+    docs: &'a String,
+    extras: Option<&'a CodeExtras>,
+}
+
+impl<'a> ClinicalCodeView<'a> {
+    pub fn name(&self) -> &'a String {
+        self.name
+    }
+
+    pub fn docs(&self) -> &'a String {
+        self.docs
+    }
+
+    /// Get the short description of the code if there is one
+    pub fn short_docs(&self) -> Option<&'a String> {
+        self.extras?.short_docs.as_ref()
+    }
+
+    /// Get the short description of the code, or the full
+    /// description if there is no short description
+    pub fn short_docs_or_docs(&self) -> &'a String {
+        self.short_docs().unwrap_or(self.docs)
+    }
+
+    /// Get the alternative clinical terms for the code
+    pub fn synonyms(&self) -> &'a Vec<String> {
+        self.extras
+            .map(|extras| &extras.synonyms)
+            .unwrap_or(&NO_SYNONYMS)
+    }
+
+    /// Copy the code out of the store
+    pub fn to_clinical_code(&self) -> ClinicalCode {
+        ClinicalCode::with_synonyms(
+            self.name.clone(),
+            self.docs.clone(),
+            self.short_docs().cloned(),
+            self.synonyms().clone(),
+        )
+    }
+}
+
+/// Hash a string in the same way for insertion into and lookup
+/// from a StringTable
+fn hash_str(string: &str) -> u64 {
+    let mut hasher = FxHasher::default();
+    string.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A table of distinct strings, each of which has a u32 id (its
+/// position in the table). Each string is only stored once: the map
+/// used to find the id of a string holds only the ids, and compares
+/// strings by looking them up in the table.
+#[derive(Debug, Default)]
+struct StringTable {
+    strings: Vec<String>,
+    ids: HashMap<u32, (), BuildHasherDefault<FxHasher>>,
+}
+
+impl StringTable {
+    /// Get the id of a string, if it is in the table
+    fn get(&self, string: &str) -> Option<u32> {
+        let strings = &self.strings;
+        self.ids
+            .raw_entry()
+            .from_hash(hash_str(string), |id| strings[*id as usize] == string)
+            .map(|(id, _)| *id)
+    }
+
+    /// Get the id of a string, adding it to the table if
+    /// it is not already there
+    fn intern(&mut self, string: &str) -> u32 {
+        let Self { strings, ids } = self;
+        let hash = hash_str(string);
+        match ids
+            .raw_entry_mut()
+            .from_hash(hash, |id| strings[*id as usize] == string)
+        {
+            RawEntryMut::Occupied(entry) => *entry.key(),
+            RawEntryMut::Vacant(entry) => {
+                let id = next_id(strings.len());
+                strings.push(string.to_string());
+                entry.insert_with_hasher(hash, id, (), |id| hash_str(&strings[*id as usize]));
+                id
+            }
+        }
+    }
+
+    /// Get the string with this id
+    fn string(&self, id: u32) -> &String {
+        &self.strings[id as usize]
+    }
+}
+
+/// Convert the length of a table into the id of the next element
+fn next_id(len: usize) -> u32 {
+    u32::try_from(len).expect("more than u32::MAX entries in clinical code store")
+}
+
+/// A clinical code in the form written to a saved code store
+#[derive(Serialize, Deserialize)]
+struct StoredCode {
+    name: String,
+    docs: String,
+    short_docs: Option<String>,
+    synonyms: Vec<String>,
+    groups: Option<HashSet<String>>,
+}
+
 /// Stores the data for all the clinical codes that have been seen by the
 /// program.
 /// 
@@ -123,23 +357,56 @@ macro_rules! name {
 /// diagnosis and procedure code with the same name (some ICD-10 and OPCS-4
 /// codes overlap) will get different IDs, and the same store can be used for
 /// both diagnosis and procedure codes.
+///
+/// The store may hold tens of millions of codes (e.g. when parsing all of
+/// HES), so the names and descriptions are interned in separate tables of
+/// distinct strings, and each code is stored as the pair of u32 ids of its
+/// name and description.
 /// 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ClinicalCodeStore {
-    /// The purpose of the bidirectional map is to try to make both inserting
-    /// codes and retrieving code data fast. Code insertion is the bottleneck
-    /// when codes are being parsed (although a cache layer mapping unparsed codes
-    /// to ids in this store can help), and then code retrieval is the bottleneck
-    /// when data is being obtained from the patient struct for the purpose of
-    /// creating a dataframe for R or Python.
-    ids_to_codes: BiMap<u64, ClinicalCode>,
+    /// The distinct code names
+    names: StringTable,
+    /// The distinct code descriptions
+    docs: StringTable,
+    /// The (name, docs) ids of the codes, indexed by code id. Retrieving
+    /// code data by id is the bottleneck when data is being obtained from
+    /// the patient struct for the purpose of creating a dataframe for R or
+    /// Python, so this is a plain vector lookup.
+    codes: Vec<(u32, u32)>,
+    /// Map from the (name, docs) ids of a code back to the code id. Code
+    /// insertion is the bottleneck when codes are being parsed (although
+    /// a cache layer mapping unparsed codes to ids in this store can help),
+    /// so the map uses a fast non-cryptographic hasher.
+    ids: HashMap<(u32, u32), u32, BuildHasherDefault<FxHasher>>,
+    /// The short descriptions and synonyms, for the codes that have them
+    extras: HashMap<u32, CodeExtras, BuildHasherDefault<FxHasher>>,
+    /// The groups containing each code, indexed by id. These are
+    /// recorded when a code is parsed (e.g. by find_exact), and are
+    /// None for codes that have not been parsed.
+    groups: Vec<Option<HashSet<String>>>,
 }
 
 impl ClinicalCodeStore {
     /// Create an empty ClinicalCodeStore
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty ClinicalCodeStore with space reserved for
+    /// (at least) capacity codes. Use this if you know roughly how
+    /// many distinct codes will be inserted (e.g. the number of codes
+    /// in a code tree), to avoid re-allocating while parsing.
+    pub fn with_capacity(capacity: usize) -> Self {
         Self {
-            ids_to_codes: BiMap::new(),
+            names: StringTable {
+                strings: Vec::with_capacity(capacity),
+                ids: HashMap::with_capacity_and_hasher(capacity, Default::default()),
+            },
+            codes: Vec::with_capacity(capacity),
+            ids: HashMap::with_capacity_and_hasher(capacity, Default::default()),
+            groups: Vec::with_capacity(capacity),
+            ..Default::default()
         }
     }
 
@@ -148,34 +415,198 @@ impl ClinicalCodeStore {
     /// refer to it. If the clinical code is already in the
     /// store, return its id without re-inserting.
     pub fn clinical_code_ref_from(&mut self, clinical_code: ClinicalCode) -> ClinicalCodeRef {
-        match self.ids_to_codes.get_by_right(&clinical_code) {
+        match self.find(&clinical_code.name, &clinical_code.docs) {
             // Code is already there, return id
-            Some(id) => ClinicalCodeRef::from(*id),
-            None => {
-                // Requires that elements are never removed, which is true
-                let next_id = self
-                    .num_stored_codes()
-                    .try_into()
-                    .expect("Unexpected failure to convert length of map into 64bit id");
-                self.ids_to_codes.insert(next_id, clinical_code);
-                ClinicalCodeRef::from(next_id)
-            }
+            Some(clinical_code_ref) => clinical_code_ref,
+            None => self.insert_new(
+                &clinical_code.name,
+                &clinical_code.docs,
+                clinical_code.short_docs,
+                clinical_code.synonyms,
+            ),
+        }
+    }
+
+    /// Same as clinical_code_ref_from, but takes the name and docs of
+    /// the code by reference. Strings are only copied if the code is
+    /// not already in the store, so this is the cheapest way to obtain
+    /// a reference when most codes have been seen before.
+    pub fn clinical_code_ref_from_parts(&mut self, name: &str, docs: &str) -> ClinicalCodeRef {
+        match self.find(name, docs) {
+            Some(clinical_code_ref) => clinical_code_ref,
+            None => self.insert_new(name, docs, None, Vec::new()),
+        }
+    }
+
+    /// Same as clinical_code_ref_from_parts, but for a code or category
+    /// in a code tree. If the code is not already in the store, its
+    /// short description and synonyms are stored along with it.
+    pub fn clinical_code_ref_from_category(&mut self, category: &Categories) -> ClinicalCodeRef {
+        match self.find(category.name(), category.docs()) {
+            Some(clinical_code_ref) => clinical_code_ref,
+            None => self.insert_new(
+                category.name(),
+                category.docs(),
+                category.short_docs().cloned(),
+                category.synonyms().clone(),
+            ),
         }
     }
 
+    /// Find the reference to a code, if it is in the store
+    fn find(&self, name: &str, docs: &str) -> Option<ClinicalCodeRef> {
+        let key = (self.names.get(name)?, self.docs.get(docs)?);
+        self.ids.get(&key).map(|id| ClinicalCodeRef::from(*id))
+    }
+
+    /// Insert a code that is known not to be in the store
+    fn insert_new(
+        &mut self,
+        name: &str,
+        docs: &str,
+        short_docs: Option<String>,
+        synonyms: Vec<String>,
+    ) -> ClinicalCodeRef {
+        // Requires that elements are never removed, which is true.
+        let next_id = next_id(self.num_stored_codes());
+        let key = (self.names.intern(name), self.docs.intern(docs));
+        self.codes.push(key);
+        self.ids.insert(key, next_id);
+        self.groups.push(None);
+        if short_docs.is_some() || !synonyms.is_empty() {
+            let extras = CodeExtras {
+                short_docs,
+                synonyms,
+            };
+            self.extras.insert(next_id, extras);
+        }
+        ClinicalCodeRef::from(next_id)
+    }
+
     /// Get the clinincal code corresponding to a code
     /// reference. Returns None if the reference does not correspond
-    /// to any clinical code. The result is a reference, so it is
-    /// up to you to clone it if you want to modify it.
-    pub fn clinical_code_from(&self, clinical_code_ref: &ClinicalCodeRef) -> Option<&ClinicalCode> {
-        self.ids_to_codes.get_by_left(&clinical_code_ref.id())
+    /// to any clinical code. The result borrows from the store, so
+    /// use ClinicalCodeView::to_clinical_code if you want to modify it.
+    pub fn clinical_code_from(
+        &self,
+        clinical_code_ref: &ClinicalCodeRef,
+    ) -> Option<ClinicalCodeView<'_>> {
+        let id = clinical_code_ref.id();
+        let (name, docs) = self.codes.get(id as usize)?;
+        Some(ClinicalCodeView {
+            name: self.names.string(*name),
+            docs: self.docs.string(*docs),
+            extras: self.extras.get(&id),
+        })
+    }
+
+    /// Record the groups that contain a code. This is called when a
+    /// code is parsed (the code tree finds the groups in the same
+    /// traversal as the code), so groups_of can return them later
+    /// without searching the tree again. The groups are only recorded
+    /// the first time, because they are the same every time the same
+    /// code is parsed.
+    pub(crate) fn record_groups(
+        &mut self,
+        clinical_code_ref: &ClinicalCodeRef,
+        groups: &HashSet<String>,
+    ) {
+        if let Some(entry) = self.groups.get_mut(clinical_code_ref.id() as usize) {
+            entry.get_or_insert_with(|| groups.clone());
+        }
+    }
+
+    /// Get the groups that contain a code, as found when the code was
+    /// parsed (e.g. by ClinicalCodeTree::find_exact). Returns None if
+    /// the reference does not correspond to any code, or if the code
+    /// was put in the store without being parsed (e.g. by
+    /// ClinicalCodeTree::codes_in_group).
+    pub fn groups_of(&self, clinical_code_ref: &ClinicalCodeRef) -> Option<&HashSet<String>> {
+        self.groups.get(clinical_code_ref.id() as usize)?.as_ref()
     }
 
     /// Get the total number of codes stored in the map. This is also the
     /// value of the next id, because codes are not removed once they have
     /// been added.
     pub fn num_stored_codes(&self) -> usize {
-        self.ids_to_codes.len()
+        self.codes.len()
+    }
+
+    /// Write the store to a file, which can be read back using load
+    ///
+    /// Use this to keep the store alongside data containing code
+    /// references (for example a serialised collection of patients),
+    /// so that the references can be converted back to codes later.
+    /// The ids of all the codes, and the groups recorded for them (see
+    /// groups_of), are preserved. Returns an error if the file cannot
+    /// be written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), HbrError> {
+        let stored: Vec<StoredCode> = (0..next_id(self.num_stored_codes()))
+            .map(|id| {
+                let clinical_code_ref = ClinicalCodeRef::from(id);
+                let code = self
+                    .clinical_code_from(&clinical_code_ref)
+                    .expect("Every id below the number of codes is valid");
+                StoredCode {
+                    name: code.name().clone(),
+                    docs: code.docs().clone(),
+                    short_docs: code.short_docs().cloned(),
+                    synonyms: code.synonyms().clone(),
+                    groups: self.groups_of(&clinical_code_ref).cloned(),
+                }
+            })
+            .collect();
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(STORE_MAGIC)?;
+        writer.write_all(&STORE_VERSION.to_le_bytes())?;
+        bincode::serialize_into(&mut writer, &stored)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read a store from a file written by save. Every code has the
+    /// same id (ClinicalCodeRef) that it had in the saved store.
+    ///
+    /// Returns HbrError::InvalidCodeStoreFile if the file is not a
+    /// saved code store, or was written using a different version
+    /// of the format.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, HbrError> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 8];
+        let mut version = [0; 4];
+        reader
+            .read_exact(&mut magic)
+            .and_then(|_| reader.read_exact(&mut version))
+            .map_err(|_| HbrError::InvalidCodeStoreFile(String::from("file is too short")))?;
+        if &magic != STORE_MAGIC {
+            return Err(HbrError::InvalidCodeStoreFile(String::from(
+                "not a code store file",
+            )));
+        }
+        let version = u32::from_le_bytes(version);
+        if version != STORE_VERSION {
+            return Err(HbrError::InvalidCodeStoreFile(format!(
+                "code store format version {version} (expected {STORE_VERSION})"
+            )));
+        }
+
+        let stored: Vec<StoredCode> = bincode::deserialize_from(reader)?;
+        let mut store = Self::with_capacity(stored.len());
+        for code in stored {
+            if store.find(&code.name, &code.docs).is_some() {
+                return Err(HbrError::InvalidCodeStoreFile(format!(
+                    "code {} appears more than once",
+                    code.name
+                )));
+            }
+            let clinical_code_ref =
+                store.insert_new(&code.name, &code.docs, code.short_docs, code.synonyms);
+            if let Some(groups) = code.groups {
+                store.record_groups(&clinical_code_ref, &groups);
+            }
+        }
+        Ok(store)
     }
 }
 
@@ -306,6 +737,23 @@ mod tests {
         assert_eq!(code_read.docs(), "What the code means...");
     }
 
+    #[test]
+    fn test_insertion_by_parts_matches_owned_insertion() {
+        let mut clinical_code_store = ClinicalCodeStore::with_capacity(10);
+        let code = ClinicalCode::new(String::from("I21.0"), String::from("What the code means..."));
+
+        let code_ref_1 = clinical_code_store.clinical_code_ref_from(code);
+        let code_ref_2 =
+            clinical_code_store.clinical_code_ref_from_parts("I21.0", "What the code means...");
+        assert_eq!(code_ref_1, code_ref_2);
+        assert_eq!(clinical_code_store.num_stored_codes(), 1);
+
+        // Different docs gives a different code
+        let code_ref_3 = clinical_code_store.clinical_code_ref_from_parts("I21.0", "Other docs");
+        assert_ne!(code_ref_1, code_ref_3);
+        assert_eq!(clinical_code_store.num_stored_codes(), 2);
+    }
+
     #[test]
     fn test_request_for_nonexistent_code_is_none() {
         let mut clinical_code_store = ClinicalCodeStore::new();
@@ -317,4 +765,71 @@ mod tests {
         assert_eq!(code_read, None);
     }
 
+    #[test]
+    fn test_names_and_docs_are_interned() {
+        let mut clinical_code_store = ClinicalCodeStore::new();
+        let code_ref_1 = clinical_code_store.clinical_code_ref_from_parts("I21.0", "Docs 1");
+        let code_ref_2 = clinical_code_store.clinical_code_ref_from_parts("I21.0", "Docs 2");
+        let code_ref_3 = clinical_code_store.clinical_code_ref_from_parts("K75.1", "Docs 1");
+        assert_eq!(clinical_code_store.num_stored_codes(), 3);
+
+        // Each distinct string is only stored once
+        assert_eq!(clinical_code_store.names.strings.len(), 2);
+        assert_eq!(clinical_code_store.docs.strings.len(), 2);
+
+        let code_read = clinical_code_store.clinical_code_from(&code_ref_2).unwrap();
+        assert_eq!(code_read.name(), "I21.0");
+        assert_eq!(code_read.docs(), "Docs 2");
+        let code_read = clinical_code_store.clinical_code_from(&code_ref_3).unwrap();
+        assert_eq!(code_read.name(), "K75.1");
+        assert_eq!(code_read.docs(), "Docs 1");
+        assert!(code_read.synonyms().is_empty());
+
+        let code = clinical_code_store
+            .clinical_code_from(&code_ref_1)
+            .unwrap()
+            .to_clinical_code();
+        assert_eq!(clinical_code_store.clinical_code_ref_from(code), code_ref_1);
+        assert_eq!(clinical_code_store.num_stored_codes(), 3);
+    }
+
+    #[test]
+    fn test_save_and_load_code_store() {
+        let mut clinical_code_store = ClinicalCodeStore::new();
+        let code_ref_1 = clinical_code_store.clinical_code_ref_from_parts("I21.0", "Docs 1");
+        let code_ref_2 = clinical_code_store.clinical_code_ref_from(ClinicalCode::with_synonyms(
+            String::from("K75.1"),
+            String::from("Docs 2"),
+            Some(String::from("Short docs")),
+            vec![String::from("Synonym")],
+        ));
+        let groups = HashSet::from([String::from("acs")]);
+        clinical_code_store.record_groups(&code_ref_1, &groups);
+
+        let path = std::env::temp_dir().join("rust_hbr_test_save_and_load_code_store.bin");
+        clinical_code_store.save(&path).expect("Failed to save store");
+        let loaded = ClinicalCodeStore::load(&path);
+        std::fs::remove_file(&path).expect("Failed to remove file");
+        let loaded = loaded.expect("Failed to load store");
+
+        assert_eq!(loaded.num_stored_codes(), 2);
+        let code_read = loaded.clinical_code_from(&code_ref_1).unwrap();
+        assert_eq!(code_read.name(), "I21.0");
+        assert_eq!(loaded.groups_of(&code_ref_1), Some(&groups));
+        assert_eq!(loaded.groups_of(&code_ref_2), None);
+        let code_read = loaded.clinical_code_from(&code_ref_2).unwrap();
+        assert_eq!(code_read.name(), "K75.1");
+        assert_eq!(code_read.short_docs().unwrap(), "Short docs");
+        assert_eq!(code_read.synonyms(), &vec![String::from("Synonym")]);
+    }
+
+    #[test]
+    fn test_load_rejects_other_files() {
+        let path = std::env::temp_dir().join("rust_hbr_test_load_rejects_other_files.bin");
+        std::fs::write(&path, "not a code store").expect("Failed to write file");
+        let result = ClinicalCodeStore::load(&path);
+        std::fs::remove_file(&path).expect("Failed to remove file");
+        assert!(matches!(result, Err(HbrError::InvalidCodeStoreFile(_))));
+    }
+
 }
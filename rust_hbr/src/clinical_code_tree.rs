@@ -8,7 +8,11 @@
 //! original file, meaning the parser can assume the categories
 //! are sorted. The original file is one of icd10.yaml or opcs4.yaml
 //! (or anything else in that format -- it should serialize/
-//! deserialize correctly.
+//! deserialize correctly. The same structure can also be read
+//! from JSON using from_json_reader(), and a tree can be written
+//! back out as YAML or JSON with to_writer()/to_json_writer().
+//! For faster loading, a tree can be compiled to a binary cache
+//! file with compile_to_cache() and read back with from_cache().
 //!
 //! The ClinicalCodeTree defines a tree of Categories, where each
 //! leaf node is (for example) an ICD-10 code and each non-leaf
@@ -21,13 +25,48 @@
 use index::Index;
 use rand::seq::SliceRandom;
 use rand_chacha::ChaCha8Rng;
-use serde::{Deserialize, Serialize};
-use std::io::Read;
-use std::{cmp::Ordering, collections::HashSet};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize, Serializer};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
+};
 
 use crate::clinical_code::{ClinicalCode, ClinicalCodeRef, ClinicalCodeStore};
-
+use crate::error::HbrError;
+
+mod binary;
+mod bnf;
+mod cache;
+mod compressed;
+#[cfg(feature = "default-trees")]
+mod defaults;
+mod diff;
+mod flat;
+mod gem;
+mod icd11;
 mod index;
+#[cfg(feature = "mmap")]
+mod lazy;
+mod membership;
+mod merge;
+mod pattern;
+mod range;
+mod typed;
+mod validate;
+
+pub use cache::CodeParserCache;
+pub use diff::{CodeTreeDiff, DocsChange, GroupChange};
+pub use flat::FlatCodeListFormat;
+pub use gem::{GemEntry, GemMapping};
+pub use membership::{GroupMembership, ParseStatus};
+pub use icd11::icd11_cluster_codes;
+#[cfg(feature = "mmap")]
+pub use lazy::LazyCodeTree;
+pub use typed::{DiagnosisCodeTree, ProcedureCodeTree};
+pub use validate::{GroupNameRules, GroupNamingConvention, TreeDiagnostic};
 
 /// The Code/Categories struct
 ///
@@ -38,12 +77,20 @@ mod index;
 ///
 /// TODO: RENAME TO CATEGORY
 ///
-#[derive(PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Debug)]
 pub struct Categories {
     /// The name of the code or category; e.g. A01.0
     name: String,
     /// The code description; e.g.
     docs: String,
+    /// A compact version of the description (e.g. for
+    /// labels in a user interface)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    short_docs: Option<String>,
+    /// Alternative clinical terms for the code or category,
+    /// which are also matched by search()
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    synonyms: Vec<String>,
     /// The index used to order the sub-categories
     index: Index,
     /// The set of sub-categories. If there are no
@@ -51,13 +98,68 @@ pub struct Categories {
     /// the name and docs are the information about
     /// the code. If there are sub-categories, then
     /// the name and docs apply to the category
+    #[serde(skip_serializing_if = "Option::is_none")]
     categories: Option<Vec<Categories>>,
     /// A set of code groups that do not contain this
     /// category or any sub-category
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_sorted_set_option"
+    )]
     exclude: Option<HashSet<String>>,
+    /// Extra information about the code or category, such as
+    /// where it came from (e.g. source: Schnier 2017) or local
+    /// notes. This is not used by the parser.
+    #[serde(
+        default,
+        skip_serializing_if = "HashMap::is_empty",
+        serialize_with = "serialize_sorted_map"
+    )]
+    metadata: HashMap<String, String>,
+}
+
+/// Serialize a set of strings as a sorted list, so that writing
+/// a code tree to a file always produces the same output
+fn serialize_sorted_set<S: Serializer>(
+    set: &HashSet<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut sorted: Vec<&String> = set.iter().collect();
+    sorted.sort();
+    sorted.serialize(serializer)
+}
+
+/// Serialize a map in key order, for the same reason as
+/// serialize_sorted_set
+fn serialize_sorted_map<S: Serializer, V: Serialize>(
+    map: &HashMap<String, V>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let sorted: BTreeMap<&String, &V> = map.iter().collect();
+    sorted.serialize(serializer)
 }
 
+/// Same as serialize_sorted_set, for an optional set
+fn serialize_sorted_set_option<S: Serializer>(
+    set: &Option<HashSet<String>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match set {
+        Some(set) => serialize_sorted_set(set, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Sort the categories (recursively) by index. The indices are also
+/// converted to lower case first, because this function is called on
+/// every tree after it is loaded (whatever the case of the indices in
+/// the codes file), so comparing codes with the index does not need
+/// any case conversion.
 fn sort_categories_list_in_place(categories: &mut Vec<Categories>) {
+    for category in categories.iter_mut() {
+        category.index.make_lowercase();
+    }
+
     // Sort the categories by the index field
     categories.sort_by(|c1, c2| c1.index.cmp(&c2.index));
 
@@ -67,37 +169,25 @@ fn sort_categories_list_in_place(categories: &mut Vec<Categories>) {
     }
 }
 
-/// Pick a sub-category at random. Panics if there are no
-/// sub-categories (do not call on leaf nodes). Returns
-/// error if there are no sub-categories (input vector is
-/// length zero)
+/// Pick a sub-category at random. Returns an error if
+/// there are no sub-categories (input vector is length zero)
 fn pick_subcategory_uniform_random<'a>(
     categories: &'a Vec<Categories>,
     rng: &mut ChaCha8Rng,
-) -> Result<&'a Categories, &'static str> {
-    if categories.len() == 0 {
-        Err("No categories to pick from")
-    } else {
-        let choice = categories
-            .choose(rng)
-            .expect("Should be Some, categories list is not empty");
-        Ok(&choice)
-    }
+) -> Result<&'a Categories, HbrError> {
+    categories.choose(rng).ok_or(HbrError::EmptyCategories)
 }
 
 impl Categories {
     /// Get a random clinical code from one of the (leaf)
-    /// sub-categories of this category.  
-    fn random_clinical_code(&self, rng: &mut ChaCha8Rng) -> ClinicalCode {
-        if self.is_leaf() {
-            ClinicalCode::from(self)
-        } else {
-            let sub_categories = self
-                .categories()
-                .expect("Non-leaf node will have sub-categories");
-            let choice = pick_subcategory_uniform_random(sub_categories, rng)
-                .expect("Subcategory list is non-empty, so should not fail");
-            choice.random_clinical_code(rng)
+    /// sub-categories of this category. Returns an error if
+    /// a category on the way down has no sub-categories.
+    fn random_clinical_code(&self, rng: &mut ChaCha8Rng) -> Result<ClinicalCode, HbrError> {
+        match self.categories() {
+            Some(sub_categories) => {
+                pick_subcategory_uniform_random(sub_categories, rng)?.random_clinical_code(rng)
+            }
+            None => Ok(ClinicalCode::from(self)),
         }
     }
 
@@ -140,10 +230,27 @@ impl Categories {
         &self.docs
     }
 
+    /// Get the short description of this code or category,
+    /// if there is one
+    pub fn short_docs(&self) -> Option<&String> {
+        self.short_docs.as_ref()
+    }
+
+    /// Get the alternative clinical terms for this code or category
+    pub fn synonyms(&self) -> &Vec<String> {
+        &self.synonyms
+    }
+
     /// Get the index of this code or category
     pub fn index(&self) -> &Index {
         &self.index
     }
+
+    /// Get the extra information (key-value pairs) attached to
+    /// this code or category in the codes file
+    pub fn metadata(&self) -> &HashMap<String, String> {
+        &self.metadata
+    }
 }
 
 /// Remove whitespace, dots and convert all characters
@@ -156,6 +263,34 @@ fn normalise_code(code: String) -> String {
         .collect()
 }
 
+/// The rules used to normalise codes before searching for
+/// them in a code tree
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CodeNormalisation {
+    /// Remove whitespace and dots and convert to lower case (used
+    /// for ICD-10 and OPCS-4)
+    #[default]
+    Standard,
+    /// As for Standard, but only the stem code of an ICD-11 cluster
+    /// (the part before any & or /) is used
+    Icd11,
+}
+
+impl CodeNormalisation {
+    /// Normalise a code using these rules
+    fn normalise(&self, code: String) -> String {
+        match self {
+            Self::Standard => normalise_code(code),
+            Self::Icd11 => icd11::normalise_icd11_code(&code),
+        }
+    }
+
+    fn is_standard(&self) -> bool {
+        *self == Self::Standard
+    }
+}
+
 fn get_codes_in_group(
     group: &String,
     categories: &Vec<Categories>,
@@ -173,16 +308,13 @@ fn get_codes_in_group(
     // include it in the results. For non-leaf categories,
     // call this function again and append the resulting
     for category in categories_left {
-        if category.is_leaf() && !category.exclude().contains(group) {
-            let clinical_code = ClinicalCode::from(category);
-            let clinical_code_ref = code_store.clinical_code_ref_from(clinical_code);
-            codes_in_group.push(clinical_code_ref);
-        } else {
-            let sub_categories = category
-                .categories()
-                .expect("There are always sub-categories for non-leaf");
+        if let Some(sub_categories) = category.categories() {
             let mut new_codes = get_codes_in_group(group, sub_categories, code_store);
             codes_in_group.append(&mut new_codes);
+        } else {
+            let clinical_code_ref =
+                code_store.clinical_code_ref_from_category(category);
+            codes_in_group.push(clinical_code_ref);
         }
     }
 
@@ -190,14 +322,141 @@ fn get_codes_in_group(
     return codes_in_group;
 }
 
+/// Same as get_codes_in_group, but also store the names of the
+/// categories above each code. The path argument holds the names
+/// of the categories above the current level (from the top down).
+fn get_codes_in_group_with_paths(
+    group: &String,
+    categories: &[Categories],
+    path: &mut Vec<String>,
+    code_store: &mut ClinicalCodeStore,
+    codes_in_group: &mut Vec<(ClinicalCodeRef, Vec<String>)>,
+) {
+    for category in categories
+        .iter()
+        .filter(|cat| !cat.exclude().contains(group))
+    {
+        if let Some(sub_categories) = category.categories() {
+            path.push(category.name().clone());
+            get_codes_in_group_with_paths(group, sub_categories, path, code_store, codes_in_group);
+            path.pop();
+        } else {
+            let clinical_code_ref =
+                code_store.clinical_code_ref_from_category(category);
+            codes_in_group.push((clinical_code_ref, path.clone()));
+        }
+    }
+}
+
+/// Call visit on every code (leaf) under the categories, in tree
+/// order, along with the set of groups that contain the code. The
+/// groups argument is the set of groups that have not been excluded
+/// by any parent category.
+fn visit_codes<'a, F>(categories: &'a Vec<Categories>, groups: &HashSet<String>, visit: &mut F)
+where
+    F: FnMut(&'a Categories, &HashSet<String>),
+{
+    for category in categories {
+        let mut groups_left = groups.clone();
+        if let Some(exclude) = &category.exclude {
+            groups_left.retain(|group| !exclude.contains(group));
+        }
+        match category.categories() {
+            Some(sub_categories) => visit_codes(sub_categories, &groups_left, visit),
+            None => visit(category, &groups_left),
+        }
+    }
+}
+
+/// Collect the normalised names of all the categories (including
+/// codes) that are in the set of names
+fn find_names_in_categories(
+    names: &HashSet<String>,
+    categories: &Vec<Categories>,
+    found: &mut HashSet<String>,
+) {
+    for category in categories {
+        let name = normalise_code(category.name().clone());
+        if names.contains(&name) {
+            found.insert(name);
+        }
+        if let Some(sub_categories) = category.categories() {
+            find_names_in_categories(names, sub_categories, found);
+        }
+    }
+}
+
+/// Set the excludes on the categories so that the group contains
+/// only the categories (and everything underneath them) whose
+/// normalised name is in include. If inside is true, the categories
+/// are underneath an included category, so none are excluded.
+/// Returns true if any of the categories contain codes in the group.
+fn tag_group_in_categories(
+    group: &String,
+    include: &HashSet<String>,
+    categories: &mut [Categories],
+    inside: bool,
+) -> bool {
+    let mut any_included = false;
+    for category in categories.iter_mut() {
+        // Start from a clean slate for this group
+        if let Some(exclude) = &mut category.exclude {
+            exclude.remove(group);
+            if exclude.is_empty() {
+                category.exclude = None;
+            }
+        }
+
+        let this_included = inside || include.contains(&normalise_code(category.name.clone()));
+        let included = match &mut category.categories {
+            Some(sub_categories) => {
+                tag_group_in_categories(group, include, sub_categories, this_included)
+                    || this_included
+            }
+            None => this_included,
+        };
+        if !included {
+            category
+                .exclude
+                .get_or_insert_with(HashSet::new)
+                .insert(group.clone());
+        }
+        any_included |= included;
+    }
+    any_included
+}
+
+/// Collect all the codes (leaves) under the categories whose name
+/// or description contains the (lowercase) search text, ignoring case
+fn search_categories(
+    text: &str,
+    categories: &Vec<Categories>,
+    code_store: &mut ClinicalCodeStore,
+) -> Vec<ClinicalCodeRef> {
+    let mut matches = Vec::new();
+    for category in categories {
+        if let Some(sub_categories) = category.categories() {
+            matches.append(&mut search_categories(text, sub_categories, code_store));
+        } else if category.name().to_lowercase().contains(text)
+            || category.docs().to_lowercase().contains(text)
+            || category
+                .synonyms()
+                .iter()
+                .any(|synonym| synonym.to_lowercase().contains(text))
+        {
+            matches.push(code_store.clinical_code_ref_from_category(category));
+        }
+    }
+    matches
+}
+
 /// Return the category in the supplied vector that contains the code,
-/// or return the error variant "not found" if the code is not present
-/// in any category. The search is performed using the index of the
-/// category.
+/// or return None if the code is not present in any category. The
+/// search is performed using the index of the category.
 fn locate_code_in_categories<'a>(
     code: &String,
     categories: &'a Vec<Categories>,
-) -> Result<&'a Categories, &'static str> {
+) -> Option<&'a Categories> {
     // Look through the index keys at the current level
     // and find the position of the code. Inside the codes
     // structure, the index keys provide an array to search
@@ -211,12 +470,15 @@ fn locate_code_in_categories<'a>(
 
     // Determine whether a code is in the category by comparing
     let compare_code_with_category = |cat: &Categories| -> Ordering {
-        cat.index().compare(code)
+        cat.index().relation_to(code)
     };
 
     match categories.binary_search_by(compare_code_with_category) {
-        Ok(position) => Ok(&categories[position]),
-        Err(_) =>  Err("not found"),
+        Ok(position) => Some(&categories[position]),
+        Err(_) => {
+            tracing::trace!(code = %code, "code is not in any category at this level");
+            None
+        }
     }
 
     // If found == false, then a match was not found. This
@@ -235,42 +497,179 @@ fn locate_code_in_categories<'a>(
     // return *position;
 }
 
-/// Return the name and docs field of a code (depending on the bool argument)
-/// if it exists in the categories tree, or return an error variant if the
-/// code is not present in the list of categories.
+/// Return the deepest category in the categories tree that contains the
+/// code, or None if the code is not contained in any category at the top
+/// level. If the code is a valid code, the returned category will be the
+/// leaf for that code.
 ///
-/// Note: You can also use this function to find the groups that a code is in.
-/// See the corresponding C++ function https://github.com/jrs0/rdb/blob/main
-/// /src/category.cpp#L192. In that case, you need to return a type that wraps
-/// the ClinicalCodeRef and also some reference to the groups.
+/// The groups argument should start as the full set of groups in the tree.
+/// Every category passed through on the way down removes the groups
+/// in its exclude set, so that when the function returns only the groups
+/// containing the returned category remain (see the corresponding C++ function
+/// https://github.com/jrs0/rdb/blob/main/src/category.cpp#L192).
 ///
-fn locate_code_in_tree(
-    code: String,
-    categories: &Vec<Categories>,
-    code_store: &mut ClinicalCodeStore,
-) -> Result<ClinicalCodeRef, &'static str> {
+fn locate_code_in_tree<'a>(
+    code: &String,
+    categories: &'a Vec<Categories>,
+    groups: &mut HashSet<String>,
+) -> Option<&'a Categories> {
     // Locate the category containing the code at the current level
-    if let Ok(cat) = locate_code_in_categories(&code, categories) {
-        // If there is a subcategory, make a call to this function
-        // to process the next category down. Otherwise you are
-        // at a leaf node, so start returning up the call graph.
-        // TODO: since this function is linearly recursive,
-        // there should be a tail-call optimisation available here
-        // somewhere.
-        if !cat.is_leaf() {
-            let sub_categories = cat
-                .categories()
-                .expect("Expecting sub-categories for non-leaf node");
-
-            // There are sub-categories -- parse the code at the next level
-            // down (put a try catch here for the case where the next level
-            // down isn't better)
-            locate_code_in_tree(code, sub_categories, code_store)
-        } else {
-            Ok(code_store.clinical_code_ref_from(ClinicalCode::from(cat)))
+    let cat = locate_code_in_categories(code, categories)?;
+
+    // Any group excluded at this level does not contain the code
+    if let Some(exclude) = &cat.exclude {
+        groups.retain(|group| !exclude.contains(group));
+    }
+
+    // If there is a subcategory, make a call to this function
+    // to process the next category down. If the code is not in
+    // any of the sub-categories, this category is the deepest
+    // one containing the code. Otherwise you are at a leaf node,
+    // so start returning up the call graph.
+    match cat.categories() {
+        Some(sub_categories) => locate_code_in_tree(code, sub_categories, groups).or(Some(cat)),
+        None => Some(cat),
+    }
+}
+
+/// Push every category on the path from the top of the tree down to
+/// the deepest category containing the code onto the path, in order
+/// from the top down (so the path is empty if the code is not in any
+/// category at the top level).
+fn locate_path_in_tree<'a>(
+    code: &String,
+    categories: &'a Vec<Categories>,
+    path: &mut Vec<&'a Categories>,
+) {
+    if let Some(cat) = locate_code_in_categories(code, categories) {
+        path.push(cat);
+        if let Some(sub_categories) = cat.categories() {
+            locate_path_in_tree(code, sub_categories, path);
+        }
+    }
+}
+
+/// Search the whole tree (depth first) for the category with this
+/// normalised name, for names that cannot be found using the index
+/// (e.g. ICD-10 chapters, which are named by roman numerals). If the
+/// category is found, the path to it (from the top down, including the
+/// category) is left in path and true is returned.
+fn find_path_by_name<'a>(
+    name: &String,
+    categories: &'a Vec<Categories>,
+    normalisation: &CodeNormalisation,
+    path: &mut Vec<&'a Categories>,
+) -> bool {
+    for category in categories {
+        path.push(category);
+        if normalisation.normalise(category.name().clone()) == *name {
+            return true;
+        }
+        if let Some(sub_categories) = category.categories() {
+            if find_path_by_name(name, sub_categories, normalisation, path) {
+                return true;
+            }
+        }
+        path.pop();
+    }
+    false
+}
+
+/// The number of codes in a group, and some examples of the
+/// codes (see ClinicalCodeTree::group_summaries)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupSummary {
+    /// The name of the group
+    pub group: String,
+    /// The number of codes (leaves) in the group
+    pub n_codes: usize,
+    /// The names of the first few codes in the group, in tree order
+    pub example_codes: Vec<String>,
+}
+
+/// Counts of the contents of a code tree, for checking which
+/// edition or variant of a codes file was loaded (see
+/// ClinicalCodeTree::summary)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeSummary {
+    /// The number of codes (leaves) in the tree
+    pub n_codes: usize,
+    /// The number of categories that are not codes, at any
+    /// level of the tree (including the chapters)
+    pub n_categories: usize,
+    /// The number of chapters (e.g. the 22 ICD-10 chapters)
+    pub n_chapters: usize,
+    /// The number of codes in each group (see group_sizes)
+    pub group_sizes: HashMap<String, usize>,
+}
+
+/// Count the codes (leaves) and the other categories in a list
+/// of categories, including all their sub-categories
+fn count_codes_and_categories(categories: &[Categories]) -> (usize, usize) {
+    let mut n_codes = 0;
+    let mut n_categories = 0;
+    for category in categories {
+        match category.categories() {
+            Some(sub_categories) => {
+                let (sub_codes, sub_categories) = count_codes_and_categories(sub_categories);
+                n_codes += sub_codes;
+                n_categories += sub_categories + 1;
+            }
+            None => n_codes += 1,
         }
-    } else {
-        Err("not found")
+    }
+    (n_codes, n_categories)
+}
+
+/// The result of matching a code in a ClinicalCodeTree
+///
+/// As well as a reference to the matched code, this contains the
+/// set of code groups that contain the code. The groups are collected
+/// during the same tree traversal that finds the code, so there is no
+/// need to search the tree again to find out which groups a code is in.
+///
+/// For an inexact match (from find_nearest), the code refers to the
+/// deepest category containing the code instead of a leaf, and the
+/// groups are the ones that are not excluded anywhere on the path to
+/// that category.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeMatch {
+    code: ClinicalCodeRef,
+    groups: HashSet<String>,
+    exact: bool,
+}
+
+impl CodeMatch {
+    /// Make a code match for a code system stored outside a
+    /// ClinicalCodeTree (e.g. SNOMED CT)
+    pub(crate) fn new(code: ClinicalCodeRef, groups: HashSet<String>, exact: bool) -> Self {
+        Self {
+            code,
+            groups,
+            exact,
+        }
+    }
+
+    /// Get the reference to the matched code
+    pub fn code(&self) -> ClinicalCodeRef {
+        self.code
+    }
+
+    /// Get the set of groups that contain the matched code
+    pub fn groups(&self) -> &HashSet<String> {
+        &self.groups
+    }
+
+    /// Returns true if the matched code is in the group
+    pub fn in_group(&self, group: &str) -> bool {
+        self.groups.contains(group)
+    }
+
+    /// Returns true if the code matched a leaf of the tree exactly,
+    /// and false if the match is the nearest category containing
+    /// the code
+    pub fn is_exact(&self) -> bool {
+        self.exact
     }
 }
 
@@ -280,14 +679,48 @@ fn locate_code_in_tree(
 /// for ICD-10 and OPCS-4 codes. It includes the code
 /// tree itself, a list of code groups, and tags embedded
 /// in the tree indicating which codes are in which group.
-#[derive(PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, JsonSchema, Debug)]
 pub struct ClinicalCodeTree {
     categories: Vec<Categories>,
     /// The list of clinical code group names that are
     /// present in this code tree
+    #[serde(serialize_with = "serialize_sorted_set")]
     groups: HashSet<String>,
+    /// How to normalise codes before searching the tree. This is
+    /// only present in the file for code systems that are not
+    /// normalised in the standard way (e.g. ICD-11).
+    #[serde(default, skip_serializing_if = "CodeNormalisation::is_standard")]
+    normalisation: CodeNormalisation,
+    /// Extra information about each group (key-value pairs, such
+    /// as the paper the group definition came from). Groups do not
+    /// need to have an entry.
+    #[serde(
+        default,
+        skip_serializing_if = "HashMap::is_empty",
+        serialize_with = "serialize_sorted_map"
+    )]
+    group_metadata: HashMap<String, HashMap<String, String>>,
+    /// Map from every normalised code in the tree to the code and
+    /// the groups that contain it. This is only present if it has
+    /// been built using with_exact_index(), and is not part of the
+    /// codes file.
+    #[serde(skip)]
+    exact_index: Option<HashMap<String, (ClinicalCode, HashSet<String>)>>,
+}
+
+/// Trees are equal if they contain the same codes and groups,
+/// whether or not the exact index has been built
+impl PartialEq for ClinicalCodeTree {
+    fn eq(&self, other: &Self) -> bool {
+        self.categories == other.categories
+            && self.groups == other.groups
+            && self.normalisation == other.normalisation
+            && self.group_metadata == other.group_metadata
+    }
 }
 
+impl Eq for ClinicalCodeTree {}
+
 impl ClinicalCodeTree {
     /// Read a clinical code tree from a byte source
     ///
@@ -296,31 +729,132 @@ impl ClinicalCodeTree {
     /// be true in the byte source (e.g. underlying yaml file).
     ///
     /// You can pass the result of std::fs::File::open() on
-    /// a yaml file to this function.
-    pub fn from_reader<R>(reader: R) -> Self
+    /// a yaml file to this function. The file can also be
+    /// compressed using gzip or zstd (e.g. icd10.yaml.gz), which
+    /// is detected automatically. Returns an error if the
+    /// contents cannot be deserialized to a code tree, or
+    /// HbrError::InvalidIndex if any index is malformed (a range
+    /// that ends before it starts, a range whose start and end have
+    /// different lengths, or a sub-category outside the range of its
+    /// parent), because the tree could not be searched correctly.
+    pub fn from_reader<R>(reader: R) -> Result<Self, HbrError>
+    where
+        R: Read,
+    {
+        let tree = Self::from_reader_unchecked(reader)?;
+        validate::check_indices(&tree.categories)?;
+        Ok(tree)
+    }
+
+    /// Same as from_reader, but without checking the indices
+    fn from_reader_unchecked<R>(reader: R) -> Result<Self, HbrError>
     where
         R: Read,
     {
-        let mut tree: Self =
-            serde_yaml::from_reader(reader).expect("Failed to deserialize to Categories");
+        let _span = tracing::debug_span!("load_code_tree", format = "yaml").entered();
+        let reader = compressed::MaybeCompressed::new(reader)?;
+        let mut tree: Self = serde_yaml::from_reader(reader)?;
         sort_categories_list_in_place(&mut tree.categories);
-        tree
+        tracing::debug!(num_groups = tree.groups.len(), "loaded code tree");
+        Ok(tree)
+    }
+
+    /// Write the clinical code tree to a byte sink as YAML
+    ///
+    /// The output is in the same format as the codes files
+    /// read by from_reader, so a tree can be read, modified
+    /// and written back without losing information. Categories
+    /// are written in index order (the tree is always kept
+    /// sorted), and the groups and excludes are written as sorted
+    /// lists, so writing the same tree twice gives identical files.
+    ///
+    /// You can pass the result of std::fs::File::create() to this
+    /// function.
+    pub fn to_writer<W>(&self, writer: W) -> Result<(), HbrError>
+    where
+        W: Write,
+    {
+        serde_yaml::to_writer(writer, self)?;
+        Ok(())
+    }
+
+    /// Read a clinical code tree from a byte source containing JSON
+    ///
+    /// The JSON has the same structure as the YAML codes files
+    /// (see from_reader), and the categories are sorted in the
+    /// same way. Compressed files and malformed indices are also
+    /// handled in the same way.
+    pub fn from_json_reader<R>(reader: R) -> Result<Self, HbrError>
+    where
+        R: Read,
+    {
+        let _span = tracing::debug_span!("load_code_tree", format = "json").entered();
+        let reader = compressed::MaybeCompressed::new(reader)?;
+        let mut tree: Self = serde_json::from_reader(reader)?;
+        sort_categories_list_in_place(&mut tree.categories);
+        validate::check_indices(&tree.categories)?;
+        tracing::debug!(num_groups = tree.groups.len(), "loaded code tree");
+        Ok(tree)
+    }
+
+    /// Write the clinical code tree to a byte sink as (pretty-printed)
+    /// JSON, in the same structure as to_writer.
+    pub fn to_json_writer<W>(&self, writer: W) -> Result<(), HbrError>
+    where
+        W: Write,
+    {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Build the flattened index used by find_exact
+    ///
+    /// By default, find_exact searches the tree one level at a time
+    /// (a binary search at each level). After calling this function,
+    /// find_exact instead looks up the code in a hash map containing
+    /// every code in the tree, along with the groups that contain it.
+    /// This uses more memory, but is much faster when parsing large
+    /// numbers of codes (e.g. all the diagnosis fields in HES). The
+    /// index is kept up to date if the tree is changed (e.g. by
+    /// tag_group). find_nearest is not affected.
+    pub fn with_exact_index(mut self) -> Self {
+        self.build_exact_index();
+        self
+    }
+
+    /// Returns true if the flattened index used by find_exact has
+    /// been built (see with_exact_index)
+    pub fn has_exact_index(&self) -> bool {
+        self.exact_index.is_some()
+    }
+
+    /// Build (or rebuild) the exact index from the current tree
+    fn build_exact_index(&mut self) {
+        let mut exact_index = HashMap::new();
+        let normalisation = self.normalisation;
+        visit_codes(&self.categories, &self.groups, &mut |code, groups| {
+            exact_index.insert(
+                normalisation.normalise(code.name().clone()),
+                (ClinicalCode::from(code), groups.clone()),
+            );
+        });
+        self.exact_index = Some(exact_index);
     }
 
     /// Generate a clinical code at random from the tree of codes
     ///
     /// Always returns a clinical code (i.e. a leaf), never a category.
     /// The random element is picked by choosing from the sub-categories
-    /// uniformly at random until a code is reached.
+    /// uniformly at random until a code is reached. Returns an error
+    /// if a category with no sub-categories is reached.
     pub fn random_clinical_code(
         &self,
         rng: &mut ChaCha8Rng,
         code_store: &mut ClinicalCodeStore,
-    ) -> ClinicalCodeRef {
-        let clinical_code = pick_subcategory_uniform_random(&self.categories, rng)
-            .expect("Should be Some, Categories list should not be empty")
-            .random_clinical_code(rng);
-        code_store.clinical_code_ref_from(clinical_code)
+    ) -> Result<ClinicalCodeRef, HbrError> {
+        let clinical_code =
+            pick_subcategory_uniform_random(&self.categories, rng)?.random_clinical_code(rng)?;
+        Ok(code_store.clinical_code_ref_from(clinical_code))
     }
 
     /// Pick an element uniformly at random from the specified
@@ -333,33 +867,304 @@ impl ClinicalCodeTree {
         rng: &mut ChaCha8Rng,
         code_store: &mut ClinicalCodeStore,
         group: &String,
-    ) -> Result<ClinicalCodeRef, &'static str> {
-        if let Ok(codes_in_group) = self.codes_in_group(group, code_store) {
-            if let Some(code) = codes_in_group.choose(rng) {
-                Ok(*code)
-            } else {
-                Err("Code group is empty")
+    ) -> Result<ClinicalCodeRef, HbrError> {
+        self.codes_in_group(group, code_store)?
+            .choose(rng)
+            .copied()
+            .ok_or_else(|| HbrError::EmptyGroup(group.clone()))
+    }
+
+    /// Pick a code uniformly at random from all the codes that are
+    /// not in any of the specified groups
+    ///
+    /// This is intended for generating codes for synthetic control
+    /// patients, who must not have any of the codes used to define
+    /// the condition of interest. Unlike random_clinical_code, each
+    /// allowed code is equally likely to be picked. Returns an error
+    /// if any of the groups is undefined, or if every code in the
+    /// tree is in one of the groups.
+    pub fn random_clinical_code_excluding(
+        &self,
+        rng: &mut ChaCha8Rng,
+        code_store: &mut ClinicalCodeStore,
+        groups: &[&str],
+    ) -> Result<ClinicalCodeRef, HbrError> {
+        let excluded_groups = self.group_set(groups)?;
+
+        // Only keep codes where all the groups have been excluded
+        // somewhere on the way down the tree
+        let mut allowed_codes = Vec::new();
+        visit_codes(&self.categories, &excluded_groups, &mut |code, groups_left| {
+            if groups_left.is_empty() {
+                allowed_codes.push(code);
             }
-        } else {
-            Err("Error occurred fetching codes in group")
-        }
+        });
+        let code = allowed_codes.choose(rng).ok_or(HbrError::EmptyCategories)?;
+        Ok(code_store.clinical_code_ref_from_category(code))
     }
 
     /// Get all the clinical codes in a particular group
     ///
     /// The result is either a vector of references to clinical codes
-    /// or an error string if the group does not exist in the code tree.
+    /// or an error if the group does not exist in the code tree.
     ///
     pub fn codes_in_group(
         &self,
         group: &String,
         code_store: &mut ClinicalCodeStore,
-    ) -> Result<Vec<ClinicalCodeRef>, &'static str> {
+    ) -> Result<Vec<ClinicalCodeRef>, HbrError> {
+        let _span = tracing::debug_span!("codes_in_group", group = %group).entered();
         if !self.groups.contains(group) {
-            Err("Clinical code tree does not contain that group")
+            tracing::debug!("group is not defined in the code tree");
+            Err(HbrError::UnknownGroup(group.clone()))
         } else {
-            Ok(get_codes_in_group(group, &self.categories, code_store))
+            let codes = get_codes_in_group(group, &self.categories, code_store);
+            tracing::debug!(num_codes = codes.len(), "found codes in group");
+            Ok(codes)
+        }
+    }
+
+    /// Get all the clinical codes in a group, along with the names
+    /// of the categories containing each code
+    ///
+    /// The codes are the same (and in the same order) as the result of
+    /// codes_in_group. The categories are listed from the top of the tree
+    /// down (e.g. the chapter, then the block, then the category for an
+    /// ICD-10 code), which is useful for checking where the codes in a
+    /// group definition come from. Returns an error if the group does
+    /// not exist in the code tree.
+    pub fn codes_in_group_with_paths(
+        &self,
+        group: &String,
+        code_store: &mut ClinicalCodeStore,
+    ) -> Result<Vec<(ClinicalCodeRef, Vec<String>)>, HbrError> {
+        if !self.groups.contains(group) {
+            return Err(HbrError::UnknownGroup(group.clone()));
         }
+        let mut codes = Vec::new();
+        get_codes_in_group_with_paths(
+            group,
+            &self.categories,
+            &mut Vec::new(),
+            code_store,
+            &mut codes,
+        );
+        Ok(codes)
+    }
+
+    /// Get all the codes that are in at least one of the groups
+    ///
+    /// The codes are found in a single traversal of the tree, and are
+    /// returned in tree order (each code appears once, even if it is
+    /// in several of the groups). Returns an error if any of the groups
+    /// does not exist in the code tree.
+    pub fn codes_in_any_of(
+        &self,
+        groups: &[&str],
+        code_store: &mut ClinicalCodeStore,
+    ) -> Result<Vec<ClinicalCodeRef>, HbrError> {
+        let groups = self.group_set(groups)?;
+        Ok(self.codes_where(&groups, code_store, |groups_left| !groups_left.is_empty()))
+    }
+
+    /// Get all the codes that are in every one of the groups
+    ///
+    /// As for codes_in_any_of, the codes are found in a single
+    /// traversal of the tree and returned in tree order. Returns an
+    /// error if any of the groups does not exist in the code tree.
+    pub fn codes_in_all_of(
+        &self,
+        groups: &[&str],
+        code_store: &mut ClinicalCodeStore,
+    ) -> Result<Vec<ClinicalCodeRef>, HbrError> {
+        let groups = self.group_set(groups)?;
+        Ok(self.codes_where(&groups, code_store, |groups_left| groups_left.len() == groups.len()))
+    }
+
+    /// Convert a list of group names to a set, checking that every
+    /// group is defined in the tree
+    fn group_set(&self, groups: &[&str]) -> Result<HashSet<String>, HbrError> {
+        let mut group_set = HashSet::new();
+        for group in groups {
+            if !self.groups.contains(*group) {
+                return Err(HbrError::UnknownGroup(group.to_string()));
+            }
+            group_set.insert(group.to_string());
+        }
+        Ok(group_set)
+    }
+
+    /// Get the codes (in tree order) for which keep returns true,
+    /// when passed the subset of the groups that contain the code
+    fn codes_where<F>(
+        &self,
+        groups: &HashSet<String>,
+        code_store: &mut ClinicalCodeStore,
+        keep: F,
+    ) -> Vec<ClinicalCodeRef>
+    where
+        F: Fn(&HashSet<String>) -> bool,
+    {
+        let mut codes = Vec::new();
+        visit_codes(&self.categories, groups, &mut |code, groups_left| {
+            if keep(groups_left) {
+                codes.push(code_store.clinical_code_ref_from_category(code));
+            }
+        });
+        codes
+    }
+
+    /// Write all the codes in a group to a CSV file
+    ///
+    /// The file has the columns code, docs and group (the
+    /// group column is the same on every row), with one row
+    /// per code in tree order. This is intended for handing
+    /// a flat list of codes to clinicians for review. Returns
+    /// an error if the group is not defined in the tree or the
+    /// file cannot be written.
+    pub fn write_group_csv<P: AsRef<Path>>(
+        &self,
+        group: &String,
+        path: P,
+    ) -> Result<(), HbrError> {
+        let mut code_store = ClinicalCodeStore::new();
+        let codes = self.codes_in_group(group, &mut code_store)?;
+
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(["code", "docs", "group"])?;
+        for code_ref in codes {
+            let code = code_store
+                .clinical_code_from(&code_ref)
+                .expect("Code was just added to the store");
+            writer.write_record([code.name(), code.docs(), group])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Get the number of codes in each group defined in the tree
+    ///
+    /// The result maps every group name to the number of codes
+    /// (leaves) in the group, including groups with no codes. All
+    /// the groups are counted in a single traversal of the tree,
+    /// which is cheaper than calling codes_in_group for each group.
+    pub fn group_sizes(&self) -> HashMap<String, usize> {
+        let mut sizes: HashMap<String, usize> =
+            self.groups.iter().map(|group| (group.clone(), 0)).collect();
+        visit_codes(&self.categories, &self.groups, &mut |_, groups| {
+            for group in groups {
+                *sizes.get_mut(group).expect("Group is in the tree") += 1;
+            }
+        });
+        sizes
+    }
+
+    /// Summarise each group defined in the tree, for checking the
+    /// group definitions at a glance
+    ///
+    /// Returns one GroupSummary per group, sorted by group name, with
+    /// the number of codes in the group and the first max_examples
+    /// codes in the group (in tree order). As for group_sizes, all the
+    /// groups are summarised in a single traversal of the tree.
+    pub fn group_summaries(&self, max_examples: usize) -> Vec<GroupSummary> {
+        let mut summaries: HashMap<String, GroupSummary> = self
+            .groups
+            .iter()
+            .map(|group| {
+                let summary = GroupSummary {
+                    group: group.clone(),
+                    n_codes: 0,
+                    example_codes: Vec::new(),
+                };
+                (group.clone(), summary)
+            })
+            .collect();
+        visit_codes(&self.categories, &self.groups, &mut |code, groups| {
+            for group in groups {
+                let summary = summaries.get_mut(group).expect("Group is in the tree");
+                summary.n_codes += 1;
+                if summary.example_codes.len() < max_examples {
+                    summary.example_codes.push(code.name().clone());
+                }
+            }
+        });
+        let mut summaries: Vec<GroupSummary> = summaries.into_values().collect();
+        summaries.sort_by(|a, b| a.group.cmp(&b.group));
+        summaries
+    }
+
+    /// Count the codes, categories and chapters in the tree, along
+    /// with the number of codes in each group
+    ///
+    /// The codes files have a single top-level category for the
+    /// whole code system (e.g. ICD-10), so the chapters are the
+    /// categories underneath it. If there is more than one top-level
+    /// category, they are counted as the chapters instead.
+    pub fn summary(&self) -> TreeSummary {
+        let (n_codes, n_categories) = count_codes_and_categories(&self.categories);
+        let n_chapters = match self.categories.as_slice() {
+            [root] => root.categories().map_or(0, Vec::len),
+            top_level => top_level.len(),
+        };
+        TreeSummary {
+            n_codes,
+            n_categories,
+            n_chapters,
+            group_sizes: self.group_sizes(),
+        }
+    }
+
+    /// Define a code group containing the listed codes and categories
+    ///
+    /// The group contains every code that is (or is underneath) one
+    /// of the codes or categories in include (matched by name, after
+    /// normalisation). The excludes in the tree are set so that all
+    /// other codes are not in the group, so the group is saved when
+    /// the tree is written out. If the group already exists, it is
+    /// replaced.
+    ///
+    /// This is the easiest way to build groups for trees that do not
+    /// come with any, for example a medication tree where a group like
+    /// oral_anticoagulant is defined by a few BNF sub-paragraphs or
+    /// chemical substances. An item in include can also be a range of
+    /// codes such as I20-I25 (see expand_range), so that groups
+    /// published as ranges can be used directly. Returns an error
+    /// (without changing the tree) if any of the names in include are
+    /// not in the tree, or if a range is invalid or contains no codes.
+    pub fn tag_group(&mut self, group: &str, include: &[&str]) -> Result<(), HbrError> {
+        let mut names = HashSet::new();
+        for name in include {
+            if name.contains('-') {
+                let code_range = range::CodeRange::parse(name, &self.normalisation)?;
+                let mut codes = Vec::new();
+                range::codes_in_range(
+                    &code_range,
+                    &self.categories,
+                    &self.normalisation,
+                    &mut codes,
+                );
+                if codes.is_empty() {
+                    return Err(HbrError::CodeNotFound(name.to_string()));
+                }
+                names.extend(codes.iter().map(|code| normalise_code(code.name().clone())));
+            } else {
+                names.insert(normalise_code(name.to_string()));
+            }
+        }
+        let include = names;
+        let mut found = HashSet::new();
+        find_names_in_categories(&include, &self.categories, &mut found);
+        if let Some(missing) = include.difference(&found).next() {
+            return Err(HbrError::CodeNotFound(missing.clone()));
+        }
+
+        let group = group.to_string();
+        tag_group_in_categories(&group, &include, &mut self.categories, false);
+        self.groups.insert(group);
+        if self.has_exact_index() {
+            self.build_exact_index();
+        }
+        Ok(())
     }
 
     /// Get the list of groups defined in the clinical code tree
@@ -367,6 +1172,43 @@ impl ClinicalCodeTree {
         &self.groups
     }
 
+    /// Get the extra information attached to a group in the codes
+    /// file (empty if there is none). Returns HbrError::UnknownGroup
+    /// if the group is not defined in the tree.
+    pub fn group_metadata(&self, group: &str) -> Result<HashMap<String, String>, HbrError> {
+        if !self.groups.contains(group) {
+            return Err(HbrError::UnknownGroup(group.to_string()));
+        }
+        Ok(self.group_metadata.get(group).cloned().unwrap_or_default())
+    }
+
+    /// Get the extra information attached to a code or category in
+    /// the codes file (empty if there is none). Returns
+    /// HbrError::CodeNotFound if the name is not in the tree.
+    pub fn metadata_of(&self, name: &str) -> Result<&HashMap<String, String>, HbrError> {
+        let path = self.path_to(name)?;
+        Ok(path.last().expect("Path contains the category").metadata())
+    }
+
+    /// Get the description of a code or category in the tree (e.g.
+    /// the docs of a chapter, which cannot be matched using find_exact).
+    /// Returns HbrError::CodeNotFound if the name is not in the tree.
+    pub fn docs_of(&self, name: &str) -> Result<&String, HbrError> {
+        let path = self.path_to(name)?;
+        Ok(path.last().expect("Path contains the category").docs())
+    }
+
+    /// Search for codes by name or description
+    ///
+    /// Returns all the codes (not categories) whose name or description
+    /// contains the search text, ignoring case. The codes are returned
+    /// in the order they appear in the tree. This is intended for
+    /// interactive use (e.g. a search box in the codes editor); use
+    /// find_exact to parse codes from data.
+    pub fn search(&self, text: &str, code_store: &mut ClinicalCodeStore) -> Vec<ClinicalCodeRef> {
+        search_categories(&text.to_lowercase(), &self.categories, code_store)
+    }
+
     /// Find a particular code in the tree, or return an error
     /// if it is not present.
     ///
@@ -381,26 +1223,183 @@ impl ClinicalCodeTree {
     /// - The code has any of the standard uses of X, D or A (or other
     ///   modifiers) which would make the match not exact.
     ///
-    /// If the match succeeds, then a reference to the code is returned.
-    /// If the match fails, an error variant is returned with the error
-    /// string "no match", or another error (if it occurred).
+    /// If the match succeeds, then a CodeMatch is returned, which contains
+    /// a reference to the code and the set of groups that contain the code
+    /// (the groups are found during the same tree traversal, so there is no
+    /// need to search the tree again to get them). If the match fails,
+    /// HbrError::CodeNotFound is returned.
+    ///
+    /// Each call to this function will search the entire tree, which is
+    /// slow (even though it is a binary search). In code that repeatedly
+    /// searches for exact code matches, either build the flattened index
+    /// using with_exact_index(), or cache the result of this function in
+    /// a map from the code String argument to CodeMatch.
+    pub fn find_exact(
+        &self,
+        code: String,
+        code_store: &mut ClinicalCodeStore,
+    ) -> Result<CodeMatch, HbrError> {
+        let _span = tracing::trace_span!("find_exact", code = %code).entered();
+        if let Some(exact_index) = &self.exact_index {
+            let normalised_code = self.normalisation.normalise(code.clone());
+            let (clinical_code, groups) = exact_index
+                .get(&normalised_code)
+                .ok_or_else(|| HbrError::CodeNotFound(code))?;
+            let code = code_store.clinical_code_ref_from(clinical_code.clone());
+            code_store.record_groups(&code, groups);
+            return Ok(CodeMatch {
+                code,
+                groups: groups.clone(),
+                exact: true,
+            });
+        }
+        // Check the match is exact before storing the code, so that a
+        // rejected code leaves the code store unchanged
+        match self.locate_nearest(&code) {
+            Some((cat, groups, true)) => Ok(Self::code_match_from(cat, groups, true, code_store)),
+            _ => Err(HbrError::CodeNotFound(code)),
+        }
+    }
+
+    /// Find the deepest category in the tree that contains a code, or
+    /// return an error if the code is not in any category.
+    ///
+    /// This is intended for imperfectly coded data, where find_exact
+    /// would reject the code. For example, "I21.9X" (trailing material)
+    /// matches the code I21.9, and "I21" (a truncated code) matches the
+    /// category I21. The returned CodeMatch is flagged as inexact in
+    /// these cases (see is_exact()); a code that is present in the tree
+    /// gives the same (exact) result as find_exact.
+    ///
+    /// The groups in the result are the groups that are not excluded
+    /// anywhere on the path to the matched category. If the match is a
+    /// category, some of the codes inside it may still be excluded from
+    /// these groups.
+    pub fn find_nearest(
+        &self,
+        code: String,
+        code_store: &mut ClinicalCodeStore,
+    ) -> Result<CodeMatch, HbrError> {
+        let _span = tracing::trace_span!("find_nearest", code = %code).entered();
+        let (cat, groups, exact) = self
+            .locate_nearest(&code)
+            .ok_or_else(|| HbrError::CodeNotFound(code))?;
+        Ok(Self::code_match_from(cat, groups, exact, code_store))
+    }
+
+    /// Find the deepest category containing a code, along with the groups
+    /// containing the category and whether the category is an exact match
+    /// for the code (see find_nearest). The code store is not modified.
+    fn locate_nearest(&self, code: &str) -> Option<(&Categories, HashSet<String>, bool)> {
+        let normalised_code = self.normalisation.normalise(code.to_string());
+        let mut groups = self.groups.clone();
+        let cat = locate_code_in_tree(&normalised_code, &self.categories, &mut groups)?;
+        let exact =
+            cat.is_leaf() && self.normalisation.normalise(cat.name().clone()) == normalised_code;
+        Some((cat, groups, exact))
+    }
+
+    /// Store the category found by locate_nearest in the code store, and
+    /// make the CodeMatch to return
+    fn code_match_from(
+        cat: &Categories,
+        groups: HashSet<String>,
+        exact: bool,
+        code_store: &mut ClinicalCodeStore,
+    ) -> CodeMatch {
+        let code = code_store.clinical_code_ref_from_category(cat);
+        code_store.record_groups(&code, &groups);
+        CodeMatch {
+            code,
+            groups,
+            exact,
+        }
+    }
+
+    /// Get the path from the top of the tree down to the code or
+    /// category with this name (after normalisation), including the
+    /// code or category itself as the last element. The index is
+    /// used to find the name if possible, falling back to searching
+    /// the whole tree. Returns HbrError::CodeNotFound if the name is
+    /// not in the tree.
+    fn path_to(&self, name: &str) -> Result<Vec<&Categories>, HbrError> {
+        let normalised_name = self.normalisation.normalise(name.to_string());
+        let mut path = Vec::new();
+        locate_path_in_tree(&normalised_name, &self.categories, &mut path);
+        let position = path
+            .iter()
+            .rposition(|cat| self.normalisation.normalise(cat.name().clone()) == normalised_name);
+        match position {
+            Some(position) => path.truncate(position + 1),
+            None => {
+                path.clear();
+                if !find_path_by_name(
+                    &normalised_name,
+                    &self.categories,
+                    &self.normalisation,
+                    &mut path,
+                ) {
+                    return Err(HbrError::CodeNotFound(name.to_string()));
+                }
+            }
+        }
+        Ok(path)
+    }
+
+    /// Get the categories containing a code, starting with the
+    /// immediate parent and going up to the top of the tree
     ///
-    /// Each call to this function will search the entire tree, which is
-    /// slow (even though it is a binary search). In code that repeatedly
-    /// searches for exact code matches, you should cache the result of
-    /// this function in a map from the code String argument to ClinicalCodeRef.
+    /// For example, the ancestors of the ICD-10 code I21.9 are I21
+    /// (the 3-character category), I20-I25 (the block), IX (the
+    /// chapter) and the root ICD-10 category. This is intended for
+    /// rolling up results to a coarser level for reporting. The code
+    /// can also be the name of a category, in which case the categories
+    /// containing it are returned. Returns HbrError::CodeNotFound if
+    /// the code is not in the tree.
+    pub fn ancestors(
+        &self,
+        code: &str,
+        code_store: &mut ClinicalCodeStore,
+    ) -> Result<Vec<ClinicalCodeRef>, HbrError> {
+        let mut path = self.path_to(code)?;
+        path.pop();
+        Ok(path
+            .iter()
+            .rev()
+            .map(|cat| code_store.clinical_code_ref_from_category(cat))
+            .collect())
+    }
+
+    /// Get the immediate sub-categories and codes of a category
     ///
-    /// Note: traversing the tree looking for a code also gives you the groups
-    /// the code is in for free (that operation also requires a tree traversal).
-    /// So it might be a good idea to store the groups too, instead of having
-    /// to traverse the tree twice.
-    pub fn find_exact(
+    /// Each child is returned along with a flag that is true if the
+    /// child is a category (so it has children of its own) and false
+    /// if it is a code. The children are in tree order. Pass an empty
+    /// name to get the top level of the tree. This is intended for
+    /// browsing the tree one level at a time (e.g. from the bindings
+    /// or the codes editor). Returns HbrError::CodeNotFound if the
+    /// category is not in the tree; the result is empty if the name
+    /// is a code.
+    pub fn children_of(
         &self,
-        code: String,
+        category_name: &str,
         code_store: &mut ClinicalCodeStore,
-    ) -> Result<ClinicalCodeRef, &'static str> {
-        let normalised_code = normalise_code(code);
-        locate_code_in_tree(normalised_code, &self.categories, code_store)
+    ) -> Result<Vec<(ClinicalCodeRef, bool)>, HbrError> {
+        let children = if category_name.trim().is_empty() {
+            Some(&self.categories)
+        } else {
+            let path = self.path_to(category_name)?;
+            let category = path.last().expect("Path contains the category");
+            category.categories()
+        };
+        Ok(children
+            .into_iter()
+            .flatten()
+            .map(|cat| {
+                let code_ref = code_store.clinical_code_ref_from_category(cat);
+                (code_ref, !cat.is_leaf())
+            })
+            .collect())
     }
 }
 
@@ -428,7 +1427,10 @@ mod tests {
                 name: String::from($name),
                 docs: String::from($docs),
                 index: $index,
+                short_docs: None,
+                synonyms: Vec::new(),
                 exclude: None,
+                metadata: HashMap::new(),
                 categories: Some($categories),
             }
         };
@@ -441,7 +1443,10 @@ mod tests {
                 name: String::from($name),
                 docs: String::from($docs),
                 index: $index,
+                short_docs: None,
+                synonyms: Vec::new(),
                 exclude: None,
+                metadata: HashMap::new(),
                 categories: None,
             }
         };
@@ -475,6 +1480,9 @@ mod tests {
                 String::from("group2"),
                 String::from("another"),
             ]),
+            normalisation: CodeNormalisation::Standard,
+            group_metadata: HashMap::new(),
+            exact_index: None,
         }
     }
 
@@ -512,7 +1520,8 @@ mod tests {
         - another
         "#;
 
-        let code_tree = ClinicalCodeTree::from_reader(yaml.as_bytes());
+        let code_tree =
+            ClinicalCodeTree::from_reader(yaml.as_bytes()).expect("Failed to parse codes file");
         assert_eq!(code_tree, code_tree_example_1());
     }
 
@@ -550,10 +1559,127 @@ mod tests {
         - another
         "#;
 
-        let code_tree = ClinicalCodeTree::from_reader(yaml.as_bytes());
+        let code_tree =
+            ClinicalCodeTree::from_reader(yaml.as_bytes()).expect("Failed to parse codes file");
         assert_eq!(code_tree, code_tree_example_1());
     }
 
+    #[test]
+    fn check_yaml_roundtrip() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+
+        let mut yaml = Vec::new();
+        code_tree.to_writer(&mut yaml).expect("Failed to write codes file");
+        let roundtrip =
+            ClinicalCodeTree::from_reader(yaml.as_slice()).expect("Failed to parse codes file");
+        assert_eq!(code_tree, roundtrip);
+
+        // Writing again gives exactly the same output
+        let mut yaml_again = Vec::new();
+        roundtrip.to_writer(&mut yaml_again).expect("Failed to write codes file");
+        assert_eq!(yaml, yaml_again);
+
+        // Leaves are written without a categories key
+        let yaml = String::from_utf8(yaml).expect("Output should be valid UTF-8");
+        assert!(!yaml.contains("null"));
+    }
+
+    #[test]
+    fn check_json_roundtrip() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("opcs4_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open opcs4 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+
+        let mut json = Vec::new();
+        code_tree.to_json_writer(&mut json).expect("Failed to write codes file");
+        let roundtrip = ClinicalCodeTree::from_json_reader(json.as_slice())
+            .expect("Failed to parse codes file");
+        assert_eq!(code_tree, roundtrip);
+    }
+
+    #[test]
+    fn check_write_group_csv() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+
+        let csv_path = std::env::temp_dir().join("rust_hbr_check_write_group_csv.csv");
+        code_tree
+            .write_group_csv(&format!("atrial_fib"), &csv_path)
+            .expect("Failed to write group");
+        let contents = std::fs::read_to_string(&csv_path).expect("Failed to read csv");
+        std::fs::remove_file(&csv_path).expect("Failed to remove csv");
+
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines[0], "code,docs,group");
+        assert_eq!(lines.len(), 7);
+        assert!(lines[1].starts_with("I48.0,"));
+        assert!(lines[1].ends_with(",atrial_fib"));
+
+        // Unknown groups are an error
+        assert!(code_tree
+            .write_group_csv(&format!("unknown_group"), &csv_path)
+            .is_err());
+    }
+
+    #[test]
+    fn check_group_sizes_match_codes_in_group() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        let mut code_store = ClinicalCodeStore::new();
+
+        let sizes = code_tree.group_sizes();
+        assert_eq!(sizes.len(), code_tree.groups().len());
+        assert_eq!(sizes["atrial_fib"], 6);
+        for group in code_tree.groups() {
+            let codes = code_tree
+                .codes_in_group(group, &mut code_store)
+                .expect("Group should exist");
+            assert_eq!(sizes[group], codes.len());
+        }
+
+        let summaries = code_tree.group_summaries(3);
+        assert_eq!(summaries.len(), code_tree.groups().len());
+        assert!(summaries.windows(2).all(|pair| pair[0].group < pair[1].group));
+        for summary in summaries {
+            assert_eq!(summary.n_codes, sizes[&summary.group]);
+            let codes = code_tree
+                .codes_in_group(&summary.group, &mut code_store)
+                .expect("Group should exist");
+            let first_codes: Vec<&String> = codes
+                .iter()
+                .take(3)
+                .map(|code| name!(code, code_store))
+                .collect();
+            assert_eq!(summary.example_codes.iter().collect::<Vec<_>>(), first_codes);
+        }
+
+        let tree_summary = code_tree.summary();
+        assert_eq!(tree_summary.n_codes, 10607);
+        assert_eq!(tree_summary.n_categories, 1877);
+        assert_eq!(tree_summary.n_chapters, 22);
+        assert_eq!(tree_summary.group_sizes, sizes);
+    }
+
     #[test]
     fn check_code_normalisation() {
         let string = format!("A00.0");
@@ -580,7 +1706,7 @@ mod tests {
         let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
 
         // Should execute without panic
-        let code_tree = ClinicalCodeTree::from_reader(f);
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
     }
 
     #[test]
@@ -594,7 +1720,7 @@ mod tests {
         let f = std::fs::File::open(file_path).expect("Failed to open opcs4 file");
 
         // Should execute without panic
-        let code_tree = ClinicalCodeTree::from_reader(f);
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
     }
 
     // Check that the correct codes are returned from the hard-coded test files.
@@ -608,7 +1734,7 @@ mod tests {
         let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
 
         // Should execute without panic
-        let code_tree = ClinicalCodeTree::from_reader(f);
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
 
         let mut code_store = ClinicalCodeStore::new();
 
@@ -657,6 +1783,86 @@ mod tests {
         );
     }
 
+    #[test]
+    fn check_codes_in_any_of_and_all_of() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let mut code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        code_tree
+            .tag_group("mi", &["I21-I22"])
+            .expect("Failed to tag group");
+        let mut code_store = ClinicalCodeStore::new();
+
+        // The atrial fibrillation codes are not MI codes, so any_of is
+        // both groups together, in tree order
+        let codes = code_tree
+            .codes_in_any_of(&["atrial_fib", "mi"], &mut code_store)
+            .expect("Groups should be present");
+        let mut expected = code_tree
+            .codes_in_group(&format!("mi"), &mut code_store)
+            .unwrap();
+        expected.extend(
+            code_tree
+                .codes_in_group(&format!("atrial_fib"), &mut code_store)
+                .unwrap(),
+        );
+        assert_eq!(codes, expected);
+
+        // The STEMI codes are a subset of the MI codes
+        let codes = code_tree
+            .codes_in_all_of(&["acs_stemi_schnier", "mi"], &mut code_store)
+            .expect("Groups should be present");
+        let code_names: Vec<&String> = codes.iter().map(|code| name!(code, code_store)).collect();
+        assert_eq!(
+            code_names,
+            vec!["I21.0", "I21.1", "I21.2", "I21.3", "I22.0", "I22.1", "I22.8"]
+        );
+        let codes = code_tree
+            .codes_in_all_of(&["atrial_fib", "mi"], &mut code_store)
+            .expect("Groups should be present");
+        assert!(codes.is_empty());
+
+        assert!(code_tree
+            .codes_in_any_of(&["atrial_fib", "unknown_group"], &mut code_store)
+            .is_err());
+    }
+
+    #[test]
+    fn check_codes_in_group_with_paths() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        let mut code_store = ClinicalCodeStore::new();
+
+        let group = format!("acs_stemi_schnier");
+        let codes_with_paths = code_tree
+            .codes_in_group_with_paths(&group, &mut code_store)
+            .expect("Group should be present");
+        let codes: Vec<_> = codes_with_paths.iter().map(|(code, _)| *code).collect();
+        assert_eq!(
+            codes,
+            code_tree.codes_in_group(&group, &mut code_store).unwrap()
+        );
+
+        // The first code is I21.0, under the chapter, block and category
+        let (code, path) = &codes_with_paths[0];
+        assert_eq!(name!(code, code_store), "I21.0");
+        assert_eq!(path.len(), 4);
+        assert_eq!(path[2..], ["I20-I25", "I21"]);
+
+        assert!(code_tree
+            .codes_in_group_with_paths(&format!("unknown_group"), &mut code_store)
+            .is_err());
+    }
+
     #[test]
     fn check_nonexistent_group_returns_error() {
         let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -667,13 +1873,13 @@ mod tests {
         let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
 
         // Should execute without panic
-        let code_tree = ClinicalCodeTree::from_reader(f);
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
 
         let mut code_store = ClinicalCodeStore::new();
 
         // Try to read a group of codes which is not in the file
         let unknown_group = code_tree.codes_in_group(&format!("unknown_group"), &mut code_store);
-        assert!(unknown_group.is_err());
+        assert!(matches!(unknown_group, Err(HbrError::UnknownGroup(_))));
     }
 
     /// Convenience macro to make a HashSet<String> from a vector
@@ -692,7 +1898,7 @@ mod tests {
         let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
 
         // Should execute without panic
-        let code_tree = ClinicalCodeTree::from_reader(f);
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
 
         let mut code_store = ClinicalCodeStore::new();
     }
@@ -707,7 +1913,7 @@ mod tests {
         let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
 
         // Should execute without panic
-        let code_tree = ClinicalCodeTree::from_reader(f);
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
 
         // Note the order of the groups is not defined -- just
         // the set is being checked
@@ -755,7 +1961,7 @@ mod tests {
         let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
 
         // Should execute without panic
-        let code_tree = ClinicalCodeTree::from_reader(f);
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
 
         let mut code_store = ClinicalCodeStore::new();
 
@@ -777,6 +1983,462 @@ mod tests {
         }
     }
 
+    #[test]
+    fn check_randomly_chosen_code_is_not_in_excluded_groups() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        let mut code_store = ClinicalCodeStore::new();
+
+        let excluded = ["atrial_fib", "acs_stemi_schnier"];
+        let mut rng = make_rng(222, "clinical_code_test_id");
+        for _ in 0..100 {
+            let random_code = code_tree
+                .random_clinical_code_excluding(&mut rng, &mut code_store, &excluded)
+                .expect("Should be able to pick a valid code");
+            let code_match = code_tree
+                .find_exact(name!(random_code, code_store).clone(), &mut code_store)
+                .expect("Random code should be in the tree");
+            assert!(excluded.iter().all(|group| !code_match.in_group(group)));
+        }
+
+        let result =
+            code_tree.random_clinical_code_excluding(&mut rng, &mut code_store, &["unknown_group"]);
+        assert!(matches!(result, Err(HbrError::UnknownGroup(_))));
+    }
+
+    #[test]
+    fn check_find_exact_groups_match_codes_in_group() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        let mut code_store = ClinicalCodeStore::new();
+
+        // An atrial fibrillation code is in the atrial_fib group
+        let code_match = code_tree
+            .find_exact(format!("I48.0"), &mut code_store)
+            .expect("The code should be an exact match");
+        assert!(code_match.in_group("atrial_fib"));
+
+        // For each group, every code in the group should report
+        // that group when matched, and no other codes should
+        let all_groups: Vec<String> = code_tree.groups().iter().cloned().collect();
+        let mut rng = make_rng(222, "clinical_code_test_id");
+        for _ in 0..200 {
+            let random_code = code_tree
+                .random_clinical_code(&mut rng, &mut code_store)
+                .expect("Should be able to pick a valid code");
+            let code = code_store
+                .clinical_code_from(&random_code)
+                .expect("Expecting a valid code")
+                .clone();
+            let code_match = code_tree
+                .find_exact(code.name().clone(), &mut code_store)
+                .expect("The code should be an exact match");
+            for group in all_groups.iter() {
+                let in_group = code_tree
+                    .codes_in_group(group, &mut code_store)
+                    .expect("Group should exist")
+                    .contains(&random_code);
+                assert_eq!(code_match.in_group(group), in_group);
+            }
+        }
+    }
+
+    #[test]
+    fn check_parsed_codes_record_groups_in_store() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        let mut code_store = ClinicalCodeStore::new();
+
+        // Codes that have not been parsed have no recorded groups
+        let codes = code_tree
+            .codes_in_group(&format!("acs_stemi_schnier"), &mut code_store)
+            .expect("Group should exist");
+        assert_eq!(code_store.groups_of(&codes[0]), None);
+
+        let code_match = code_tree
+            .find_exact(format!("I48.0"), &mut code_store)
+            .expect("The code should be an exact match");
+        let groups = code_store
+            .groups_of(&code_match.code())
+            .expect("Groups should be recorded");
+        assert_eq!(groups, code_match.groups());
+        assert!(groups.contains("atrial_fib"));
+
+        // The exact index records the same groups
+        let indexed_tree = code_tree.with_exact_index();
+        let mut code_store = ClinicalCodeStore::new();
+        let code_match = indexed_tree
+            .find_exact(format!("I48.0"), &mut code_store)
+            .expect("The code should be an exact match");
+        assert_eq!(code_store.groups_of(&code_match.code()), Some(groups));
+    }
+
+    #[test]
+    fn check_exact_index_matches_tree_search() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        let indexed_tree = code_tree.clone().with_exact_index();
+        assert!(indexed_tree.has_exact_index());
+        assert_eq!(code_tree, indexed_tree);
+        let mut code_store = ClinicalCodeStore::new();
+
+        let mut rng = make_rng(222, "clinical_code_test_id");
+        for _ in 0..200 {
+            let random_code = code_tree
+                .random_clinical_code(&mut rng, &mut code_store)
+                .expect("Should be able to pick a valid code");
+            let name = name!(random_code, code_store).clone();
+            let code_match = code_tree
+                .find_exact(name.clone(), &mut code_store)
+                .expect("The code should be an exact match");
+            let indexed_match = indexed_tree
+                .find_exact(name, &mut code_store)
+                .expect("The code should be an exact match");
+            assert_eq!(code_match, indexed_match);
+        }
+
+        for code in ["I21", "I21.9X", "XYZ", ""] {
+            assert!(indexed_tree.find_exact(code.to_string(), &mut code_store).is_err());
+        }
+        let code_match = indexed_tree
+            .find_exact(format!(" i480 "), &mut code_store)
+            .expect("The code should be an exact match");
+        assert!(code_match.in_group("atrial_fib"));
+    }
+
+    #[test]
+    fn check_find_nearest_returns_deepest_category() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        let mut code_store = ClinicalCodeStore::new();
+
+        // A valid code is an exact match
+        let code_match = code_tree
+            .find_nearest(format!("I21.9"), &mut code_store)
+            .expect("Should match");
+        assert!(code_match.is_exact());
+        assert_eq!(name!(code_match.code(), code_store), "I21.9");
+
+        // Trailing material matches the code, but not exactly
+        let code_match = code_tree
+            .find_nearest(format!("I21.9X"), &mut code_store)
+            .expect("Should match");
+        assert!(!code_match.is_exact());
+        assert_eq!(name!(code_match.code(), code_store), "I21.9");
+        assert!(code_tree
+            .find_exact(format!("I21.9X"), &mut code_store)
+            .is_err());
+
+        // A truncated code matches the category
+        let code_match = code_tree
+            .find_nearest(format!("I21"), &mut code_store)
+            .expect("Should match");
+        assert!(!code_match.is_exact());
+        assert_eq!(name!(code_match.code(), code_store), "I21");
+        assert!(code_tree.find_exact(format!("I21"), &mut code_store).is_err());
+
+        // A rejected code is not added to the code store
+        let mut code_store = ClinicalCodeStore::new();
+        assert!(code_tree
+            .find_exact(format!("I21.9X"), &mut code_store)
+            .is_err());
+        assert!(code_tree.find_exact(format!("I21"), &mut code_store).is_err());
+        assert_eq!(code_store.num_stored_codes(), 0);
+    }
+
+    #[test]
+    fn check_tag_group_defines_group() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let mut code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        let mut code_store = ClinicalCodeStore::new();
+
+        // A category and a single code
+        code_tree
+            .tag_group("cholera_or_typhoid", &["A00", "A01.0"])
+            .expect("Codes should be present");
+        let codes = code_tree
+            .codes_in_group(&format!("cholera_or_typhoid"), &mut code_store)
+            .expect("Group should exist");
+        let names: Vec<&String> = codes.iter().map(|code| name!(code, code_store)).collect();
+        assert_eq!(names, vec!["A00.0", "A00.1", "A00.9", "A01.0"]);
+
+        // Existing groups are not changed
+        assert_eq!(code_tree.group_sizes()["atrial_fib"], 6);
+
+        // Retagging replaces the group
+        code_tree
+            .tag_group("cholera_or_typhoid", &["A01.0"])
+            .expect("Codes should be present");
+        assert_eq!(code_tree.group_sizes()["cholera_or_typhoid"], 1);
+
+        assert!(code_tree.tag_group("unknown", &["Q99.9X"]).is_err());
+        assert!(!code_tree.groups().contains("unknown"));
+
+        // Groups can be defined using ranges of codes
+        code_tree
+            .tag_group("ihd", &["I20-I25"])
+            .expect("Range should contain codes");
+        let expected = code_tree
+            .expand_range("I20-I25", &mut code_store)
+            .expect("Range should be valid");
+        let codes = code_tree
+            .codes_in_group(&format!("ihd"), &mut code_store)
+            .expect("Group should exist");
+        assert_eq!(codes, expected);
+        assert!(code_tree.tag_group("unknown", &["I25-I20"]).is_err());
+    }
+
+    #[test]
+    fn check_ancestors_go_up_to_top_of_tree() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        let mut code_store = ClinicalCodeStore::new();
+
+        let ancestors = code_tree
+            .ancestors("I21.9", &mut code_store)
+            .expect("Code should be present");
+        let names: Vec<&String> = ancestors
+            .iter()
+            .map(|code| name!(code, code_store))
+            .collect();
+        assert_eq!(names, vec!["I21", "I20-I25", "IX", "ICD-10"]);
+        let docs = code_store
+            .clinical_code_from(&ancestors[0])
+            .expect("Code should be in the store")
+            .docs();
+        assert_eq!(docs, "Acute myocardial infarction");
+
+        let ancestors = code_tree
+            .ancestors("I21", &mut code_store)
+            .expect("Category should be present");
+        assert_eq!(ancestors.len(), 3);
+
+        // Chapters are not found using the index
+        let ancestors = code_tree
+            .ancestors("IX", &mut code_store)
+            .expect("Chapter should be present");
+        assert_eq!(name!(ancestors[0], code_store), "ICD-10");
+
+        assert!(code_tree.ancestors("I21.9X", &mut code_store).is_err());
+    }
+
+    #[test]
+    fn check_children_of_category() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        let mut code_store = ClinicalCodeStore::new();
+
+        let top_level = code_tree
+            .children_of("", &mut code_store)
+            .expect("Top level should be present");
+        assert_eq!(top_level.len(), 1);
+        assert_eq!(name!(top_level[0].0, code_store), "ICD-10");
+        assert!(top_level[0].1);
+
+        let children = code_tree
+            .children_of("I20-I25", &mut code_store)
+            .expect("Block should be present");
+        let names: Vec<&String> = children
+            .iter()
+            .map(|(code, _)| name!(code, code_store))
+            .collect();
+        assert_eq!(names, vec!["I20", "I21", "I22", "I23", "I24", "I25"]);
+        assert!(children.iter().all(|(_, is_category)| *is_category));
+
+        let children = code_tree
+            .children_of("I21", &mut code_store)
+            .expect("Category should be present");
+        assert!(children.iter().all(|(_, is_category)| !is_category));
+
+        let children = code_tree
+            .children_of("I21.9", &mut code_store)
+            .expect("Code should be present");
+        assert!(children.is_empty());
+
+        assert!(code_tree.children_of("I21.9X", &mut code_store).is_err());
+    }
+
+    #[test]
+    fn check_metadata_roundtrip() {
+        let yaml = r#"
+        categories:
+        - name: I21
+          docs: Acute myocardial infarction
+          index: I21
+          metadata:
+            source: Schnier 2017
+          categories:
+          - name: I21.0
+            docs: Acute transmural myocardial infarction of anterior wall
+            index: I210
+            metadata:
+              note: local addition
+              added: 2023-06-01
+        groups: [acs]
+        group_metadata:
+          acs:
+            source: Bezin 2015
+        "#;
+        let code_tree =
+            ClinicalCodeTree::from_reader(yaml.as_bytes()).expect("Failed to parse codes file");
+        assert_eq!(
+            code_tree.metadata_of("I21").expect("Category should be present")["source"],
+            "Schnier 2017"
+        );
+        assert_eq!(
+            code_tree.metadata_of("I21.0").expect("Code should be present")["added"],
+            "2023-06-01"
+        );
+        assert_eq!(
+            code_tree.docs_of("I21").expect("Category should be present"),
+            "Acute myocardial infarction"
+        );
+        assert!(code_tree.docs_of("I22").is_err());
+        assert_eq!(
+            code_tree.group_metadata("acs").expect("Group should exist")["source"],
+            "Bezin 2015"
+        );
+        assert!(code_tree.group_metadata("bleeding").is_err());
+
+        let mut written = Vec::new();
+        code_tree.to_writer(&mut written).expect("Failed to write codes file");
+        let roundtrip =
+            ClinicalCodeTree::from_reader(written.as_slice()).expect("Failed to parse codes file");
+        assert_eq!(code_tree, roundtrip);
+
+        // Trees without metadata are written without it
+        let code_tree = code_tree_example_1();
+        let mut written = Vec::new();
+        code_tree.to_writer(&mut written).expect("Failed to write codes file");
+        assert!(!String::from_utf8(written).unwrap().contains("metadata"));
+    }
+
+    #[test]
+    fn check_short_docs_and_synonyms() {
+        let yaml = r#"
+        categories:
+        - name: I21
+          docs: Acute myocardial infarction
+          index: I21
+          categories:
+          - name: I21.0
+            docs: Acute transmural myocardial infarction of anterior wall
+            short_docs: Anterior STEMI
+            synonyms: [Anterior wall heart attack]
+            index: I210
+          - name: I21.9
+            docs: Acute myocardial infarction, unspecified
+            index: I219
+        groups: []
+        "#;
+        let code_tree =
+            ClinicalCodeTree::from_reader(yaml.as_bytes()).expect("Failed to parse codes file");
+        let mut code_store = ClinicalCodeStore::new();
+
+        // The short docs and synonyms are available from the code store
+        let code_ref = code_tree
+            .find_exact(format!("I21.0"), &mut code_store)
+            .expect("Code should be present")
+            .code();
+        let code = code_store.clinical_code_from(&code_ref).unwrap();
+        assert_eq!(code.short_docs_or_docs(), "Anterior STEMI");
+        assert_eq!(code.synonyms(), &vec![format!("Anterior wall heart attack")]);
+        let code_ref = code_tree
+            .find_exact(format!("I21.9"), &mut code_store)
+            .expect("Code should be present")
+            .code();
+        let code = code_store.clinical_code_from(&code_ref).unwrap();
+        assert_eq!(code.short_docs(), None);
+        assert_eq!(code.short_docs_or_docs(), code.docs());
+
+        // Search matches the synonyms
+        let results = code_tree.search("heart attack", &mut code_store);
+        assert_eq!(results.len(), 1);
+        assert_eq!(name!(results[0], code_store), "I21.0");
+
+        let mut written = Vec::new();
+        code_tree.to_writer(&mut written).expect("Failed to write codes file");
+        let roundtrip =
+            ClinicalCodeTree::from_reader(written.as_slice()).expect("Failed to parse codes file");
+        assert_eq!(code_tree, roundtrip);
+    }
+
+    #[test]
+    fn check_search_matches_name_and_docs() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        let mut code_store = ClinicalCodeStore::new();
+
+        // Search is case insensitive and matches the docs
+        let results = code_tree.search("CHOLERA", &mut code_store);
+        let code_names: Vec<_> = results
+            .iter()
+            .map(|code_ref| name!(code_ref, code_store).clone())
+            .collect();
+        assert!(code_names.starts_with(&[
+            String::from("A00.0"),
+            String::from("A00.1"),
+            String::from("A00.9")
+        ]));
+        for code_ref in results {
+            let docs = code_store
+                .clinical_code_from(&code_ref)
+                .expect("Clinical code should be present")
+                .docs()
+                .to_lowercase();
+            assert!(docs.contains("cholera"));
+        }
+
+        // Search also matches the code name
+        let results = code_tree.search("i48.", &mut code_store);
+        assert_eq!(results.len(), 6);
+    }
+
     #[test]
     fn check_random_code_find_match_roundtrip() {
         let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -787,7 +2449,7 @@ mod tests {
         let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
 
         // Should execute without panic
-        let code_tree = ClinicalCodeTree::from_reader(f);
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
 
         let mut code_store = ClinicalCodeStore::new();
 
@@ -797,7 +2459,8 @@ mod tests {
         let mut rng = make_rng(222, "clinical_code_test_id");
         for _ in 0..1000 {
             let random_code = code_tree
-                .random_clinical_code(&mut rng, &mut code_store);
+                .random_clinical_code(&mut rng, &mut code_store)
+                .expect("Should be able to pick a valid code");
 
             let code = code_store
                 .clinical_code_from(&random_code)
@@ -808,7 +2471,7 @@ mod tests {
                 .find_exact(code.name().clone(), &mut code_store)
                 .expect("The code should be an exact match");
 
-            assert_eq!(random_code, found_code);
+            assert_eq!(random_code, found_code.code());
         }
     }
 }
@@ -0,0 +1,229 @@
+//! Compiled binary cache of a code tree
+//!
+//! Reading a large codes file (e.g. the full ICD-10 tree) from YAML
+//! and sorting it is slow, and this happens every time a parser is
+//! constructed. A tree can instead be compiled once to a binary cache
+//! file, which is much faster to load. The file starts with a short
+//! header (a magic string and a format version), followed by the tree
+//! encoded using bincode. The tree is written after it has been
+//! sorted, so no sorting is needed when the cache is loaded.
+//!
+//! bincode is not a self-describing format, so it cannot read the
+//! YAML representation of the tree (which skips empty fields and
+//! writes the index untagged). The tree is converted to the plain
+//! structures below before it is encoded.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Categories, ClinicalCodeTree, CodeNormalisation, Index};
+use crate::error::HbrError;
+
+/// The first bytes of every cache file
+const CACHE_MAGIC: &[u8; 8] = b"HBRCODES";
+
+/// The version of the cache format. This must be increased whenever
+/// the structures below change, so that old cache files are rejected
+/// instead of being decoded incorrectly.
+const CACHE_VERSION: u32 = 2;
+
+/// Encoding of an Index in the cache, as the start and (for a
+/// range) the end of the index. Use with #[serde(with = ...)].
+pub(super) mod cached_index {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Index;
+
+    pub fn serialize<S: Serializer>(index: &Index, serializer: S) -> Result<S::Ok, S::Error> {
+        match index {
+            Index::Single(start) => (start, None::<&String>).serialize(serializer),
+            Index::Dual(start, end) => (start, Some(end)).serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Index, D::Error> {
+        let (start, end) = <(String, Option<String>)>::deserialize(deserializer)?;
+        Ok(match end {
+            Some(end) => Index::Dual(start, end),
+            None => Index::Single(start),
+        })
+    }
+}
+
+/// A category in the form written to the cache
+#[derive(Serialize, Deserialize)]
+struct CachedCategories {
+    name: String,
+    docs: String,
+    short_docs: Option<String>,
+    synonyms: Vec<String>,
+    #[serde(with = "cached_index")]
+    index: Index,
+    categories: Option<Vec<CachedCategories>>,
+    exclude: Option<HashSet<String>>,
+    metadata: HashMap<String, String>,
+}
+
+/// A code tree in the form written to the cache
+#[derive(Serialize, Deserialize)]
+struct CachedTree {
+    categories: Vec<CachedCategories>,
+    groups: HashSet<String>,
+    normalisation: CodeNormalisation,
+    group_metadata: HashMap<String, HashMap<String, String>>,
+}
+
+impl From<&Categories> for CachedCategories {
+    fn from(category: &Categories) -> Self {
+        Self {
+            name: category.name.clone(),
+            docs: category.docs.clone(),
+            short_docs: category.short_docs.clone(),
+            synonyms: category.synonyms.clone(),
+            index: category.index.clone(),
+            categories: category
+                .categories
+                .as_ref()
+                .map(|categories| categories.iter().map(CachedCategories::from).collect()),
+            exclude: category.exclude.clone(),
+            metadata: category.metadata.clone(),
+        }
+    }
+}
+
+impl From<CachedCategories> for Categories {
+    fn from(category: CachedCategories) -> Self {
+        Self {
+            name: category.name,
+            docs: category.docs,
+            short_docs: category.short_docs,
+            synonyms: category.synonyms,
+            index: category.index,
+            categories: category
+                .categories
+                .map(|categories| categories.into_iter().map(Categories::from).collect()),
+            exclude: category.exclude,
+            metadata: category.metadata,
+        }
+    }
+}
+
+impl ClinicalCodeTree {
+    /// Write the tree to a binary cache file, which can be loaded
+    /// much faster than the codes file using from_cache
+    ///
+    /// The exact index (see with_exact_index) is not written to the
+    /// cache. Returns an error if the file cannot be written.
+    pub fn compile_to_cache<P: AsRef<Path>>(&self, path: P) -> Result<(), HbrError> {
+        let cached = CachedTree {
+            categories: self.categories.iter().map(CachedCategories::from).collect(),
+            groups: self.groups.clone(),
+            normalisation: self.normalisation,
+            group_metadata: self.group_metadata.clone(),
+        };
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(CACHE_MAGIC)?;
+        writer.write_all(&CACHE_VERSION.to_le_bytes())?;
+        bincode::serialize_into(&mut writer, &cached)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read a tree from a binary cache file written by
+    /// compile_to_cache
+    ///
+    /// Returns HbrError::InvalidCodeTreeCache if the file is not
+    /// a cache file, or was written by a different version of this
+    /// crate (in which case the cache should be compiled again from
+    /// the codes file).
+    pub fn from_cache<P: AsRef<Path>>(path: P) -> Result<Self, HbrError> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 8];
+        let mut version = [0; 4];
+        reader
+            .read_exact(&mut magic)
+            .and_then(|_| reader.read_exact(&mut version))
+            .map_err(|_| HbrError::InvalidCodeTreeCache(String::from("file is too short")))?;
+        if &magic != CACHE_MAGIC {
+            return Err(HbrError::InvalidCodeTreeCache(String::from(
+                "not a code tree cache file",
+            )));
+        }
+        let version = u32::from_le_bytes(version);
+        if version != CACHE_VERSION {
+            return Err(HbrError::InvalidCodeTreeCache(format!(
+                "cache format version {version} (expected {CACHE_VERSION})"
+            )));
+        }
+
+        let cached: CachedTree = bincode::deserialize_from(reader)?;
+        Ok(Self {
+            categories: cached
+                .categories
+                .into_iter()
+                .map(Categories::from)
+                .collect(),
+            groups: cached.groups,
+            normalisation: cached.normalisation,
+            group_metadata: cached.group_metadata,
+            exact_index: None,
+        })
+    }
+}
+
+/// Tests for the binary cache
+#[cfg(test)]
+mod tests {
+
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn check_cache_roundtrip() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let mut code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        code_tree
+            .tag_group("acs", &["I20-I25"])
+            .expect("Failed to tag group");
+
+        let cache_path = std::env::temp_dir().join("rust_hbr_check_cache_roundtrip.bin");
+        code_tree
+            .compile_to_cache(&cache_path)
+            .expect("Failed to write cache");
+        let cached = ClinicalCodeTree::from_cache(&cache_path);
+        std::fs::remove_file(&cache_path).expect("Failed to remove cache");
+
+        assert_eq!(cached.expect("Failed to read cache"), code_tree);
+    }
+
+    #[test]
+    fn check_invalid_cache_is_rejected() {
+        let cache_path = std::env::temp_dir().join("rust_hbr_check_invalid_cache.bin");
+
+        // Not a cache file
+        std::fs::write(&cache_path, "categories: []").expect("Failed to write file");
+        assert!(matches!(
+            ClinicalCodeTree::from_cache(&cache_path),
+            Err(HbrError::InvalidCodeTreeCache(_))
+        ));
+
+        // Cache file from a different version
+        let mut contents = CACHE_MAGIC.to_vec();
+        contents.extend((CACHE_VERSION + 1).to_le_bytes());
+        std::fs::write(&cache_path, contents).expect("Failed to write file");
+        let result = ClinicalCodeTree::from_cache(&cache_path);
+        std::fs::remove_file(&cache_path).expect("Failed to remove file");
+        assert!(matches!(result, Err(HbrError::InvalidCodeTreeCache(_))));
+    }
+}
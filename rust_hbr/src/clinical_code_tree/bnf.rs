@@ -0,0 +1,198 @@
+//! Medication code trees based on the BNF hierarchy
+//!
+//! Prescriptions in England are coded using BNF codes, where each
+//! level of the British National Formulary hierarchy is a prefix of
+//! the full code:
+//! * chapter (2 characters, e.g. 02 Cardiovascular system);
+//! * section (4, e.g. 0208 Anticoagulants and protamine);
+//! * paragraph (6, e.g. 020802 Oral anticoagulants);
+//! * sub-paragraph (7, e.g. 0208020);
+//! * chemical substance (9, e.g. 0208020V0 Warfarin sodium);
+//! * product (11) and presentation (15, e.g. 0208020V0AAAAAA).
+//!
+//! The tree is read from the NHSBSA "BNF Code Information" CSV file,
+//! which has one row per presentation with the name and code of every
+//! level. Presentations are the codes (leaves) of the tree. Groups such
+//! as oral_anticoagulant can then be defined using tag_group with the
+//! relevant sub-paragraphs or chemical substances.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+
+use super::{
+    sort_categories_list_in_place, Categories, ClinicalCodeTree, CodeNormalisation, Index,
+};
+use crate::error::HbrError;
+
+/// The (name, code) column pairs for each level of the hierarchy,
+/// from the top (chapter) to the bottom (presentation)
+const BNF_LEVELS: [(&str, &str); 7] = [
+    ("BNF Chapter", "BNF Chapter Code"),
+    ("BNF Section", "BNF Section Code"),
+    ("BNF Paragraph", "BNF Paragraph Code"),
+    ("BNF Subparagraph", "BNF Subparagraph Code"),
+    ("BNF Chemical Substance", "BNF Chemical Substance Code"),
+    ("BNF Product", "BNF Product Code"),
+    ("BNF Presentation", "BNF Presentation Code"),
+];
+
+/// A node of the BNF hierarchy while the tree is being built,
+/// with the sub-nodes keyed by their code
+#[derive(Default)]
+struct BnfNode {
+    name: String,
+    children: BTreeMap<String, BnfNode>,
+}
+
+/// Convert a node (with the given code) into a category
+fn make_category(code: &str, node: BnfNode) -> Categories {
+    let categories = if node.children.is_empty() {
+        None
+    } else {
+        Some(
+            node.children
+                .into_iter()
+                .map(|(code, child)| make_category(&code, child))
+                .collect(),
+        )
+    };
+    Categories {
+        name: code.to_string(),
+        docs: node.name,
+        short_docs: None,
+        synonyms: Vec::new(),
+        index: Index::make_single(code),
+        categories,
+        exclude: None,
+        metadata: HashMap::new(),
+    }
+}
+
+impl ClinicalCodeTree {
+    /// Build a medication code tree from the NHSBSA BNF code
+    /// information CSV file
+    ///
+    /// Each category is named by its BNF code, and its description is
+    /// the BNF name. All the chapters are placed under a single root
+    /// category named BNF. Levels that repeat the code of the level
+    /// above (as happens for some appliance chapters) are skipped. The
+    /// tree has no groups; use tag_group to define them.
+    ///
+    /// Returns an error if the file cannot be read, is missing any of
+    /// the BNF columns, or contains no codes.
+    pub fn from_bnf_reader<R: Read>(reader: R) -> Result<Self, HbrError> {
+        let mut csv_reader = csv::Reader::from_reader(reader);
+        let headers = csv_reader.headers()?.clone();
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|column| column.trim() == name)
+                .ok_or(HbrError::InvalidCodeList(format!("missing {name} column")))
+        };
+        let mut levels = Vec::new();
+        for (name_column, code_column) in BNF_LEVELS {
+            levels.push((column(name_column)?, column(code_column)?));
+        }
+
+        let mut root = BnfNode::default();
+        for record in csv_reader.records() {
+            let record = record?;
+            let mut node = &mut root;
+            let mut parent_code = "";
+            for (name_column, code_column) in levels.iter() {
+                let code = record.get(*code_column).unwrap_or_default().trim();
+                if code.is_empty() || code == parent_code {
+                    continue;
+                }
+                let name = record.get(*name_column).unwrap_or_default().trim();
+                node = node.children.entry(code.to_string()).or_default();
+                if node.name.is_empty() {
+                    node.name = name.to_string();
+                }
+                parent_code = code;
+            }
+        }
+
+        let first = root.children.keys().next().cloned();
+        let last = root.children.keys().last().cloned();
+        let (first, last) = match (first, last) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(HbrError::InvalidCodeList(String::from("no codes found"))),
+        };
+        let chapters = root
+            .children
+            .into_iter()
+            .map(|(code, node)| make_category(&code, node))
+            .collect();
+        let mut categories = vec![Categories {
+            name: String::from("BNF"),
+            docs: String::from("British National Formulary"),
+            short_docs: None,
+            synonyms: Vec::new(),
+            index: Index::make_dual(&first, &last),
+            categories: Some(chapters),
+            exclude: None,
+            metadata: HashMap::new(),
+        }];
+        sort_categories_list_in_place(&mut categories);
+        Ok(Self {
+            categories,
+            groups: Default::default(),
+            normalisation: CodeNormalisation::Standard,
+            group_metadata: HashMap::new(),
+            exact_index: None,
+        })
+    }
+}
+
+/// Tests for the BNF medication tree
+///
+/// The CSV file is a small extract of the NHSBSA file, with the
+/// same columns.
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::clinical_code::ClinicalCodeStore;
+    use crate::name;
+
+    const BNF_CSV: &str = "BNF Chapter,BNF Chapter Code,BNF Section,BNF Section Code,BNF Paragraph,BNF Paragraph Code,BNF Subparagraph,BNF Subparagraph Code,BNF Chemical Substance,BNF Chemical Substance Code,BNF Product,BNF Product Code,BNF Presentation,BNF Presentation Code\n\
+        Cardiovascular System,02,Anticoagulants And Protamine,0208,Oral anticoagulants,020802,Oral anticoagulants,0208020,Warfarin sodium,0208020V0,Warfarin sodium,0208020V0AA,Warfarin 1mg tablets,0208020V0AAAAAA\n\
+        Cardiovascular System,02,Anticoagulants And Protamine,0208,Oral anticoagulants,020802,Oral anticoagulants,0208020,Warfarin sodium,0208020V0,Warfarin sodium,0208020V0AA,Warfarin 3mg tablets,0208020V0AAABAB\n\
+        Cardiovascular System,02,Anticoagulants And Protamine,0208,Oral anticoagulants,020802,Oral anticoagulants,0208020,Apixaban,0208020Z0,Eliquis,0208020Z0BB,Eliquis 5mg tablets,0208020Z0BBAAAB\n\
+        Cardiovascular System,02,Antiplatelet Drugs,0209,Antiplatelet Drugs,020900,Antiplatelet Drugs,0209000,Clopidogrel,0209000C0,Clopidogrel,0209000C0AA,Clopidogrel 75mg tablets,0209000C0AAAAAA\n\
+        Cardiovascular System,02,Antiplatelet Drugs,0209,Antiplatelet Drugs,020900,Antiplatelet Drugs,0209000,Ticagrelor,0209000V0,Brilique,0209000V0BB,Brilique 90mg tablets,0209000V0BBAAAA\n\
+        Gastro-Intestinal System,01,Antisecretory Drugs+Mucosal Protectants,0103,Proton pump inhibitors,010305,Proton pump inhibitors,0103050,Omeprazole,0103050P0,Omeprazole,0103050P0AA,Omeprazole 20mg gastro-resistant capsules,0103050P0AAAAAA\n";
+
+    #[test]
+    fn check_bnf_file_builds_tree() {
+        let mut code_tree =
+            ClinicalCodeTree::from_bnf_reader(BNF_CSV.as_bytes()).expect("Should build code tree");
+        let mut code_store = ClinicalCodeStore::new();
+
+        let code_match = code_tree
+            .find_exact(format!("0208020Z0BBAAAB"), &mut code_store)
+            .expect("Presentation should be present");
+        assert_eq!(name!(code_match.code(), code_store), "0208020Z0BBAAAB");
+
+        // Chemical substances are categories
+        assert!(code_tree.find_exact(format!("0208020V0"), &mut code_store).is_err());
+
+        // Define groups of medications
+        code_tree
+            .tag_group("oral_anticoagulant", &["0208020"])
+            .expect("Sub-paragraph should be present");
+        code_tree
+            .tag_group("p2y12_inhibitor", &["0209000C0", "0209000V0"])
+            .expect("Chemical substances should be present");
+        let sizes = code_tree.group_sizes();
+        assert_eq!(sizes["oral_anticoagulant"], 3);
+        assert_eq!(sizes["p2y12_inhibitor"], 2);
+
+        let code_match = code_tree
+            .find_exact(format!("0209000V0BBAAAA"), &mut code_store)
+            .expect("Presentation should be present");
+        assert!(code_match.in_group("p2y12_inhibitor"));
+        assert!(!code_match.in_group("oral_anticoagulant"));
+    }
+}
@@ -0,0 +1,151 @@
+//! Memoised code parsing
+//!
+//! Raw data (e.g. the diagnosis columns in HES) contains the same
+//! code strings many times over, often with the same formatting
+//! quirks. The CodeParserCache stores the result of find_exact for
+//! each distinct input string, so that each one is only looked up in
+//! the tree once. This is the caching recommended in the find_exact
+//! docs, in a form that can be shared by the language bindings.
+
+use std::collections::HashMap;
+
+use super::{ClinicalCodeTree, CodeMatch};
+use crate::clinical_code::{ClinicalCodeRef, ClinicalCodeStore};
+use crate::error::HbrError;
+
+/// A code tree together with the store holding the parsed codes
+/// and a map from each raw input string to the result of parsing it
+#[derive(Debug)]
+pub struct CodeParserCache {
+    code_tree: ClinicalCodeTree,
+    code_store: ClinicalCodeStore,
+    /// Map from the raw input string to the match (or None
+    /// if the code was not found)
+    matches: HashMap<String, Option<CodeMatch>>,
+}
+
+impl CodeParserCache {
+    /// Make an empty cache for parsing codes in the tree
+    pub fn new(code_tree: ClinicalCodeTree) -> Self {
+        Self {
+            code_tree,
+            code_store: ClinicalCodeStore::new(),
+            matches: HashMap::new(),
+        }
+    }
+
+    /// Find an exact match for the code in the tree (see
+    /// ClinicalCodeTree::find_exact), using the cached result if the
+    /// same string has been parsed before. Returns
+    /// HbrError::CodeNotFound if the code is not in the tree.
+    pub fn find_exact(&mut self, code: &str) -> Result<&CodeMatch, HbrError> {
+        if !self.matches.contains_key(code) {
+            let code_match = self
+                .code_tree
+                .find_exact(code.to_string(), &mut self.code_store)
+                .ok();
+            self.matches.insert(code.to_string(), code_match);
+        }
+        self.matches[code]
+            .as_ref()
+            .ok_or_else(|| HbrError::CodeNotFound(code.to_string()))
+    }
+
+    /// Get all the codes in a group (see ClinicalCodeTree::codes_in_group),
+    /// adding them to the code store
+    pub fn codes_in_group(&mut self, group: &String) -> Result<Vec<ClinicalCodeRef>, HbrError> {
+        self.code_tree.codes_in_group(group, &mut self.code_store)
+    }
+
+    /// Get the code tree used for parsing
+    pub fn code_tree(&self) -> &ClinicalCodeTree {
+        &self.code_tree
+    }
+
+    /// Get the store containing the matched codes, for looking
+    /// up the name and description of a match
+    pub fn code_store(&self) -> &ClinicalCodeStore {
+        &self.code_store
+    }
+
+    /// Get the store mutably, for calling other functions of the
+    /// code tree that add codes to the store
+    pub fn code_store_mut(&mut self) -> &mut ClinicalCodeStore {
+        &mut self.code_store
+    }
+
+    /// Get the code tree together with the mutable store, for calling
+    /// functions of the tree that add codes to the store (e.g.
+    /// ClinicalCodeTree::ancestors)
+    pub fn tree_and_store(&mut self) -> (&ClinicalCodeTree, &mut ClinicalCodeStore) {
+        (&self.code_tree, &mut self.code_store)
+    }
+
+    /// Get the number of distinct input strings in the cache
+    pub fn num_cached(&self) -> usize {
+        self.matches.len()
+    }
+
+    /// Remove all the cached results (the code store is kept, so
+    /// code references from earlier matches stay valid)
+    pub fn clear(&mut self) {
+        self.matches.clear();
+    }
+}
+
+/// Tests for the code parser cache
+#[cfg(test)]
+mod tests {
+
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::name;
+
+    #[test]
+    fn check_cache_gives_same_result_as_tree() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        let mut cache = CodeParserCache::new(code_tree);
+
+        for _ in 0..2 {
+            let code_ref = cache
+                .find_exact("I48.0")
+                .expect("Code should be present")
+                .code();
+            assert_eq!(name!(code_ref, cache.code_store()), "I48.0");
+            assert!(cache.find_exact("I48.0").unwrap().in_group("atrial_fib"));
+            assert!(matches!(
+                cache.find_exact("I21.9X"),
+                Err(HbrError::CodeNotFound(_))
+            ));
+        }
+        assert_eq!(cache.num_cached(), 2);
+
+        // Different formatting of the same code is cached separately,
+        // but gives the same code reference
+        let first_ref = cache
+            .find_exact("I48.0")
+            .expect("Code should be present")
+            .code();
+        let code_ref = cache
+            .find_exact("i480")
+            .expect("Code should be present")
+            .code();
+        assert_eq!(code_ref, first_ref);
+        assert_eq!(cache.num_cached(), 3);
+
+        cache.clear();
+        assert_eq!(cache.num_cached(), 0);
+
+        let codes = cache
+            .codes_in_group(&format!("atrial_fib"))
+            .expect("Group should exist");
+        assert!(codes.contains(&code_ref));
+    }
+}
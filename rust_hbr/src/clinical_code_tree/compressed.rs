@@ -0,0 +1,109 @@
+//! Reading compressed codes files
+//!
+//! The full codes files (e.g. ICD-10 with all the groups) are large,
+//! and are usually stored compressed. The compression is detected
+//! from the first bytes of the file (the magic bytes), so that
+//! from_reader and from_json_reader can read gzip (.gz), zstd (.zst)
+//! or uncompressed files without being told which one it is.
+
+use std::io::{BufRead, BufReader, Read};
+
+use flate2::bufread::GzDecoder;
+use ruzstd::frame_decoder::FrameDecoder;
+use ruzstd::streaming_decoder::StreamingDecoder;
+
+use crate::error::HbrError;
+
+/// The first bytes of a gzip file
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// The first bytes of a zstd frame
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// A reader that decompresses the underlying reader if necessary. The
+/// zstd decoder holds its whole window buffer, so it is boxed to keep
+/// the plain and gzip readers small.
+pub(super) enum MaybeCompressed<R: Read> {
+    Plain(BufReader<R>),
+    Gzip(GzDecoder<BufReader<R>>),
+    Zstd(Box<StreamingDecoder<BufReader<R>, FrameDecoder>>),
+}
+
+impl<R: Read> MaybeCompressed<R> {
+    /// Wrap the reader in a decompressor if it starts with the
+    /// magic bytes of gzip or zstd
+    pub(super) fn new(reader: R) -> Result<Self, HbrError> {
+        let mut reader = BufReader::new(reader);
+        let start = reader.fill_buf()?;
+        if start.starts_with(GZIP_MAGIC) {
+            Ok(Self::Gzip(GzDecoder::new(reader)))
+        } else if start.starts_with(ZSTD_MAGIC) {
+            let decoder = StreamingDecoder::new(reader).map_err(|err| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string())
+            })?;
+            Ok(Self::Zstd(Box::new(decoder)))
+        } else {
+            Ok(Self::Plain(reader))
+        }
+    }
+}
+
+impl<R: Read> Read for MaybeCompressed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Plain(reader) => reader.read(buf),
+            Self::Gzip(reader) => reader.read(buf),
+            Self::Zstd(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Tests for reading compressed files
+#[cfg(test)]
+mod tests {
+
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use crate::clinical_code_tree::ClinicalCodeTree;
+
+    fn icd10_yaml() -> Vec<u8> {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+        std::fs::read(file_path).expect("Failed to read icd10 file")
+    }
+
+    #[test]
+    fn check_gzipped_codes_file() {
+        let yaml = icd10_yaml();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&yaml).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let code_tree =
+            ClinicalCodeTree::from_reader(yaml.as_slice()).expect("Failed to parse codes file");
+        let from_compressed = ClinicalCodeTree::from_reader(compressed.as_slice())
+            .expect("Failed to parse compressed codes file");
+        assert_eq!(from_compressed, code_tree);
+    }
+
+    #[test]
+    fn check_zstd_codes_file() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml.zst");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open compressed icd10 file");
+        let from_compressed =
+            ClinicalCodeTree::from_reader(f).expect("Failed to parse compressed codes file");
+        let code_tree = ClinicalCodeTree::from_reader(icd10_yaml().as_slice())
+            .expect("Failed to parse codes file");
+        assert_eq!(from_compressed, code_tree);
+    }
+}
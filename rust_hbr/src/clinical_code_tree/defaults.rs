@@ -0,0 +1,55 @@
+//! Code trees embedded in the library
+//!
+//! With the default-trees feature, the ICD-10 and OPCS-4 codes files
+//! maintained in scripts/codes_files (including all the code groups)
+//! are compiled into the library, so that the Python and R packages
+//! work without the user having to locate the codes files on disk.
+
+use super::ClinicalCodeTree;
+use crate::error::HbrError;
+
+/// The ICD-10 codes file, including the code groups
+const ICD10_CODES_FILE: &[u8] = include_bytes!("../../../scripts/codes_files/icd10.yaml");
+
+/// The OPCS-4 codes file, including the code groups
+const OPCS4_CODES_FILE: &[u8] = include_bytes!("../../../scripts/codes_files/opcs4.yaml");
+
+impl ClinicalCodeTree {
+    /// Get the ICD-10 code tree embedded in the library
+    ///
+    /// The codes file is parsed each time this function is called,
+    /// so keep the result instead of calling it repeatedly. Returns an
+    /// error if the embedded file cannot be parsed (which is a bug).
+    pub fn icd10_default() -> Result<Self, HbrError> {
+        Self::from_reader(ICD10_CODES_FILE)
+    }
+
+    /// Get the OPCS-4 code tree embedded in the library (see
+    /// icd10_default)
+    pub fn opcs4_default() -> Result<Self, HbrError> {
+        Self::from_reader(OPCS4_CODES_FILE)
+    }
+}
+
+/// Tests for the embedded code trees
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::clinical_code::ClinicalCodeStore;
+
+    #[test]
+    fn check_default_trees_contain_codes() {
+        let mut code_store = ClinicalCodeStore::new();
+
+        let icd10 = ClinicalCodeTree::icd10_default().expect("Failed to parse ICD-10 codes");
+        let code_match = icd10
+            .find_exact(format!("I21.9"), &mut code_store)
+            .expect("Code should be present");
+        assert!(code_match.in_group("mi_schnier"));
+
+        let opcs4 = ClinicalCodeTree::opcs4_default().expect("Failed to parse OPCS-4 codes");
+        assert!(opcs4.groups().contains("pci"));
+        assert!(opcs4.find_exact(format!("K75.1"), &mut code_store).is_ok());
+    }
+}
@@ -0,0 +1,247 @@
+//! Comparison of two code trees
+//!
+//! Used to review the changes made to a codes file (for example
+//! in the codes editor) before committing them. Codes are matched
+//! between the two trees by their normalised name, so a change in
+//! the position of a code in the tree (or in its index) is not
+//! reported, only changes that affect how codes are parsed and
+//! grouped.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{normalise_code, visit_codes, ClinicalCodeTree};
+
+/// A code whose description differs between two trees
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocsChange {
+    /// The name of the code (as written in the new tree)
+    pub code: String,
+    /// The description in the old tree
+    pub old_docs: String,
+    /// The description in the new tree
+    pub new_docs: String,
+}
+
+/// The changes to the codes in one group between two trees
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupChange {
+    /// The name of the group
+    pub group: String,
+    /// Codes in the group in the new tree but not the old tree
+    pub added_codes: Vec<String>,
+    /// Codes in the group in the old tree but not the new tree
+    pub removed_codes: Vec<String>,
+}
+
+/// The differences between two code trees (see ClinicalCodeTree::diff)
+///
+/// All the lists are sorted (codes by normalised name), so the
+/// same pair of trees always gives the same diff.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodeTreeDiff {
+    /// Codes in the new tree that are not in the old tree
+    pub added_codes: Vec<String>,
+    /// Codes in the old tree that are not in the new tree
+    pub removed_codes: Vec<String>,
+    /// Codes in both trees whose description has changed
+    pub changed_docs: Vec<DocsChange>,
+    /// Groups defined in the new tree but not the old tree
+    pub added_groups: Vec<String>,
+    /// Groups defined in the old tree but not the new tree
+    pub removed_groups: Vec<String>,
+    /// Changes to the codes in each group (in either tree) whose
+    /// codes have changed
+    pub group_changes: Vec<GroupChange>,
+}
+
+impl CodeTreeDiff {
+    /// Returns true if there are no differences between the trees
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The information about a code needed for comparing trees
+struct CodeInfo {
+    name: String,
+    docs: String,
+    groups: BTreeSet<String>,
+}
+
+/// Get all the codes in a tree, keyed by normalised name
+fn codes_by_name(tree: &ClinicalCodeTree) -> BTreeMap<String, CodeInfo> {
+    let mut codes = BTreeMap::new();
+    visit_codes(&tree.categories, &tree.groups, &mut |code, groups| {
+        let info = CodeInfo {
+            name: code.name().clone(),
+            docs: code.docs().clone(),
+            groups: groups.iter().cloned().collect(),
+        };
+        codes.insert(normalise_code(code.name().clone()), info);
+    });
+    codes
+}
+
+/// Get the names of the codes in a group, keyed by normalised name
+fn group_members<'a>(
+    codes: &'a BTreeMap<String, CodeInfo>,
+    group: &String,
+) -> BTreeMap<&'a String, &'a String> {
+    codes
+        .iter()
+        .filter(|(_, info)| info.groups.contains(group))
+        .map(|(key, info)| (key, &info.name))
+        .collect()
+}
+
+impl ClinicalCodeTree {
+    /// Compare this (old) tree with a new tree
+    ///
+    /// Reports the codes that have been added or removed, the codes
+    /// whose description has changed, the groups that have been added
+    /// or removed, and the codes that have been added to or removed
+    /// from each group. A code that is added to (or removed from) the
+    /// tree is also listed in the changes of any group containing it.
+    pub fn diff(&self, new: &ClinicalCodeTree) -> CodeTreeDiff {
+        let old_codes = codes_by_name(self);
+        let new_codes = codes_by_name(new);
+
+        let mut diff = CodeTreeDiff::default();
+        for (key, info) in old_codes.iter() {
+            match new_codes.get(key) {
+                None => diff.removed_codes.push(info.name.clone()),
+                Some(new_info) if new_info.docs != info.docs => {
+                    diff.changed_docs.push(DocsChange {
+                        code: new_info.name.clone(),
+                        old_docs: info.docs.clone(),
+                        new_docs: new_info.docs.clone(),
+                    })
+                }
+                Some(_) => (),
+            }
+        }
+        for (key, info) in new_codes.iter() {
+            if !old_codes.contains_key(key) {
+                diff.added_codes.push(info.name.clone());
+            }
+        }
+
+        let old_groups: BTreeSet<&String> = self.groups.iter().collect();
+        let new_groups: BTreeSet<&String> = new.groups.iter().collect();
+        diff.added_groups = new_groups
+            .difference(&old_groups)
+            .map(|g| g.to_string())
+            .collect();
+        diff.removed_groups = old_groups
+            .difference(&new_groups)
+            .map(|g| g.to_string())
+            .collect();
+
+        for group in old_groups.union(&new_groups) {
+            let old_members = group_members(&old_codes, group);
+            let new_members = group_members(&new_codes, group);
+            let added_codes: Vec<String> = new_members
+                .iter()
+                .filter(|(key, _)| !old_members.contains_key(*key))
+                .map(|(_, name)| name.to_string())
+                .collect();
+            let removed_codes: Vec<String> = old_members
+                .iter()
+                .filter(|(key, _)| !new_members.contains_key(*key))
+                .map(|(_, name)| name.to_string())
+                .collect();
+            if !added_codes.is_empty() || !removed_codes.is_empty() {
+                diff.group_changes.push(GroupChange {
+                    group: group.to_string(),
+                    added_codes,
+                    removed_codes,
+                });
+            }
+        }
+        diff
+    }
+}
+
+/// Tests for comparing code trees
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const OLD_TREE: &str = r#"
+    categories:
+    - name: I21
+      docs: Acute myocardial infarction
+      index: I21
+      categories:
+      - name: I21.0
+        docs: Acute transmural myocardial infarction of anterior wall
+        index: I210
+      - name: I21.9
+        docs: Acute myocardial infarction, unspecified
+        index: I219
+        exclude: [acs_stemi]
+    groups: [acs_stemi, mi]
+    "#;
+
+    const NEW_TREE: &str = r#"
+    categories:
+    - name: I21
+      docs: Acute myocardial infarction
+      index: I21
+      categories:
+      - name: I21.0
+        docs: STEMI of anterior wall
+        index: I210
+        exclude: [acs_stemi]
+      - name: I21.4
+        docs: Acute subendocardial myocardial infarction
+        index: I214
+    groups: [acs_stemi, acs_nstemi]
+    "#;
+
+    #[test]
+    fn check_identical_trees_have_empty_diff() {
+        let tree =
+            ClinicalCodeTree::from_reader(OLD_TREE.as_bytes()).expect("Failed to parse codes file");
+        assert!(tree.diff(&tree).is_empty());
+    }
+
+    #[test]
+    fn check_diff_reports_changes() {
+        let old =
+            ClinicalCodeTree::from_reader(OLD_TREE.as_bytes()).expect("Failed to parse codes file");
+        let new =
+            ClinicalCodeTree::from_reader(NEW_TREE.as_bytes()).expect("Failed to parse codes file");
+        let diff = old.diff(&new);
+
+        assert_eq!(diff.added_codes, vec!["I21.4"]);
+        assert_eq!(diff.removed_codes, vec!["I21.9"]);
+        assert_eq!(
+            diff.changed_docs,
+            vec![DocsChange {
+                code: format!("I21.0"),
+                old_docs: format!("Acute transmural myocardial infarction of anterior wall"),
+                new_docs: format!("STEMI of anterior wall"),
+            }]
+        );
+        assert_eq!(diff.added_groups, vec!["acs_nstemi"]);
+        assert_eq!(diff.removed_groups, vec!["mi"]);
+
+        let changes: BTreeMap<&String, &GroupChange> = diff
+            .group_changes
+            .iter()
+            .map(|change| (&change.group, change))
+            .collect();
+        assert_eq!(changes[&format!("acs_stemi")].added_codes, vec!["I21.4"]);
+        assert_eq!(changes[&format!("acs_stemi")].removed_codes, vec!["I21.0"]);
+        assert_eq!(
+            changes[&format!("acs_nstemi")].added_codes,
+            vec!["I21.0", "I21.4"]
+        );
+        assert_eq!(
+            changes[&format!("mi")].removed_codes,
+            vec!["I21.0", "I21.9"]
+        );
+    }
+}
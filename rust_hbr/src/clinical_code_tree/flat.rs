@@ -0,0 +1,352 @@
+//! Build a clinical code tree from a flat list of codes
+//!
+//! NHS TRUD distributes ICD-10 (5th edition) and OPCS-4 (4.10) as
+//! flat text files, with one code and description per line, instead
+//! of as a hierarchy. This module reconstructs the hierarchy from
+//! the codes themselves: a code is placed under the longest other
+//! code in the list that is a prefix of it (e.g. A00.1 goes under
+//! A00). Codes with no parent are collected into one category per
+//! starting letter, and all the letters are placed under a single
+//! root category, mirroring the structure of the YAML codes files.
+//!
+//! The resulting tree has no groups. Groups can be added by editing
+//! the tree and writing it back out with to_writer().
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Read};
+
+use super::{
+    normalise_code, sort_categories_list_in_place, Categories, ClinicalCodeTree, CodeNormalisation,
+    Index,
+};
+use crate::error::HbrError;
+
+/// The layout of a flat code list file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlatCodeListFormat {
+    /// The ICD-10 5th edition codes and titles file, which is
+    /// tab-separated with a header row. The code is read from
+    /// the CODE column and the description from the DESCRIPTION
+    /// column.
+    Icd10Trud,
+    /// The OPCS-4 codes and titles file, which has no header. Each
+    /// line contains the code followed by the description, either
+    /// separated by a tab or (in the fixed-width files) by spaces.
+    Opcs4Trud,
+    /// The ICD-9-CM diagnosis codes file distributed by CMS (e.g.
+    /// CMS32_DESC_LONG_DX.txt), which has the same layout as
+    /// Opcs4Trud. Codes are written without a dot (e.g. 0010), and
+    /// the dot is added back to the code name (e.g. 001.0).
+    Icd9Cms,
+}
+
+impl FlatCodeListFormat {
+    /// The name of the root category of the tree
+    fn root_name(&self) -> &'static str {
+        match self {
+            Self::Icd10Trud => "ICD-10",
+            Self::Opcs4Trud => "OPCS-4",
+            Self::Icd9Cms => "ICD-9",
+        }
+    }
+}
+
+/// Add the dot to an ICD-9 code written without one. The dot
+/// comes after the first three characters, or after the first
+/// four for the E (external cause) codes.
+fn icd9_code_with_dot(code: &str) -> String {
+    let split = if code.starts_with('E') { 4 } else { 3 };
+    match (code.get(..split), code.get(split..)) {
+        (Some(category), Some(rest)) if !rest.is_empty() && !code.contains('.') => {
+            format!("{category}.{rest}")
+        }
+        _ => code.to_string(),
+    }
+}
+
+/// Split a line into the code and the description, using
+/// the tab if there is one, or otherwise the first run of
+/// whitespace (for fixed-width files).
+fn split_code_and_docs(line: &str) -> Option<(&str, &str)> {
+    let (code, docs) = match line.split_once('\t') {
+        Some(parts) => parts,
+        None => line.trim().split_once(char::is_whitespace)?,
+    };
+    Some((code.trim(), docs.trim()))
+}
+
+/// Read the (code, description) pairs from a flat code list
+fn read_flat_codes<R: Read>(
+    reader: R,
+    format: FlatCodeListFormat,
+) -> Result<Vec<(String, String)>, HbrError> {
+    let mut lines = BufReader::new(reader).lines();
+    let mut codes = Vec::new();
+    match format {
+        FlatCodeListFormat::Icd10Trud => {
+            let header = lines
+                .next()
+                .ok_or(HbrError::InvalidCodeList(String::from("missing header row")))??;
+            let columns: Vec<&str> = header.split('\t').map(str::trim).collect();
+            let column = |name: &str| {
+                columns
+                    .iter()
+                    .position(|column| *column == name)
+                    .ok_or(HbrError::InvalidCodeList(format!("missing {name} column")))
+            };
+            let code_column = column("CODE")?;
+            let docs_column = column("DESCRIPTION")?;
+            for line in lines {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let fields: Vec<&str> = line.split('\t').collect();
+                match (fields.get(code_column), fields.get(docs_column)) {
+                    (Some(code), Some(docs)) => {
+                        codes.push((code.trim().to_string(), docs.trim().to_string()))
+                    }
+                    _ => return Err(HbrError::InvalidCodeList(format!("short row: {line}"))),
+                }
+            }
+        }
+        FlatCodeListFormat::Opcs4Trud | FlatCodeListFormat::Icd9Cms => {
+            for line in lines {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let (code, docs) = split_code_and_docs(&line)
+                    .ok_or(HbrError::InvalidCodeList(format!("no description: {line}")))?;
+                let code = if format == FlatCodeListFormat::Icd9Cms {
+                    icd9_code_with_dot(code)
+                } else {
+                    code.to_string()
+                };
+                codes.push((code, docs.to_string()));
+            }
+        }
+    }
+    Ok(codes)
+}
+
+/// Make the category for a code (key is the normalised code),
+/// including all the codes underneath it.
+fn make_category(
+    key: &String,
+    codes: &HashMap<String, (String, String)>,
+    children: &BTreeMap<String, Vec<String>>,
+) -> Categories {
+    let (name, docs) = codes[key].clone();
+    let categories = children.get(key).map(|child_keys| {
+        child_keys
+            .iter()
+            .map(|child_key| make_category(child_key, codes, children))
+            .collect()
+    });
+    Categories {
+        name,
+        docs,
+        short_docs: None,
+        synonyms: Vec::new(),
+        index: Index::make_single(key),
+        categories,
+        exclude: None,
+        metadata: HashMap::new(),
+    }
+}
+
+impl ClinicalCodeTree {
+    /// Build a clinical code tree from a flat list of codes and
+    /// descriptions, such as the files distributed by NHS TRUD
+    ///
+    /// The hierarchy and the indices of the categories are worked
+    /// out from the codes (see the module documentation). A code
+    /// that is a prefix of other codes (e.g. A00 in ICD-10) becomes
+    /// a category, so it does not match in find_exact (use
+    /// find_nearest instead), consistent with the YAML codes files.
+    ///
+    /// Returns an error if the file cannot be read, does not match
+    /// the format, or contains no codes.
+    pub fn from_flat_reader<R: Read>(
+        reader: R,
+        format: FlatCodeListFormat,
+    ) -> Result<Self, HbrError> {
+        // Key each code by its normalised form (the same form as the
+        // index), keeping the first occurrence of any duplicates
+        let mut codes = HashMap::new();
+        for (name, docs) in read_flat_codes(reader, format)? {
+            let key = normalise_code(name.clone());
+            if !key.is_empty() {
+                codes.entry(key).or_insert((name, docs));
+            }
+        }
+        if codes.is_empty() {
+            return Err(HbrError::InvalidCodeList(String::from("no codes found")));
+        }
+
+        // Find the parent of each code (the longest proper prefix that
+        // is also a code). Codes with no parent are grouped by letter.
+        let mut children: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut letters: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for key in codes.keys() {
+            let parent = (1..key.len())
+                .rev()
+                .filter_map(|n| key.get(..n))
+                .find(|prefix| codes.contains_key(*prefix));
+            match parent {
+                Some(parent) => children.entry(parent.to_string()).or_default(),
+                None => letters.entry(key.chars().take(1).collect()).or_default(),
+            }
+            .push(key.clone());
+        }
+
+        let letter_categories: Vec<Categories> = letters
+            .iter()
+            .map(|(letter, keys)| Categories {
+                name: letter.clone(),
+                docs: format!("{} codes starting with {letter}", format.root_name()),
+                short_docs: None,
+                synonyms: Vec::new(),
+                index: Index::make_single(letter),
+                categories: Some(
+                    keys.iter()
+                        .map(|key| make_category(key, &codes, &children))
+                        .collect(),
+                ),
+                exclude: None,
+                metadata: HashMap::new(),
+            })
+            .collect();
+
+        let first = letters.keys().next().expect("There is at least one code");
+        let last = letters.keys().last().expect("There is at least one code");
+        let root = Categories {
+            name: format.root_name().to_string(),
+            docs: format!("{} codes", format.root_name()),
+            short_docs: None,
+            synonyms: Vec::new(),
+            index: Index::make_dual(first, last),
+            categories: Some(letter_categories),
+            exclude: None,
+            metadata: HashMap::new(),
+        };
+
+        let mut categories = vec![root];
+        sort_categories_list_in_place(&mut categories);
+        Ok(Self {
+            categories,
+            groups: Default::default(),
+            normalisation: CodeNormalisation::Standard,
+            group_metadata: HashMap::new(),
+            exact_index: None,
+        })
+    }
+}
+
+/// Tests for building code trees from flat code lists
+///
+/// The files are small examples in the same layout as the
+/// NHS TRUD files.
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::clinical_code::ClinicalCodeStore;
+    use crate::name;
+
+    const ICD10_TRUD: &str = "CODE\tALT_CODE\tUSAGE\tUSAGE_UK\tDESCRIPTION\n\
+        A00\tA00\tDEFAULT\t3\tCholera\n\
+        A00.0\tA000\tDEFAULT\t3\tCholera due to Vibrio cholerae 01, biovar cholerae\n\
+        A00.9\tA009\tDEFAULT\t3\tCholera, unspecified\n\
+        A33\tA33X\tDEFAULT\t1\tTetanus neonatorum\n\
+        I21\tI21\tDEFAULT\t3\tAcute myocardial infarction\n\
+        I21.0\tI210\tDEFAULT\t3\tAcute transmural myocardial infarction of anterior wall\n\
+        I21.9\tI219\tDEFAULT\t3\tAcute myocardial infarction, unspecified\n";
+
+    const OPCS4_TRUD: &str = "A01\tMajor excision of tissue of brain\n\
+        A01.1\tHemispherectomy\n\
+        A01.2\tTotal lobectomy of brain\n\
+        K75     Percutaneous transluminal balloon angioplasty and insertion of stent into coronary artery\n\
+        K75.1   Percutaneous transluminal balloon angioplasty and insertion of 1-2 drug-eluting stents into coronary artery\n";
+
+    #[test]
+    fn check_icd10_flat_list_builds_tree() {
+        let code_tree =
+            ClinicalCodeTree::from_flat_reader(ICD10_TRUD.as_bytes(), FlatCodeListFormat::Icd10Trud)
+                .expect("Should build code tree");
+        let mut code_store = ClinicalCodeStore::new();
+
+        let code_match = code_tree
+            .find_exact(format!("I21.9"), &mut code_store)
+            .expect("Code should be present");
+        assert_eq!(name!(code_match.code(), code_store), "I21.9");
+
+        // A three character code with no sub-codes is a code
+        let code_match = code_tree
+            .find_exact(format!("A33"), &mut code_store)
+            .expect("Code should be present");
+        assert_eq!(name!(code_match.code(), code_store), "A33");
+
+        // A three character code with sub-codes is a category
+        assert!(code_tree.find_exact(format!("A00"), &mut code_store).is_err());
+        let code_match = code_tree
+            .find_nearest(format!("A00"), &mut code_store)
+            .expect("Category should be present");
+        assert_eq!(name!(code_match.code(), code_store), "A00");
+
+        assert!(code_tree.find_exact(format!("B00.0"), &mut code_store).is_err());
+        assert!(code_tree.groups().is_empty());
+    }
+
+    #[test]
+    fn check_opcs4_flat_list_builds_tree() {
+        let code_tree =
+            ClinicalCodeTree::from_flat_reader(OPCS4_TRUD.as_bytes(), FlatCodeListFormat::Opcs4Trud)
+                .expect("Should build code tree");
+        let mut code_store = ClinicalCodeStore::new();
+
+        // Tab-separated and fixed-width lines are both read
+        for code in ["A01.1", "A01.2", "K75.1"] {
+            let code_match = code_tree
+                .find_exact(code.to_string(), &mut code_store)
+                .expect("Code should be present");
+            assert_eq!(name!(code_match.code(), code_store), code);
+        }
+    }
+
+    #[test]
+    fn check_icd9_flat_list_builds_tree() {
+        let icd9 = "0010    Cholera due to vibrio cholerae\n\
+            0019    Cholera, unspecified\n\
+            V0481   Need for prophylactic vaccination and inoculation against influenza\n\
+            E8000   Railway accident involving collision with rolling stock injuring railway employee\n";
+        let code_tree =
+            ClinicalCodeTree::from_flat_reader(icd9.as_bytes(), FlatCodeListFormat::Icd9Cms)
+                .expect("Should build code tree");
+        let mut code_store = ClinicalCodeStore::new();
+
+        // Codes can be found with or without the dot
+        let codes = [
+            ("001.0", "001.0"),
+            ("0019", "001.9"),
+            ("V04.81", "V04.81"),
+            ("E800.0", "E800.0"),
+        ];
+        for (code, name) in codes {
+            let code_match = code_tree
+                .find_exact(code.to_string(), &mut code_store)
+                .expect("Code should be present");
+            assert_eq!(name!(code_match.code(), code_store), name);
+        }
+    }
+
+    #[test]
+    fn check_missing_column_is_an_error() {
+        let result = ClinicalCodeTree::from_flat_reader(
+            "CODE\tTITLE\nA00\tCholera\n".as_bytes(),
+            FlatCodeListFormat::Icd10Trud,
+        );
+        assert!(matches!(result, Err(HbrError::InvalidCodeList(_))));
+    }
+}
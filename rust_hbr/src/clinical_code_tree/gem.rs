@@ -0,0 +1,179 @@
+//! ICD-9 to ICD-10 mapping using General Equivalence Mappings
+//!
+//! The General Equivalence Mappings (GEMs) published by CMS map each
+//! ICD-9-CM code to one or more ICD-10 codes. Each line of the file
+//! (e.g. 2018_I9gem.txt) contains the ICD-9 code, the ICD-10 code (both
+//! without dots), and five flags:
+//! * approximate: the codes are not exactly equivalent;
+//! * no map: there is no ICD-10 code for the ICD-9 code (the ICD-10
+//!   code is written as NoDx);
+//! * combination: the ICD-9 code needs more than one ICD-10 code;
+//! * scenario and choice list: for a combination, the ICD-10 codes
+//!   in the same scenario must be used together, picking one code
+//!   from each choice list.
+//!
+//! This is used to translate historical episodes coded in ICD-9
+//! into references to ICD-10 codes, so that the same code groups
+//! can be used for annotation.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+
+use super::{normalise_code, ClinicalCodeTree, CodeMatch};
+use crate::clinical_code::ClinicalCodeStore;
+use crate::error::HbrError;
+
+/// One target of a mapping from an ICD-9 code to ICD-10
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GemEntry {
+    /// The ICD-10 code (as written in the file, without a dot)
+    pub target: String,
+    /// True if the mapping is approximate
+    pub approximate: bool,
+    /// True if the ICD-9 code maps to a combination of ICD-10 codes
+    pub combination: bool,
+    /// The scenario number of a combination (0 otherwise)
+    pub scenario: u8,
+    /// The choice list number of a combination (0 otherwise)
+    pub choice_list: u8,
+}
+
+/// A mapping from ICD-9 codes to ICD-10 codes
+#[derive(Debug, Default)]
+pub struct GemMapping {
+    /// Map from the normalised ICD-9 code to the ICD-10 targets.
+    /// ICD-9 codes with no mapping are not stored.
+    entries: HashMap<String, Vec<GemEntry>>,
+}
+
+/// Read the value of one of the (single digit) GEM flags
+fn flag(flags: &str, n: usize) -> Result<u8, HbrError> {
+    flags
+        .get(n..n + 1)
+        .and_then(|digit| digit.parse().ok())
+        .ok_or(HbrError::InvalidCodeList(format!("invalid GEM flags {flags}")))
+}
+
+impl GemMapping {
+    /// Read a mapping from a GEM file (whitespace-separated columns
+    /// for the ICD-9 code, the ICD-10 code and the flags). Lines with
+    /// the no map flag set are skipped. Returns an error if the file
+    /// cannot be read or a line is not in the expected format.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, HbrError> {
+        let mut mapping = Self::default();
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [] => continue,
+                [source, target, flags] => {
+                    if flag(flags, 1)? == 1 {
+                        continue;
+                    }
+                    let entry = GemEntry {
+                        target: target.to_string(),
+                        approximate: flag(flags, 0)? == 1,
+                        combination: flag(flags, 2)? == 1,
+                        scenario: flag(flags, 3)?,
+                        choice_list: flag(flags, 4)?,
+                    };
+                    mapping
+                        .entries
+                        .entry(normalise_code(source.to_string()))
+                        .or_default()
+                        .push(entry);
+                }
+                _ => return Err(HbrError::InvalidCodeList(format!("invalid GEM line: {line}"))),
+            }
+        }
+        Ok(mapping)
+    }
+
+    /// Get the ICD-10 targets for an ICD-9 code (with or without the
+    /// dot). The result is empty if the code has no mapping.
+    pub fn targets(&self, icd9_code: &str) -> &[GemEntry] {
+        self.entries
+            .get(&normalise_code(icd9_code.to_string()))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Translate an ICD-9 code to the ICD-10 codes it maps to, by
+    /// looking up each target in the ICD-10 code tree. The result
+    /// includes the groups containing each ICD-10 code, ready for
+    /// annotating the episode.
+    ///
+    /// Targets that are not in the ICD-10 code tree are skipped. Returns
+    /// HbrError::CodeNotFound if none of the targets (or no mapping)
+    /// could be found for the ICD-9 code.
+    pub fn translate(
+        &self,
+        icd9_code: &str,
+        icd10_tree: &ClinicalCodeTree,
+        code_store: &mut ClinicalCodeStore,
+    ) -> Result<Vec<CodeMatch>, HbrError> {
+        let matches: Vec<CodeMatch> = self
+            .targets(icd9_code)
+            .iter()
+            .filter_map(|entry| icd10_tree.find_exact(entry.target.clone(), code_store).ok())
+            .collect();
+        if matches.is_empty() {
+            Err(HbrError::CodeNotFound(icd9_code.to_string()))
+        } else {
+            Ok(matches)
+        }
+    }
+}
+
+/// Tests for the GEM mapping
+#[cfg(test)]
+mod tests {
+
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::name;
+
+    const GEM: &str = "0010  A000    00000\n\
+        0019  A009    00000\n\
+        00322 A0222   10000\n\
+        4100  I2109   10000\n\
+        7980  NoDx    01000\n\
+        99662 T8579XA 10111\n\
+        99662 T827XXA 10111\n";
+
+    #[test]
+    fn check_gem_file_loads() {
+        let mapping = GemMapping::from_reader(GEM.as_bytes()).expect("Should load mapping");
+        assert_eq!(mapping.targets("001.0")[0].target, "A000");
+        assert!(mapping.targets("003.22")[0].approximate);
+        assert!(mapping.targets("798.0").is_empty());
+        let targets = mapping.targets("996.62");
+        assert_eq!(targets.len(), 2);
+        assert!(targets.iter().all(|entry| entry.combination));
+        assert!(targets.iter().all(|entry| entry.scenario == 1 && entry.choice_list == 1));
+
+        assert!(GemMapping::from_reader("0010 A000\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn check_translation_to_icd10() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let icd10_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        let mut code_store = ClinicalCodeStore::new();
+        let mapping = GemMapping::from_reader(GEM.as_bytes()).expect("Should load mapping");
+
+        let matches = mapping
+            .translate("001.0", &icd10_tree, &mut code_store)
+            .expect("Should translate");
+        assert_eq!(matches.len(), 1);
+        assert_eq!(name!(matches[0].code(), code_store), "A00.0");
+
+        assert!(mapping.translate("798.0", &icd10_tree, &mut code_store).is_err());
+        assert!(mapping.translate("123.4", &icd10_tree, &mut code_store).is_err());
+    }
+}
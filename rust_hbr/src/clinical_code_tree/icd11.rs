@@ -0,0 +1,281 @@
+//! Support for ICD-11 (MMS) code trees
+//!
+//! ICD-11 codes differ from ICD-10 codes in a few ways that matter
+//! for parsing:
+//! * stem codes are four characters that can start with a digit
+//!   (e.g. 1A00), and subcategories add characters after a dot
+//!   (e.g. 1A00.0, BA41.Z);
+//! * codes can be combined into clusters using & (adding an extension
+//!   code or another stem code) and / (linking two stem codes), e.g.
+//!   NC72.2&XJ6Q1. The codes in a cluster are separate entries in the
+//!   tree, so a cluster must be split before looking up each code;
+//! * extension codes (chapter X, e.g. XA0060) can be of different
+//!   lengths and are never used on their own as a diagnosis;
+//! * chapters and blocks do not have codes, and a chapter can contain
+//!   both blocks and categories directly.
+//!
+//! The tree is loaded from the WHO linearization file (the tab-separated
+//! "LinearizationMiniOutput" file for MMS), which lists every chapter,
+//! block and category in tree order, with the depth of each entry shown
+//! by the number of leading dashes on its title.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+
+use super::{
+    sort_categories_list_in_place, Categories, ClinicalCodeTree, CodeNormalisation, Index,
+};
+use crate::error::HbrError;
+
+/// Split an ICD-11 cluster (e.g. "NC72.2&XJ6Q1/PA01") into the
+/// codes it contains, in order. The first code is the stem code; the
+/// rest are the extension codes or linked stem codes. Whitespace around
+/// each code is removed, and a single code gives a list of length one.
+pub fn icd11_cluster_codes(cluster: &str) -> Vec<String> {
+    cluster
+        .split(['&', '/'])
+        .map(str::trim)
+        .filter(|code| !code.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Normalise an ICD-11 code, by using only the stem code of a
+/// cluster, and then removing dots and whitespace and converting
+/// to lower case as for other codes.
+pub(super) fn normalise_icd11_code(code: &str) -> String {
+    let stem = code.split(['&', '/']).next().unwrap_or_default();
+    super::normalise_code(stem.to_string())
+}
+
+/// One row of the linearization file
+struct LinearizationRow {
+    depth: usize,
+    name: String,
+    docs: String,
+    /// The normalised code, if the row is a category
+    code: Option<String>,
+}
+
+/// Read the chapter, block and category rows from a linearization file
+fn read_linearization<R: Read>(reader: R) -> Result<Vec<LinearizationRow>, HbrError> {
+    let mut lines = BufReader::new(reader).lines();
+    let header = lines
+        .next()
+        .ok_or(HbrError::InvalidCodeList(String::from("missing header row")))??;
+    let columns: Vec<&str> = header.split('\t').map(str::trim).collect();
+    let column = |name: &str| {
+        columns
+            .iter()
+            .position(|column| column.eq_ignore_ascii_case(name))
+            .ok_or(HbrError::InvalidCodeList(format!("missing {name} column")))
+    };
+    let code_column = column("Code")?;
+    let block_column = column("BlockId")?;
+    let title_column = column("Title")?;
+    let kind_column = column("ClassKind")?;
+    let chapter_column = column("ChapterNo")?;
+
+    let mut rows = Vec::new();
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        let field = |n: usize| {
+            fields
+                .get(n)
+                .copied()
+                .ok_or(HbrError::InvalidCodeList(format!("short row: {line}")))
+        };
+
+        // Depth is shown by the dashes at the start of the title
+        let mut title = field(title_column)?;
+        let mut depth = 0;
+        while let Some(rest) = title.strip_prefix('-') {
+            title = rest.trim_start();
+            depth += 1;
+        }
+        let docs = title.to_string();
+
+        let (name, code) = match field(kind_column)? {
+            "chapter" => (format!("Chapter {}", field(chapter_column)?), None),
+            "block" => (field(block_column)?.to_string(), None),
+            "category" => {
+                let name = field(code_column)?.to_string();
+                let code = normalise_icd11_code(&name);
+                (name, Some(code))
+            }
+            kind => return Err(HbrError::InvalidCodeList(format!("unknown ClassKind {kind}"))),
+        };
+        rows.push(LinearizationRow {
+            depth,
+            name,
+            docs,
+            code,
+        });
+    }
+    Ok(rows)
+}
+
+/// Build the sub-tree rooted at rows[*position], moving the position
+/// past all of its descendants. Returns None for a chapter or block
+/// that contains no codes, which cannot be given an index.
+fn make_category(rows: &Vec<LinearizationRow>, position: &mut usize) -> Option<Categories> {
+    let row = &rows[*position];
+    *position += 1;
+
+    let mut sub_categories = Vec::new();
+    while *position < rows.len() && rows[*position].depth > row.depth {
+        if let Some(sub_category) = make_category(rows, position) {
+            sub_categories.push(sub_category);
+        }
+    }
+
+    // Categories are indexed by their code. Chapters and blocks are
+    // indexed by the range of the (stem parts of) the codes inside
+    // them, so every code in the chapter or block is in the range.
+    let index = match &row.code {
+        Some(code) => Index::make_single(code),
+        None => {
+            let (start, end) = code_range(&sub_categories)?;
+            Index::make_dual(&start, &end)
+        }
+    };
+    let categories = if sub_categories.is_empty() {
+        None
+    } else {
+        Some(sub_categories)
+    };
+    Some(Categories {
+        name: row.name.clone(),
+        docs: row.docs.clone(),
+        short_docs: None,
+        synonyms: Vec::new(),
+        index,
+        categories,
+        exclude: None,
+        metadata: HashMap::new(),
+    })
+}
+
+/// Get the smallest and largest stem code (the first four characters
+/// of the index) in a list of categories
+fn code_range(categories: &[Categories]) -> Option<(String, String)> {
+    let stems = categories.iter().flat_map(|category| {
+        let (start, end) = category.index.range();
+        [start, end].map(|code| code.chars().take(4).collect::<String>())
+    });
+    let start = stems.clone().min()?;
+    let end = stems.max()?;
+    Some((start, end))
+}
+
+impl ClinicalCodeTree {
+    /// Build an ICD-11 code tree from the WHO MMS linearization
+    /// file (the tab-separated LinearizationMiniOutput file)
+    ///
+    /// Chapters are named "Chapter N" and blocks by their block id,
+    /// and both take their description from the title. Chapters and
+    /// blocks that contain no codes are left out. The tree uses
+    /// ICD-11 code normalisation, so find_exact and find_nearest
+    /// accept clusters and look up the stem code (use
+    /// icd11_cluster_codes to look up the other codes in a cluster).
+    ///
+    /// Returns an error if the file cannot be read or is not in the
+    /// expected format. The tree has no groups.
+    pub fn from_icd11_linearization<R: Read>(reader: R) -> Result<Self, HbrError> {
+        let rows = read_linearization(reader)?;
+        let mut categories = Vec::new();
+        let mut position = 0;
+        while position < rows.len() {
+            if let Some(category) = make_category(&rows, &mut position) {
+                categories.push(category);
+            }
+        }
+        if categories.is_empty() {
+            return Err(HbrError::InvalidCodeList(String::from("no codes found")));
+        }
+        sort_categories_list_in_place(&mut categories);
+        Ok(Self {
+            categories,
+            groups: Default::default(),
+            normalisation: CodeNormalisation::Icd11,
+            group_metadata: HashMap::new(),
+            exact_index: None,
+        })
+    }
+}
+
+/// Tests for ICD-11 code trees
+///
+/// The linearization file is a small extract in the same layout
+/// as the WHO file.
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::clinical_code::ClinicalCodeStore;
+    use crate::name;
+
+    const LINEARIZATION: &str = "Foundation URI\tLinearization URI\tCode\tBlockId\tTitle\tClassKind\tDepthInKind\tIsResidual\tChapterNo\n\
+        u1\tl1\t\t\tCertain infectious or parasitic diseases\tchapter\t1\tFalse\t01\n\
+        u2\tl2\t\tBlockL1-1A0\t- Gastroenteritis or colitis of infectious origin\tblock\t1\tFalse\t01\n\
+        u3\tl3\t1A00\t\t- - Cholera\tcategory\t1\tFalse\t01\n\
+        u4\tl4\t1A01\t\t- - Intestinal infection due to other Vibrio\tcategory\t1\tFalse\t01\n\
+        u5\tl5\t1A02\t\t- - Intestinal infections due to Shigella\tcategory\t1\tFalse\t01\n\
+        u6\tl6\t\t\tDiseases of the circulatory system\tchapter\t1\tFalse\t11\n\
+        u7\tl7\t\tBlockL1-BA4\t- Ischaemic heart diseases\tblock\t1\tFalse\t11\n\
+        u8\tl8\tBA41\t\t- - Acute myocardial infarction\tcategory\t1\tFalse\t11\n\
+        u9\tl9\tBA41.0\t\t- - - Acute ST elevation myocardial infarction\tcategory\t2\tFalse\t11\n\
+        u10\tl10\tBA41.Z\t\t- - - Acute myocardial infarction, unspecified\tcategory\t2\tTrue\t11\n\
+        u11\tl11\tBA40\t\t- - Angina pectoris\tcategory\t1\tFalse\t11\n\
+        u12\tl12\t\tBlockL1-BA8\t- Empty block\tblock\t1\tFalse\t11\n\
+        u15\tl15\tBE2Z\t\t- Diseases of the circulatory system, unspecified\tcategory\t1\tTrue\t11\n\
+        u13\tl13\t\t\tExtension Codes\tchapter\t1\tFalse\tX\n\
+        u14\tl14\tXA0060\t\t- Left ventricle\tcategory\t1\tFalse\tX\n";
+
+    #[test]
+    fn check_cluster_is_split_into_codes() {
+        assert_eq!(icd11_cluster_codes("NC72.2&XJ6Q1"), vec!["NC72.2", "XJ6Q1"]);
+        assert_eq!(
+            icd11_cluster_codes("BA41.0 & XA0060 / 1A00"),
+            vec!["BA41.0", "XA0060", "1A00"]
+        );
+        assert_eq!(icd11_cluster_codes("1A00"), vec!["1A00"]);
+        assert_eq!(normalise_icd11_code("BA41.Z&XA0060"), "ba41z");
+    }
+
+    #[test]
+    fn check_linearization_builds_tree() {
+        let code_tree = ClinicalCodeTree::from_icd11_linearization(LINEARIZATION.as_bytes())
+            .expect("Should build code tree");
+        let mut code_store = ClinicalCodeStore::new();
+
+        // Chapter 11 contains both blocks and categories
+        for code in ["1A00", "1A02", "BA41.0", "BA41.Z", "BA40", "BE2Z", "XA0060"] {
+            let code_match = code_tree
+                .find_exact(code.to_string(), &mut code_store)
+                .expect("Code should be present");
+            assert_eq!(name!(code_match.code(), code_store), code);
+        }
+
+        // A cluster matches its stem code
+        let code_match = code_tree
+            .find_exact(format!("BA41.0&XA0060"), &mut code_store)
+            .expect("Stem code should be present");
+        assert_eq!(name!(code_match.code(), code_store), "BA41.0");
+
+        // BA41 has subcategories, so it is not an exact match
+        assert!(code_tree.find_exact(format!("BA41"), &mut code_store).is_err());
+        assert!(code_tree.find_exact(format!("1A03"), &mut code_store).is_err());
+
+        // The ICD-11 normalisation is kept when the tree is saved
+        let mut yaml = Vec::new();
+        code_tree.to_writer(&mut yaml).expect("Failed to write codes file");
+        let roundtrip =
+            ClinicalCodeTree::from_reader(yaml.as_slice()).expect("Failed to parse codes file");
+        assert_eq!(code_tree, roundtrip);
+    }
+}
@@ -1,5 +1,6 @@
 use std::cmp::Ordering;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// Index used to sort the code categories.
@@ -10,6 +11,8 @@ use serde::{Deserialize, Serialize};
 /// sorting categories into order or comparing
 /// a code with a category for binary search purposes.
 /// Both A and B are the same length, and A may equal B.
+/// The strings are stored in lower case (the same case as
+/// normalised codes), so codes can be compared directly.
 /// 
 /// The rule which determines whether a code c is in
 /// the range (a, b) is as follows:
@@ -25,51 +28,63 @@ use serde::{Deserialize, Serialize};
 /// variant is used, which stores (a, b). Note also that
 /// in this case, c is in (a, a) if and only if c' = a.
 ///
-#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum Index {
     Single(String),
     Dual(String, String),
 }
 
-impl Index {
-    pub fn make_single(start: &str) -> Self {
-        Self::Single(String::from(start))
+/// Indices are ordered by the start of the range, and then by
+/// the end of the range, treating Single(a) as the range (a, a).
+/// This means that a list of categories containing both Single
+/// and Dual indices (for example an ICD-11 chapter containing
+/// both blocks and categories) sorts into code order.
+impl Ord for Index {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.range().cmp(&other.range())
     }
+}
 
-    /// Return true if self (a, b) contains code,
-    /// otherwise returns false. 
-    pub fn contains(&self, code: &String) -> bool {
+impl PartialOrd for Index {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Index {
+    /// Get the start and end of the range, where the start
+    /// and end are the same for a Single index.
+    pub(super) fn range(&self) -> (&String, &String) {
         match self {
-            Self::Single(a) => {
-                let c_prime = &code[..a.len()];
-                c_prime == a
-            },
-            Self::Dual(a, b) => {
-                let c_prime = code[..a.len()].to_string();
-                (a <= &c_prime) && (&c_prime >= b)
-            }
+            Self::Single(a) => (a, a),
+            Self::Dual(a, b) => (a, b),
         }
     }
 
-    /// Return true if self (a, b) is above code (i.e.
-    /// code is below 
-    pub fn is_lt(&self, code: &String) -> bool {
+    /// Make an index for a single code or category. The code
+    /// is converted to lower case.
+    pub fn make_single(start: &str) -> Self {
+        Self::Single(start.to_lowercase())
+    }
+
+    /// Convert the index to lower case, for indices that were
+    /// not made using make_single or make_dual (e.g. read from
+    /// a codes file written with upper case indices)
+    pub(super) fn make_lowercase(&mut self) {
         match self {
-            Self::Single(a) => {
-                let c_prime = &code[..a.len()];
-                c_prime == a
-            },
+            Self::Single(a) => *a = a.to_lowercase(),
             Self::Dual(a, b) => {
-                let c_prime = &code[..a.len()].to_string();
-                (a <= c_prime) && (c_prime <= b)
+                *a = a.to_lowercase();
+                *b = b.to_lowercase();
             }
         }
-    }    
-
+    }
 
+    /// Make an index for a range of codes. The start and end
+    /// are converted to lower case.
     pub fn make_dual(start: &str, end: &str) -> Self {
-        Self::Dual(String::from(start), String::from(end))
+        Self::Dual(start.to_lowercase(), end.to_lowercase())
     }
 
     /// Compare a normalised code with an Index to determine if the
@@ -81,38 +96,29 @@ impl Index {
     /// the code, and Greater if self is strictly above the code. This
     /// might feel the wrong way round, but think of it like "how does 
     /// self compare with the argument" (it is consistent with the 
-    /// direction of std::cmp).
+    /// direction of std::cmp, so it can be passed directly to
+    /// binary_search_by).
     /// 
-    /// Note: due to a discrepancy in the Index format (letters are
-    /// upper case), letters in the normalised code are converted back
-    /// to upper case before comparison with Index. This should be fixed
-    /// in the Index (and therefore in the codes files too), so that all
-    /// codes are in the lower case format.
-    pub fn compare(&self, code: &String) -> Ordering {
-        let code = code.to_ascii_uppercase();
-        match self {
-            Self::Single(a) => {
-                let c_prime = &code[..a.len()].to_string();
-                if c_prime == a {
-                    Ordering::Equal
-                } else if c_prime < a {
-                    Ordering::Greater
-                } else {
-                    Ordering::Less
-                }
-            },
-            Self::Dual(a, b) => {
-                let c_prime = &code[..a.len()].to_string();
-                if (a <= c_prime) && (c_prime <= b) {
-                    Ordering::Equal
-                } else if c_prime < a {
-                    Ordering::Greater
-                } else {
-                    Ordering::Less
-                }
-            }
+    /// If the code is shorter than the Index (e.g. a truncated code),
+    /// the whole code is compared, so that the Index is above the code
+    /// whenever the code is a prefix of the Index.
+    pub fn relation_to(&self, code: &str) -> Ordering {
+        let (a, b) = self.range();
+        let c_prime = code.get(..a.len()).unwrap_or(code);
+        if c_prime < a.as_str() {
+            Ordering::Greater
+        } else if c_prime > b.as_str() {
+            Ordering::Less
+        } else {
+            Ordering::Equal
         }
     }
+
+    /// Return true if the normalised code lies in the Index range
+    /// (see relation_to)
+    pub fn contains(&self, code: &str) -> bool {
+        self.relation_to(code) == Ordering::Equal
+    }
 }
 
 /// Tests for the index
@@ -166,22 +172,34 @@ mod tests {
         assert!(i2 > i1);
     }
 
+    /// Single indices sort in range order among Dual indices
+    #[test]
+    fn single_and_dual_index_compare_by_range() {
+        let i1 = Index::make_dual("BA40", "BA41");
+        let i2 = Index::make_single("BE2Z");
+        assert!(i1 < i2);
+
+        let i1 = Index::make_single("A00");
+        let i2 = Index::make_dual("A01", "A09");
+        assert!(i1 < i2);
+    }
+
     #[test]
     fn check_codes_lie_in_index_range() {
         let i = Index::make_dual("I00", "I02");
         // Check the lower boundary edge case
         let code = format!("i000");
-        assert_eq!(i.compare(&code), Ordering::Equal);
+        assert_eq!(i.relation_to(&code), Ordering::Equal);
         // Check the upper boundary edge cases. Note that the
         // code is truncated to the length of the upper limit 
         // before comparison
         let code = format!("i02");
-        assert_eq!(i.compare(&code), Ordering::Equal);
+        assert_eq!(i.relation_to(&code), Ordering::Equal);
         let code = format!("i0223");
-        assert_eq!(i.compare(&code), Ordering::Equal);
+        assert_eq!(i.relation_to(&code), Ordering::Equal);
         // Check internal code
         let code = format!("i011");
-        assert_eq!(i.compare(&code), Ordering::Equal);
+        assert_eq!(i.relation_to(&code), Ordering::Equal);
     }
 
     #[test]
@@ -189,10 +207,10 @@ mod tests {
         let i = Index::make_dual("I00", "I02");
         // Check the upper boundary edge case
         let code = format!("h999");
-        assert_eq!(i.compare(&code), Ordering::Greater);
+        assert_eq!(i.relation_to(&code), Ordering::Greater);
         // Check internal code
         let code = format!("a001");
-        assert_eq!(i.compare(&code), Ordering::Greater);
+        assert_eq!(i.relation_to(&code), Ordering::Greater);
     }
 
     #[test]
@@ -200,10 +218,42 @@ mod tests {
         let i = Index::make_dual("I00", "I02");
         // Check the lower boundary edge case
         let code = format!("i030");
-        assert_eq!(i.compare(&code), Ordering::Less);
+        assert_eq!(i.relation_to(&code), Ordering::Less);
         // Check internal code
         let code = format!("z001");
-        assert_eq!(i.compare(&code), Ordering::Less);
+        assert_eq!(i.relation_to(&code), Ordering::Less);
+    }
+
+    #[test]
+    fn check_index_contains_codes() {
+        let i = Index::make_dual("I00", "I02");
+        assert!(i.contains("i000"));
+        assert!(i.contains("i011"));
+        assert!(i.contains("i0223"));
+        assert!(!i.contains("h999"));
+        assert!(!i.contains("i030"));
+        // A truncated code is not in the range of a longer index
+        assert!(!i.contains("i0"));
+
+        let i = Index::make_single("I21");
+        assert!(i.contains("i21"));
+        assert!(i.contains("i219"));
+        assert!(!i.contains("i22"));
+        assert!(!i.contains("i2"));
+        assert_eq!(i.relation_to("i20"), Ordering::Greater);
+        assert_eq!(i.relation_to("i22"), Ordering::Less);
+    }
+
+    #[test]
+    fn check_index_is_stored_in_lower_case() {
+        let i = Index::make_dual("I00", "I02");
+        assert_eq!(i, Index::Dual(format!("i00"), format!("i02")));
+
+        // Indices read from a codes file are converted on load
+        let mut i: Index = serde_yaml::from_str("- I00\n- I02\n").unwrap();
+        i.make_lowercase();
+        assert_eq!(i, Index::Dual(format!("i00"), format!("i02")));
+        assert_eq!(serde_yaml::to_string(&i).unwrap(), "- i00\n- i02\n");
     }
 
 }
@@ -0,0 +1,352 @@
+//! Lazily loaded code trees
+//!
+//! For very large vocabularies (e.g. SNOMED CT), even loading the
+//! binary cache (see compile_to_cache) means decoding the whole tree,
+//! most of which is never visited. The lazy cache format instead
+//! stores each list of sub-categories as a separate block, along
+//! with the position of the blocks under each category. The file is
+//! memory-mapped, and a block is only decoded when a search passes
+//! through it, so looking up a code only decodes the levels on the
+//! path down to the code.
+//!
+//! The layout of the file is the magic string and format version,
+//! followed by the position of the header, then the blocks of
+//! categories (children before parents), and then the header (the
+//! groups and the position of the top level of the tree). All the
+//! blocks and the header are encoded using bincode.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::Path;
+
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+
+use super::binary::cached_index;
+use super::{Categories, ClinicalCodeTree, CodeMatch, CodeNormalisation, Index};
+use crate::clinical_code::{ClinicalCode, ClinicalCodeStore};
+use crate::error::HbrError;
+
+/// The first bytes of every lazy cache file
+const LAZY_CACHE_MAGIC: &[u8; 8] = b"HBRLAZY\0";
+
+/// The version of the lazy cache format (see CACHE_VERSION
+/// in the binary module)
+const LAZY_CACHE_VERSION: u32 = 2;
+
+/// The length of the magic string, version and header position
+const LAZY_CACHE_PREAMBLE: usize = 20;
+
+/// The position of an encoded block in the file
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+struct Block {
+    offset: u64,
+    len: u64,
+}
+
+/// A category in a block, which refers to the block containing
+/// its sub-categories instead of holding them directly
+#[derive(Serialize, Deserialize)]
+struct LazyCategory {
+    name: String,
+    docs: String,
+    short_docs: Option<String>,
+    synonyms: Vec<String>,
+    #[serde(with = "cached_index")]
+    index: Index,
+    /// The block of sub-categories, or None for a leaf (a code)
+    categories: Option<Block>,
+    exclude: Option<HashSet<String>>,
+}
+
+/// The header, stored at the end of the file
+#[derive(Serialize, Deserialize)]
+struct LazyHeader {
+    groups: HashSet<String>,
+    normalisation: CodeNormalisation,
+    group_metadata: HashMap<String, HashMap<String, String>>,
+    top_level: Block,
+}
+
+/// Encode a list of categories (and all the categories under them),
+/// appending the blocks to the buffer. Returns the block holding the
+/// list itself.
+fn write_categories(categories: &Vec<Categories>, buffer: &mut Vec<u8>) -> Result<Block, HbrError> {
+    let mut level = Vec::new();
+    for category in categories {
+        let sub_categories = match &category.categories {
+            Some(sub_categories) => Some(write_categories(sub_categories, buffer)?),
+            None => None,
+        };
+        level.push(LazyCategory {
+            name: category.name.clone(),
+            docs: category.docs.clone(),
+            short_docs: category.short_docs.clone(),
+            synonyms: category.synonyms.clone(),
+            index: category.index.clone(),
+            categories: sub_categories,
+            exclude: category.exclude.clone(),
+        });
+    }
+    let offset = buffer.len() as u64;
+    bincode::serialize_into(&mut *buffer, &level)?;
+    Ok(Block {
+        offset,
+        len: buffer.len() as u64 - offset,
+    })
+}
+
+/// A code tree in a memory-mapped lazy cache file (written using
+/// ClinicalCodeTree::compile_to_lazy_cache). The tree supports
+/// searching for codes in the same way as ClinicalCodeTree, but
+/// only decodes the parts of the tree that are searched.
+#[derive(Debug)]
+pub struct LazyCodeTree {
+    mmap: Mmap,
+    groups: HashSet<String>,
+    normalisation: CodeNormalisation,
+    group_metadata: HashMap<String, HashMap<String, String>>,
+    top_level: Block,
+}
+
+impl LazyCodeTree {
+    /// Open a lazy cache file. Only the header is decoded.
+    ///
+    /// Returns HbrError::InvalidCodeTreeCache if the file is not
+    /// a lazy cache file, or was written using a different version
+    /// of the format. The file must not be modified while the tree
+    /// is open.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, HbrError> {
+        let file = File::open(path)?;
+        // Safety: the map is read-only, and the file is not expected
+        // to change while it is open (see above)
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < LAZY_CACHE_PREAMBLE || &mmap[..8] != LAZY_CACHE_MAGIC {
+            return Err(HbrError::InvalidCodeTreeCache(String::from(
+                "not a lazy code tree cache file",
+            )));
+        }
+        let version = u32::from_le_bytes(mmap[8..12].try_into().unwrap());
+        if version != LAZY_CACHE_VERSION {
+            return Err(HbrError::InvalidCodeTreeCache(format!(
+                "lazy cache format version {version} (expected {LAZY_CACHE_VERSION})"
+            )));
+        }
+        let header_offset = u64::from_le_bytes(mmap[12..20].try_into().unwrap());
+        let header: LazyHeader = bincode::deserialize(
+            mmap.get(header_offset as usize..)
+                .ok_or_else(|| HbrError::InvalidCodeTreeCache(String::from("missing header")))?,
+        )?;
+
+        Ok(Self {
+            mmap,
+            groups: header.groups,
+            normalisation: header.normalisation,
+            group_metadata: header.group_metadata,
+            top_level: header.top_level,
+        })
+    }
+
+    /// Get the list of groups in the tree
+    pub fn groups(&self) -> &HashSet<String> {
+        &self.groups
+    }
+
+    /// Get the metadata for a group (see
+    /// ClinicalCodeTree::group_metadata)
+    pub fn group_metadata(&self, group: &str) -> Result<HashMap<String, String>, HbrError> {
+        if !self.groups.contains(group) {
+            return Err(HbrError::UnknownGroup(group.to_string()));
+        }
+        Ok(self.group_metadata.get(group).cloned().unwrap_or_default())
+    }
+
+    /// Decode one list of categories from the file
+    fn read_block(&self, block: Block) -> Result<Vec<LazyCategory>, HbrError> {
+        // The offsets come from the file, so a corrupt file must not
+        // be able to make the end of the block overflow
+        let start = block.offset as usize;
+        let bytes = start
+            .checked_add(block.len as usize)
+            .and_then(|end| self.mmap.get(start..end))
+            .ok_or_else(|| HbrError::InvalidCodeTreeCache(String::from("block out of range")))?;
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    /// Find an exact match for a code (see ClinicalCodeTree::find_exact)
+    pub fn find_exact(
+        &self,
+        code: String,
+        code_store: &mut ClinicalCodeStore,
+    ) -> Result<CodeMatch, HbrError> {
+        // As for ClinicalCodeTree, a rejected code is not stored
+        match self.locate_nearest(&code)? {
+            Some((cat, groups, true)) => Ok(Self::code_match_from(cat, groups, true, code_store)),
+            _ => Err(HbrError::CodeNotFound(code)),
+        }
+    }
+
+    /// Find the deepest category that contains a code (see
+    /// ClinicalCodeTree::find_nearest). Only the lists of categories
+    /// on the path down to the match are decoded.
+    pub fn find_nearest(
+        &self,
+        code: String,
+        code_store: &mut ClinicalCodeStore,
+    ) -> Result<CodeMatch, HbrError> {
+        let _span = tracing::trace_span!("lazy_find_nearest", code = %code).entered();
+        let (cat, groups, exact) = self
+            .locate_nearest(&code)?
+            .ok_or_else(|| HbrError::CodeNotFound(code))?;
+        Ok(Self::code_match_from(cat, groups, exact, code_store))
+    }
+
+    /// Find the deepest category containing a code, the groups containing
+    /// it, and whether it is an exact match, without modifying a code
+    /// store. Returns None if the code is not in any category.
+    fn locate_nearest(
+        &self,
+        code: &str,
+    ) -> Result<Option<(LazyCategory, HashSet<String>, bool)>, HbrError> {
+        let normalised_code = self.normalisation.normalise(code.to_string());
+        let mut groups = self.groups.clone();
+        let mut nearest = None;
+        let mut next_block = Some(self.top_level);
+        while let Some(block) = next_block {
+            let mut level = self.read_block(block)?;
+            let Ok(position) = level.binary_search_by(|cat| cat.index.relation_to(&normalised_code))
+            else {
+                break;
+            };
+            let cat = level.swap_remove(position);
+            if let Some(exclude) = &cat.exclude {
+                groups.retain(|group| !exclude.contains(group));
+            }
+            next_block = cat.categories;
+            nearest = Some(cat);
+        }
+
+        Ok(nearest.map(|cat| {
+            let exact = cat.categories.is_none()
+                && self.normalisation.normalise(cat.name.clone()) == normalised_code;
+            (cat, groups, exact)
+        }))
+    }
+
+    /// Store the category found by locate_nearest in the code store, and
+    /// make the CodeMatch to return
+    fn code_match_from(
+        cat: LazyCategory,
+        groups: HashSet<String>,
+        exact: bool,
+        code_store: &mut ClinicalCodeStore,
+    ) -> CodeMatch {
+        let code = code_store.clinical_code_ref_from(ClinicalCode::with_synonyms(
+            cat.name,
+            cat.docs,
+            cat.short_docs,
+            cat.synonyms,
+        ));
+        code_store.record_groups(&code, &groups);
+        CodeMatch {
+            code,
+            groups,
+            exact,
+        }
+    }
+}
+
+impl ClinicalCodeTree {
+    /// Write the tree to a lazy cache file, which can be opened
+    /// using LazyCodeTree::open
+    ///
+    /// The metadata of codes and categories is not written to the
+    /// lazy cache. Returns an error if the file cannot be written.
+    pub fn compile_to_lazy_cache<P: AsRef<Path>>(&self, path: P) -> Result<(), HbrError> {
+        let mut buffer = Vec::new();
+        buffer.extend(LAZY_CACHE_MAGIC);
+        buffer.extend(LAZY_CACHE_VERSION.to_le_bytes());
+        // Placeholder for the header position
+        buffer.extend(0u64.to_le_bytes());
+
+        let top_level = write_categories(&self.categories, &mut buffer)?;
+        let header = LazyHeader {
+            groups: self.groups.clone(),
+            normalisation: self.normalisation,
+            group_metadata: self.group_metadata.clone(),
+            top_level,
+        };
+        let header_offset = buffer.len() as u64;
+        bincode::serialize_into(&mut buffer, &header)?;
+        buffer[12..LAZY_CACHE_PREAMBLE].copy_from_slice(&header_offset.to_le_bytes());
+
+        std::fs::write(path, buffer)?;
+        Ok(())
+    }
+}
+
+/// Tests for lazily loaded code trees
+#[cfg(test)]
+mod tests {
+
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::name;
+
+    #[test]
+    fn check_lazy_tree_finds_same_codes_as_tree() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+
+        let cache_path = std::env::temp_dir().join("rust_hbr_check_lazy_tree.bin");
+        code_tree
+            .compile_to_lazy_cache(&cache_path)
+            .expect("Failed to write lazy cache");
+        let lazy_tree = LazyCodeTree::open(&cache_path).expect("Failed to open lazy cache");
+        assert_eq!(lazy_tree.groups(), code_tree.groups());
+
+        let mut code_store = ClinicalCodeStore::new();
+        for code in ["I21.9", "i480", "I48.0", "I21", "I21.9X"] {
+            let expected = code_tree.find_nearest(code.to_string(), &mut code_store);
+            let code_match = lazy_tree.find_nearest(code.to_string(), &mut code_store);
+            let (expected, code_match) = (expected.unwrap(), code_match.unwrap());
+            assert_eq!(code_match.code(), expected.code());
+            assert_eq!(code_match.groups(), expected.groups());
+            assert_eq!(code_match.is_exact(), expected.is_exact());
+        }
+
+        let code_match = lazy_tree
+            .find_exact(format!("I48.0"), &mut code_store)
+            .expect("Code should be present");
+        assert_eq!(name!(code_match.code(), code_store), "I48.0");
+        assert!(code_match.in_group("atrial_fib"));
+        assert!(lazy_tree
+            .find_exact(format!("XYZ"), &mut code_store)
+            .is_err());
+        let num_stored_codes = code_store.num_stored_codes();
+        assert!(lazy_tree
+            .find_exact(format!("I21.9X"), &mut code_store)
+            .is_err());
+        assert_eq!(code_store.num_stored_codes(), num_stored_codes);
+
+        // A block past the end of the address space is out of range
+        let block = Block {
+            offset: u64::MAX - 1,
+            len: 2,
+        };
+        assert!(matches!(
+            lazy_tree.read_block(block),
+            Err(HbrError::InvalidCodeTreeCache(_))
+        ));
+
+        drop(lazy_tree);
+        std::fs::remove_file(&cache_path).expect("Failed to remove cache");
+    }
+}
@@ -0,0 +1,218 @@
+//! Group membership of a column of raw codes
+//!
+//! The main use of the code groups is to turn the diagnosis and
+//! procedure columns of raw data (e.g. HES) into features for a model:
+//! for each code, whether it is in each group. This module does that
+//! in one pass over the codes, parsing each distinct raw code once,
+//! and returns the result as one boolean column per group (along with
+//! the status of parsing each code), which can be converted directly
+//! into a polars DataFrame.
+
+use std::collections::HashMap;
+
+use super::ClinicalCodeTree;
+use crate::clinical_code::ClinicalCodeStore;
+
+/// The result of parsing one raw code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseStatus {
+    /// The code is in the tree (see ClinicalCodeTree::find_exact)
+    Exact,
+    /// The code is not in the tree, but a nearest match was found
+    /// (see ClinicalCodeTree::find_nearest), for example "I10X"
+    /// matches I10
+    Nearest,
+    /// No match was found for the code
+    NotFound,
+}
+
+impl ParseStatus {
+    /// The name of the status, as used in the status column
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Exact => "exact",
+            Self::Nearest => "nearest",
+            Self::NotFound => "not_found",
+        }
+    }
+}
+
+/// A matrix of group membership for a list of raw codes, with
+/// one row per code and one column per group in the tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupMembership {
+    /// The raw codes, in the order they were passed in
+    codes: Vec<String>,
+    /// The status of parsing each code
+    status: Vec<ParseStatus>,
+    /// The names of the groups (in sorted order)
+    groups: Vec<String>,
+    /// One column per group, holding whether each code is
+    /// in the group
+    columns: Vec<Vec<bool>>,
+}
+
+impl GroupMembership {
+    /// Get the raw codes (the rows of the matrix)
+    pub fn codes(&self) -> &Vec<String> {
+        &self.codes
+    }
+
+    /// Get the status of parsing each code
+    pub fn status(&self) -> &Vec<ParseStatus> {
+        &self.status
+    }
+
+    /// Get the names of the groups (the columns of the matrix)
+    pub fn groups(&self) -> &Vec<String> {
+        &self.groups
+    }
+
+    /// Get the membership column for a group, or None if the
+    /// group is not in the tree
+    pub fn column(&self, group: &str) -> Option<&Vec<bool>> {
+        let position = self.groups.iter().position(|name| name == group)?;
+        Some(&self.columns[position])
+    }
+
+    /// Convert the matrix to a polars DataFrame, with a code column,
+    /// a status column and one boolean column per group
+    #[cfg(any(feature = "synth", feature = "ingest"))]
+    pub fn to_polars(&self) -> polars::prelude::PolarsResult<polars::prelude::DataFrame> {
+        use polars::prelude::*;
+
+        let status: Vec<&str> = self.status.iter().map(ParseStatus::as_str).collect();
+        let mut columns = vec![
+            Series::new("code", &self.codes),
+            Series::new("status", status),
+        ];
+        for (group, column) in self.groups.iter().zip(&self.columns) {
+            columns.push(Series::new(group, column));
+        }
+        DataFrame::new(columns)
+    }
+}
+
+impl ClinicalCodeTree {
+    /// Find which groups each of the raw codes is in
+    ///
+    /// Each code is parsed using find_nearest, so that codes with
+    /// trailing material (such as the padded codes "I10X" in HES) are
+    /// still matched, and the status records whether the match was
+    /// exact. The groups of a nearest match are the groups of the
+    /// matched category (see find_nearest). Codes that are not found
+    /// are in no groups. Each distinct raw code is only parsed once.
+    pub fn group_membership(&self, codes: &[&str]) -> GroupMembership {
+        let _span = tracing::debug_span!("group_membership", num_codes = codes.len()).entered();
+
+        let mut groups: Vec<String> = self.groups.iter().cloned().collect();
+        groups.sort();
+
+        let mut code_store = ClinicalCodeStore::new();
+        let mut parsed = HashMap::new();
+        let mut status = Vec::with_capacity(codes.len());
+        let mut columns = vec![Vec::with_capacity(codes.len()); groups.len()];
+        for code in codes {
+            let (code_status, in_groups) = parsed.entry(*code).or_insert_with(|| {
+                match self.find_nearest(code.to_string(), &mut code_store) {
+                    Ok(code_match) => {
+                        let code_status = if code_match.is_exact() {
+                            ParseStatus::Exact
+                        } else {
+                            ParseStatus::Nearest
+                        };
+                        let in_groups: Vec<bool> = groups
+                            .iter()
+                            .map(|group| code_match.in_group(group))
+                            .collect();
+                        (code_status, in_groups)
+                    }
+                    Err(_) => (ParseStatus::NotFound, vec![false; groups.len()]),
+                }
+            });
+            status.push(*code_status);
+            for (column, in_group) in columns.iter_mut().zip(in_groups.iter()) {
+                column.push(*in_group);
+            }
+        }
+
+        GroupMembership {
+            codes: codes.iter().map(|code| code.to_string()).collect(),
+            status,
+            groups,
+            columns,
+        }
+    }
+}
+
+/// Tests for group membership
+#[cfg(test)]
+mod tests {
+
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn check_group_membership() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+
+        let codes = ["I48.0", "I21.0", "I480X", "123", "I48.0"];
+        let membership = code_tree.group_membership(&codes);
+        assert_eq!(membership.codes().len(), 5);
+        assert_eq!(
+            membership.status(),
+            &vec![
+                ParseStatus::Exact,
+                ParseStatus::Exact,
+                ParseStatus::Nearest,
+                ParseStatus::NotFound,
+                ParseStatus::Exact
+            ]
+        );
+        assert_eq!(
+            membership
+                .column("atrial_fib")
+                .expect("Group should be present"),
+            &vec![true, false, true, false, true]
+        );
+        assert_eq!(
+            membership
+                .column("acs_stemi_schnier")
+                .expect("Group should be present"),
+            &vec![false, true, false, false, false]
+        );
+        assert!(membership.column("unknown_group").is_none());
+        assert_eq!(membership.groups().len(), code_tree.groups().len());
+    }
+
+    #[cfg(any(feature = "synth", feature = "ingest"))]
+    #[test]
+    fn check_group_membership_to_polars() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+
+        let membership = code_tree.group_membership(&["I48.0", "123"]);
+        let df = membership.to_polars().expect("Failed to make DataFrame");
+        assert_eq!(df.shape(), (2, 2 + code_tree.groups().len()));
+        let atrial_fib: Vec<Option<bool>> = df
+            .column("atrial_fib")
+            .unwrap()
+            .bool()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(atrial_fib, vec![Some(true), Some(false)]);
+    }
+}
@@ -0,0 +1,340 @@
+//! Merging two code trees
+//!
+//! This is used to combine trees that mostly contain the same codes,
+//! for example two editions of ICD-10, or a base tree plus a few
+//! local hospital codes. Categories are matched between the trees
+//! by their normalised name at each level. Matching categories are
+//! merged, and categories present in only one tree are copied into
+//! the result.
+//!
+//! The groups of the merged tree are the union of the groups of the
+//! two trees. A code is in a group in the merged tree if it is in the
+//! group in either tree. The excludes are recalculated so that this
+//! is true, taking into account excludes inherited from the parent
+//! categories in each tree.
+
+use std::collections::{HashMap, HashSet};
+
+use super::validate::TreeDiagnostic;
+use super::{normalise_code, sort_categories_list_in_place, Categories, ClinicalCodeTree};
+use crate::error::HbrError;
+
+/// The groups that are only defined in one of the trees
+struct MergeGroups {
+    mine_only: HashSet<String>,
+    theirs_only: HashSet<String>,
+}
+
+/// The groups excluded by the parent categories (including
+/// excludes inherited from further up the tree), in each of the
+/// two trees and in the merged tree
+struct ParentExcludes {
+    mine: HashSet<String>,
+    theirs: HashSet<String>,
+    merged: HashSet<String>,
+}
+
+/// The groups excluded for a category, including the groups
+/// excluded by its parents
+fn effective_exclude(parent: &HashSet<String>, category: &Categories) -> HashSet<String> {
+    parent.union(&category.exclude()).cloned().collect()
+}
+
+/// Set the exclude of a category so that the groups excluded for
+/// it in the merged tree are merged_exclude
+fn set_exclude(
+    category: &mut Categories,
+    merged_exclude: &HashSet<String>,
+    parent_exclude: &HashSet<String>,
+) {
+    let exclude: HashSet<String> = merged_exclude.difference(parent_exclude).cloned().collect();
+    category.exclude = if exclude.is_empty() {
+        None
+    } else {
+        Some(exclude)
+    };
+}
+
+/// Check that two categories with the same name can be merged
+fn check_conflict(mine: &Categories, theirs: &Categories) -> Result<(), HbrError> {
+    let name = mine.name();
+    if mine.is_leaf() != theirs.is_leaf() {
+        Err(HbrError::MergeConflict(format!(
+            "{name} is a code in one tree and a category in the other"
+        )))
+    } else if mine.index() != theirs.index() {
+        Err(HbrError::MergeConflict(format!(
+            "{name} has different indices"
+        )))
+    } else if mine.is_leaf() && mine.docs() != theirs.docs() {
+        Err(HbrError::MergeConflict(format!(
+            "{name} has different descriptions"
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Add the metadata from theirs to mine, where name is the code,
+/// category or group the metadata is attached to. It is a conflict
+/// for the same key to have different values.
+fn merge_metadata(
+    mine: &mut HashMap<String, String>,
+    theirs: &HashMap<String, String>,
+    name: &str,
+) -> Result<(), HbrError> {
+    for (key, value) in theirs {
+        match mine.get(key) {
+            Some(existing) if existing != value => {
+                return Err(HbrError::MergeConflict(format!(
+                    "{name} has different values for metadata {key}"
+                )))
+            }
+            _ => {
+                mine.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Merge the categories in theirs into mine
+fn merge_categories(
+    mine: &mut Vec<Categories>,
+    theirs: &Vec<Categories>,
+    parent: &ParentExcludes,
+    groups: &MergeGroups,
+) -> Result<(), HbrError> {
+    let mut theirs_by_name: HashMap<String, &Categories> = theirs
+        .iter()
+        .map(|category| (normalise_code(category.name().clone()), category))
+        .collect();
+
+    for category in mine.iter_mut() {
+        let mine_exclude = effective_exclude(&parent.mine, category);
+        let merged_exclude = match theirs_by_name.remove(&normalise_code(category.name().clone())) {
+            Some(other) => {
+                check_conflict(category, other)?;
+                let name = category.name.clone();
+                merge_metadata(&mut category.metadata, &other.metadata, &name)?;
+                let theirs_exclude = effective_exclude(&parent.theirs, other);
+
+                // A group defined in both trees is only excluded if
+                // it is excluded in both trees
+                let merged_exclude: HashSet<String> = mine_exclude
+                    .iter()
+                    .filter(|group| {
+                        groups.mine_only.contains(*group) || theirs_exclude.contains(*group)
+                    })
+                    .chain(
+                        theirs_exclude
+                            .iter()
+                            .filter(|group| groups.theirs_only.contains(*group)),
+                    )
+                    .cloned()
+                    .collect();
+
+                if let (Some(sub_categories), Some(other_sub_categories)) =
+                    (&mut category.categories, other.categories())
+                {
+                    let excludes = ParentExcludes {
+                        mine: mine_exclude,
+                        theirs: theirs_exclude,
+                        merged: merged_exclude.clone(),
+                    };
+                    merge_categories(sub_categories, other_sub_categories, &excludes, groups)?;
+                }
+                merged_exclude
+            }
+            None => mine_exclude.union(&groups.theirs_only).cloned().collect(),
+        };
+        set_exclude(category, &merged_exclude, &parent.merged);
+    }
+
+    // Copy the categories that are only in theirs, keeping
+    // them in the same order as in the original tree
+    for other in theirs {
+        if theirs_by_name.contains_key(&normalise_code(other.name().clone())) {
+            let mut category = other.clone();
+            let merged_exclude: HashSet<String> = effective_exclude(&parent.theirs, other)
+                .union(&groups.mine_only)
+                .cloned()
+                .collect();
+            set_exclude(&mut category, &merged_exclude, &parent.merged);
+            mine.push(category);
+        }
+    }
+    Ok(())
+}
+
+impl ClinicalCodeTree {
+    /// Merge two code trees, returning the combined tree
+    ///
+    /// Codes and categories with the same name (after normalisation)
+    /// at the same level of the trees are treated as the same, and
+    /// everything else is copied from whichever tree it is in. The
+    /// groups of the result are the union of the groups in the two
+    /// trees, and each group contains the codes that are in the group
+    /// in either tree.
+    ///
+    /// Returns HbrError::MergeConflict if the trees disagree about a
+    /// code or category (a different description for the same code,
+    /// a different index, or a name that is a code in one tree and a
+    /// category in the other), if the trees use different code
+    /// normalisation, or if the merged tree has overlapping categories
+    /// or duplicate codes (which happens if the same code is in a
+    /// different place in the two trees).
+    pub fn merge(&self, other: &ClinicalCodeTree) -> Result<ClinicalCodeTree, HbrError> {
+        if self.normalisation != other.normalisation {
+            return Err(HbrError::MergeConflict(String::from(
+                "trees use different code normalisation",
+            )));
+        }
+
+        let groups = MergeGroups {
+            mine_only: self.groups.difference(&other.groups).cloned().collect(),
+            theirs_only: other.groups.difference(&self.groups).cloned().collect(),
+        };
+        let parent = ParentExcludes {
+            mine: HashSet::new(),
+            theirs: HashSet::new(),
+            merged: HashSet::new(),
+        };
+        let mut merged = self.clone();
+        merge_categories(&mut merged.categories, &other.categories, &parent, &groups)?;
+        sort_categories_list_in_place(&mut merged.categories);
+        merged.groups = self.groups.union(&other.groups).cloned().collect();
+        for (group, metadata) in other.group_metadata.iter() {
+            let merged_metadata = merged.group_metadata.entry(group.clone()).or_default();
+            merge_metadata(merged_metadata, metadata, group)?;
+        }
+        if merged.has_exact_index() {
+            merged.build_exact_index();
+        }
+
+        for diagnostic in merged.validate() {
+            if let TreeDiagnostic::OverlappingCategories { .. }
+            | TreeDiagnostic::DuplicateCode { .. } = diagnostic
+            {
+                return Err(HbrError::MergeConflict(diagnostic.to_string()));
+            }
+        }
+        Ok(merged)
+    }
+}
+
+/// Tests for merging code trees
+#[cfg(test)]
+mod tests {
+
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::clinical_code::ClinicalCodeStore;
+
+    const BASE_TREE: &str = r#"
+    categories:
+    - name: I21
+      docs: Acute myocardial infarction
+      index: I21
+      exclude: [bleeding]
+      categories:
+      - name: I21.0
+        docs: Acute transmural myocardial infarction of anterior wall
+        index: I210
+      - name: I21.9
+        docs: Acute myocardial infarction, unspecified
+        index: I219
+        exclude: [acs]
+    groups: [acs, bleeding]
+    "#;
+
+    const LOCAL_TREE: &str = r#"
+    categories:
+    - name: I21
+      docs: Acute myocardial infarction
+      index: I21
+      categories:
+      - name: I21.9
+        docs: Acute myocardial infarction, unspecified
+        index: I219
+      - name: I21.X
+        docs: Local code for suspected myocardial infarction
+        index: I21X
+        exclude: [acs]
+    - name: K92
+      docs: Other diseases of digestive system
+      index: K92
+      exclude: [local_mi]
+      categories:
+      - name: K92.2
+        docs: Gastrointestinal haemorrhage, unspecified
+        index: K922
+    groups: [acs, local_mi]
+    "#;
+
+    fn groups_of(tree: &ClinicalCodeTree, code: &str) -> Vec<String> {
+        let mut code_store = ClinicalCodeStore::new();
+        let code_match = tree
+            .find_exact(code.to_string(), &mut code_store)
+            .expect("Code should be present");
+        let mut groups: Vec<String> = code_match.groups().iter().cloned().collect();
+        groups.sort();
+        groups
+    }
+
+    #[test]
+    fn check_merge_combines_codes_and_groups() {
+        let base = ClinicalCodeTree::from_reader(BASE_TREE.as_bytes())
+            .expect("Failed to parse codes file");
+        let local = ClinicalCodeTree::from_reader(LOCAL_TREE.as_bytes())
+            .expect("Failed to parse codes file");
+        let merged = base.merge(&local).expect("Trees should merge");
+
+        assert_eq!(merged.groups().len(), 3);
+        assert_eq!(merged.validate(), vec![]);
+
+        // I21.0 is only in the base tree, so it is not in local_mi,
+        // and I21.9 is in acs in the local tree only
+        assert_eq!(groups_of(&merged, "I21.0"), vec!["acs"]);
+        assert_eq!(groups_of(&merged, "I21.9"), vec!["acs", "local_mi"]);
+        assert_eq!(groups_of(&merged, "I21.X"), vec!["local_mi"]);
+        // bleeding is only defined in the base tree, which does
+        // not contain K92.2
+        assert_eq!(groups_of(&merged, "K92.2"), vec!["acs"]);
+
+        let sizes = merged.group_sizes();
+        assert_eq!(sizes["acs"], 3);
+        assert_eq!(sizes["bleeding"], 0);
+        assert_eq!(sizes["local_mi"], 2);
+    }
+
+    #[test]
+    fn check_merge_with_itself_is_unchanged() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open codes file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        let merged = code_tree.merge(&code_tree).expect("Trees should merge");
+        assert!(code_tree.diff(&merged).is_empty());
+    }
+
+    #[test]
+    fn check_merge_conflicts_are_errors() {
+        let base = ClinicalCodeTree::from_reader(BASE_TREE.as_bytes())
+            .expect("Failed to parse codes file");
+        let conflicting = BASE_TREE.replace(
+            "docs: Acute myocardial infarction, unspecified",
+            "docs: Myocardial infarction",
+        );
+        let conflicting = ClinicalCodeTree::from_reader(conflicting.as_bytes())
+            .expect("Failed to parse codes file");
+        assert!(matches!(
+            base.merge(&conflicting),
+            Err(HbrError::MergeConflict(_))
+        ));
+    }
+}
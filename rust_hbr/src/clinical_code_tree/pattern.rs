@@ -0,0 +1,147 @@
+//! Wildcard queries for codes
+//!
+//! A pattern is a code containing the wildcards * (any number of
+//! characters, including none) and ? (exactly one character), such as
+//! I2* or I21.?. The pattern is normalised in the same way as codes
+//! (so I21.? and i21? are the same pattern) and compared with the
+//! normalised name of each code. The part of the pattern before the
+//! first wildcard is used as a code range, so that only the part of
+//! the tree that could match is searched.
+
+use super::range::{codes_in_range, CodeRange};
+use super::{visit_codes, ClinicalCodeTree};
+use crate::clinical_code::{ClinicalCodeRef, ClinicalCodeStore};
+use crate::error::HbrError;
+
+/// Returns true if the text matches the glob pattern (with the
+/// wildcards * and ?)
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|n| glob_match(rest, &text[n..])),
+        Some(('?', rest)) => !text.is_empty() && glob_match(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+impl ClinicalCodeTree {
+    /// Get all the codes whose (normalised) name matches a pattern
+    ///
+    /// The pattern can contain * to match any number of characters and
+    /// ? to match a single character; for example, I2* matches all the
+    /// codes starting with I2, and I21.? matches I21.0 to I21.9 but not
+    /// I21 or I21.01. A pattern with no wildcards only matches the code
+    /// itself. The codes are returned in tree order. Returns
+    /// HbrError::InvalidCodeRange if the pattern is empty.
+    pub fn codes_matching_pattern(
+        &self,
+        pattern: &str,
+        code_store: &mut ClinicalCodeStore,
+    ) -> Result<Vec<ClinicalCodeRef>, HbrError> {
+        let normalised: Vec<char> = self
+            .normalisation
+            .normalise(pattern.to_string())
+            .chars()
+            .collect();
+        if normalised.is_empty() {
+            return Err(HbrError::InvalidCodeRange(pattern.to_string()));
+        }
+
+        // Only search the codes starting with the fixed prefix
+        let prefix: String = normalised
+            .iter()
+            .take_while(|c| **c != '*' && **c != '?')
+            .collect();
+        let mut candidates = Vec::new();
+        if prefix.is_empty() {
+            visit_codes(&self.categories, &self.groups, &mut |code, _| {
+                candidates.push(code)
+            });
+        } else {
+            let range = CodeRange::parse(&prefix, &self.normalisation)?;
+            codes_in_range(
+                &range,
+                &self.categories,
+                &self.normalisation,
+                &mut candidates,
+            );
+        }
+
+        Ok(candidates
+            .iter()
+            .filter(|code| {
+                let name: Vec<char> = self
+                    .normalisation
+                    .normalise(code.name().clone())
+                    .chars()
+                    .collect();
+                glob_match(&normalised, &name)
+            })
+            .map(|code| code_store.clinical_code_ref_from_category(code))
+            .collect())
+    }
+}
+
+/// Tests for wildcard queries
+#[cfg(test)]
+mod tests {
+
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::name;
+
+    #[test]
+    fn check_glob_matching() {
+        let chars = |s: &str| s.chars().collect::<Vec<char>>();
+        assert!(glob_match(&chars("i2*"), &chars("i219")));
+        assert!(glob_match(&chars("i2*"), &chars("i2")));
+        assert!(glob_match(&chars("i21?"), &chars("i219")));
+        assert!(!glob_match(&chars("i21?"), &chars("i21")));
+        assert!(glob_match(&chars("*9"), &chars("i219")));
+        assert!(glob_match(&chars("i*1*"), &chars("i219")));
+        assert!(!glob_match(&chars("i21"), &chars("i219")));
+    }
+
+    #[test]
+    fn check_codes_matching_pattern() {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+        let mut code_store = ClinicalCodeStore::new();
+
+        // A prefix pattern gives the same codes as the range
+        let codes = code_tree
+            .codes_matching_pattern("I2*", &mut code_store)
+            .expect("Pattern should be valid");
+        let expected = code_tree
+            .expand_range("I2", &mut code_store)
+            .expect("Range should be valid");
+        assert_eq!(codes, expected);
+
+        let codes = code_tree
+            .codes_matching_pattern("I21.?", &mut code_store)
+            .expect("Pattern should be valid");
+        let names: Vec<&String> = codes.iter().map(|code| name!(code, code_store)).collect();
+        assert!(names.contains(&&format!("I21.0")));
+        assert!(names
+            .iter()
+            .all(|name| name.starts_with("I21.") && name.len() == 5));
+
+        // Patterns starting with a wildcard search the whole tree
+        let codes = code_tree
+            .codes_matching_pattern("*48.0", &mut code_store)
+            .expect("Pattern should be valid");
+        let names: Vec<&String> = codes.iter().map(|code| name!(code, code_store)).collect();
+        assert!(names.contains(&&format!("I48.0")));
+        assert!(names.iter().all(|name| name.ends_with("48.0")));
+
+        assert!(code_tree
+            .codes_matching_pattern("", &mut code_store)
+            .is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Expansion of code ranges
+//!
+//! Code groups in published papers are often given as ranges of
+//! codes, such as I20-I25 (all the codes from I20 up to and including
+//! everything under I25) or K55.0-K55.9. A code c is in the range
+//! (start, end) if truncating c to the length of start gives a string
+//! at least start, and truncating c to the length of end gives a
+//! string at most end. This is the same rule used for the Index of a
+//! category, which means that the index can be used to skip whole
+//! categories that do not overlap the range.
+
+use super::{Categories, ClinicalCodeTree, CodeNormalisation};
+use crate::clinical_code::{ClinicalCodeRef, ClinicalCodeStore};
+use crate::error::HbrError;
+
+/// Truncate a string to at most n characters
+fn truncate(s: &str, n: usize) -> &str {
+    s.get(..n).unwrap_or(s)
+}
+
+/// A range of codes, stored as normalised (lower case) codes
+pub(super) struct CodeRange {
+    start: String,
+    end: String,
+}
+
+impl CodeRange {
+    /// Parse a range written as start-end (or a single code, which is
+    /// the range containing the code and everything under it)
+    pub(super) fn parse(range: &str, normalisation: &CodeNormalisation) -> Result<Self, HbrError> {
+        let invalid = || HbrError::InvalidCodeRange(range.to_string());
+        let mut ends = range
+            .split('-')
+            .map(|code| normalisation.normalise(code.to_string()));
+        let start = ends.next().ok_or_else(invalid)?;
+        let end = ends.next().unwrap_or(start.clone());
+        if ends.next().is_some() || start.is_empty() || end.is_empty() {
+            return Err(invalid());
+        }
+        let length = start.len().min(end.len());
+        if truncate(&start, length) > truncate(&end, length) {
+            return Err(invalid());
+        }
+        Ok(Self { start, end })
+    }
+
+    /// Returns true if the (normalised) code is in the range
+    fn contains(&self, code: &str) -> bool {
+        truncate(code, self.start.len()) >= self.start.as_str()
+            && truncate(code, self.end.len()) <= self.end.as_str()
+    }
+
+    /// Returns true if some code in the category could be in the range,
+    /// based on the index of the category
+    fn overlaps(&self, category: &Categories) -> bool {
+        let (a, b) = category.index().range();
+        let below_start = {
+            let n = b.len().min(self.start.len());
+            truncate(b, n) < truncate(&self.start, n)
+        };
+        let above_end = {
+            let n = a.len().min(self.end.len());
+            truncate(a, n) > truncate(&self.end, n)
+        };
+        !below_start && !above_end
+    }
+}
+
+/// Collect all the codes (leaves) under the categories that are in
+/// the range, in tree order
+pub(super) fn codes_in_range<'a>(
+    range: &CodeRange,
+    categories: &'a [Categories],
+    normalisation: &CodeNormalisation,
+    codes: &mut Vec<&'a Categories>,
+) {
+    for category in categories.iter().filter(|cat| range.overlaps(cat)) {
+        match category.categories() {
+            Some(sub_categories) => codes_in_range(range, sub_categories, normalisation, codes),
+            None => {
+                let code = normalisation.normalise(category.name().clone());
+                if range.contains(&code) {
+                    codes.push(category);
+                }
+            }
+        }
+    }
+}
+
+impl ClinicalCodeTree {
+    /// Get all the codes in a range such as "I20-I25" or "K55.0-K55.9"
+    ///
+    /// The range includes every code that starts with the start or end
+    /// code, and every code in between (so I20-I25 includes I25.9). A
+    /// single code (with no -) is the range containing that code and
+    /// everything under it. The codes are returned in tree order, and
+    /// the result is empty if no codes are in the range. Returns
+    /// HbrError::InvalidCodeRange if the range cannot be parsed, or if
+    /// the start of the range is after the end.
+    pub fn expand_range(
+        &self,
+        range: &str,
+        code_store: &mut ClinicalCodeStore,
+    ) -> Result<Vec<ClinicalCodeRef>, HbrError> {
+        let range = CodeRange::parse(range, &self.normalisation)?;
+        let mut codes = Vec::new();
+        codes_in_range(&range, &self.categories, &self.normalisation, &mut codes);
+        Ok(codes
+            .into_iter()
+            .map(|code| code_store.clinical_code_ref_from_category(code))
+            .collect())
+    }
+}
+
+/// Tests for code ranges
+#[cfg(test)]
+mod tests {
+
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::name;
+
+    fn icd10_tree() -> ClinicalCodeTree {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push("icd10_example.yaml");
+
+        let f = std::fs::File::open(file_path).expect("Failed to open icd10 file");
+        ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file")
+    }
+
+    #[test]
+    fn check_range_of_categories_expands_to_codes() {
+        let code_tree = icd10_tree();
+        let mut code_store = ClinicalCodeStore::new();
+
+        let codes = code_tree
+            .expand_range("I20-I25", &mut code_store)
+            .expect("Range should be valid");
+        let names: Vec<&String> = codes.iter().map(|code| name!(code, code_store)).collect();
+        assert!(names.contains(&&format!("I20.0")));
+        assert!(names.contains(&&format!("I21.9")));
+        assert!(names.contains(&&format!("I25.9")));
+        assert!(names
+            .iter()
+            .all(|name| name.as_str() >= "I20" && name.as_str() < "I26"));
+
+        // Same codes as expanding each category separately
+        let mut count = 0;
+        for category in ["I20", "I21", "I22", "I23", "I24", "I25"] {
+            count += code_tree
+                .expand_range(category, &mut code_store)
+                .expect("Range should be valid")
+                .len();
+        }
+        assert_eq!(codes.len(), count);
+    }
+
+    #[test]
+    fn check_range_of_codes_expands_to_codes() {
+        let code_tree = icd10_tree();
+        let mut code_store = ClinicalCodeStore::new();
+
+        let codes = code_tree
+            .expand_range("I21.0 - I21.2", &mut code_store)
+            .expect("Range should be valid");
+        let names: Vec<&String> = codes.iter().map(|code| name!(code, code_store)).collect();
+        assert_eq!(names, vec!["I21.0", "I21.1", "I21.2"]);
+    }
+
+    #[test]
+    fn check_invalid_ranges_are_errors() {
+        let code_tree = icd10_tree();
+        let mut code_store = ClinicalCodeStore::new();
+        for range in ["I25-I20", "I20-", "I20-I21-I22", ""] {
+            assert!(matches!(
+                code_tree.expand_range(range, &mut code_store),
+                Err(HbrError::InvalidCodeRange(_))
+            ));
+        }
+    }
+}
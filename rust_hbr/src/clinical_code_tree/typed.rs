@@ -0,0 +1,138 @@
+//! Code trees for diagnoses and procedures
+//!
+//! The same ClinicalCodeTree type is used for ICD-10 (diagnoses) and
+//! OPCS-4 (procedures), so nothing stops a program from looking up a
+//! procedure code in the diagnosis tree, which silently gives the
+//! wrong result (or none at all). DiagnosisCodeTree and
+//! ProcedureCodeTree wrap a ClinicalCodeTree and return DiagnosisCode
+//! or ProcedureCode references, so a mix-up becomes a compile error
+//! wherever the result is used (e.g. when building an Episode).
+//!
+//! The groups containing a code found using find_exact are recorded
+//! in the code store (see ClinicalCodeStore::groups_of).
+
+use std::io::Read;
+
+use super::ClinicalCodeTree;
+use crate::clinical_code::{ClinicalCodeStore, DiagnosisCode, ProcedureCode};
+use crate::error::HbrError;
+
+/// Define a code tree wrapper that returns references of
+/// the typed code $code
+macro_rules! typed_code_tree {
+    ($(#[$meta:meta])* $tree:ident, $code:ident) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct $tree(ClinicalCodeTree);
+
+        impl $tree {
+            /// Wrap a code tree (it is up to the caller to make
+            /// sure the tree contains the right kind of codes)
+            pub fn new(code_tree: ClinicalCodeTree) -> Self {
+                Self(code_tree)
+            }
+
+            /// Read the code tree from a codes file (see
+            /// ClinicalCodeTree::from_reader)
+            pub fn from_reader<R: Read>(reader: R) -> Result<Self, HbrError> {
+                Ok(Self(ClinicalCodeTree::from_reader(reader)?))
+            }
+
+            /// Get the underlying code tree, for operations that
+            /// do not return code references
+            pub fn tree(&self) -> &ClinicalCodeTree {
+                &self.0
+            }
+
+            /// Get back the underlying code tree
+            pub fn into_inner(self) -> ClinicalCodeTree {
+                self.0
+            }
+
+            /// Find an exact match for a code (see
+            /// ClinicalCodeTree::find_exact)
+            pub fn find_exact(
+                &self,
+                code: String,
+                code_store: &mut ClinicalCodeStore,
+            ) -> Result<$code, HbrError> {
+                let code_match = self.0.find_exact(code, code_store)?;
+                Ok($code::from(code_match.code()))
+            }
+
+            /// Get all the codes in a group (see
+            /// ClinicalCodeTree::codes_in_group)
+            pub fn codes_in_group(
+                &self,
+                group: &String,
+                code_store: &mut ClinicalCodeStore,
+            ) -> Result<Vec<$code>, HbrError> {
+                let codes = self.0.codes_in_group(group, code_store)?;
+                Ok(codes.into_iter().map($code::from).collect())
+            }
+        }
+    };
+}
+
+typed_code_tree!(
+    /// A code tree of diagnosis codes (e.g. ICD-10)
+    DiagnosisCodeTree,
+    DiagnosisCode
+);
+
+typed_code_tree!(
+    /// A code tree of procedure codes (e.g. OPCS-4)
+    ProcedureCodeTree,
+    ProcedureCode
+);
+
+/// Tests for the typed code trees
+#[cfg(test)]
+mod tests {
+
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::name;
+
+    fn open_codes_file(file_name: &str) -> std::fs::File {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources");
+        file_path.push("test");
+        file_path.push(file_name);
+        std::fs::File::open(file_path).expect("Failed to open codes file")
+    }
+
+    #[test]
+    fn check_typed_trees_return_typed_codes() {
+        let diagnosis_tree = DiagnosisCodeTree::from_reader(open_codes_file("icd10_example.yaml"))
+            .expect("Failed to parse codes file");
+        let procedure_tree = ProcedureCodeTree::from_reader(open_codes_file("opcs4_example.yaml"))
+            .expect("Failed to parse codes file");
+        let mut code_store = ClinicalCodeStore::new();
+
+        let diagnosis: DiagnosisCode = diagnosis_tree
+            .find_exact(format!("I21.0"), &mut code_store)
+            .expect("Code should be present");
+        assert_eq!(name!(diagnosis.clinical_code_ref(), code_store), "I21.0");
+        let groups = code_store
+            .groups_of(&diagnosis.clinical_code_ref())
+            .expect("Groups should be recorded");
+        assert!(groups.contains("acs_stemi_schnier"));
+
+        let procedure: ProcedureCode = procedure_tree
+            .find_exact(format!("K75.1"), &mut code_store)
+            .expect("Code should be present");
+        assert_eq!(name!(procedure.clinical_code_ref(), code_store), "K75.1");
+        let code_match = procedure_tree
+            .tree()
+            .find_exact(format!("K75.1"), &mut code_store)
+            .expect("Code should be present");
+        assert_eq!(procedure.clinical_code_ref(), code_match.code());
+
+        let codes = diagnosis_tree
+            .codes_in_group(&format!("atrial_fib"), &mut code_store)
+            .expect("Group should exist");
+        assert!(!codes.is_empty());
+    }
+}
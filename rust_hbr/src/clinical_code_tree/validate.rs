@@ -0,0 +1,507 @@
+//! Validation of the structure of a code tree
+//!
+//! The parser assumes that the indices of the categories are well
+//! formed, and that the categories at each level are sorted and do
+//! not overlap, so that a binary search finds the right category.
+//! Hand-edited codes files can break these assumptions without any
+//! error when the file is loaded; instead, codes silently fail to
+//! match. The validate() function checks for these problems.
+//!
+//! Group names are free text in the codes file, so a typo in a group
+//! name (in the groups list or in an exclude) silently changes which
+//! codes are in the group. The validate_group_names() function checks
+//! the group names against a naming convention and/or a list of
+//! allowed names (see GroupNameRules).
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use super::{normalise_code, Categories, ClinicalCodeTree, Index};
+use crate::error::HbrError;
+
+/// A problem found in a code tree by validate()
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeDiagnostic {
+    /// A Dual index (a, b) where b < a
+    ReversedIndex { category: String },
+    /// A Dual index (a, b) where a and b have different lengths
+    IndexLengthMismatch { category: String },
+    /// Two neighbouring categories (in sorted order) have
+    /// overlapping index ranges
+    OverlappingCategories { first: String, second: String },
+    /// A sub-category has an index range that is not inside the
+    /// range of its parent, so it can never be reached
+    OutsideParentRange { category: String, parent: String },
+    /// A category excludes a group that is not in the groups list
+    UnknownExcludeGroup { category: String, group: String },
+    /// More than one code (leaf) has the same name
+    DuplicateCode { name: String, count: usize },
+    /// A group name does not follow the naming convention
+    /// (see GroupNameRules)
+    GroupNameConvention { group: String },
+    /// A group is not in the list of allowed groups
+    /// (see GroupNameRules)
+    GroupNotAllowed { group: String },
+}
+
+impl fmt::Display for TreeDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReversedIndex { category } => {
+                write!(f, "index of {category} ends before it starts")
+            }
+            Self::IndexLengthMismatch { category } => {
+                write!(
+                    f,
+                    "index of {category} has start and end of different lengths"
+                )
+            }
+            Self::OverlappingCategories { first, second } => {
+                write!(f, "index ranges of {first} and {second} overlap")
+            }
+            Self::OutsideParentRange { category, parent } => {
+                write!(
+                    f,
+                    "index range of {category} is outside its parent {parent}"
+                )
+            }
+            Self::UnknownExcludeGroup { category, group } => {
+                write!(f, "{category} excludes undefined group {group}")
+            }
+            Self::DuplicateCode { name, count } => {
+                write!(f, "code {name} appears {count} times")
+            }
+            Self::GroupNameConvention { group } => {
+                write!(f, "group name {group} does not follow the naming convention")
+            }
+            Self::GroupNotAllowed { group } => {
+                write!(f, "group {group} is not in the list of allowed groups")
+            }
+        }
+    }
+}
+
+/// A naming convention for group names
+#[derive(Debug, Clone, Copy)]
+pub enum GroupNamingConvention {
+    /// Lower case ASCII letters, digits and underscores, starting
+    /// with a letter (e.g. acs_stemi_schnier)
+    SnakeCase,
+    /// Any convention, given as a function that returns true if
+    /// the group name is valid
+    Custom(fn(&str) -> bool),
+}
+
+impl GroupNamingConvention {
+    /// Returns true if the group name follows the convention
+    pub fn is_valid(&self, group: &str) -> bool {
+        match self {
+            Self::SnakeCase => {
+                group.starts_with(|c: char| c.is_ascii_lowercase())
+                    && group
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            }
+            Self::Custom(is_valid) => is_valid(group),
+        }
+    }
+}
+
+/// The rules that group names must follow (see
+/// ClinicalCodeTree::validate_group_names). By default, there
+/// are no rules.
+#[derive(Debug, Clone, Default)]
+pub struct GroupNameRules {
+    convention: Option<GroupNamingConvention>,
+    allowed: Option<HashSet<String>>,
+}
+
+impl GroupNameRules {
+    /// Make a set of rules with no naming convention and no
+    /// list of allowed groups
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require group names to follow a naming convention
+    pub fn with_convention(mut self, convention: GroupNamingConvention) -> Self {
+        self.convention = Some(convention);
+        self
+    }
+
+    /// Require group names to be in a list of allowed groups
+    pub fn with_allowed_groups<S: AsRef<str>>(mut self, groups: &[S]) -> Self {
+        self.allowed = Some(groups.iter().map(|g| g.as_ref().to_string()).collect());
+        self
+    }
+
+    /// Check one group name, adding any problems to the
+    /// diagnostics list
+    fn check(&self, group: &str, diagnostics: &mut Vec<TreeDiagnostic>) {
+        if let Some(convention) = &self.convention {
+            if !convention.is_valid(group) {
+                diagnostics.push(TreeDiagnostic::GroupNameConvention {
+                    group: group.to_string(),
+                });
+            }
+        }
+        if let Some(allowed) = &self.allowed {
+            if !allowed.contains(group) {
+                diagnostics.push(TreeDiagnostic::GroupNotAllowed {
+                    group: group.to_string(),
+                });
+            }
+        }
+    }
+}
+
+/// Check that the groups excluded by a category are defined,
+/// adding any problems to the diagnostics list
+fn check_exclude_groups(
+    category: &Categories,
+    groups: &HashSet<String>,
+    diagnostics: &mut Vec<TreeDiagnostic>,
+) {
+    for group in category.exclude.iter().flatten() {
+        if !groups.contains(group) {
+            diagnostics.push(TreeDiagnostic::UnknownExcludeGroup {
+                category: category.name().clone(),
+                group: group.clone(),
+            });
+        }
+    }
+}
+
+/// Check the excludes of the categories (and everything underneath
+/// them), adding any problems to the diagnostics list
+fn validate_exclude_groups(
+    categories: &Vec<Categories>,
+    groups: &HashSet<String>,
+    diagnostics: &mut Vec<TreeDiagnostic>,
+) {
+    for category in categories {
+        check_exclude_groups(category, groups, diagnostics);
+        if let Some(sub_categories) = category.categories() {
+            validate_exclude_groups(sub_categories, groups, diagnostics);
+        }
+    }
+}
+
+/// Returns true if the index range of the category is inside the
+/// index range of the parent
+fn inside_range(parent: &Index, category: &Index) -> bool {
+    let (start, end) = category.range();
+    parent.contains(start) && parent.contains(end)
+}
+
+/// Returns true if the range (in sorted order) of the first
+/// index overlaps with the range of the second
+fn ranges_overlap(first: &Index, second: &Index) -> bool {
+    let (_, first_end) = first.range();
+    let (second_start, _) = second.range();
+    let truncated = second_start.get(..first_end.len()).unwrap_or(second_start);
+    truncated <= first_end.as_str()
+}
+
+/// Check that the index of a category is well formed and inside
+/// the range of its parent, adding any problems to the diagnostics
+/// list
+fn check_index(
+    category: &Categories,
+    parent: Option<&Categories>,
+    diagnostics: &mut Vec<TreeDiagnostic>,
+) {
+    if let Index::Dual(a, b) = category.index() {
+        if b < a {
+            diagnostics.push(TreeDiagnostic::ReversedIndex {
+                category: category.name().clone(),
+            });
+        }
+        if a.len() != b.len() {
+            diagnostics.push(TreeDiagnostic::IndexLengthMismatch {
+                category: category.name().clone(),
+            });
+        }
+    }
+    if let Some(parent) = parent {
+        if !inside_range(parent.index(), category.index()) {
+            diagnostics.push(TreeDiagnostic::OutsideParentRange {
+                category: category.name().clone(),
+                parent: parent.name().clone(),
+            });
+        }
+    }
+}
+
+/// Check the indices of the categories (and everything underneath
+/// them), adding any problems to the diagnostics list
+fn validate_indices(
+    categories: &Vec<Categories>,
+    parent: Option<&Categories>,
+    diagnostics: &mut Vec<TreeDiagnostic>,
+) {
+    for category in categories {
+        check_index(category, parent, diagnostics);
+        if let Some(sub_categories) = category.categories() {
+            validate_indices(sub_categories, Some(category), diagnostics);
+        }
+    }
+}
+
+/// Check the indices of a tree that has just been read from a codes
+/// file (see ClinicalCodeTree::from_reader). Returns
+/// HbrError::InvalidIndex listing the malformed indices (reversed or
+/// mismatched ranges, and sub-categories outside their parent) if
+/// there are any.
+pub(super) fn check_indices(categories: &Vec<Categories>) -> Result<(), HbrError> {
+    let mut diagnostics = Vec::new();
+    validate_indices(categories, None, &mut diagnostics);
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(HbrError::InvalidIndex(diagnostics))
+    }
+}
+
+/// Check the categories (and everything underneath them), adding
+/// any problems to the diagnostics list
+fn validate_categories(
+    categories: &Vec<Categories>,
+    parent: Option<&Categories>,
+    groups: &HashSet<String>,
+    code_counts: &mut HashMap<String, usize>,
+    diagnostics: &mut Vec<TreeDiagnostic>,
+) {
+    for category in categories {
+        let name = category.name().clone();
+        check_index(category, parent, diagnostics);
+        check_exclude_groups(category, groups, diagnostics);
+        match category.categories() {
+            Some(sub_categories) => validate_categories(
+                sub_categories,
+                Some(category),
+                groups,
+                code_counts,
+                diagnostics,
+            ),
+            None => *code_counts.entry(normalise_code(name)).or_insert(0) += 1,
+        }
+    }
+
+    // The categories are sorted, so only neighbours can overlap
+    for pair in categories.windows(2) {
+        if ranges_overlap(pair[0].index(), pair[1].index()) {
+            diagnostics.push(TreeDiagnostic::OverlappingCategories {
+                first: pair[0].name().clone(),
+                second: pair[1].name().clone(),
+            });
+        }
+    }
+}
+
+impl ClinicalCodeTree {
+    /// Check the structure of the code tree
+    ///
+    /// Returns the list of problems found (empty if the tree is
+    /// valid). The checks are for malformed indices (a Dual index
+    /// that ends before it starts, or with start and end of different
+    /// lengths), neighbouring categories whose ranges overlap,
+    /// sub-categories outside the range of their parent, excludes
+    /// of groups that are not defined, and codes that appear more
+    /// than once.
+    pub fn validate(&self) -> Vec<TreeDiagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut code_counts = HashMap::new();
+        validate_categories(
+            &self.categories,
+            None,
+            &self.groups,
+            &mut code_counts,
+            &mut diagnostics,
+        );
+        let mut duplicates: Vec<(String, usize)> = code_counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .collect();
+        duplicates.sort();
+        for (name, count) in duplicates {
+            diagnostics.push(TreeDiagnostic::DuplicateCode { name, count });
+        }
+        diagnostics
+    }
+
+    /// Check the group names in the tree against a set of rules
+    ///
+    /// Every group in the groups list is checked against the naming
+    /// convention and the list of allowed groups (if given in the
+    /// rules). Groups that are excluded by a category but are not in
+    /// the groups list are also returned (as UnknownExcludeGroup),
+    /// because they are usually a misspelling of a defined group.
+    /// Returns the list of problems found (empty if all the names are
+    /// valid).
+    pub fn validate_group_names(&self, rules: &GroupNameRules) -> Vec<TreeDiagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut groups: Vec<&String> = self.groups.iter().collect();
+        groups.sort();
+        for group in groups {
+            rules.check(group, &mut diagnostics);
+        }
+        validate_exclude_groups(&self.categories, &self.groups, &mut diagnostics);
+        diagnostics
+    }
+}
+
+/// Tests for code tree validation
+#[cfg(test)]
+mod tests {
+
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn check_example_files_are_valid() {
+        for file_name in ["icd10_example.yaml", "opcs4_example.yaml"] {
+            let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            file_path.push("resources");
+            file_path.push("test");
+            file_path.push(file_name);
+
+            let f = std::fs::File::open(file_path).expect("Failed to open codes file");
+            let code_tree = ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file");
+            assert_eq!(code_tree.validate(), vec![]);
+        }
+    }
+
+    #[test]
+    fn check_problems_are_found() {
+        let yaml = r#"
+        categories:
+        - name: cat1
+          docs: category 1
+          index: [B00, A99]
+          categories:
+          - name: A00.1
+            docs: code 1
+            index: A001
+          - name: C00.1
+            docs: outside the parent
+            index: C001
+        - name: cat2
+          docs: category 2
+          index: [B10, B2]
+          exclude: [group1, group2]
+          categories:
+          - name: B10.0
+            docs: code 2
+            index: B100
+          - name: B10.0
+            docs: duplicate of code 2
+            index: B1000
+        groups:
+        - group1
+        "#;
+
+        // The malformed indices are rejected when the file is loaded
+        let result = ClinicalCodeTree::from_reader(yaml.as_bytes());
+        let Err(HbrError::InvalidIndex(index_diagnostics)) = result else {
+            panic!("Expected malformed indices to be rejected, got {result:?}");
+        };
+        assert!(index_diagnostics.contains(&TreeDiagnostic::ReversedIndex {
+            category: format!("cat1"),
+        }));
+        assert!(index_diagnostics.contains(&TreeDiagnostic::IndexLengthMismatch {
+            category: format!("cat2"),
+        }));
+        assert!(!index_diagnostics
+            .iter()
+            .any(|d| matches!(d, TreeDiagnostic::DuplicateCode { .. })));
+
+        let code_tree = ClinicalCodeTree::from_reader_unchecked(yaml.as_bytes())
+            .expect("Failed to parse codes file");
+        let diagnostics = code_tree.validate();
+
+        let expected = [
+            TreeDiagnostic::ReversedIndex {
+                category: format!("cat1"),
+            },
+            TreeDiagnostic::OutsideParentRange {
+                category: format!("A00.1"),
+                parent: format!("cat1"),
+            },
+            TreeDiagnostic::IndexLengthMismatch {
+                category: format!("cat2"),
+            },
+            TreeDiagnostic::UnknownExcludeGroup {
+                category: format!("cat2"),
+                group: format!("group2"),
+            },
+            TreeDiagnostic::OverlappingCategories {
+                first: format!("B10.0"),
+                second: format!("B10.0"),
+            },
+            TreeDiagnostic::DuplicateCode {
+                name: format!("b100"),
+                count: 2,
+            },
+        ];
+        for diagnostic in expected {
+            assert!(
+                diagnostics.contains(&diagnostic),
+                "missing {diagnostic} in {diagnostics:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn check_group_name_rules() {
+        let yaml = r#"
+        categories:
+        - name: cat1
+          docs: category 1
+          index: [A00, A99]
+          exclude: [bleeding_major, Bleeding]
+          categories:
+          - name: A00.1
+            docs: code 1
+            index: A001
+            exclude: [bleding]
+        groups:
+        - bleeding_major
+        - Bleeding
+        - ischaemia_2
+        "#;
+        let code_tree =
+            ClinicalCodeTree::from_reader(yaml.as_bytes()).expect("Failed to parse codes file");
+
+        // With no rules, only undefined excluded groups are found
+        assert_eq!(
+            code_tree.validate_group_names(&GroupNameRules::new()),
+            vec![TreeDiagnostic::UnknownExcludeGroup {
+                category: format!("A00.1"),
+                group: format!("bleding"),
+            }]
+        );
+
+        let rules = GroupNameRules::new()
+            .with_convention(GroupNamingConvention::SnakeCase)
+            .with_allowed_groups(&["bleeding_major", "ischaemia_2"]);
+        let diagnostics = code_tree.validate_group_names(&rules);
+        assert_eq!(
+            diagnostics[..2],
+            [
+                TreeDiagnostic::GroupNameConvention {
+                    group: format!("Bleeding"),
+                },
+                TreeDiagnostic::GroupNotAllowed {
+                    group: format!("Bleeding"),
+                },
+            ]
+        );
+        assert_eq!(diagnostics.len(), 3);
+
+        let rules = GroupNameRules::new()
+            .with_convention(GroupNamingConvention::Custom(|group| !group.contains('_')));
+        assert_eq!(code_tree.validate_group_names(&rules).len(), 3);
+    }
+}
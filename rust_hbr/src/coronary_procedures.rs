@@ -0,0 +1,415 @@
+//! The coronary procedures table contains coronary angiograms and
+//! percutaneous coronary interventions (PCI), with the procedural
+//! details that are used as covariates in the bleeding risk models.
+//! Each row is one procedure, with its start and end times, the access
+//! site (radial or femoral), and for PCI the type of stent and the
+//! vessels treated.
+//!
+//! The subjects are the patients in the registry who have an acute
+//! coronary syndrome (see PatientConditions), who also have ACS
+//! diagnoses in the episodes table, so that the procedures can be
+//! linked to ACS index events.
+
+use crate::seeded_column_block::{make_chunks, to_polars, SeededColumnBlock, SyntheticRows};
+use crate::seeded_rng::make_rng;
+use crate::synth_data::{
+    DurationRange, EventTiming, GeneratorVersion, PatientRegistry, SyntheticPatient,
+};
+use chrono::NaiveDateTime;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+use polars::prelude::*;
+
+/// The default length of a procedure (20 minutes to two hours)
+pub const DEFAULT_PROCEDURE_DURATION: DurationRange = DurationRange {
+    min_minutes: 20,
+    max_minutes: 120,
+};
+
+/// The coronary vessels that can be treated (left main stem, left
+/// anterior descending, left circumflex and right coronary artery), in
+/// the order they are listed in vessels_treated
+const VESSELS: [&str; 4] = ["LMS", "LAD", "LCx", "RCA"];
+
+/// The details of one procedure
+#[derive(Debug, Clone, PartialEq)]
+struct Procedure {
+    /// angiography (diagnostic only) or pci
+    procedure_type: &'static str,
+    /// radial or femoral
+    access_site: &'static str,
+    /// DES (drug-eluting stent), BMS (bare-metal stent), or none
+    /// (balloon angioplasty only). None if the procedure is not a PCI.
+    stent_type: Option<&'static str>,
+    /// The vessels treated, separated by semicolons (e.g. "LAD;RCA").
+    /// None if the procedure is not a PCI.
+    vessels_treated: Option<String>,
+}
+
+/// Pick the vessels treated in a PCI (one vessel in 70% of procedures,
+/// two in 25% and three in 5%). The LAD is treated most often, and the
+/// left main stem least often.
+fn make_vessels_treated(rng: &mut ChaCha8Rng) -> String {
+    let num_vessels = match rng.gen_range(0..20) {
+        0..=13 => 1,
+        14..=18 => 2,
+        _ => 3,
+    };
+    let weights = [1, 8, 4, 5];
+    let vessels = VESSELS
+        .iter()
+        .zip(weights)
+        .collect::<Vec<_>>()
+        .choose_multiple_weighted(rng, num_vessels, |(_, weight)| *weight)
+        .expect("Vessel weights are valid")
+        .map(|(vessel, _)| **vessel)
+        .collect::<Vec<_>>();
+    // List the vessels in a fixed order
+    VESSELS
+        .into_iter()
+        .filter(|vessel| vessels.contains(vessel))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Pick a procedure. 70% of procedures are PCI and the others are
+/// diagnostic angiograms. Radial access is used in 85% of procedures,
+/// and most PCIs use drug-eluting stents.
+fn make_procedure(rng: &mut ChaCha8Rng) -> Procedure {
+    let pci = rng.gen_bool(0.7);
+    let access_site = if rng.gen_bool(0.85) {
+        "radial"
+    } else {
+        "femoral"
+    };
+    if !pci {
+        return Procedure {
+            procedure_type: "angiography",
+            access_site,
+            stent_type: None,
+            vessels_treated: None,
+        };
+    }
+    let stent_type = match rng.gen_range(0..20) {
+        0..=17 => "DES",
+        18 => "BMS",
+        _ => "none",
+    };
+    Procedure {
+        procedure_type: "pci",
+        access_site,
+        stent_type: Some(stent_type),
+        vessels_treated: Some(make_vessels_treated(rng)),
+    }
+}
+
+/// The indices of the patients in the registry who have an ACS, or all
+/// the patients if none of them do (which can only happen in a very
+/// small registry)
+fn acs_patients(registry: &PatientRegistry) -> Vec<usize> {
+    let acs_patients: Vec<_> = registry
+        .patients()
+        .iter()
+        .enumerate()
+        .filter(|(_, patient)| patient.conditions.acs)
+        .map(|(n, _)| n)
+        .collect();
+    if acs_patients.is_empty() {
+        (0..registry.num_patients()).collect()
+    } else {
+        acs_patients
+    }
+}
+
+/// Pick a patient uniformly from the patients with an ACS
+fn sample_acs_patient<'a>(
+    rng: &mut ChaCha8Rng,
+    registry: &'a PatientRegistry,
+    acs_patients: &[usize],
+) -> &'a SyntheticPatient {
+    let n = acs_patients.choose(rng).expect("Registry is not empty");
+    &registry.patients()[*n]
+}
+
+/// One row of the coronary procedures table
+#[derive(Debug)]
+pub struct CoronaryProceduresRow {
+    pub subject: String,
+    pub procedure_start_time: NaiveDateTime,
+    pub procedure_end_time: NaiveDateTime,
+    pub procedure_type: String,
+    pub access_site: String,
+    pub stent_type: Option<String>,
+    pub vessels_treated: Option<String>,
+}
+
+/// Iterator over the rows of the coronary procedures table (see
+/// PathologyBloodRows for how the column blocks are seeded)
+pub struct CoronaryProceduresRows {
+    registry: PatientRegistry,
+    /// The indices of the patients the subjects are picked from
+    acs_patients: Vec<usize>,
+    timing: EventTiming,
+    subject_rng: ChaCha8Rng,
+    procedure_time_rng: ChaCha8Rng,
+    procedure_rng: ChaCha8Rng,
+    rows_left: usize,
+}
+
+impl CoronaryProceduresRows {
+    /// Make an iterator that will return num_rows rows. The arguments
+    /// have the same meaning as in make_coronary_procedures.
+    pub fn new(
+        block_id: &str,
+        registry: &PatientRegistry,
+        timing: EventTiming,
+        num_rows: usize,
+    ) -> Self {
+        let global_seed = registry.global_seed();
+        let subject_block_id = format!("{block_id}subject");
+        let procedure_time_block_id = format!("{block_id}procedure_time");
+        let procedure_block_id = format!("{block_id}procedure");
+        Self {
+            registry: registry.clone(),
+            acs_patients: acs_patients(registry),
+            timing,
+            subject_rng: make_rng(global_seed, subject_block_id.as_ref()),
+            procedure_time_rng: make_rng(global_seed, procedure_time_block_id.as_ref()),
+            procedure_rng: make_rng(global_seed, procedure_block_id.as_ref()),
+            rows_left: num_rows,
+        }
+    }
+}
+
+impl Iterator for CoronaryProceduresRows {
+    type Item = CoronaryProceduresRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rows_left == 0 {
+            return None;
+        }
+        self.rows_left -= 1;
+
+        let patient = sample_acs_patient(&mut self.subject_rng, &self.registry, &self.acs_patients);
+        let (procedure_start_time, procedure_end_time) =
+            self.timing.pick(&mut self.procedure_time_rng);
+        let procedure = make_procedure(&mut self.procedure_rng);
+        Some(CoronaryProceduresRow {
+            subject: patient.subject.clone(),
+            procedure_start_time,
+            procedure_end_time,
+            procedure_type: String::from(procedure.procedure_type),
+            access_site: String::from(procedure.access_site),
+            stent_type: procedure.stent_type.map(String::from),
+            vessels_treated: procedure.vessels_treated,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.rows_left, Some(self.rows_left))
+    }
+}
+
+impl SyntheticRows for CoronaryProceduresRows {
+    fn take_chunk(&mut self, chunk_size: usize) -> DataFrame {
+        let num_rows = chunk_size.min(self.rows_left);
+        self.rows_left -= num_rows;
+        let Self {
+            registry,
+            acs_patients,
+            timing,
+            subject_rng,
+            procedure_time_rng,
+            procedure_rng,
+            ..
+        } = self;
+
+        let (subject, (procedure_times, procedures)) = rayon::join(
+            || {
+                let subject: Vec<_> = (0..num_rows)
+                    .map(|_| {
+                        sample_acs_patient(subject_rng, registry, acs_patients)
+                            .subject
+                            .as_str()
+                    })
+                    .collect();
+                Series::new("subject", subject)
+            },
+            || {
+                rayon::join(
+                    || {
+                        (0..num_rows)
+                            .map(|_| timing.pick(procedure_time_rng))
+                            .collect::<Vec<_>>()
+                    },
+                    || {
+                        (0..num_rows)
+                            .map(|_| make_procedure(procedure_rng))
+                            .collect::<Vec<_>>()
+                    },
+                )
+            },
+        );
+
+        let (procedure_start_time, procedure_end_time): (Vec<_>, Vec<_>) =
+            procedure_times.into_iter().unzip();
+        let mut procedure_type = Vec::with_capacity(num_rows);
+        let mut access_site = Vec::with_capacity(num_rows);
+        let mut stent_type = Vec::with_capacity(num_rows);
+        let mut vessels_treated = Vec::with_capacity(num_rows);
+        for procedure in procedures {
+            procedure_type.push(procedure.procedure_type);
+            access_site.push(procedure.access_site);
+            stent_type.push(procedure.stent_type);
+            vessels_treated.push(procedure.vessels_treated);
+        }
+
+        let seeded_column_blocks = vec![
+            SeededColumnBlock {
+                columns: vec![subject],
+            },
+            SeededColumnBlock {
+                columns: vec![
+                    Series::new("procedure_start_time", procedure_start_time),
+                    Series::new("procedure_end_time", procedure_end_time),
+                ],
+            },
+            SeededColumnBlock {
+                columns: vec![
+                    Series::new("procedure_type", procedure_type),
+                    Series::new("access_site", access_site),
+                    Series::new("stent_type", stent_type),
+                    Series::new("vessels_treated", vessels_treated),
+                ],
+            },
+        ];
+        to_polars(seeded_column_blocks)
+    }
+}
+
+/// Create the coronary procedures table, with one row per angiogram or
+/// PCI (see the module documentation). Unlike the other tables, the
+/// subjects are only picked from the patients in the registry with an
+/// ACS. The columns are:
+///
+/// * subject: the patient id
+/// * procedure_start_time, procedure_end_time: when the procedure
+///   started and finished
+/// * procedure_type: angiography or pci
+/// * access_site: radial or femoral
+/// * stent_type: DES, BMS or none (balloon only) for a PCI, and null
+///   for an angiogram
+/// * vessels_treated: the vessels treated in a PCI, separated by
+///   semicolons in the order LMS, LAD, LCx, RCA (e.g. "LAD;RCA"), and
+///   null for an angiogram
+///
+/// The procedures start at times picked from the window in the timing,
+/// and last for a duration picked from its duration range (by default,
+/// DEFAULT_PROCEDURE_DURATION).
+pub fn make_coronary_procedures(
+    block_id: &str,
+    registry: &PatientRegistry,
+    version: GeneratorVersion,
+    timing: EventTiming,
+    num_rows: usize,
+) -> DataFrame {
+    let global_seed = registry.global_seed();
+    let _span =
+        tracing::debug_span!("make_coronary_procedures", block_id, global_seed, num_rows).entered();
+    match version {
+        GeneratorVersion::V1 => {
+            CoronaryProceduresRows::new(block_id, registry, timing, num_rows).take_chunk(num_rows)
+        }
+    }
+}
+
+/// Create the coronary procedures table in chunks of (at most) chunk_size
+/// rows (see the synth_data module documentation).
+pub fn make_coronary_procedures_chunks(
+    block_id: &str,
+    registry: &PatientRegistry,
+    version: GeneratorVersion,
+    timing: EventTiming,
+    num_rows: usize,
+    chunk_size: usize,
+) -> impl Iterator<Item = DataFrame> {
+    let global_seed = registry.global_seed();
+    let span = tracing::debug_span!(
+        "make_coronary_procedures_chunks",
+        block_id,
+        global_seed,
+        num_rows,
+        chunk_size
+    );
+    let rows = match version {
+        GeneratorVersion::V1 => CoronaryProceduresRows::new(block_id, registry, timing, num_rows),
+    };
+    make_chunks(rows, chunk_size, span)
+}
+
+/// Tests for the coronary procedures table
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn check_procedures_are_for_acs_patients() {
+        let registry = PatientRegistry::new(0, 100);
+        let timing = EventTiming::with_duration(DEFAULT_PROCEDURE_DURATION);
+        let rows: Vec<_> =
+            CoronaryProceduresRows::new("coronary_procedures", &registry, timing, 500).collect();
+        for row in &rows {
+            let n: usize = row.subject["bristol_".len()..].parse().unwrap();
+            assert!(registry.patients()[n - 1].conditions.acs);
+            let minutes = (row.procedure_end_time - row.procedure_start_time).num_minutes();
+            assert!((20..120).contains(&minutes));
+            match row.procedure_type.as_str() {
+                "pci" => {
+                    assert!(row.stent_type.is_some());
+                    let vessels = row.vessels_treated.as_ref().unwrap();
+                    assert!(vessels.split(';').all(|vessel| VESSELS.contains(&vessel)));
+                }
+                _ => {
+                    assert_eq!(row.procedure_type, "angiography");
+                    assert_eq!(row.stent_type, None);
+                    assert_eq!(row.vessels_treated, None);
+                }
+            }
+        }
+        assert!(rows.iter().any(|row| row.procedure_type == "angiography"));
+        assert!(rows.iter().any(|row| row
+            .vessels_treated
+            .as_deref()
+            .is_some_and(|v| v.contains(';'))));
+
+        // The dataframe and the chunks contain the same rows as the
+        // iterator
+        let table = make_coronary_procedures(
+            "coronary_procedures",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            500,
+        );
+        let vessels_treated = table.column("vessels_treated").unwrap().utf8().unwrap();
+        for (n, row) in rows.iter().enumerate() {
+            assert_eq!(vessels_treated.get(n), row.vessels_treated.as_deref());
+        }
+        let chunks: Vec<_> = make_coronary_procedures_chunks(
+            "coronary_procedures",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            500,
+            64,
+        )
+        .collect();
+        let mut joined = chunks[0].clone();
+        for chunk in &chunks[1..] {
+            joined.vstack_mut(chunk).unwrap();
+        }
+        assert!(joined.frame_equal_missing(&table));
+    }
+}
@@ -0,0 +1,489 @@
+//! Corrupting synthetic tables with realistic data quality problems
+//!
+//! Real extracts contain defects that the clean synthetic tables do
+//! not, such as codes with trailing whitespace or in the wrong case,
+//! placeholder dates, impossible results, free text in numerical
+//! fields, and duplicated records. A CorruptionSpec gives the rate at
+//! which each kind of defect is injected, and a Corrupter applies it to
+//! the chunks of a table, so that the ingestion code (including the
+//! deduplication) can be tested against dirty data.
+//!
+//! The defects are picked using a random number generator for each
+//! column, seeded from the global seed and the table name, so the same
+//! spec always corrupts the same values (the rows to duplicate are
+//! picked using another generator for the table). The generators are
+//! kept from one chunk to the next, so the result does not depend on
+//! how the table is split into chunks.
+
+use std::collections::HashMap;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use polars::prelude::*;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::error::HbrError;
+use crate::seeded_rng::make_rng;
+use crate::synth_data::to_timestamp;
+
+/// Placeholders found in place of numerical results
+const NON_NUMERIC_VALUES: [&str; 5] = ["NIL", "N/A", "see comment", "haemolysed", ""];
+
+/// Values that are outside the possible range of any result
+const OUT_OF_RANGE_VALUES: [i64; 3] = [-1, -999, 99999];
+
+/// Whitespace added to the end of strings
+const TRAILING_WHITESPACE: [&str; 3] = [" ", "  ", "\t"];
+
+/// The largest shift (in minutes, earlier or later) of the timestamps
+/// in a near-duplicate row
+const NEAR_DUPLICATE_MAX_SHIFT_MINUTES: i64 = 60;
+
+/// Placeholder dates used in place of timestamps (1900-01-01 is a
+/// common "unknown" value, and 2200-01-01 is in the future)
+fn impossible_dates() -> [NaiveDateTime; 2] {
+    [(1900, 1, 1), (2200, 1, 1)].map(|(year, month, day)| {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+    })
+}
+
+/// The rate (between 0 and 1) of each kind of defect, and the columns
+/// that are left alone. Each rate is the probability that a (non-null)
+/// value which can have the defect is corrupted, or for duplicates,
+/// that a row is copied. Rates that are not given are zero.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorruptionSpec {
+    /// Strings with whitespace added to the end (e.g. "I21.0 ")
+    pub trailing_whitespace: f64,
+    /// Strings with the case of each letter picked at random (e.g.
+    /// "i21.0" or "HaEmoglobin")
+    pub mixed_case: f64,
+    /// Timestamps replaced by a placeholder date (1900-01-01 or
+    /// 2200-01-01)
+    pub impossible_dates: f64,
+    /// Numbers (including numbers stored as strings) replaced by a
+    /// value outside any possible range (e.g. -1 or 99999)
+    pub out_of_range: f64,
+    /// Numbers stored as strings replaced by free text (e.g. "NIL")
+    pub non_numeric: f64,
+    /// Rows followed by an exact copy of themselves
+    pub duplicates: f64,
+    /// Rows followed by a copy with all its timestamps shifted by the
+    /// same small amount (up to an hour earlier or later), like the
+    /// same record extracted twice with slightly different times
+    pub near_duplicates: f64,
+    /// Columns that are never corrupted (by default only subject, so
+    /// that corrupted tables can still be joined)
+    pub exclude_columns: Vec<String>,
+}
+
+impl Default for CorruptionSpec {
+    fn default() -> Self {
+        Self {
+            trailing_whitespace: 0.0,
+            mixed_case: 0.0,
+            impossible_dates: 0.0,
+            out_of_range: 0.0,
+            non_numeric: 0.0,
+            duplicates: 0.0,
+            near_duplicates: 0.0,
+            exclude_columns: vec![String::from("subject")],
+        }
+    }
+}
+
+impl CorruptionSpec {
+    /// Inject every kind of defect in the values at the same rate (no
+    /// rows are duplicated)
+    pub fn uniform(rate: f64) -> Self {
+        Self {
+            trailing_whitespace: rate,
+            mixed_case: rate,
+            impossible_dates: rate,
+            out_of_range: rate,
+            non_numeric: rate,
+            ..Default::default()
+        }
+    }
+
+    /// Check that all the rates are between 0 and 1 (returns
+    /// HbrError::InvalidDatasetSpec otherwise)
+    pub fn validate(&self) -> Result<(), HbrError> {
+        let rates = [
+            ("trailing_whitespace", self.trailing_whitespace),
+            ("mixed_case", self.mixed_case),
+            ("impossible_dates", self.impossible_dates),
+            ("out_of_range", self.out_of_range),
+            ("non_numeric", self.non_numeric),
+            ("duplicates", self.duplicates),
+            ("near_duplicates", self.near_duplicates),
+        ];
+        for (name, rate) in rates {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(HbrError::InvalidDatasetSpec(format!(
+                    "corruption rate {name} must be between 0 and 1 (found {rate})"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the column is left alone
+    fn is_excluded(&self, name: &str) -> bool {
+        self.exclude_columns.iter().any(|excluded| excluded == name)
+    }
+
+    /// Corrupt a string value. Numbers stored as strings can be
+    /// replaced by free text or out of range values, and then any
+    /// string can have its case changed or whitespace added.
+    fn corrupt_string(&self, rng: &mut ChaCha8Rng, value: &str) -> String {
+        let mut value = String::from(value);
+        if value.parse::<f64>().is_ok() {
+            if rng.gen_bool(self.non_numeric) {
+                value = String::from(*NON_NUMERIC_VALUES.choose(rng).unwrap());
+            } else if rng.gen_bool(self.out_of_range) {
+                value = OUT_OF_RANGE_VALUES.choose(rng).unwrap().to_string();
+            }
+        }
+        if rng.gen_bool(self.mixed_case) {
+            value = value
+                .chars()
+                .map(|c| {
+                    if rng.gen() {
+                        c.to_ascii_uppercase()
+                    } else {
+                        c.to_ascii_lowercase()
+                    }
+                })
+                .collect();
+        }
+        if rng.gen_bool(self.trailing_whitespace) {
+            value.push_str(TRAILING_WHITESPACE.choose(rng).unwrap());
+        }
+        value
+    }
+
+    /// Corrupt one column, depending on its type. Columns of other
+    /// types (e.g. boolean flags) are returned unchanged.
+    fn corrupt_series(&self, rng: &mut ChaCha8Rng, series: &Series) -> Series {
+        let name = series.name();
+        match series.dtype() {
+            DataType::Utf8 => {
+                let values: Vec<_> = series
+                    .utf8()
+                    .expect("Column is a string column")
+                    .into_iter()
+                    .map(|value| value.map(|value| self.corrupt_string(rng, value)))
+                    .collect();
+                Series::new(name, values)
+            }
+            DataType::Int64 => {
+                let values: Vec<_> = series
+                    .i64()
+                    .expect("Column is an integer column")
+                    .into_iter()
+                    .map(|value| {
+                        value.map(|value| {
+                            if rng.gen_bool(self.out_of_range) {
+                                *OUT_OF_RANGE_VALUES.choose(rng).unwrap()
+                            } else {
+                                value
+                            }
+                        })
+                    })
+                    .collect();
+                Series::new(name, values)
+            }
+            DataType::Float64 => {
+                let values: Vec<_> = series
+                    .f64()
+                    .expect("Column is a float column")
+                    .into_iter()
+                    .map(|value| {
+                        value.map(|value| {
+                            if rng.gen_bool(self.out_of_range) {
+                                *OUT_OF_RANGE_VALUES.choose(rng).unwrap() as f64
+                            } else {
+                                value
+                            }
+                        })
+                    })
+                    .collect();
+                Series::new(name, values)
+            }
+            DataType::Datetime(time_unit, _) => {
+                let impossible_dates =
+                    impossible_dates().map(|date| to_timestamp(date, *time_unit));
+                let values: Vec<_> = series
+                    .cast(&DataType::Int64)
+                    .expect("Timestamps can be converted to integers")
+                    .i64()
+                    .expect("Column is an integer column")
+                    .into_iter()
+                    .map(|value| {
+                        value.map(|value| {
+                            if rng.gen_bool(self.impossible_dates) {
+                                *impossible_dates.choose(rng).unwrap()
+                            } else {
+                                value
+                            }
+                        })
+                    })
+                    .collect();
+                Series::new(name, values)
+                    .cast(series.dtype())
+                    .expect("Integers can be converted back to timestamps")
+            }
+            _ => series.clone(),
+        }
+    }
+}
+
+/// Applies a CorruptionSpec to the chunks of one table
+pub struct Corrupter {
+    spec: CorruptionSpec,
+    global_seed: u64,
+    block_id: String,
+    /// One generator for each column, made when the column is first
+    /// corrupted
+    rngs: HashMap<String, ChaCha8Rng>,
+    /// The generator for picking the rows to duplicate
+    duplicates_rng: ChaCha8Rng,
+}
+
+impl Corrupter {
+    /// Make a corrupter for the table with the given block id (the
+    /// name of the table in a dataset). The spec must be valid (see
+    /// CorruptionSpec::validate).
+    pub fn new(spec: CorruptionSpec, global_seed: u64, block_id: &str) -> Self {
+        Self {
+            spec,
+            global_seed,
+            block_id: String::from(block_id),
+            rngs: HashMap::new(),
+            duplicates_rng: make_rng(global_seed, &format!("{block_id}duplicates")),
+        }
+    }
+
+    /// Corrupt the next chunk of the table. The values are corrupted
+    /// first, so exact duplicates are the same as the (corrupted) rows
+    /// they copy.
+    pub fn corrupt(&mut self, table: DataFrame) -> DataFrame {
+        let _span = tracing::debug_span!(
+            "corrupt",
+            block_id = %self.block_id,
+            num_rows = table.height()
+        )
+        .entered();
+        let columns = table
+            .get_columns()
+            .iter()
+            .map(|series| {
+                let name = series.name();
+                if self.spec.is_excluded(name) {
+                    return series.clone();
+                }
+                let rng = self.rngs.entry(String::from(name)).or_insert_with(|| {
+                    let column_block_id = format!("{}{name}corruption", self.block_id);
+                    make_rng(self.global_seed, &column_block_id)
+                });
+                self.spec.corrupt_series(rng, series)
+            })
+            .collect();
+        let table = DataFrame::new(columns).expect("Corrupted columns have the same length");
+        self.duplicate_rows(table)
+    }
+
+    /// Duplicate rows of a chunk, putting each copy straight after the
+    /// row it copies. Near-duplicates have all their timestamps (apart
+    /// from those in excluded columns) shifted by the same number of
+    /// minutes, so the time between events in the row does not change.
+    fn duplicate_rows(&mut self, table: DataFrame) -> DataFrame {
+        let rng = &mut self.duplicates_rng;
+        let mut rows = Vec::with_capacity(table.height());
+        let mut shift_minutes = Vec::with_capacity(table.height());
+        for row in 0..table.height() as IdxSize {
+            rows.push(row);
+            shift_minutes.push(0);
+            if rng.gen_bool(self.spec.duplicates) {
+                rows.push(row);
+                shift_minutes.push(0);
+            } else if rng.gen_bool(self.spec.near_duplicates) {
+                let minutes = rng.gen_range(1..=NEAR_DUPLICATE_MAX_SHIFT_MINUTES);
+                rows.push(row);
+                shift_minutes.push(if rng.gen() { minutes } else { -minutes });
+            }
+        }
+        if rows.len() == table.height() {
+            return table;
+        }
+        tracing::trace!(
+            num_duplicates = rows.len() - table.height(),
+            "duplicated rows"
+        );
+        let table = table
+            .take(&IdxCa::from_vec("", rows))
+            .expect("Duplicated rows are in the table");
+        let columns = table
+            .get_columns()
+            .iter()
+            .map(|series| match series.dtype() {
+                DataType::Datetime(time_unit, _) if !self.spec.is_excluded(series.name()) => {
+                    let minute = match time_unit {
+                        TimeUnit::Nanoseconds => 60_000_000_000,
+                        TimeUnit::Microseconds => 60_000_000,
+                        TimeUnit::Milliseconds => 60_000,
+                    };
+                    let shifts: Vec<_> = shift_minutes.iter().map(|m| m * minute).collect();
+                    let timestamps = series
+                        .cast(&DataType::Int64)
+                        .expect("Timestamps can be converted to integers");
+                    (&timestamps + &Series::new("", shifts))
+                        .cast(series.dtype())
+                        .expect("Integers can be converted back to timestamps")
+                }
+                _ => series.clone(),
+            })
+            .collect();
+        DataFrame::new(columns).expect("Duplicated columns have the same length")
+    }
+}
+
+/// Tests for corrupting synthetic tables
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::pathology_blood::{make_pathology_blood, DEFAULT_PROCESSING_TIME};
+    use crate::synth_data::{EventTiming, GeneratorVersion, PatientRegistry};
+
+    #[test]
+    fn check_corruption_is_seeded_and_independent_of_chunks() {
+        let registry = PatientRegistry::new(0, 100);
+        let timing = EventTiming::with_duration(DEFAULT_PROCESSING_TIME);
+        let table = make_pathology_blood(
+            "pathology_blood",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            100,
+        );
+
+        // Nothing changes if all the rates are zero
+        let mut corrupter = Corrupter::new(CorruptionSpec::default(), 0, "pathology_blood");
+        assert!(corrupter.corrupt(table.clone()).frame_equal_missing(&table));
+
+        // Every string has trailing whitespace, apart from the subject
+        let spec = CorruptionSpec {
+            trailing_whitespace: 1.0,
+            ..Default::default()
+        };
+        let corrupted = Corrupter::new(spec, 0, "pathology_blood").corrupt(table.clone());
+        let test_name = corrupted.column("test_name").unwrap().utf8().unwrap();
+        assert!(test_name
+            .into_iter()
+            .all(|name| name.unwrap().ends_with(char::is_whitespace)));
+        assert!(corrupted
+            .column("subject")
+            .unwrap()
+            .series_equal(table.column("subject").unwrap()));
+
+        // Corrupting the table in two chunks gives the same result
+        let spec = CorruptionSpec::uniform(0.3);
+        let whole = Corrupter::new(spec.clone(), 0, "pathology_blood").corrupt(table.clone());
+        let mut corrupter = Corrupter::new(spec, 0, "pathology_blood");
+        let mut chunks = corrupter.corrupt(table.slice(0, 40));
+        chunks
+            .vstack_mut(&corrupter.corrupt(table.slice(40, 60)))
+            .unwrap();
+        assert!(chunks.frame_equal_missing(&whole));
+        assert!(!whole.frame_equal_missing(&table));
+
+        assert!(CorruptionSpec::uniform(1.5).validate().is_err());
+    }
+
+    /// The subject, test name, result, and collected and available times
+    /// in each row of a pathology blood table
+    fn blood_rows(table: &DataFrame) -> Vec<(String, String, String, i64, i64)> {
+        let column = |name| table.column(name).unwrap();
+        let timestamps = |name| column(name).cast(&DataType::Int64).unwrap();
+        let collected = timestamps("sample_collected_date_time");
+        let available = timestamps("result_available_date_time");
+        column("subject")
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .zip(column("test_name").utf8().unwrap())
+            .zip(column("test_result").utf8().unwrap())
+            .zip(collected.i64().unwrap())
+            .zip(available.i64().unwrap())
+            .map(|((((subject, name), result), collected), available)| {
+                (
+                    String::from(subject.unwrap()),
+                    String::from(name.unwrap()),
+                    String::from(result.unwrap()),
+                    collected.unwrap(),
+                    available.unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn check_duplicates_follow_the_rows_they_copy() {
+        let registry = PatientRegistry::new(0, 100);
+        let timing = EventTiming::with_duration(DEFAULT_PROCESSING_TIME);
+        let table = make_pathology_blood(
+            "pathology_blood",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            200,
+        );
+        let spec = CorruptionSpec {
+            duplicates: 0.1,
+            near_duplicates: 0.1,
+            ..Default::default()
+        };
+        let duplicated = Corrupter::new(spec.clone(), 0, "pathology_blood").corrupt(table.clone());
+
+        // Each row of the table is followed by any copies of it
+        let rows = blood_rows(&table);
+        let duplicated_rows = blood_rows(&duplicated);
+        let (mut num_exact, mut num_near) = (0, 0);
+        let mut n = 0;
+        for (m, row) in rows.iter().enumerate() {
+            assert_eq!(&duplicated_rows[n], row);
+            n += 1;
+            if duplicated_rows.get(n) == rows.get(m + 1) {
+                continue;
+            }
+            let (subject, name, result, collected, available) = &duplicated_rows[n];
+            assert_eq!((subject, name, result), (&row.0, &row.1, &row.2));
+            let shift = collected - row.3;
+            assert_eq!(available - row.4, shift);
+            assert!(shift.abs() <= 60 * 60 * 1000);
+            if shift == 0 {
+                num_exact += 1;
+            } else {
+                num_near += 1;
+            }
+            n += 1;
+        }
+        assert_eq!(n, duplicated_rows.len());
+        assert!(num_exact > 0 && num_near > 0);
+
+        // Duplicating the rows in two chunks gives the same result
+        let mut corrupter = Corrupter::new(spec, 0, "pathology_blood");
+        let mut chunks = corrupter.corrupt(table.slice(0, 80));
+        chunks
+            .vstack_mut(&corrupter.corrupt(table.slice(80, 120)))
+            .unwrap();
+        assert!(chunks.frame_equal_missing(&duplicated));
+    }
+}
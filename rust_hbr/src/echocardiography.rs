@@ -0,0 +1,385 @@
+//! The echocardiography table contains the findings of transthoracic
+//! echocardiograms. Each row is one study, with the time of the scan
+//! and of the report, the left ventricular ejection fraction (LVEF),
+//! and the grade of disease of the aortic, mitral and tricuspid valves.
+//!
+//! Like the blood tests in the pathology_blood table, these are
+//! measurements that need ingesting into the patient's measurement
+//! history, but with a different structure (a numerical result, a
+//! qualitative grade derived from it, and categorical findings). The
+//! findings depend on the patient: patients with an acute coronary
+//! syndrome often have impaired LV function, and valve disease becomes
+//! more common with age.
+
+use crate::seeded_column_block::{make_chunks, to_polars, SeededColumnBlock, SyntheticRows};
+use crate::seeded_rng::make_rng;
+use crate::synth_data::{
+    DurationRange, EventTiming, GeneratorVersion, PatientRegistry, SyntheticPatient,
+};
+use chrono::NaiveDateTime;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+use polars::prelude::*;
+
+/// The default time from the scan until the report is available (30
+/// minutes to a week)
+pub const DEFAULT_REPORT_TIME: DurationRange = DurationRange {
+    min_minutes: 30,
+    max_minutes: 10080,
+};
+
+/// The grades of valve disease, from least to most severe
+const VALVE_GRADES: [&str; 4] = ["none", "mild", "moderate", "severe"];
+
+/// The findings of one echocardiogram
+#[derive(Debug, Clone, PartialEq)]
+struct EchoFindings {
+    /// The ejection fraction (percent), or None if it was not measured
+    /// (only the LV function was reported)
+    lvef: Option<i64>,
+    /// The grade of LV systolic function
+    lv_function: &'static str,
+    aortic_stenosis: &'static str,
+    mitral_regurgitation: &'static str,
+    tricuspid_regurgitation: &'static str,
+}
+
+/// The grade of LV systolic function for an ejection fraction (normal
+/// is 50% or more)
+fn lv_function(lvef: i64) -> &'static str {
+    match lvef {
+        50.. => "normal",
+        40..=49 => "mildly impaired",
+        30..=39 => "moderately impaired",
+        _ => "severely impaired",
+    }
+}
+
+/// Pick the grade of disease of one valve. The probability of any
+/// disease is 2% below the age of 40, rising by 1% a year to at most
+/// 50%, and 60% of the disease is mild, 30% moderate and 10% severe.
+fn make_valve_grade(rng: &mut ChaCha8Rng, age: i32) -> &'static str {
+    let disease_probability = (0.02 + 0.01 * (age - 40).max(0) as f64).min(0.5);
+    if !rng.gen_bool(disease_probability) {
+        return VALVE_GRADES[0];
+    }
+    match rng.gen_range(0..10) {
+        0..=5 => VALVE_GRADES[1],
+        6..=8 => VALVE_GRADES[2],
+        _ => VALVE_GRADES[3],
+    }
+}
+
+/// Make the findings of an echocardiogram for the patient, whose age
+/// is the age at the time of the scan. The LVEF is uniform in 50 - 75%,
+/// or 20 - 60% if the patient has had an ACS, and is not measured in
+/// 15% of studies.
+fn make_echo_findings(rng: &mut ChaCha8Rng, patient: &SyntheticPatient, age: i32) -> EchoFindings {
+    let lvef = if patient.conditions.acs {
+        rng.gen_range(20..60)
+    } else {
+        rng.gen_range(50..75)
+    };
+    let measured = rng.gen_bool(0.85);
+    EchoFindings {
+        lvef: measured.then_some(lvef),
+        lv_function: lv_function(lvef),
+        aortic_stenosis: make_valve_grade(rng, age),
+        mitral_regurgitation: make_valve_grade(rng, age),
+        tricuspid_regurgitation: make_valve_grade(rng, age),
+    }
+}
+
+/// One row of the echocardiography table
+#[derive(Debug)]
+pub struct EchocardiographyRow {
+    pub subject: String,
+    pub study_date_time: NaiveDateTime,
+    pub report_date_time: NaiveDateTime,
+    pub lvef: Option<i64>,
+    pub lv_function: String,
+    pub aortic_stenosis: String,
+    pub mitral_regurgitation: String,
+    pub tricuspid_regurgitation: String,
+}
+
+/// Iterator over the rows of the echocardiography table (see
+/// PathologyBloodRows for how the column blocks are seeded)
+pub struct EchocardiographyRows {
+    registry: PatientRegistry,
+    /// When the scans are done, and how long until they are reported
+    timing: EventTiming,
+    subject_rng: ChaCha8Rng,
+    study_time_rng: ChaCha8Rng,
+    findings_rng: ChaCha8Rng,
+    rows_left: usize,
+}
+
+impl EchocardiographyRows {
+    /// Make an iterator that will return num_rows rows. The arguments
+    /// have the same meaning as in make_echocardiography.
+    pub fn new(
+        block_id: &str,
+        registry: &PatientRegistry,
+        timing: EventTiming,
+        num_rows: usize,
+    ) -> Self {
+        let global_seed = registry.global_seed();
+        let subject_block_id = format!("{block_id}subject");
+        let study_time_block_id = format!("{block_id}study_time");
+        let findings_block_id = format!("{block_id}findings");
+        Self {
+            registry: registry.clone(),
+            timing,
+            subject_rng: make_rng(global_seed, subject_block_id.as_ref()),
+            study_time_rng: make_rng(global_seed, study_time_block_id.as_ref()),
+            findings_rng: make_rng(global_seed, findings_block_id.as_ref()),
+            rows_left: num_rows,
+        }
+    }
+}
+
+impl Iterator for EchocardiographyRows {
+    type Item = EchocardiographyRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rows_left == 0 {
+            return None;
+        }
+        self.rows_left -= 1;
+
+        let patient = self.registry.sample(&mut self.subject_rng);
+        let (study_date_time, report_date_time) = self.timing.pick(&mut self.study_time_rng);
+        let age = patient.age_at(study_date_time);
+        let findings = make_echo_findings(&mut self.findings_rng, patient, age);
+        Some(EchocardiographyRow {
+            subject: patient.subject.clone(),
+            study_date_time,
+            report_date_time,
+            lvef: findings.lvef,
+            lv_function: String::from(findings.lv_function),
+            aortic_stenosis: String::from(findings.aortic_stenosis),
+            mitral_regurgitation: String::from(findings.mitral_regurgitation),
+            tricuspid_regurgitation: String::from(findings.tricuspid_regurgitation),
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.rows_left, Some(self.rows_left))
+    }
+}
+
+impl SyntheticRows for EchocardiographyRows {
+    /// The findings depend on the subject and their age at the time of the
+    /// scan, so the findings block is generated after the other two.
+    fn take_chunk(&mut self, chunk_size: usize) -> DataFrame {
+        let num_rows = chunk_size.min(self.rows_left);
+        self.rows_left -= num_rows;
+        let Self {
+            registry,
+            timing,
+            subject_rng,
+            study_time_rng,
+            findings_rng,
+            ..
+        } = self;
+
+        let (patients, study_times) = rayon::join(
+            || {
+                (0..num_rows)
+                    .map(|_| registry.sample(subject_rng))
+                    .collect::<Vec<_>>()
+            },
+            || {
+                (0..num_rows)
+                    .map(|_| timing.pick(study_time_rng))
+                    .collect::<Vec<_>>()
+            },
+        );
+        let findings: Vec<_> = patients
+            .iter()
+            .zip(&study_times)
+            .map(|(patient, (study_date_time, _))| {
+                let age = patient.age_at(*study_date_time);
+                make_echo_findings(findings_rng, patient, age)
+            })
+            .collect();
+        let subject: Vec<_> = patients
+            .iter()
+            .map(|patient| patient.subject.as_str())
+            .collect();
+
+        let (study_date_time, report_date_time): (Vec<_>, Vec<_>) = study_times.into_iter().unzip();
+        let mut lvef = Vec::with_capacity(num_rows);
+        let mut lv_function = Vec::with_capacity(num_rows);
+        let mut aortic_stenosis = Vec::with_capacity(num_rows);
+        let mut mitral_regurgitation = Vec::with_capacity(num_rows);
+        let mut tricuspid_regurgitation = Vec::with_capacity(num_rows);
+        for findings in findings {
+            lvef.push(findings.lvef);
+            lv_function.push(findings.lv_function);
+            aortic_stenosis.push(findings.aortic_stenosis);
+            mitral_regurgitation.push(findings.mitral_regurgitation);
+            tricuspid_regurgitation.push(findings.tricuspid_regurgitation);
+        }
+
+        let seeded_column_blocks = vec![
+            SeededColumnBlock {
+                columns: vec![Series::new("subject", subject)],
+            },
+            SeededColumnBlock {
+                columns: vec![
+                    Series::new("study_date_time", study_date_time),
+                    Series::new("report_date_time", report_date_time),
+                ],
+            },
+            SeededColumnBlock {
+                columns: vec![
+                    Series::new("lvef", lvef),
+                    Series::new("lv_function", lv_function),
+                    Series::new("aortic_stenosis", aortic_stenosis),
+                    Series::new("mitral_regurgitation", mitral_regurgitation),
+                    Series::new("tricuspid_regurgitation", tricuspid_regurgitation),
+                ],
+            },
+        ];
+        to_polars(seeded_column_blocks)
+    }
+}
+
+/// Create the echocardiography table, with one row per study. The
+/// columns are:
+///
+/// * subject: the patient id
+/// * study_date_time: when the scan was done
+/// * report_date_time: when the report was available
+/// * lvef: the LV ejection fraction (percent), null if only the LV
+///   function was reported
+/// * lv_function: normal, mildly impaired, moderately impaired or
+///   severely impaired
+/// * aortic_stenosis, mitral_regurgitation, tricuspid_regurgitation:
+///   none, mild, moderate or severe
+///
+/// The scans are done at times picked from the window in the timing,
+/// and reported after a time picked from its duration range (by
+/// default, DEFAULT_REPORT_TIME). The version is the version of the
+/// generator to use (see GeneratorVersion).
+pub fn make_echocardiography(
+    block_id: &str,
+    registry: &PatientRegistry,
+    version: GeneratorVersion,
+    timing: EventTiming,
+    num_rows: usize,
+) -> DataFrame {
+    let global_seed = registry.global_seed();
+    let _span =
+        tracing::debug_span!("make_echocardiography", block_id, global_seed, num_rows).entered();
+    match version {
+        GeneratorVersion::V1 => {
+            EchocardiographyRows::new(block_id, registry, timing, num_rows).take_chunk(num_rows)
+        }
+    }
+}
+
+/// Create the echocardiography table in chunks of (at most) chunk_size
+/// rows (see the synth_data module documentation).
+pub fn make_echocardiography_chunks(
+    block_id: &str,
+    registry: &PatientRegistry,
+    version: GeneratorVersion,
+    timing: EventTiming,
+    num_rows: usize,
+    chunk_size: usize,
+) -> impl Iterator<Item = DataFrame> {
+    let global_seed = registry.global_seed();
+    let span = tracing::debug_span!(
+        "make_echocardiography_chunks",
+        block_id,
+        global_seed,
+        num_rows,
+        chunk_size
+    );
+    let rows = match version {
+        GeneratorVersion::V1 => EchocardiographyRows::new(block_id, registry, timing, num_rows),
+    };
+    make_chunks(rows, chunk_size, span)
+}
+
+/// Tests for the echocardiography table
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn check_findings_depend_on_the_patient() {
+        let registry = PatientRegistry::new(0, 1000);
+        let timing = EventTiming::with_duration(DEFAULT_REPORT_TIME);
+        let rows: Vec<_> =
+            EchocardiographyRows::new("echocardiography", &registry, timing, 5000).collect();
+
+        let mut acs_lvef = Vec::new();
+        let mut other_lvef = Vec::new();
+        let mut valve_disease_by_age = [(0, 0); 2];
+        for row in &rows {
+            let n: usize = row.subject["bristol_".len()..].parse().unwrap();
+            let patient = &registry.patients()[n - 1];
+            if let Some(lvef) = row.lvef {
+                assert_eq!(row.lv_function, lv_function(lvef));
+                if patient.conditions.acs {
+                    acs_lvef.push(lvef);
+                } else {
+                    assert!((50..75).contains(&lvef));
+                    other_lvef.push(lvef);
+                }
+            }
+            let grades = [
+                &row.aortic_stenosis,
+                &row.mitral_regurgitation,
+                &row.tricuspid_regurgitation,
+            ];
+            assert!(grades
+                .iter()
+                .all(|grade| VALVE_GRADES.contains(&grade.as_str())));
+            let older = usize::from(patient.age_at(row.study_date_time) >= 70);
+            valve_disease_by_age[older].0 += grades.iter().filter(|g| g.as_str() != "none").count();
+            valve_disease_by_age[older].1 += grades.len();
+        }
+        let mean = |lvef: &[i64]| lvef.iter().sum::<i64>() as f64 / lvef.len() as f64;
+        assert!(mean(&acs_lvef) < mean(&other_lvef) - 10.0);
+        assert!(rows.iter().any(|row| row.lvef.is_none()));
+        let [(younger_disease, younger), (older_disease, older)] = valve_disease_by_age;
+        assert!(
+            older_disease as f64 / older as f64 > 2.0 * younger_disease as f64 / younger as f64
+        );
+
+        // The dataframe and the chunks contain the same rows as the
+        // iterator
+        let table = make_echocardiography(
+            "echocardiography",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            5000,
+        );
+        let lvef = table.column("lvef").unwrap().i64().unwrap();
+        for (n, row) in rows.iter().enumerate() {
+            assert_eq!(lvef.get(n), row.lvef);
+        }
+        let chunks: Vec<_> = make_echocardiography_chunks(
+            "echocardiography",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            5000,
+            700,
+        )
+        .collect();
+        let mut joined = chunks[0].clone();
+        for chunk in &chunks[1..] {
+            joined.vstack_mut(chunk).unwrap();
+        }
+        assert!(joined.frame_equal_missing(&table));
+    }
+}
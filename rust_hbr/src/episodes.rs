@@ -0,0 +1,487 @@
+//! The episodes table contains hospital episodes, in the same layout as
+//! the HIC episodes extract. Each row is one episode (the part of a
+//! hospital spell under one consultant), with the spell it belongs to,
+//! its start and end times, and the ICD-10 code of its primary
+//! diagnosis.
+//!
+//! By default, the primary diagnosis is picked uniformly from a short
+//! list of codes. To give the synthetic data realistic prevalences of
+//! the code groups used in the models, the codes can instead be picked
+//! according to a frequency table (CodeFrequencies), such as a table of
+//! diagnosis counts derived from published hospital episode statistics.
+//!
+//! Patients with a condition (see PatientConditions) are also given
+//! diagnoses for that condition in some of their episodes, so that the
+//! codes are consistent with the other tables (e.g. patients with CKD
+//! diagnoses have low eGFR results in the pathology_blood table).
+
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use crate::error::HbrError;
+use crate::seeded_column_block::{make_chunks, to_polars, SeededColumnBlock, SyntheticRows};
+use crate::seeded_rng::make_rng;
+use crate::synth_data::{
+    DurationRange, EventTiming, GeneratorVersion, PatientConditions, PatientRegistry,
+};
+use chrono::NaiveDateTime;
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use polars::prelude::*;
+
+/// The ICD-10 codes that the primary diagnosis is picked from when no
+/// frequency table is given. These include the codes relevant to
+/// bleeding risk (ACS, bleeding, anaemia, CKD, AF and cancer), and some
+/// other common reasons for admission.
+const DEFAULT_DIAGNOSES: [&str; 16] = [
+    "I21.0", "I21.4", "I20.0", "I25.1", "K92.2", "K25.4", "D64.9", "N18.3", "I48.9", "C18.9",
+    "I10", "E11.9", "I50.9", "J18.9", "R07.4", "S72.0",
+];
+
+/// The default length of an episode (up to 30 days)
+pub const DEFAULT_EPISODE_DURATION: DurationRange = DurationRange {
+    min_minutes: 0,
+    max_minutes: 43200,
+};
+
+/// The diagnoses given to patients because of their conditions
+const BLEEDING_DIAGNOSES: [&str; 3] = ["K92.2", "K25.4", "I61.9"];
+const ANAEMIA_DIAGNOSES: [&str; 2] = ["D64.9", "D50.0"];
+const CKD_DIAGNOSES: [&str; 3] = ["N18.3", "N18.4", "N18.5"];
+const ACS_DIAGNOSES: [&str; 4] = ["I21.0", "I21.1", "I21.4", "I20.0"];
+
+/// The probability that the primary diagnosis of an episode is for one
+/// of the patient's conditions (if they have any), instead of being
+/// picked from the code frequencies
+const CONDITION_DIAGNOSIS_RATE: f64 = 0.3;
+
+/// The relative frequency of each ICD-10 code in the primary diagnosis
+/// column of the episodes table
+///
+/// The frequencies are weights, so they do not need to add up to one
+/// (raw episode counts can be used directly). In a dataset spec or file,
+/// the frequencies are written as a map from code to frequency, e.g.
+///
+/// ```yaml
+/// I21.0: 1250
+/// K92.2: 430
+/// N18.3: 2100
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CodeFrequencies(BTreeMap<String, f64>);
+
+impl Default for CodeFrequencies {
+    /// The default list of diagnosis codes, all with the same frequency
+    fn default() -> Self {
+        Self(
+            DEFAULT_DIAGNOSES
+                .iter()
+                .map(|code| (String::from(*code), 1.0))
+                .collect(),
+        )
+    }
+}
+
+impl CodeFrequencies {
+    /// Make a frequency table from a map of codes to frequencies.
+    /// Returns HbrError::InvalidCodeFrequencies if the table is not
+    /// valid (see validate).
+    pub fn new(frequencies: BTreeMap<String, f64>) -> Result<Self, HbrError> {
+        let frequencies = Self(frequencies);
+        frequencies.validate()?;
+        Ok(frequencies)
+    }
+
+    /// Read a frequency table from a YAML (or JSON) file containing a
+    /// map from code to frequency
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, HbrError> {
+        let frequencies: BTreeMap<String, f64> = serde_yaml::from_reader(reader)
+            .map_err(|err| HbrError::InvalidCodeFrequencies(err.to_string()))?;
+        Self::new(frequencies)
+    }
+
+    /// Check that the table contains at least one code, that all the
+    /// frequencies are finite and not negative, and that at least one
+    /// of them is positive
+    pub fn validate(&self) -> Result<(), HbrError> {
+        for (code, frequency) in &self.0 {
+            if !frequency.is_finite() || *frequency < 0.0 {
+                return Err(HbrError::InvalidCodeFrequencies(format!(
+                    "frequency of {code} must be a non-negative number (found {frequency})"
+                )));
+            }
+        }
+        if !self.0.values().any(|frequency| *frequency > 0.0) {
+            return Err(HbrError::InvalidCodeFrequencies(String::from(
+                "at least one code must have a positive frequency",
+            )));
+        }
+        Ok(())
+    }
+
+    /// The codes in the table and their frequencies (in code order)
+    pub fn frequencies(&self) -> &BTreeMap<String, f64> {
+        &self.0
+    }
+}
+
+/// Picks codes according to a (valid) CodeFrequencies table
+#[derive(Clone)]
+struct CodeSampler {
+    codes: Vec<String>,
+    index: WeightedIndex<f64>,
+}
+
+impl CodeSampler {
+    fn new(frequencies: &CodeFrequencies) -> Self {
+        let codes = frequencies.0.keys().cloned().collect();
+        let index = WeightedIndex::new(frequencies.0.values())
+            .expect("Code frequencies have been validated");
+        Self { codes, index }
+    }
+
+    fn sample(&self, rng: &mut ChaCha8Rng) -> &str {
+        &self.codes[self.index.sample(rng)]
+    }
+}
+
+/// Pick the primary diagnosis of an episode for a patient with the
+/// given conditions. If the patient has any conditions, the diagnosis
+/// is one of theirs with probability CONDITION_DIAGNOSIS_RATE, and
+/// otherwise it is picked from the code frequencies.
+fn make_diagnosis<'a>(
+    rng: &mut ChaCha8Rng,
+    diagnoses: &'a CodeSampler,
+    conditions: PatientConditions,
+) -> &'a str {
+    let condition_diagnoses: Vec<&str> = [
+        (conditions.bleeding, &BLEEDING_DIAGNOSES[..]),
+        (conditions.anaemia, &ANAEMIA_DIAGNOSES[..]),
+        (conditions.ckd, &CKD_DIAGNOSES[..]),
+        (conditions.acs, &ACS_DIAGNOSES[..]),
+    ]
+    .into_iter()
+    .filter(|(has_condition, _)| *has_condition)
+    .flat_map(|(_, codes)| codes.iter().copied())
+    .collect();
+    if !condition_diagnoses.is_empty() && rng.gen_bool(CONDITION_DIAGNOSIS_RATE) {
+        condition_diagnoses
+            .choose(rng)
+            .expect("Patient has a condition")
+    } else {
+        diagnoses.sample(rng)
+    }
+}
+
+/// Make a spell and episode id (formats "spell_nnnnnnn" and
+/// "episode_nnnnnnn")
+fn make_identifiers(rng: &mut ChaCha8Rng) -> (String, String) {
+    let spell_id = rng.gen_range(1..=5000000);
+    let episode_id = rng.gen_range(1..=50000000);
+    (format!("spell_{spell_id}"), format!("episode_{episode_id}"))
+}
+
+/// One row of the episodes table
+#[derive(Debug)]
+pub struct EpisodesRow {
+    pub subject: String,
+    pub spell_identifier: String,
+    pub episode_identifier: String,
+    pub episode_start_time: NaiveDateTime,
+    pub episode_end_time: NaiveDateTime,
+    pub diagnosis_code_icd: String,
+}
+
+/// Iterator over the rows of the episodes table (see PathologyBloodRows
+/// for how the column blocks are seeded)
+pub struct EpisodesRows {
+    registry: PatientRegistry,
+    diagnoses: CodeSampler,
+    timing: EventTiming,
+    subject_rng: ChaCha8Rng,
+    identifier_rng: ChaCha8Rng,
+    episode_time_rng: ChaCha8Rng,
+    diagnosis_rng: ChaCha8Rng,
+    rows_left: usize,
+}
+
+impl EpisodesRows {
+    /// Make an iterator that will return num_rows rows. The arguments
+    /// have the same meaning as in make_episodes.
+    pub fn new(
+        block_id: &str,
+        registry: &PatientRegistry,
+        diagnosis_frequencies: &CodeFrequencies,
+        timing: EventTiming,
+        num_rows: usize,
+    ) -> Self {
+        let global_seed = registry.global_seed();
+        let subject_block_id = format!("{block_id}subject");
+        let identifier_block_id = format!("{block_id}identifier");
+        let episode_time_block_id = format!("{block_id}episode_time");
+        let diagnosis_block_id = format!("{block_id}diagnosis");
+        Self {
+            registry: registry.clone(),
+            diagnoses: CodeSampler::new(diagnosis_frequencies),
+            timing,
+            subject_rng: make_rng(global_seed, subject_block_id.as_ref()),
+            identifier_rng: make_rng(global_seed, identifier_block_id.as_ref()),
+            episode_time_rng: make_rng(global_seed, episode_time_block_id.as_ref()),
+            diagnosis_rng: make_rng(global_seed, diagnosis_block_id.as_ref()),
+            rows_left: num_rows,
+        }
+    }
+}
+
+impl Iterator for EpisodesRows {
+    type Item = EpisodesRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rows_left == 0 {
+            return None;
+        }
+        self.rows_left -= 1;
+
+        let (spell_identifier, episode_identifier) = make_identifiers(&mut self.identifier_rng);
+        let (episode_start_time, episode_end_time) = self.timing.pick(&mut self.episode_time_rng);
+        let patient = self.registry.sample(&mut self.subject_rng);
+        let diagnosis_code_icd =
+            make_diagnosis(&mut self.diagnosis_rng, &self.diagnoses, patient.conditions);
+        Some(EpisodesRow {
+            subject: patient.subject.clone(),
+            spell_identifier,
+            episode_identifier,
+            episode_start_time,
+            episode_end_time,
+            diagnosis_code_icd: String::from(diagnosis_code_icd),
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.rows_left, Some(self.rows_left))
+    }
+}
+
+impl SyntheticRows for EpisodesRows {
+    fn take_chunk(&mut self, chunk_size: usize) -> DataFrame {
+        let num_rows = chunk_size.min(self.rows_left);
+        self.rows_left -= num_rows;
+        let Self {
+            registry,
+            diagnoses,
+            timing,
+            subject_rng,
+            identifier_rng,
+            episode_time_rng,
+            diagnosis_rng,
+            ..
+        } = self;
+
+        // The diagnosis depends on the conditions of the subject, so the
+        // diagnosis block is generated after the subject block
+        let ((subject, diagnosis_code_icd), (identifiers, episode_times)) = rayon::join(
+            || {
+                let patients: Vec<_> = (0..num_rows)
+                    .map(|_| registry.sample(subject_rng))
+                    .collect();
+                let diagnosis_code_icd: Vec<_> = patients
+                    .iter()
+                    .map(|patient| make_diagnosis(diagnosis_rng, diagnoses, patient.conditions))
+                    .collect();
+                let subject: Vec<_> = patients
+                    .iter()
+                    .map(|patient| patient.subject.as_str())
+                    .collect();
+                (
+                    Series::new("subject", subject),
+                    Series::new("diagnosis_code_icd", diagnosis_code_icd),
+                )
+            },
+            || {
+                rayon::join(
+                    || {
+                        (0..num_rows)
+                            .map(|_| make_identifiers(identifier_rng))
+                            .collect::<Vec<_>>()
+                    },
+                    || {
+                        (0..num_rows)
+                            .map(|_| timing.pick(episode_time_rng))
+                            .collect::<Vec<_>>()
+                    },
+                )
+            },
+        );
+
+        let (spell_identifier, episode_identifier): (Vec<_>, Vec<_>) =
+            identifiers.into_iter().unzip();
+        let (episode_start_time, episode_end_time): (Vec<_>, Vec<_>) =
+            episode_times.into_iter().unzip();
+
+        let seeded_column_blocks = vec![
+            SeededColumnBlock {
+                columns: vec![subject],
+            },
+            SeededColumnBlock {
+                columns: vec![
+                    Series::new("spell_identifier", spell_identifier),
+                    Series::new("episode_identifier", episode_identifier),
+                ],
+            },
+            SeededColumnBlock {
+                columns: vec![
+                    Series::new("episode_start_time", episode_start_time),
+                    Series::new("episode_end_time", episode_end_time),
+                ],
+            },
+            SeededColumnBlock {
+                columns: vec![diagnosis_code_icd],
+            },
+        ];
+        to_polars(seeded_column_blocks)
+    }
+}
+
+/// Create the hospital episodes table. The primary diagnosis
+/// (diagnosis_code_icd) is picked
+/// according to the diagnosis_frequencies (use CodeFrequencies::default()
+/// to pick uniformly from a short list of codes). The episodes start at
+/// times picked from the window in the timing, and last for a duration
+/// picked from its duration range (by default, up to
+/// DEFAULT_EPISODE_DURATION).
+pub fn make_episodes(
+    block_id: &str,
+    registry: &PatientRegistry,
+    version: GeneratorVersion,
+    diagnosis_frequencies: &CodeFrequencies,
+    timing: EventTiming,
+    num_rows: usize,
+) -> DataFrame {
+    let global_seed = registry.global_seed();
+    let _span = tracing::debug_span!("make_episodes", block_id, global_seed, num_rows).entered();
+    match version {
+        GeneratorVersion::V1 => {
+            EpisodesRows::new(block_id, registry, diagnosis_frequencies, timing, num_rows)
+                .take_chunk(num_rows)
+        }
+    }
+}
+
+/// Create the episodes table in chunks of (at most) chunk_size rows (see
+/// the synth_data module documentation).
+pub fn make_episodes_chunks(
+    block_id: &str,
+    registry: &PatientRegistry,
+    version: GeneratorVersion,
+    diagnosis_frequencies: &CodeFrequencies,
+    timing: EventTiming,
+    num_rows: usize,
+    chunk_size: usize,
+) -> impl Iterator<Item = DataFrame> {
+    let global_seed = registry.global_seed();
+    let span = tracing::debug_span!(
+        "make_episodes_chunks",
+        block_id,
+        global_seed,
+        num_rows,
+        chunk_size
+    );
+    let rows = match version {
+        GeneratorVersion::V1 => {
+            EpisodesRows::new(block_id, registry, diagnosis_frequencies, timing, num_rows)
+        }
+    };
+    make_chunks(rows, chunk_size, span)
+}
+
+/// Tests for the episodes table
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn check_episodes_follow_diagnosis_frequencies() {
+        let registry = PatientRegistry::new(0, 100);
+        let timing = EventTiming::with_duration(DEFAULT_EPISODE_DURATION);
+        let episodes = make_episodes(
+            "episodes",
+            &registry,
+            GeneratorVersion::V1,
+            &CodeFrequencies::default(),
+            timing,
+            1000,
+        );
+        assert_eq!(episodes.height(), 1000);
+
+        // The dataframe contains the same rows as the iterator
+        let rows: Vec<_> = EpisodesRows::new(
+            "episodes",
+            &registry,
+            &CodeFrequencies::default(),
+            timing,
+            10,
+        )
+        .collect();
+        let diagnosis = episodes
+            .column("diagnosis_code_icd")
+            .unwrap()
+            .utf8()
+            .unwrap();
+        for (n, row) in rows.iter().enumerate() {
+            assert_eq!(diagnosis.get(n), Some(row.diagnosis_code_icd.as_str()));
+            assert!(row.episode_start_time <= row.episode_end_time);
+        }
+
+        // Codes are picked in proportion to their frequencies, and codes
+        // with zero frequency are never picked (the other codes are the
+        // diagnoses of the patients' conditions)
+        let frequencies = [("I21.0", 9.0), ("I10", 1.0), ("J18.9", 0.0)]
+            .map(|(code, frequency)| (String::from(code), frequency));
+        let frequencies = CodeFrequencies::new(BTreeMap::from(frequencies)).unwrap();
+        let episodes = make_episodes(
+            "episodes",
+            &registry,
+            GeneratorVersion::V1,
+            &frequencies,
+            timing,
+            10000,
+        );
+        let diagnosis = episodes
+            .column("diagnosis_code_icd")
+            .unwrap()
+            .utf8()
+            .unwrap();
+        let count = |code| diagnosis.into_iter().filter(|d| *d == Some(code)).count();
+        let picked = count("I21.0") + count("I10");
+        assert!((0.87..0.93).contains(&(count("I21.0") as f64 / picked as f64)));
+        assert!(picked > 9000);
+        assert_eq!(count("J18.9"), 0);
+
+        // Only patients with CKD have CKD diagnoses
+        let subject = episodes.column("subject").unwrap().utf8().unwrap();
+        let ckd_diagnoses: Vec<_> = subject
+            .into_iter()
+            .zip(diagnosis)
+            .filter(|(_, diagnosis)| CKD_DIAGNOSES.contains(&diagnosis.unwrap()))
+            .map(|(subject, _)| subject.unwrap())
+            .collect();
+        assert!(!ckd_diagnoses.is_empty());
+        for subject in ckd_diagnoses {
+            let patient = registry.patients().iter().find(|p| p.subject == subject);
+            assert!(patient.unwrap().conditions.ckd);
+        }
+
+        let yaml = "I21.0: 9\nI10: 1\nJ18.9: 0\n";
+        assert_eq!(
+            CodeFrequencies::from_reader(yaml.as_bytes()).unwrap(),
+            frequencies
+        );
+        assert!(CodeFrequencies::from_reader("I21.0: -1\n".as_bytes()).is_err());
+        assert!(CodeFrequencies::from_reader("I21.0: 0\n".as_bytes()).is_err());
+    }
+}
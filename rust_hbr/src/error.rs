@@ -0,0 +1,130 @@
+//! Error type for the rust_hbr crate
+//!
+//! All fallible functions in the crate return HbrError, so that the
+//! Python and R bindings can convert errors into exceptions (or R
+//! conditions) instead of panicking and aborting the process.
+
+use thiserror::Error;
+
+#[cfg(feature = "codes")]
+use crate::clinical_code_tree::TreeDiagnostic;
+
+/// Write a list of problems in a codes file on one line
+#[cfg(feature = "codes")]
+fn diagnostics_list(diagnostics: &[TreeDiagnostic]) -> String {
+    let diagnostics: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+    diagnostics.join("; ")
+}
+
+#[derive(Error, Debug)]
+pub enum HbrError {
+    /// A code tree could not be read from or written to a YAML codes file
+    #[error("codes file YAML error: {0}")]
+    CodesFile(#[from] serde_yaml::Error),
+
+    /// A code tree could not be read from or written to a JSON codes file
+    #[error("codes file JSON error: {0}")]
+    CodesFileJson(#[from] serde_json::Error),
+
+    /// A code tree or code store could not be encoded or decoded
+    /// in the binary cache format
+    #[error("binary cache error: {0}")]
+    CodeTreeCache(#[from] bincode::Error),
+
+    /// A file is not a code tree cache file, or was written using
+    /// a different version of the cache format
+    #[error("invalid code tree cache: {0}")]
+    InvalidCodeTreeCache(String),
+
+    /// A file is not a saved code store, or was written using a
+    /// different version of the format
+    #[error("invalid code store file: {0}")]
+    InvalidCodeStoreFile(String),
+
+    /// A CSV file could not be written
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+
+    /// An input/output error occurred
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// A flat code list (e.g. from NHS TRUD) is not in the expected format
+    #[error("invalid code list: {0}")]
+    InvalidCodeList(String),
+
+    /// A code range (e.g. I20-I25) could not be parsed, or the
+    /// start of the range is after the end
+    #[error("invalid code range {0}")]
+    InvalidCodeRange(String),
+
+    /// A code was not found in the code tree
+    #[error("code {0} not found in code tree")]
+    CodeNotFound(String),
+
+    /// A codes file contains categories with malformed indices (see
+    /// TreeDiagnostic), so codes would silently fail to match
+    #[cfg(feature = "codes")]
+    #[error("invalid index in codes file: {}", diagnostics_list(.0))]
+    InvalidIndex(Vec<TreeDiagnostic>),
+
+    /// A code group is not defined in the code tree
+    #[error("code group {0} is not defined in code tree")]
+    UnknownGroup(String),
+
+    /// A code group is defined but does not contain any codes
+    #[error("code group {0} is empty")]
+    EmptyGroup(String),
+
+    /// Two code trees could not be merged because they disagree
+    /// about a code or category
+    #[error("cannot merge code trees: {0}")]
+    MergeConflict(String),
+
+    /// A category in the code tree has an empty list of sub-categories
+    #[error("no categories to pick from")]
+    EmptyCategories,
+
+    /// A field in a row of a data source had an unexpected value
+    #[error("unexpected {field} {found} (expected {expected})")]
+    UnexpectedValue {
+        field: String,
+        expected: String,
+        found: String,
+    },
+
+    /// The test_name in a row of the pathology_blood table is not
+    /// one that can be converted into a measurement
+    #[error("unrecognised test_name {0}")]
+    UnrecognisedTestName(String),
+
+    /// A lab result is in a unit that is not in the conversion table
+    /// for the analyte (see units)
+    #[error("unknown unit {unit} for {analyte}")]
+    UnknownUnit { analyte: String, unit: String },
+
+    /// A measurement value could not be parsed from a string
+    #[error("failed to convert {0} to a measurement value")]
+    InvalidMeasurementValue(String),
+
+    /// A synthetic dataset spec could not be parsed, or is not valid
+    #[error("invalid dataset spec: {0}")]
+    InvalidDatasetSpec(String),
+
+    /// A table of code frequencies used to generate synthetic data
+    /// could not be parsed, or is not valid
+    #[error("invalid code frequencies: {0}")]
+    InvalidCodeFrequencies(String),
+
+    /// A synthetic dataset does not match its manifest (or the
+    /// manifest could not be read)
+    #[error("dataset verification failed: {0}")]
+    DatasetVerification(String),
+
+    /// A synthetic data table could not be built or written, a table
+    /// could not be read during preprocessing, or a table of features
+    /// could not be made
+    #[cfg(any(feature = "synth", feature = "ingest"))]
+    #[error("polars error: {0}")]
+    Polars(#[from] polars::error::PolarsError),
+}
@@ -1,23 +1,132 @@
 //! Contains random seed-controlled synthetic datasets for use in
 //! testing data preprocessing functions.
 //!
+//! The crate is split into the following cargo features (enabled
+//! by default unless stated otherwise):
+//! * codes: clinical code parsing (clinical_code, clinical_code_tree
+//!   and snomed). This only depends on lightweight crates, and builds
+//!   for wasm32.
+//! * mmap: lazily loaded code trees backed by a memory-mapped file
+//!   (does not build for wasm32)
+//! * default-trees: embed the ICD-10 and OPCS-4 codes files in the
+//!   library (not enabled by default)
+//! * synth: seeded synthetic data tables (depends on polars)
+//! * parquet: write synthetic datasets to parquet, CSV or NDJSON files
+//!   with a manifest for verifying them (see synth_dataset::generate_dataset
+//!   and manifest::verify_dataset; not enabled by default)
+//! * cli: the synth-data command line program for generating synthetic
+//!   datasets (depends on clap; not enabled by default)
+//! * patient: patient-level data structures and the conversion of raw
+//!   values into them, including the normalisation of lab result
+//!   units (depends on mongodb/bson)
+//! * ingest: data sources, parquet input/output and preprocessing
+//!   (depends on datafusion, connectorx and polars). With synth, the
+//!   synthetic tables can also be made as Arrow record batches (see
+//!   to_record_batch)
+//!
+//! Consumers that only need to parse clinical codes should disable the
+//! default features and enable only codes, which avoids the slow build
+//! times and large binaries that come with the data dependencies.
+//!
 
+#[cfg(feature = "ingest")]
 use datafusion::arrow::record_batch::RecordBatch;
+#[cfg(feature = "ingest")]
 use datafusion::parquet::arrow::arrow_writer::ArrowWriter;
+#[cfg(feature = "ingest")]
 use datafusion::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+#[cfg(feature = "ingest")]
 use std::fs;
 
-pub use pathology_blood::make_pathology_blood;
+pub use error::HbrError;
+
+#[cfg(feature = "synth")]
+pub use coronary_procedures::{
+    make_coronary_procedures, make_coronary_procedures_chunks, CoronaryProceduresRow,
+    CoronaryProceduresRows, DEFAULT_PROCEDURE_DURATION,
+};
+#[cfg(feature = "synth")]
+pub use echocardiography::{
+    make_echocardiography, make_echocardiography_chunks, EchocardiographyRow,
+    EchocardiographyRows, DEFAULT_REPORT_TIME,
+};
+#[cfg(feature = "synth")]
+pub use episodes::{
+    make_episodes, make_episodes_chunks, CodeFrequencies, EpisodesRow, EpisodesRows,
+    DEFAULT_EPISODE_DURATION,
+};
+#[cfg(feature = "synth")]
+pub use pathology_blood::{
+    make_pathology_blood, make_pathology_blood_chunks, make_pathology_blood_lazy, PathologyBloodRow,
+    PathologyBloodRows, DEFAULT_PROCESSING_TIME,
+};
+#[cfg(feature = "synth")]
+pub use prescriptions::{
+    make_prescriptions, make_prescriptions_chunks, PrescriptionsRow, PrescriptionsRows,
+    DEFAULT_PRESCRIPTION_DURATION,
+};
+#[cfg(all(feature = "synth", feature = "ingest"))]
+pub use synth_arrow::to_record_batch;
+#[cfg(feature = "synth")]
+pub use swd_attributes::{
+    make_swd_attributes, make_swd_attributes_chunks, SwdAttributesRow, SwdAttributesRows,
+};
+#[cfg(feature = "synth")]
+pub use ward_transfers::{
+    make_ward_transfers, make_ward_transfers_chunks, WardTransfersRow, WardTransfersRows,
+    DEFAULT_SPELL_DURATION,
+};
+#[cfg(feature = "synth")]
+pub use synth_data::{
+    local_to_utc, to_timezone_aware, DateWindow, DurationRange, EventTiming, Gender,
+    GeneratorVersion, PatientConditions, PatientRegistry, SyntheticPatient, DEFAULT_NUM_PATIENTS,
+    LOCAL_TIME_ZONE,
+};
 
+#[cfg(feature = "synth")]
+pub mod corruption;
+#[cfg(feature = "synth")]
+mod coronary_procedures;
+#[cfg(feature = "synth")]
+mod echocardiography;
+#[cfg(feature = "synth")]
+mod episodes;
+#[cfg(feature = "parquet")]
+pub mod manifest;
+#[cfg(feature = "synth")]
 mod pathology_blood;
+#[cfg(feature = "synth")]
+mod prescriptions;
+#[cfg(feature = "synth")]
+mod swd_attributes;
+#[cfg(feature = "synth")]
+mod ward_transfers;
+#[cfg(feature = "synth")]
 mod seeded_column_block;
+#[cfg(all(feature = "synth", feature = "ingest"))]
+mod synth_arrow;
+#[cfg(feature = "synth")]
 mod synth_data;
+#[cfg(feature = "synth")]
+pub mod synth_dataset;
+#[cfg(feature = "patient")]
 pub mod patient;
+#[cfg(feature = "codes")]
 pub mod clinical_code;
+#[cfg(feature = "codes")]
 pub mod clinical_code_tree;
+#[cfg(feature = "codes")]
+pub mod snomed;
 pub mod seeded_rng;
+pub mod error;
+#[cfg(feature = "patient")]
 pub mod preprocess;
+#[cfg(feature = "patient")]
+pub mod units;
+#[cfg(feature = "codes")]
+pub mod schema;
 
+#[cfg(feature = "ingest")]
 pub fn save_record_batch(filename: &str, batch: RecordBatch) {
     let file = fs::File::create(filename).unwrap();
     let mut writer = ArrowWriter::try_new(file, batch.schema(), None).unwrap();
@@ -25,13 +134,14 @@ pub fn save_record_batch(filename: &str, batch: RecordBatch) {
     writer.close().unwrap();
 }
 
+#[cfg(feature = "ingest")]
 pub fn load_record_batch(filename: &str) -> RecordBatch {
     let file = fs::File::open(filename).unwrap();
     let builder = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
-    println!("Converted arrow schema is: {}", builder.schema());
+    tracing::debug!(filename, schema = %builder.schema(), "converted arrow schema");
     let mut reader = builder.build().unwrap();
     let record_batch = reader.next().unwrap().unwrap();
-    println!("Read {} records.", record_batch.num_rows());
+    tracing::debug!(filename, num_rows = record_batch.num_rows(), "read records");
     record_batch
 }
 
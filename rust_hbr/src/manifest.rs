@@ -0,0 +1,251 @@
+//! Manifests of generated synthetic datasets
+//!
+//! generate_dataset writes a manifest (manifest.json) next to the
+//! tables, which records the dataset spec (including the global seed
+//! and the version of the generators), the version of this crate, and
+//! the number of rows and a content hash of each file. verify_dataset
+//! checks a dataset against its manifest in two ways: the files on disk
+//! must not have changed, and generating each table again from the spec
+//! must give exactly the same contents. The second check means that any
+//! change to the generators that breaks reproducibility (the same spec
+//! no longer giving the same data) is detected, for example by
+//! verifying a stored dataset in CI.
+//!
+//! The hashes are BLAKE2s-256 hashes of the file contents, written in
+//! hexadecimal.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use blake2::{Blake2s256, Digest};
+use serde::{Deserialize, Serialize};
+
+use crate::error::HbrError;
+use crate::synth_dataset::{write_table, DatasetSpec, SyntheticTable};
+
+/// The name of the manifest file in a dataset directory
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// The version of this crate, recorded in the manifest to help find
+/// the cause of a dataset that is not reproduced (the version of the
+/// generators themselves is in the spec)
+pub const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Writes to an inner writer, keeping a hash of everything written
+pub(crate) struct HashingWriter<W> {
+    inner: W,
+    hasher: Blake2s256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Blake2s256::new(),
+        }
+    }
+
+    /// The hash of everything written so far (in hexadecimal)
+    pub(crate) fn hash(&self) -> String {
+        format!("{:x}", self.hasher.clone().finalize())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Hash the contents of a file
+fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut writer = HashingWriter::new(std::io::sink());
+    std::io::copy(&mut File::open(path)?, &mut writer)?;
+    Ok(writer.hash())
+}
+
+/// One file written by generate_dataset
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// The table in the file
+    pub table: SyntheticTable,
+    /// The name of the file (in the dataset directory)
+    pub file_name: String,
+    /// The number of rows written to the file
+    pub num_rows: usize,
+    /// The hash of the contents of the file
+    pub hash: String,
+}
+
+/// The record of a generated dataset, written to manifest.json
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatasetManifest {
+    /// The version of this crate that wrote the dataset
+    pub crate_version: String,
+    /// The spec the dataset was generated from
+    pub spec: DatasetSpec,
+    /// The files in the dataset, in the same order as the tables in
+    /// the spec
+    pub files: Vec<ManifestFile>,
+}
+
+impl DatasetManifest {
+    /// Make the manifest for files generated by this version of the
+    /// crate
+    pub fn new(spec: DatasetSpec, files: Vec<ManifestFile>) -> Self {
+        Self {
+            crate_version: String::from(CRATE_VERSION),
+            spec,
+            files,
+        }
+    }
+
+    /// Read the manifest in a dataset directory. Returns
+    /// HbrError::DatasetVerification if there is no manifest, or it
+    /// cannot be parsed or contains an invalid spec.
+    pub fn read<P: AsRef<Path>>(output_dir: P) -> Result<Self, HbrError> {
+        let path = output_dir.as_ref().join(MANIFEST_FILE_NAME);
+        let file = File::open(&path).map_err(|err| {
+            HbrError::DatasetVerification(format!("could not open {}: {err}", path.display()))
+        })?;
+        let manifest: Self = serde_json::from_reader(file).map_err(|err| {
+            HbrError::DatasetVerification(format!("could not parse {}: {err}", path.display()))
+        })?;
+        manifest.spec.validate()?;
+        Ok(manifest)
+    }
+
+    /// Write the manifest to a dataset directory, returning its path
+    pub fn write<P: AsRef<Path>>(&self, output_dir: P) -> Result<PathBuf, HbrError> {
+        let path = output_dir.as_ref().join(MANIFEST_FILE_NAME);
+        let file = File::create(&path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(path)
+    }
+}
+
+/// Check a dataset written by generate_dataset against its manifest.
+/// Each file must have the hash in the manifest, and generating the
+/// table again from the spec in the manifest must give the same number
+/// of rows and the same hash. Returns the manifest if the dataset is
+/// verified, or HbrError::DatasetVerification listing the problems.
+///
+/// Every table is generated again (without writing it to disk), so
+/// this takes about as long as generating the dataset.
+pub fn verify_dataset<P: AsRef<Path>>(output_dir: P) -> Result<DatasetManifest, HbrError> {
+    let _span = tracing::info_span!("verify_dataset").entered();
+    let manifest = DatasetManifest::read(&output_dir)?;
+    let registry = manifest.spec.patient_registry();
+    let mut problems = Vec::new();
+    for file in &manifest.files {
+        let Some(table_spec) = manifest
+            .spec
+            .tables
+            .iter()
+            .find(|table_spec| table_spec.table == file.table)
+        else {
+            problems.push(format!("table {} is not in the spec", file.table.name()));
+            continue;
+        };
+        let path = output_dir.as_ref().join(&file.file_name);
+        match hash_file(&path) {
+            Ok(hash) if hash == file.hash => {}
+            Ok(_) => problems.push(format!("{} has been changed", file.file_name)),
+            Err(err) => problems.push(format!("could not read {}: {err}", file.file_name)),
+        }
+        tracing::debug!(table = file.table.name(), "regenerating table");
+        let mut writer = HashingWriter::new(std::io::sink());
+        let num_rows = write_table(&manifest.spec, table_spec, &registry, &mut writer)?;
+        if num_rows != file.num_rows || writer.hash() != file.hash {
+            problems.push(format!(
+                "{} is not reproduced by the spec ({num_rows} rows with hash {}, expected {} rows with hash {})",
+                file.file_name,
+                writer.hash(),
+                file.num_rows,
+                file.hash
+            ));
+        }
+    }
+    if problems.is_empty() {
+        return Ok(manifest);
+    }
+    let mut message = problems.join("; ");
+    if manifest.crate_version != CRATE_VERSION {
+        message.push_str(&format!(
+            " (the dataset was generated by version {} of rust_hbr, and this is version {CRATE_VERSION})",
+            manifest.crate_version
+        ));
+    }
+    Err(HbrError::DatasetVerification(message))
+}
+
+/// Tests for writing and verifying dataset manifests
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::synth_data::GeneratorVersion;
+    use crate::synth_dataset::{generate_dataset, OutputFormat, TableSpec};
+
+    #[test]
+    fn check_generated_dataset_is_verified() {
+        // The directory is unique to this run of the test, so that
+        // concurrent test runs do not write to the same files
+        let output_dir = std::env::temp_dir().join(format!(
+            "rust_hbr_check_dataset_manifest_{}",
+            std::process::id()
+        ));
+        let spec = DatasetSpec {
+            seed: 7,
+            num_patients: 100,
+            tables: vec![
+                TableSpec::new(SyntheticTable::PathologyBlood, 200),
+                TableSpec::new(SyntheticTable::Patients, 500),
+            ],
+            format: OutputFormat::Csv,
+            chunk_size: Some(64),
+            corruption: None,
+            generator_version: GeneratorVersion::LATEST,
+        };
+        generate_dataset(&spec, &output_dir).unwrap();
+        let manifest = verify_dataset(&output_dir).expect("Dataset should be verified");
+        assert_eq!(manifest.spec, spec);
+        let num_rows: Vec<_> = manifest.files.iter().map(|file| file.num_rows).collect();
+        assert_eq!(num_rows, vec![200, 100]);
+
+        // The CSV files do not depend on the chunk size
+        let mut other_manifest = manifest.clone();
+        other_manifest.spec.chunk_size = None;
+        other_manifest.write(&output_dir).unwrap();
+        assert!(verify_dataset(&output_dir).is_ok());
+
+        // Changing a file is detected
+        let path = output_dir.join("patients.csv");
+        let mut contents = std::fs::read_to_string(&path).unwrap();
+        contents.push_str("bristol_101,female,2000-01-01T00:00:00.000\n");
+        std::fs::write(&path, contents).unwrap();
+        assert!(matches!(
+            verify_dataset(&output_dir),
+            Err(HbrError::DatasetVerification(_))
+        ));
+
+        // Changing the spec (so the data is not reproduced) is detected
+        generate_dataset(&spec, &output_dir).unwrap();
+        let mut other_manifest = manifest;
+        other_manifest.spec.seed = 8;
+        other_manifest.write(&output_dir).unwrap();
+        assert!(matches!(
+            verify_dataset(&output_dir),
+            Err(HbrError::DatasetVerification(_))
+        ));
+
+        std::fs::remove_dir_all(&output_dir).expect("Failed to remove dataset");
+    }
+}
@@ -1,23 +1,34 @@
 //! The pathology blood table contains blood test result including haemoglobin,
 //! platelet count, etc. The columns include the test name and category, the
 //! result and unit, and sample collection date and processing times.
-
-use crate::seeded_column_block::{to_polars, SeededColumnBlock};
+//!
+//! The results depend on the conditions of the patient (see
+//! PatientConditions): patients with bleeding or anaemia have lower
+//! haemoglobin, and patients with CKD have lower eGFR. They also depend
+//! on the gender of the patient and their age when the sample was
+//! collected, in roughly the same way as in real populations: men have
+//! higher haemoglobin and women have higher platelet counts, and
+//! haemoglobin, platelets and eGFR all fall in older patients. The
+//! normal range of haemoglobin depends on gender.
+
+use crate::error::HbrError;
+use crate::seeded_column_block::{make_chunks, to_polars, SeededColumnBlock, SyntheticRows};
 use crate::seeded_rng::make_rng;
-use crate::synth_data::{make_gender, make_subject, Gender};
-use chrono::{Duration, NaiveDateTime};
+use crate::synth_data::{
+    DurationRange, EventTiming, Gender, GeneratorVersion, PatientRegistry, SyntheticPatient,
+};
+use chrono::NaiveDateTime;
 use rand::prelude::*;
 use rand_chacha::ChaCha8Rng;
 
 use polars::prelude::*;
+use std::sync::Arc;
 
-fn make_result_flag(rng: &mut ChaCha8Rng) -> Option<String> {
-    if rng.gen() {
-        Some(String::from("<"))
-    } else {
-        None
-    }
-}
+/// The default time taken to process a blood sample (up to one week)
+pub const DEFAULT_PROCESSING_TIME: DurationRange = DurationRange {
+    min_minutes: 0,
+    max_minutes: 10080,
+};
 
 /// Lab test data in the format required for the pathology_blood table.
 /// String data type is used to match type in synthetic data table.
@@ -39,6 +50,22 @@ struct BloodTest {
 }
 
 impl BloodTest {
+    /// The result flag for the test, which is "<" if the result is below
+    /// the normal range, ">" if it is above the normal range, and None if
+    /// it is inside the normal range (or the test has no normal range,
+    /// or the result is not a number)
+    fn result_flag(&self) -> Option<String> {
+        let test_result: f64 = self.test_result.parse().ok()?;
+        let parse_range = |range: &Option<String>| range.as_ref()?.parse::<f64>().ok();
+        if parse_range(&self.result_lower_range).is_some_and(|lower| test_result < lower) {
+            Some(String::from("<"))
+        } else if parse_range(&self.result_upper_range).is_some_and(|upper| test_result > upper) {
+            Some(String::from(">"))
+        } else {
+            None
+        }
+    }
+
     /// Regular full-blood-count haemoglobin (not electrophoresis)
     ///
     /// The gender is required to determine the normal test result range.
@@ -108,201 +135,579 @@ impl BloodTest {
     }
 }
 
-/// Make a uniform random haemoglobin measurement in the range
-/// 0 - 19g/dL
-fn make_random_haemoglobin(rng: &mut ChaCha8Rng) -> BloodTest {
-    let gender = make_gender(rng);
-    let test_result = rng.gen_range(0..190);
-    BloodTest::new_haemoglobin(test_result, gender)
+/// The age from which results start to fall (the results of younger
+/// patients, including any samples taken before the patient was born,
+/// do not depend on age)
+const DECLINE_START_AGE: i32 = 40;
+
+/// The number of years since results started to fall with age (the
+/// results never fall below zero, even for the very old ages that are
+/// possible with a date window far in the future)
+fn years_of_decline(age: i32) -> u32 {
+    (age - DECLINE_START_AGE).max(0) as u32
+}
+
+/// Make a uniform random haemoglobin measurement, with the normal
+/// range for the patient's gender. The result is in the range 115 -
+/// 185 g/L for men and 105 - 165 g/L for women, or 50 - 120 g/L if the
+/// patient has bleeding or anaemia. Both ranges fall by 1 g/L for every
+/// four years over 40.
+fn make_random_haemoglobin(
+    rng: &mut ChaCha8Rng,
+    patient: &SyntheticPatient,
+    age: i32,
+) -> BloodTest {
+    let conditions = patient.conditions;
+    let test_result: u32 = if conditions.bleeding || conditions.anaemia {
+        rng.gen_range(50..120)
+    } else {
+        match patient.gender {
+            Gender::Female => rng.gen_range(105..165),
+            Gender::Male => rng.gen_range(115..185),
+        }
+    };
+    BloodTest::new_haemoglobin(
+        test_result.saturating_sub(years_of_decline(age) / 4),
+        patient.gender,
+    )
 }
 
 /// Make a uniform random platelet count measurement in the range
-/// 0 - 500e9/L
-fn make_random_platelets(rng: &mut ChaCha8Rng) -> BloodTest {
-    let test_result = rng.gen_range(0..500);
-    BloodTest::new_platelets(test_result)
+/// 80 - 450e9/L for men and 90 - 480e9/L for women (so that a few
+/// results show thrombocytopenia), which falls by 1e9/L for every year
+/// over 40
+fn make_random_platelets(rng: &mut ChaCha8Rng, patient: &SyntheticPatient, age: i32) -> BloodTest {
+    let test_result: u32 = match patient.gender {
+        Gender::Female => rng.gen_range(90..480),
+        Gender::Male => rng.gen_range(80..450),
+    };
+    BloodTest::new_platelets(test_result.saturating_sub(years_of_decline(age)))
 }
 
-/// Make a uniform random eGFR in the range 0 - 150, but greater
-/// than 90 will be stored as >90
-fn make_random_egfr(rng: &mut ChaCha8Rng) -> BloodTest {
-    let test_result = rng.gen_range(0..150);
+/// Make a uniform random eGFR in the range 70 - 130, which falls by
+/// 1 mL/min for every year over 40, or 5 - 60 if the patient has CKD
+/// (greater than 90 will be stored as >90)
+fn make_random_egfr(rng: &mut ChaCha8Rng, patient: &SyntheticPatient, age: i32) -> BloodTest {
+    let test_result = if patient.conditions.ckd {
+        rng.gen_range(5..60)
+    } else {
+        let test_result: u32 = rng.gen_range(70..130);
+        test_result.saturating_sub(years_of_decline(age))
+    };
     BloodTest::new_egfr(test_result)
 }
 
-/// This is an example function that makes the subject column from
-/// an id and a seed. The id should always stay the same (otherwise
-/// the data will change). The colunn name is allowed to change (this
-/// covers the case where you want to change the column name but not
-/// change the data.)
-fn make_subject_columns(
-    block_id: &str,
-    global_seed: u64,
-    column_name: String,
-    num_rows: usize,
-) -> SeededColumnBlock {
-    // Augment the id with the seed and hash to get the
-    // seed to be used.
-    let mut rng = make_rng(global_seed, block_id);
-
-    let mut subject = Vec::new();
-    for _ in 0..num_rows {
-        subject.push(make_subject(&mut rng));
+/// Make a random blood test for the patient, whose age is the age when
+/// the sample was collected, picking uniformly between the supported
+/// test types.
+fn make_random_blood_test(rng: &mut ChaCha8Rng, patient: &SyntheticPatient, age: i32) -> BloodTest {
+    match rng.gen_range(0..3) {
+        0 => make_random_haemoglobin(rng, patient, age),
+        1 => make_random_platelets(rng, patient, age),
+        2 => make_random_egfr(rng, patient, age),
+        _ => panic!("Blood test index out of range"),
     }
+}
+
+/// One row of the pathology_blood table
+#[derive(Debug)]
+pub struct PathologyBloodRow {
+    pub subject: String,
+    pub laboratory_department: Option<String>,
+    pub order_name: String,
+    pub test_name: String,
+    pub test_result: String,
+    pub test_result_unit: Option<String>,
+    pub result_lower_range: Option<String>,
+    pub result_upper_range: Option<String>,
+    pub sample_collected_date_time: NaiveDateTime,
+    pub result_available_date_time: NaiveDateTime,
+    pub result_flag: Option<String>,
+    pub brc_name: String,
+}
 
-    SeededColumnBlock {
-        columns: vec![Series::new(column_name.as_ref(), subject)],
+/// Iterator over the rows of the pathology_blood table
+///
+/// Each seeded column block keeps its own random number generator,
+/// and all the generators are advanced together one row at a time.
+/// This means the rows are identical to the ones that would be obtained
+/// by generating each block of columns in full (which is how the
+/// dataframes are made, see take_chunk), but only one row needs to be
+/// held in memory at a time.
+pub struct PathologyBloodRows {
+    registry: PatientRegistry,
+    /// When the samples are collected, and how long they take to process
+    timing: EventTiming,
+    subject_rng: ChaCha8Rng,
+    blood_test_rng: ChaCha8Rng,
+    sample_time_rng: ChaCha8Rng,
+    rows_left: usize,
+}
+
+impl PathologyBloodRows {
+    /// Make an iterator that will return num_rows rows. The arguments
+    /// have the same meaning as in make_pathology_blood.
+    pub fn new(
+        block_id: &str,
+        registry: &PatientRegistry,
+        timing: EventTiming,
+        num_rows: usize,
+    ) -> Self {
+        let global_seed = registry.global_seed();
+        let subject_block_id = format!("{block_id}subject");
+        let blood_test_block_id = format!("{block_id}blood_test");
+        let sample_time_block_id = format!("{block_id}sample_time");
+        Self {
+            registry: registry.clone(),
+            timing,
+            subject_rng: make_rng(global_seed, subject_block_id.as_ref()),
+            blood_test_rng: make_rng(global_seed, blood_test_block_id.as_ref()),
+            sample_time_rng: make_rng(global_seed, sample_time_block_id.as_ref()),
+            rows_left: num_rows,
+        }
     }
 }
 
-/// Creates a block of columns that includes the blood test name
-/// and family, the test result, test unit, and upper and lower
-/// ranges
-fn make_blood_test_columns(block_id: &str, global_seed: u64, num_rows: usize) -> SeededColumnBlock {
-    // Augment the id with the seed and hash to get the
-    // seed to be used.
-    let mut rng = make_rng(global_seed, block_id);
-
-    let mut order_name = Vec::new();
-    let mut test_name = Vec::new();
-    let mut test_result = Vec::new();
-    let mut test_result_unit = Vec::new();
-    let mut result_lower_range = Vec::new();
-    let mut result_upper_range = Vec::new();
-
-    for _ in 0..num_rows {
-        // Make a random blood test
-        let blood_test = match rng.gen_range(0..3) {
-            0 => make_random_haemoglobin(&mut rng),
-            1 => make_random_platelets(&mut rng),
-            2 => make_random_egfr(&mut rng),
-            _ => panic!("Blood test index out of range"),
-        };
+impl Iterator for PathologyBloodRows {
+    type Item = PathologyBloodRow;
 
-        // Make the test type and results
-        order_name.push(blood_test.order_name);
-        test_name.push(blood_test.test_name);
-        test_result.push(blood_test.test_result);
-        test_result_unit.push(blood_test.test_result_unit);
-        result_lower_range.push(blood_test.result_lower_range);
-        result_upper_range.push(blood_test.result_upper_range);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rows_left == 0 {
+            return None;
+        }
+        self.rows_left -= 1;
+
+        let patient = self.registry.sample(&mut self.subject_rng);
+        let (sample_collected_date_time, result_available_date_time) =
+            self.timing.pick(&mut self.sample_time_rng);
+        let age = patient.age_at(sample_collected_date_time);
+        let blood_test = make_random_blood_test(&mut self.blood_test_rng, patient, age);
+        let result_flag = blood_test.result_flag();
+        Some(PathologyBloodRow {
+            subject: patient.subject.clone(),
+            // Lab department is always None
+            laboratory_department: None,
+            order_name: blood_test.order_name,
+            test_name: blood_test.test_name,
+            test_result: blood_test.test_result,
+            test_result_unit: blood_test.test_result_unit,
+            result_lower_range: blood_test.result_lower_range,
+            result_upper_range: blood_test.result_upper_range,
+            sample_collected_date_time,
+            result_available_date_time,
+            result_flag,
+            // brc name is always Bristol
+            brc_name: String::from("bristol"),
+        })
     }
 
-    SeededColumnBlock {
-        columns: vec![
-            Series::new("order_name", order_name),
-            Series::new("test_name", test_name),
-            Series::new("test_result", test_result),
-            Series::new("test_result_unit", test_result_unit),
-            Series::new("result_lower_range", result_lower_range),
-            Series::new("result_upper_range", result_upper_range),
-        ],
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.rows_left, Some(self.rows_left))
     }
 }
 
-fn make_sample_time_columns(
-    block_id: &str,
-    global_seed: u64,
-    num_rows: usize,
-) -> SeededColumnBlock {
-    let mut rng = make_rng(global_seed, block_id);
-
-    let mut sample_collected_date_time = Vec::new();
-    let mut result_available_date_time = Vec::new();
-
-    for _ in 0..num_rows {
-        // Sample collected at any date from 1970 to roughly now, and
-        // up to 1 week processing time
-        let sample_collected_timestamp =
-            NaiveDateTime::from_timestamp_opt(60 * rng.gen_range(0..28150015), 0).unwrap();
-        let processing_time = Duration::seconds(60 * rng.gen_range(0..10080));
-        sample_collected_date_time.push(sample_collected_timestamp);
-        result_available_date_time.push(sample_collected_timestamp + processing_time);
+impl SyntheticRows for PathologyBloodRows {
+    fn take_chunk(&mut self, chunk_size: usize) -> DataFrame {
+        let num_rows = chunk_size.min(self.rows_left);
+        self.rows_left -= num_rows;
+        let Self {
+            registry,
+            timing,
+            subject_rng,
+            blood_test_rng,
+            sample_time_rng,
+            ..
+        } = self;
+
+        // The blood test results depend on the gender and conditions of
+        // the subject, and their age when the sample was collected, so
+        // the blood test block is generated after the subject and sample
+        // time blocks
+        let (patients, sample_times) = rayon::join(
+            || {
+                (0..num_rows)
+                    .map(|_| registry.sample(subject_rng))
+                    .collect::<Vec<_>>()
+            },
+            || {
+                (0..num_rows)
+                    .map(|_| timing.pick(sample_time_rng))
+                    .collect::<Vec<_>>()
+            },
+        );
+        let blood_tests: Vec<_> = patients
+            .iter()
+            .zip(&sample_times)
+            .map(|(patient, (sample_collected_date_time, _))| {
+                let age = patient.age_at(*sample_collected_date_time);
+                make_random_blood_test(blood_test_rng, patient, age)
+            })
+            .collect();
+        let subject: Vec<_> = patients
+            .iter()
+            .map(|patient| patient.subject.as_str())
+            .collect();
+        let subject = Series::new("subject", subject);
+
+        let mut order_name = Vec::with_capacity(num_rows);
+        let mut test_name = Vec::with_capacity(num_rows);
+        let mut test_result = Vec::with_capacity(num_rows);
+        let mut test_result_unit = Vec::with_capacity(num_rows);
+        let mut result_lower_range = Vec::with_capacity(num_rows);
+        let mut result_upper_range = Vec::with_capacity(num_rows);
+        let mut result_flag = Vec::with_capacity(num_rows);
+        for blood_test in blood_tests {
+            result_flag.push(blood_test.result_flag());
+            order_name.push(blood_test.order_name);
+            test_name.push(blood_test.test_name);
+            test_result.push(blood_test.test_result);
+            test_result_unit.push(blood_test.test_result_unit);
+            result_lower_range.push(blood_test.result_lower_range);
+            result_upper_range.push(blood_test.result_upper_range);
+        }
+        let (sample_collected_date_time, result_available_date_time): (Vec<_>, Vec<_>) =
+            sample_times.into_iter().unzip();
+
+        let seeded_column_blocks = vec![
+            SeededColumnBlock {
+                columns: vec![subject],
+            },
+            // Lab department is always None
+            SeededColumnBlock {
+                columns: vec![Series::new(
+                    "laboratory_department",
+                    vec![None::<String>; num_rows],
+                )],
+            },
+            SeededColumnBlock {
+                columns: vec![
+                    Series::new("order_name", order_name),
+                    Series::new("test_name", test_name),
+                    Series::new("test_result", test_result),
+                    Series::new("test_result_unit", test_result_unit),
+                    Series::new("result_lower_range", result_lower_range),
+                    Series::new("result_upper_range", result_upper_range),
+                ],
+            },
+            SeededColumnBlock {
+                columns: vec![
+                    Series::new("sample_collected_date_time", sample_collected_date_time),
+                    Series::new("result_available_date_time", result_available_date_time),
+                ],
+            },
+            // The result flag is derived from the blood test block
+            SeededColumnBlock {
+                columns: vec![Series::new("result_flag", result_flag)],
+            },
+            // brc name is always Bristol
+            SeededColumnBlock {
+                columns: vec![Series::new("brc_name", vec!["bristol"; num_rows])],
+            },
+        ];
+        to_polars(seeded_column_blocks)
     }
+}
 
-    SeededColumnBlock {
-        columns: vec![
-            Series::new("sample_collected_date_time", sample_collected_date_time),
-            Series::new("result_available_date_time", result_available_date_time),
-        ],
+/// Create the blood results table. Generated data is randomly generated based on
+/// the global seed of the registry (the purpose is mainly the format of the data).
+/// The subjects are picked from the patients in the registry, and the results
+/// depend on their conditions, gender and age. Currently includes the following
+/// blood tests:
+///
+/// * haemoglobin
+/// * platelet count
+/// * eGFR
+///
+/// The samples are collected at times picked from the window in the
+/// timing, and the results are available after a processing time picked
+/// from its duration range (by default, samples are collected from 1970
+/// to 2023, and processing takes up to DEFAULT_PROCESSING_TIME). The
+/// version is the version of the generator to use (see GeneratorVersion).
+pub fn make_pathology_blood(
+    block_id: &str,
+    registry: &PatientRegistry,
+    version: GeneratorVersion,
+    timing: EventTiming,
+    num_rows: usize,
+) -> DataFrame {
+    let global_seed = registry.global_seed();
+    let _span =
+        tracing::debug_span!("make_pathology_blood", block_id, global_seed, num_rows).entered();
+    // Parts of the generator that change in a later version are picked
+    // here (or passed to the rows), so that the older versions still
+    // generate the same data
+    match version {
+        GeneratorVersion::V1 => {
+            PathologyBloodRows::new(block_id, registry, timing, num_rows).take_chunk(num_rows)
+        }
     }
 }
 
-/// This column is either < or Null. Unknown interpretation.
-fn make_result_flag_column(
+/// Create the blood results table in chunks of (at most) chunk_size rows
+/// (see the synth_data module documentation). If chunk_size is zero,
+/// the whole table is generated in one chunk.
+pub fn make_pathology_blood_chunks(
     block_id: &str,
-    global_seed: u64,
-    column_name: String,
+    registry: &PatientRegistry,
+    version: GeneratorVersion,
+    timing: EventTiming,
     num_rows: usize,
-) -> SeededColumnBlock {
-    let mut rng = make_rng(global_seed, block_id);
+    chunk_size: usize,
+) -> impl Iterator<Item = DataFrame> {
+    let global_seed = registry.global_seed();
+    let span = tracing::debug_span!(
+        "make_pathology_blood_chunks",
+        block_id,
+        global_seed,
+        num_rows,
+        chunk_size
+    );
+    let rows = match version {
+        GeneratorVersion::V1 => PathologyBloodRows::new(block_id, registry, timing, num_rows),
+    };
+    make_chunks(rows, chunk_size, span)
+}
 
-    let mut result_flag = Vec::new();
+/// Anonymous scan used to generate the pathology_blood table
+/// inside a polars query plan
+struct PathologyBloodScan {
+    block_id: String,
+    registry: PatientRegistry,
+    version: GeneratorVersion,
+    timing: EventTiming,
+    num_rows: usize,
+}
 
-    for _ in 0..num_rows {
-        result_flag.push(make_result_flag(&mut rng));
+impl AnonymousScan for PathologyBloodScan {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
     }
 
-    SeededColumnBlock {
-        columns: vec![Series::new(column_name.as_ref(), result_flag)],
+    /// Generate only as many rows as the query needs, and only keep
+    /// the columns that the query selects. Rows are always generated
+    /// from the start of the table so that a sliced table contains the
+    /// same data as the first rows of the full table.
+    fn scan(&self, scan_opts: AnonymousScanOptions) -> PolarsResult<DataFrame> {
+        let num_rows = match scan_opts.n_rows {
+            Some(n_rows) => n_rows.min(self.num_rows),
+            None => self.num_rows,
+        };
+        let _span = tracing::debug_span!(
+            "pathology_blood_scan",
+            block_id = %self.block_id,
+            num_rows
+        )
+        .entered();
+        let df = make_pathology_blood(
+            &self.block_id,
+            &self.registry,
+            self.version,
+            self.timing,
+            num_rows,
+        );
+        match scan_opts.with_columns {
+            Some(columns) => df.select(columns.iter()),
+            None => Ok(df),
+        }
+    }
+
+    fn allows_projection_pushdown(&self) -> bool {
+        true
+    }
+
+    fn allows_slice_pushdown(&self) -> bool {
+        true
     }
 }
 
-/// Create the blood results table. Generated data is randomly generated based on
-/// the global seed with no particular statistical characteristics (the purpose is the
-/// format of the data). Currently includes the following blood tests:
+/// Create the blood results table as a polars LazyFrame
 ///
-/// * haemoglobin
-/// * platelet count
-/// * eGFR
-pub fn make_pathology_blood(block_id: &str, global_seed: u64, num_rows: usize) -> DataFrame {
-    let mut seeded_column_blocks = Vec::new();
-
-    // Make patient id column
-    let subject_block_id = format!("{block_id}subject");
-    let subjects = make_subject_columns(
-        subject_block_id.as_ref(),
-        global_seed,
-        String::from("subject"),
-        num_rows,
-    );
-    seeded_column_blocks.push(subjects);
-
-    // Lab department is always None
-    let column = vec![None as Option<String>; num_rows];
-    seeded_column_blocks.push(SeededColumnBlock {
-        columns: vec![Series::new("laboratory_department", column)],
-    });
-
-    // Make the blood test columns (a block of columns including test name, result, units,
-    // and ranges)
-    let blood_test_block_id = format!("{block_id}blood_test");
-    let blood_test_columns =
-        make_blood_test_columns(blood_test_block_id.as_ref(), global_seed, num_rows);
-    seeded_column_blocks.push(blood_test_columns);
-
-    // Make columns for sample collected time and processing times
-    let sample_time_block_id = format!("{block_id}sample_time");
-    let sample_time_columns =
-        make_sample_time_columns(sample_time_block_id.as_ref(), global_seed, num_rows);
-    seeded_column_blocks.push(sample_time_columns);
-
-    // Make the result flag columns
-    let result_flag_block_id = format!("{block_id}result_flag");
-    let column_name = String::from("result_flag");
-    let result_flag_column = make_result_flag_column(
-        result_flag_block_id.as_ref(),
-        global_seed,
-        column_name,
+/// The data is the same as make_pathology_blood, but nothing is generated
+/// until the LazyFrame is collected. Column selections and row limits (e.g.
+/// head()) applied to the result are pushed down into the generation, so
+/// only the columns and rows that are required are kept. Returns
+/// HbrError::Polars if polars cannot create the scan.
+pub fn make_pathology_blood_lazy(
+    block_id: &str,
+    registry: &PatientRegistry,
+    version: GeneratorVersion,
+    timing: EventTiming,
+    num_rows: usize,
+) -> Result<LazyFrame, HbrError> {
+    // An empty table has the same schema as the full table
+    let schema = make_pathology_blood(block_id, registry, version, timing, 0).schema();
+    let scan = PathologyBloodScan {
+        block_id: String::from(block_id),
+        registry: registry.clone(),
+        version,
+        timing,
         num_rows,
-    );
-    seeded_column_blocks.push(result_flag_column);
+    };
+    let args = ScanArgsAnonymous {
+        schema: Some(schema),
+        name: "pathology_blood",
+        ..Default::default()
+    };
+    Ok(LazyFrame::anonymous_scan(Arc::new(scan), args)?)
+}
 
-    // brc name is always Bristol
-    let column = vec![String::from("bristol"); num_rows];
-    seeded_column_blocks.push(SeededColumnBlock {
-        columns: vec![Series::new("brc_name", column)],
-    });
+/// Tests for the pathology_blood table
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::synth_data::PatientConditions;
+
+    #[test]
+    fn check_result_flag_is_derived_from_range() {
+        let registry = PatientRegistry::new(0, 100);
+        let timing = EventTiming::with_duration(DEFAULT_PROCESSING_TIME);
+        let mut flags = Vec::new();
+        for row in PathologyBloodRows::new("pathology_blood", &registry, timing, 1000) {
+            let test_result = row.test_result.parse::<f64>().ok();
+            let lower = row.result_lower_range.map(|r| r.parse::<f64>().unwrap());
+            let upper = row.result_upper_range.map(|r| r.parse::<f64>().unwrap());
+            let expected = match (test_result, lower, upper) {
+                (Some(result), Some(lower), _) if result < lower => Some("<"),
+                (Some(result), _, Some(upper)) if result > upper => Some(">"),
+                _ => None,
+            };
+            assert_eq!(row.result_flag.as_deref(), expected);
+            flags.push(row.result_flag);
+        }
+        assert!(flags.contains(&Some(String::from("<"))));
+        assert!(flags.contains(&Some(String::from(">"))));
+        assert!(flags.contains(&None));
+
+        // The dataframe has the same flags as the iterator
+        let table = make_pathology_blood(
+            "pathology_blood",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            1000,
+        );
+        let result_flag = table.column("result_flag").unwrap().utf8().unwrap();
+        for (flag, expected) in result_flag.into_iter().zip(&flags) {
+            assert_eq!(flag, expected.as_deref());
+        }
+    }
 
-    to_polars(seeded_column_blocks)
+    #[test]
+    fn check_results_depend_on_age_and_gender() {
+        let registry = PatientRegistry::new(0, 1000);
+        let timing = EventTiming::with_duration(DEFAULT_PROCESSING_TIME);
+
+        // Mean haemoglobin by gender, and the mean platelet count and
+        // proportion of eGFR results above 90 for young (under 40) and
+        // old (over 70) patients, without any conditions
+        let mut haemoglobin = [(0.0, 0); 2];
+        let mut platelets = [(0.0, 0); 2];
+        let mut egfr_above_90 = [(0.0, 0); 2];
+        for row in PathologyBloodRows::new("pathology_blood", &registry, timing, 20000) {
+            let n: usize = row.subject["bristol_".len()..].parse().unwrap();
+            let patient = &registry.patients()[n - 1];
+            if patient.conditions != PatientConditions::default() {
+                continue;
+            }
+            let age = patient.age_at(row.sample_collected_date_time);
+            let age_group = match age {
+                0..=39 => 0,
+                70.. => 1,
+                _ => continue,
+            };
+            let (sum, value) = match row.test_name.as_str() {
+                "Haemoglobin" => (
+                    &mut haemoglobin[usize::from(patient.gender == Gender::Male)],
+                    row.test_result.parse().unwrap(),
+                ),
+                "Platelets" => (&mut platelets[age_group], row.test_result.parse().unwrap()),
+                _ => (
+                    &mut egfr_above_90[age_group],
+                    f64::from(u8::from(row.test_result == ">90")),
+                ),
+            };
+            sum.0 += value;
+            sum.1 += 1;
+        }
+        let mean = |sums: [(f64, usize); 2]| sums.map(|(sum, count)| sum / count as f64);
+        let [female, male] = mean(haemoglobin);
+        assert!(male > female + 5.0);
+        let [young, old] = mean(platelets);
+        assert!(old < young - 20.0);
+        let [young, old] = mean(egfr_above_90);
+        assert!(old < young - 0.2);
+    }
+
+    #[test]
+    fn check_lazy_table_matches_dataframe() {
+        let registry = PatientRegistry::new(0, 100);
+        let timing = EventTiming::with_duration(DEFAULT_PROCESSING_TIME);
+        let table = make_pathology_blood(
+            "pathology_blood",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            1000,
+        );
+        let lazy_table = make_pathology_blood_lazy(
+            "pathology_blood",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            1000,
+        )
+        .unwrap();
+
+        // A sliced table contains the first rows of the full table
+        let first_rows = lazy_table
+            .clone()
+            .select([col("subject"), col("test_result")])
+            .limit(10)
+            .collect()
+            .unwrap();
+        let expected = table.select(["subject", "test_result"]).unwrap();
+        assert!(first_rows.frame_equal_missing(&expected.head(Some(10))));
+        assert!(lazy_table.collect().unwrap().frame_equal_missing(&table));
+    }
+
+    #[test]
+    fn check_chunks_match_dataframe() {
+        let registry = PatientRegistry::new(0, 100);
+        let timing = EventTiming::with_duration(DEFAULT_PROCESSING_TIME);
+        let table = make_pathology_blood(
+            "pathology_blood",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            1000,
+        );
+        let make_chunks = |chunk_size| -> Vec<_> {
+            make_pathology_blood_chunks(
+                "pathology_blood",
+                &registry,
+                GeneratorVersion::V1,
+                timing,
+                1000,
+                chunk_size,
+            )
+            .collect()
+        };
+
+        // The last chunk is smaller if the number of rows is not a
+        // multiple of the chunk size
+        let chunks = make_chunks(300);
+        let heights: Vec<_> = chunks.iter().map(|chunk| chunk.height()).collect();
+        assert_eq!(heights, [300, 300, 300, 100]);
+        let mut joined = chunks[0].clone();
+        for chunk in &chunks[1..] {
+            joined.vstack_mut(chunk).unwrap();
+        }
+        assert!(joined.frame_equal_missing(&table));
+
+        // A chunk size of zero generates the whole table in one chunk
+        let chunks = make_chunks(0);
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].frame_equal_missing(&table));
+    }
 }
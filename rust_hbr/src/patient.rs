@@ -1,27 +1,89 @@
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
 use spell::Spell;
 use mongodb::bson::oid::ObjectId;
 
 use self::measurements::MeasurementHistory;
+use self::mortality::Mortality;
+use self::prescriptions::PrescriptionsHistory;
 
-mod spell;
-//mod mortality;
+pub mod spell;
+pub mod index_event;
+pub mod mortality;
 pub mod measurements;
-//mod prescriptions;
+pub mod prescriptions;
+pub mod outcome;
+pub mod features;
+pub mod arc_hbr;
+pub mod summary;
+#[cfg(feature = "ingest")]
+mod record_batch;
+#[cfg(test)]
+pub(crate) mod test_utils;
+
+#[cfg(feature = "ingest")]
+pub use record_batch::patient_schema;
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default)]
 pub struct Patient {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
     pub id: Option<ObjectId>,
     pub nhs_number: Option<String>,
     pub trust_number: Option<String>,
     pub age: Option<u32>,
     pub spells: Option<Vec<Spell>>,
-    //pub mortality: Option<Mortality>,
+    pub mortality: Option<Mortality>,
     pub measurements: Option<MeasurementHistory>,
-    //pub prescriptions: Option<PrescriptionsHistory>,
+    pub prescriptions: Option<PrescriptionsHistory>,
 }
 
 impl Patient {
 
+}
+
+/// A collection of patients, for example all the patients
+/// constructed from one data extract.
+#[derive(Debug, Default)]
+pub struct PatientStore {
+    patients: Vec<Patient>,
+}
+
+impl PatientStore {
+    /// Create an empty PatientStore
+    pub fn new() -> Self {
+        Self {
+            patients: Vec::new(),
+        }
+    }
+
+    /// Add a patient to the store
+    pub fn push(&mut self, patient: Patient) {
+        self.patients.push(patient);
+    }
+
+    /// Get all the patients in the store
+    pub fn patients(&self) -> &Vec<Patient> {
+        &self.patients
+    }
+
+    /// Get the number of patients in the store
+    pub fn len(&self) -> usize {
+        self.patients.len()
+    }
+
+    /// Check whether the store contains no patients
+    pub fn is_empty(&self) -> bool {
+        self.patients.is_empty()
+    }
+
+    /// Get the patients with a patient id (nhs_number), by id
+    pub(crate) fn patients_by_id(&self) -> HashMap<&str, &Patient> {
+        self.patients
+            .iter()
+            .filter_map(|patient| Some((patient.nhs_number.as_deref()?, patient)))
+            .collect()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,1057 @@
+//! Evaluating the ARC-HBR criteria at index events
+//!
+//! The Academic Research Consortium high bleeding risk (ARC-HBR)
+//! definition (Urban et al., 2019) is a list of major and minor
+//! criteria. A patient is at high bleeding risk if they meet at least
+//! one major or two minor criteria, which is the same as a score of at
+//! least one when major criteria count 1 and minor criteria count 0.5
+//! (as in the HIC prototype, hic_arc_hbr.R).
+//!
+//! The criteria that can be computed from a Patient are evaluated at
+//! an index event (see index_event):
+//!
+//! * age: minor if 75 or over (Patient::age)
+//! * oral anticoagulant: major if an oral anticoagulant prescription
+//!   is active at the index date
+//! * NSAIDs or steroids: minor if an NSAID or oral steroid
+//!   prescription is active at the index date
+//! * chronic kidney disease: major if eGFR < 30, minor if eGFR < 60
+//! * anaemia: major if Hb < 110 g/L, minor if Hb < 119 g/L
+//! * thrombocytopenia: major if platelets < 100 x 10^9/L
+//! * prior bleeding: major if a spell in the 6 months before the index
+//!   event has a bleeding code, minor if the bleed was 6 - 12 months
+//!   before
+//! * cancer: major if there is a cancer code in the index spell or in a
+//!   spell in the 12 months before
+//! * liver cirrhosis with portal hypertension: major if both have been
+//!   coded in the index spell or before
+//! * ischaemic stroke: minor if any spell before the index spell has
+//!   an ischaemic stroke code
+//!
+//! The blood test criteria use the baseline result, which is the first
+//! result collected during the index spell. Results are matched to the
+//! criteria by analyte (see units::Analyte::from_test_name), so any of
+//! the names of a test can be used, and must be in the analyte's
+//! canonical unit (see units). A result that is a bound (e.g. <100)
+//! only meets a threshold if every value allowed by the bound is below
+//! the threshold (so <100 meets the platelet threshold, but >90 does
+//! not meet any eGFR threshold). The minor anaemia threshold
+//! is 119 g/L for women and 129 g/L for men, but Patient does not record
+//! gender, so the threshold common to both is used. The prescriptions
+//! are matched by drug name (case-insensitive, so e.g. Warfarin Sodium
+//! is an oral anticoagulant), and prescriptions with a route other than
+//! oral are left out.
+//!
+//! A criterion that cannot be evaluated because the data is missing
+//! (e.g. there is no baseline haemoglobin) is Missing, and counts as
+//! not met in the overall status.
+//!
+//! When the inputs to the criteria have already been computed (e.g. as
+//! features for a model), the same criteria can be evaluated from them
+//! using ArcHbrFeatures instead of a Patient.
+
+use chrono::{DateTime, Duration, Utc};
+
+use super::index_event::IndexEvent;
+use super::measurements::{Measurement, MeasurementValue};
+use super::spell::{code_groups, Spell};
+use super::summary::LabResult;
+use super::{Patient, PatientStore};
+use crate::clinical_code::ClinicalCodeStore;
+use crate::units::Analyte;
+
+/// The code groups used for the diagnosis-based criteria
+const BLEEDING_GROUP: &str = "bleeding";
+const CANCER_GROUP: &str = "cancer";
+const CIRRHOSIS_GROUP: &str = "cirrhosis";
+const PORTAL_HYPERTENSION_GROUP: &str = "portal_hypertension";
+const ISCHAEMIC_STROKE_GROUP: &str = "ischaemic_stroke";
+
+/// The major and (if there is one) minor thresholds of the blood test
+/// criteria, in the canonical unit of the analyte
+const EGFR_THRESHOLDS: (f64, Option<f64>) = (30.0, Some(60.0));
+const HAEMOGLOBIN_THRESHOLDS: (f64, Option<f64>) = (110.0, Some(119.0));
+const PLATELETS_THRESHOLDS: (f64, Option<f64>) = (100.0, None);
+
+/// The oral anticoagulants (a vitamin K antagonist and the direct oral
+/// anticoagulants)
+const ORAL_ANTICOAGULANTS: [&str; 5] = [
+    "warfarin",
+    "apixaban",
+    "dabigatran",
+    "edoxaban",
+    "rivaroxaban",
+];
+
+/// The NSAIDs and oral steroids
+const NSAIDS_AND_STEROIDS: [&str; 6] = [
+    "ibuprofen",
+    "naproxen",
+    "diclofenac",
+    "celecoxib",
+    "prednisolone",
+    "dexamethasone",
+];
+
+/// The ARC-HBR criteria that can be evaluated from a Patient
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArcHbrCriterion {
+    Age,
+    OralAnticoagulant,
+    NsaidsOrSteroids,
+    ChronicKidneyDisease,
+    Anaemia,
+    Thrombocytopenia,
+    PriorBleeding,
+    Cancer,
+    LiverCirrhosis,
+    IschaemicStroke,
+}
+
+impl ArcHbrCriterion {
+    /// All the criteria, in the order they are evaluated
+    pub const ALL: [Self; 10] = [
+        Self::Age,
+        Self::OralAnticoagulant,
+        Self::NsaidsOrSteroids,
+        Self::ChronicKidneyDisease,
+        Self::Anaemia,
+        Self::Thrombocytopenia,
+        Self::PriorBleeding,
+        Self::Cancer,
+        Self::LiverCirrhosis,
+        Self::IschaemicStroke,
+    ];
+
+    /// The name of the criterion in snake case (e.g.
+    /// oral_anticoagulant), for use as a column name
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Age => "age",
+            Self::OralAnticoagulant => "oral_anticoagulant",
+            Self::NsaidsOrSteroids => "nsaids_or_steroids",
+            Self::ChronicKidneyDisease => "chronic_kidney_disease",
+            Self::Anaemia => "anaemia",
+            Self::Thrombocytopenia => "thrombocytopenia",
+            Self::PriorBleeding => "prior_bleeding",
+            Self::Cancer => "cancer",
+            Self::LiverCirrhosis => "liver_cirrhosis",
+            Self::IschaemicStroke => "ischaemic_stroke",
+        }
+    }
+}
+
+/// Whether a patient meets one criterion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CriterionStatus {
+    Major,
+    Minor,
+    NotMet,
+    /// The data needed to evaluate the criterion is not available
+    Missing,
+}
+
+impl CriterionStatus {
+    /// The contribution to the ARC-HBR score (1 for major, 0.5 for
+    /// minor)
+    pub fn score(&self) -> f64 {
+        match self {
+            Self::Major => 1.0,
+            Self::Minor => 0.5,
+            Self::NotMet | Self::Missing => 0.0,
+        }
+    }
+
+    /// The name of the status in snake case (e.g. not_met)
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Major => "major",
+            Self::Minor => "minor",
+            Self::NotMet => "not_met",
+            Self::Missing => "missing",
+        }
+    }
+}
+
+/// Get the ARC-HBR score of the criteria (the sum of the criterion
+/// scores)
+pub fn arc_hbr_score(criteria: &[(ArcHbrCriterion, CriterionStatus)]) -> f64 {
+    criteria.iter().map(|(_, status)| status.score()).sum()
+}
+
+/// The ARC-HBR criteria at one index event
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArcHbr {
+    /// The patient id of the index event
+    pub patient_id: String,
+    /// The position of the index spell in the patient's spells
+    pub spell_index: usize,
+    /// The date of the index event
+    pub index_date: DateTime<Utc>,
+    /// The status of each criterion, in the order of
+    /// ArcHbrCriterion::ALL
+    pub criteria: Vec<(ArcHbrCriterion, CriterionStatus)>,
+}
+
+impl ArcHbr {
+    /// The ARC-HBR score (the sum of the criterion scores)
+    pub fn score(&self) -> f64 {
+        arc_hbr_score(&self.criteria)
+    }
+
+    /// Whether the patient is at high bleeding risk (at least one
+    /// major or two minor criteria)
+    pub fn is_hbr(&self) -> bool {
+        self.score() >= 1.0
+    }
+
+    /// Get the status of one criterion
+    pub fn status(&self, criterion: ArcHbrCriterion) -> CriterionStatus {
+        self.criteria
+            .iter()
+            .find(|(c, _)| *c == criterion)
+            .map(|(_, status)| *status)
+            .expect("Every criterion is evaluated")
+    }
+}
+
+/// The numerical value of a blood test result, which may only be
+/// known to be below or above a number (e.g. <100 or >90)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NumericValue {
+    Exact(f64),
+    Below(f64),
+    Above(f64),
+}
+
+impl NumericValue {
+    /// Read the value of a measurement. Returns None for strings that
+    /// are not a number with an optional < or > in front.
+    fn from_measurement_value(value: &MeasurementValue) -> Option<Self> {
+        match value {
+            MeasurementValue::Integer(value) => Some(Self::Exact(*value as f64)),
+            MeasurementValue::Real(value) => Some(Self::Exact(*value)),
+            MeasurementValue::String(value) => {
+                if let Some(bound) = value.strip_prefix('<') {
+                    bound.parse().ok().map(Self::Below)
+                } else if let Some(bound) = value.strip_prefix('>') {
+                    bound.parse().ok().map(Self::Above)
+                } else {
+                    value.parse().ok().map(Self::Exact)
+                }
+            }
+        }
+    }
+
+    /// Whether the value is certainly below the threshold
+    fn is_below(&self, threshold: f64) -> bool {
+        match self {
+            Self::Exact(value) => *value < threshold,
+            Self::Below(bound) => *bound <= threshold,
+            Self::Above(_) => false,
+        }
+    }
+}
+
+/// Get the status of the age criterion (minor if 75 or over)
+fn age_status(age: Option<u32>) -> CriterionStatus {
+    match age {
+        Some(age) if age >= 75 => CriterionStatus::Minor,
+        Some(_) => CriterionStatus::NotMet,
+        None => CriterionStatus::Missing,
+    }
+}
+
+/// Get the status of a blood test criterion from the baseline value
+/// and the major and minor thresholds
+fn blood_test_status(
+    baseline: Option<NumericValue>,
+    (major, minor): (f64, Option<f64>),
+) -> CriterionStatus {
+    match baseline {
+        Some(value) if value.is_below(major) => CriterionStatus::Major,
+        Some(value) if minor.is_some_and(|minor| value.is_below(minor)) => CriterionStatus::Minor,
+        Some(_) => CriterionStatus::NotMet,
+        None => CriterionStatus::Missing,
+    }
+}
+
+/// Get the status of a criterion that is met (with the status) if the
+/// flag is true
+fn flag_status(flag: Option<bool>, status: CriterionStatus) -> CriterionStatus {
+    match flag {
+        Some(true) => status,
+        Some(false) => CriterionStatus::NotMet,
+        None => CriterionStatus::Missing,
+    }
+}
+
+/// The inputs to the ARC-HBR criteria at one index event, for when
+/// they have already been computed instead of being taken from a
+/// Patient (see Patient::arc_hbr for how they are defined). A value of
+/// None means the input is missing, and the criteria that depend on it
+/// are Missing. The blood test results must be in the canonical unit
+/// of the analyte (see units).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ArcHbrFeatures {
+    /// The age of the patient at the index event
+    pub age: Option<u32>,
+    /// The baseline eGFR (in mL/min/1.73m2)
+    pub egfr: Option<f64>,
+    /// The baseline haemoglobin (in g/L)
+    pub haemoglobin: Option<f64>,
+    /// The baseline platelet count (in 10*9/L)
+    pub platelets: Option<f64>,
+    /// Whether an oral anticoagulant is prescribed at the index event
+    pub oral_anticoagulant: Option<bool>,
+    /// Whether an NSAID or oral steroid is prescribed at the index
+    /// event
+    pub nsaids_or_steroids: Option<bool>,
+    /// Whether there was a bleed in the 6 months before the index event
+    pub bleeding_within_6_months: Option<bool>,
+    /// Whether there was a bleed in the 12 months before the index
+    /// event (including the 6 months before)
+    pub bleeding_within_12_months: Option<bool>,
+    /// Whether there is cancer in the index spell or the 12 months
+    /// before
+    pub cancer: Option<bool>,
+    /// Whether liver cirrhosis with portal hypertension has been coded
+    /// up to the index spell
+    pub liver_cirrhosis: Option<bool>,
+    /// Whether there was an ischaemic stroke before the index spell
+    pub ischaemic_stroke: Option<bool>,
+}
+
+impl ArcHbrFeatures {
+    /// Evaluate the ARC-HBR criteria, using the same thresholds as
+    /// Patient::arc_hbr. The criteria are in the order of
+    /// ArcHbrCriterion::ALL (see arc_hbr_score for the score).
+    pub fn criteria(&self) -> Vec<(ArcHbrCriterion, CriterionStatus)> {
+        ArcHbrCriterion::ALL
+            .into_iter()
+            .map(|criterion| {
+                let status = match criterion {
+                    ArcHbrCriterion::Age => age_status(self.age),
+                    ArcHbrCriterion::OralAnticoagulant => {
+                        flag_status(self.oral_anticoagulant, CriterionStatus::Major)
+                    }
+                    ArcHbrCriterion::NsaidsOrSteroids => {
+                        flag_status(self.nsaids_or_steroids, CriterionStatus::Minor)
+                    }
+                    ArcHbrCriterion::ChronicKidneyDisease => {
+                        blood_test_status(self.egfr.map(NumericValue::Exact), EGFR_THRESHOLDS)
+                    }
+                    ArcHbrCriterion::Anaemia => blood_test_status(
+                        self.haemoglobin.map(NumericValue::Exact),
+                        HAEMOGLOBIN_THRESHOLDS,
+                    ),
+                    ArcHbrCriterion::Thrombocytopenia => blood_test_status(
+                        self.platelets.map(NumericValue::Exact),
+                        PLATELETS_THRESHOLDS,
+                    ),
+                    ArcHbrCriterion::PriorBleeding => {
+                        match (
+                            self.bleeding_within_6_months,
+                            self.bleeding_within_12_months,
+                        ) {
+                            (Some(true), _) => CriterionStatus::Major,
+                            (_, Some(true)) => CriterionStatus::Minor,
+                            (Some(false), Some(false)) => CriterionStatus::NotMet,
+                            _ => CriterionStatus::Missing,
+                        }
+                    }
+                    ArcHbrCriterion::Cancer => flag_status(self.cancer, CriterionStatus::Major),
+                    ArcHbrCriterion::LiverCirrhosis => {
+                        flag_status(self.liver_cirrhosis, CriterionStatus::Major)
+                    }
+                    ArcHbrCriterion::IschaemicStroke => {
+                        flag_status(self.ischaemic_stroke, CriterionStatus::Minor)
+                    }
+                };
+                (criterion, status)
+            })
+            .collect()
+    }
+}
+
+/// Get the spells that started less than the period before the index
+/// date
+fn spells_within<'a>(
+    spells: &[&'a Spell],
+    index_date: DateTime<Utc>,
+    period: Duration,
+) -> Vec<&'a Spell> {
+    spells
+        .iter()
+        .filter(|spell| index_date - spell.start < period)
+        .copied()
+        .collect()
+}
+
+/// Whether an active prescription at the index date is one of the drugs
+fn has_active_prescription(patient: &Patient, index_date: DateTime<Utc>, drugs: &[&str]) -> bool {
+    let mut prescriptions = patient
+        .prescriptions
+        .iter()
+        .flat_map(|history| history.active_at(index_date));
+    prescriptions.any(|prescription| {
+        let drug_name = prescription.drug_name.to_lowercase();
+        let oral = prescription
+            .route
+            .as_ref()
+            .is_none_or(|route| route.eq_ignore_ascii_case("oral"));
+        oral && drugs.iter().any(|drug| drug_name.contains(drug))
+    })
+}
+
+impl Patient {
+    /// Evaluate the ARC-HBR criteria at an index event of the patient
+    /// (see the module documentation for the definitions).
+    ///
+    /// The blood test results are taken from the patient's measurement
+    /// history and from the lab results (which can be for any patients;
+    /// only the ones with the index event's patient id are used). The
+    /// diagnosis_store and procedure_store must be the code stores of
+    /// the parsers used to make the patient.
+    pub fn arc_hbr(
+        &self,
+        index_event: &IndexEvent,
+        lab_results: &[LabResult],
+        diagnosis_store: &ClinicalCodeStore,
+        procedure_store: &ClinicalCodeStore,
+    ) -> ArcHbr {
+        let index_date = index_event.date;
+        let spells = self.spells.as_deref().unwrap_or_default();
+        let index_spell = spells.get(index_event.spell_index);
+        let prior_spells: Vec<_> = spells
+            .iter()
+            .enumerate()
+            .filter(|(n, spell)| *n != index_event.spell_index && spell.start < index_date)
+            .map(|(_, spell)| spell)
+            .collect();
+
+        // The first result for the analyte collected during the index
+        // spell
+        let baseline = |analyte: Analyte| {
+            let measures = |test_name: &str| Analyte::from_test_name(test_name) == Some(analyte);
+            let patient_results = self
+                .measurements
+                .iter()
+                .filter(|history| measures(&history.measurement_name))
+                .flat_map(|history| history.timeseries.iter());
+            let lab_results = lab_results
+                .iter()
+                .filter(|lab_result| {
+                    lab_result.patient_id == index_event.patient_id
+                        && measures(&lab_result.test_name)
+                })
+                .map(|lab_result| &lab_result.measurement);
+            let spell = index_spell?;
+            patient_results
+                .chain(lab_results)
+                .filter_map(|measurement: &Measurement| {
+                    let date = measurement.measurement_date?;
+                    let value = NumericValue::from_measurement_value(&measurement.value)?;
+                    (spell.start <= date && date <= spell.end).then_some((date, value))
+                })
+                .min_by_key(|(date, _)| *date)
+                .map(|(_, value)| value)
+        };
+        let from_prescriptions = |drugs: &[&str], status| {
+            if self.prescriptions.is_none() {
+                CriterionStatus::Missing
+            } else if has_active_prescription(self, index_date, drugs) {
+                status
+            } else {
+                CriterionStatus::NotMet
+            }
+        };
+        let in_spells = |spells: &[&Spell], group: &str| {
+            spells.iter().any(|spell| {
+                code_groups(
+                    spell.diagnoses(),
+                    spell.procedures(),
+                    diagnosis_store,
+                    procedure_store,
+                )
+                .iter()
+                .any(|spell_group| *spell_group == group)
+            })
+        };
+        let spells_up_to_index: Vec<_> = prior_spells.iter().copied().chain(index_spell).collect();
+        let six_months = Duration::days(183);
+        let one_year = Duration::days(365);
+
+        let criteria = ArcHbrCriterion::ALL
+            .into_iter()
+            .map(|criterion| {
+                let status = match criterion {
+                    ArcHbrCriterion::Age => age_status(self.age),
+                    ArcHbrCriterion::OralAnticoagulant => {
+                        from_prescriptions(&ORAL_ANTICOAGULANTS, CriterionStatus::Major)
+                    }
+                    ArcHbrCriterion::NsaidsOrSteroids => {
+                        from_prescriptions(&NSAIDS_AND_STEROIDS, CriterionStatus::Minor)
+                    }
+                    ArcHbrCriterion::ChronicKidneyDisease => {
+                        blood_test_status(baseline(Analyte::Egfr), EGFR_THRESHOLDS)
+                    }
+                    ArcHbrCriterion::Anaemia => {
+                        blood_test_status(baseline(Analyte::Haemoglobin), HAEMOGLOBIN_THRESHOLDS)
+                    }
+                    ArcHbrCriterion::Thrombocytopenia => {
+                        blood_test_status(baseline(Analyte::Platelets), PLATELETS_THRESHOLDS)
+                    }
+                    ArcHbrCriterion::PriorBleeding => {
+                        let recent = spells_within(&prior_spells, index_date, six_months);
+                        let last_year = spells_within(&prior_spells, index_date, one_year);
+                        if in_spells(&recent, BLEEDING_GROUP) {
+                            CriterionStatus::Major
+                        } else if in_spells(&last_year, BLEEDING_GROUP) {
+                            CriterionStatus::Minor
+                        } else {
+                            CriterionStatus::NotMet
+                        }
+                    }
+                    ArcHbrCriterion::Cancer => {
+                        let last_year = spells_within(&spells_up_to_index, index_date, one_year);
+                        if in_spells(&last_year, CANCER_GROUP) {
+                            CriterionStatus::Major
+                        } else {
+                            CriterionStatus::NotMet
+                        }
+                    }
+                    ArcHbrCriterion::LiverCirrhosis => {
+                        if in_spells(&spells_up_to_index, CIRRHOSIS_GROUP)
+                            && in_spells(&spells_up_to_index, PORTAL_HYPERTENSION_GROUP)
+                        {
+                            CriterionStatus::Major
+                        } else {
+                            CriterionStatus::NotMet
+                        }
+                    }
+                    ArcHbrCriterion::IschaemicStroke => {
+                        if in_spells(&prior_spells, ISCHAEMIC_STROKE_GROUP) {
+                            CriterionStatus::Minor
+                        } else {
+                            CriterionStatus::NotMet
+                        }
+                    }
+                };
+                (criterion, status)
+            })
+            .collect();
+
+        ArcHbr {
+            patient_id: index_event.patient_id.clone(),
+            spell_index: index_event.spell_index,
+            index_date,
+            criteria,
+        }
+    }
+}
+
+/// Evaluate the ARC-HBR criteria at each index event (see
+/// Patient::arc_hbr), matching the index events to the patients by
+/// patient id. Index events whose patient is not in the store are left
+/// out.
+pub fn evaluate_arc_hbr(
+    patient_store: &PatientStore,
+    index_events: &[IndexEvent],
+    lab_results: &[LabResult],
+    diagnosis_store: &ClinicalCodeStore,
+    procedure_store: &ClinicalCodeStore,
+) -> Vec<ArcHbr> {
+    let patients = patient_store.patients_by_id();
+    index_events
+        .iter()
+        .filter_map(|index_event| {
+            let patient = patients.get(index_event.patient_id.as_str())?;
+            Some(patient.arc_hbr(index_event, lab_results, diagnosis_store, procedure_store))
+        })
+        .collect()
+}
+
+/// Tests for evaluating the ARC-HBR criteria
+#[cfg(test)]
+mod tests {
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::patient::index_event::IndexEventSpec;
+    use crate::patient::prescriptions::{Prescription, PrescriptionsHistory};
+    use crate::patient::summary::EpisodeRecord;
+    use crate::patient::test_utils::{make_episode_at, make_parser};
+
+    fn make_date(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, 0, 0, 0).unwrap()
+    }
+
+    /// The date of the index event (and the start of the index spell,
+    /// which lasts one day)
+    fn index_date() -> DateTime<Utc> {
+        make_date(2023, 1, 10)
+    }
+
+    /// Make the episodes of patient a, with an earlier spell starting on
+    /// each of the dates (with the diagnoses), followed by the index
+    /// spell (with the index diagnoses as well as a STEMI)
+    fn make_episodes(
+        earlier_spells: &[(DateTime<Utc>, &[&str])],
+        index_diagnoses: &[&str],
+    ) -> Vec<EpisodeRecord> {
+        let mut episodes: Vec<_> = earlier_spells
+            .iter()
+            .enumerate()
+            .map(|(n, (start, diagnoses))| {
+                make_episode_at("a", &n.to_string(), *start, diagnoses, &[])
+            })
+            .collect();
+        let mut diagnoses = vec!["I21.0"];
+        diagnoses.extend(index_diagnoses);
+        episodes.push(make_episode_at("a", "index", index_date(), &diagnoses, &[]));
+        episodes
+    }
+
+    /// Evaluate the criteria at the index event of patient a (who must
+    /// have exactly one), after setting up the patient
+    fn evaluate(
+        episodes: &[EpisodeRecord],
+        lab_results: &[LabResult],
+        setup: impl FnOnce(&mut Patient),
+    ) -> ArcHbr {
+        let mut diagnosis_parser = make_parser("icd10_example.yaml");
+        let mut procedure_parser = make_parser("opcs4_example.yaml");
+        let mut patient_store =
+            PatientStore::from_episodes(episodes, &mut diagnosis_parser, &mut procedure_parser);
+        let diagnosis_store = diagnosis_parser.code_store();
+        let procedure_store = procedure_parser.code_store();
+        let spec = IndexEventSpec::new(vec![String::from("acs_stemi_schnier")]);
+        let index_events = patient_store.index_events(&spec, diagnosis_store, procedure_store);
+        assert_eq!(index_events.len(), 1);
+        setup(&mut patient_store.patients[0]);
+        let mut arc_hbr = evaluate_arc_hbr(
+            &patient_store,
+            &index_events,
+            lab_results,
+            diagnosis_store,
+            procedure_store,
+        );
+        assert_eq!(arc_hbr.len(), 1);
+        arc_hbr.remove(0)
+    }
+
+    /// Get the status of one criterion for a patient with only the
+    /// index spell and the lab results
+    fn from_lab_results(criterion: ArcHbrCriterion, lab_results: &[LabResult]) -> CriterionStatus {
+        evaluate(&make_episodes(&[], &[]), lab_results, |_| {}).status(criterion)
+    }
+
+    /// Get the status of one criterion for a patient with the earlier
+    /// spells and index diagnoses (see make_episodes)
+    fn from_diagnoses(
+        criterion: ArcHbrCriterion,
+        earlier_spells: &[(DateTime<Utc>, &[&str])],
+        index_diagnoses: &[&str],
+    ) -> CriterionStatus {
+        let episodes = make_episodes(earlier_spells, index_diagnoses);
+        evaluate(&episodes, &[], |_| {}).status(criterion)
+    }
+
+    /// Get the status of one criterion for a patient with the
+    /// prescriptions (all started before the index event)
+    fn from_prescriptions(
+        criterion: ArcHbrCriterion,
+        prescriptions: &[(&str, Option<&str>)],
+    ) -> CriterionStatus {
+        let prescriptions = prescriptions
+            .iter()
+            .map(|(drug_name, route)| Prescription {
+                drug_name: drug_name.to_string(),
+                dmd_code: None,
+                dose: None,
+                route: route.map(String::from),
+                prescription_start: Some(make_date(2022, 6, 1)),
+                prescription_stop: None,
+                data_source: None,
+            })
+            .collect();
+        let arc_hbr = evaluate(&make_episodes(&[], &[]), &[], |patient| {
+            patient.prescriptions = Some(PrescriptionsHistory { prescriptions })
+        });
+        arc_hbr.status(criterion)
+    }
+
+    fn make_lab_result(
+        patient_id: &str,
+        test_name: &str,
+        date: DateTime<Utc>,
+        value: MeasurementValue,
+    ) -> LabResult {
+        LabResult {
+            patient_id: patient_id.to_string(),
+            test_name: test_name.to_string(),
+            measurement: Measurement {
+                value,
+                measurement_date: Some(date),
+                measurement_available: None,
+                data_source: None,
+            },
+        }
+    }
+
+    #[test]
+    fn check_age() {
+        let age = |age| {
+            evaluate(&make_episodes(&[], &[]), &[], |patient| patient.age = age)
+                .status(ArcHbrCriterion::Age)
+        };
+        assert_eq!(age(Some(80)), CriterionStatus::Minor);
+        assert_eq!(age(Some(75)), CriterionStatus::Minor);
+        assert_eq!(age(Some(74)), CriterionStatus::NotMet);
+        assert_eq!(age(None), CriterionStatus::Missing);
+    }
+
+    #[test]
+    fn check_oral_anticoagulant() {
+        let criterion = ArcHbrCriterion::OralAnticoagulant;
+        // No route recorded counts as oral
+        assert_eq!(
+            from_prescriptions(criterion, &[("Apixaban", None)]),
+            CriterionStatus::Major
+        );
+        assert_eq!(
+            from_prescriptions(criterion, &[("Warfarin Sodium", Some("Oral"))]),
+            CriterionStatus::Major
+        );
+        assert_eq!(
+            from_prescriptions(criterion, &[("Aspirin", Some("Oral"))]),
+            CriterionStatus::NotMet
+        );
+        assert_eq!(
+            evaluate(&make_episodes(&[], &[]), &[], |_| {}).status(criterion),
+            CriterionStatus::Missing
+        );
+    }
+
+    #[test]
+    fn check_nsaids_or_steroids() {
+        let criterion = ArcHbrCriterion::NsaidsOrSteroids;
+        assert_eq!(
+            from_prescriptions(criterion, &[("Prednisolone", Some("oral"))]),
+            CriterionStatus::Minor
+        );
+        // A topical NSAID does not count
+        assert_eq!(
+            from_prescriptions(criterion, &[("Ibuprofen gel", Some("Topical"))]),
+            CriterionStatus::NotMet
+        );
+    }
+
+    #[test]
+    fn check_chronic_kidney_disease() {
+        let egfr = |value| {
+            let lab_result = make_lab_result("a", "eGFR/1.73m2 (CKD-EPI)", index_date(), value);
+            from_lab_results(ArcHbrCriterion::ChronicKidneyDisease, &[lab_result])
+        };
+        assert_eq!(egfr(MeasurementValue::Integer(25)), CriterionStatus::Major);
+        assert_eq!(egfr(MeasurementValue::Real(45.5)), CriterionStatus::Minor);
+        assert_eq!(egfr(MeasurementValue::Integer(60)), CriterionStatus::NotMet);
+        // A lower bound above the thresholds is not met
+        assert_eq!(
+            egfr(MeasurementValue::String(String::from(">90"))),
+            CriterionStatus::NotMet
+        );
+        assert_eq!(
+            from_lab_results(ArcHbrCriterion::ChronicKidneyDisease, &[]),
+            CriterionStatus::Missing
+        );
+    }
+
+    #[test]
+    fn check_anaemia() {
+        let haemoglobin = |date, value| {
+            make_lab_result("a", "Haemoglobin", date, MeasurementValue::Integer(value))
+        };
+        let lab_results = vec![
+            // Before the index spell
+            haemoglobin(make_date(2022, 12, 1), 90),
+            // The first result in the index spell is the baseline
+            haemoglobin(index_date() + Duration::hours(12), 130),
+            haemoglobin(index_date() + Duration::hours(1), 105),
+        ];
+        assert_eq!(
+            from_lab_results(ArcHbrCriterion::Anaemia, &lab_results),
+            CriterionStatus::Major
+        );
+        assert_eq!(
+            from_lab_results(ArcHbrCriterion::Anaemia, &lab_results[1..2]),
+            CriterionStatus::NotMet
+        );
+        let minor = haemoglobin(index_date(), 115);
+        assert_eq!(
+            from_lab_results(ArcHbrCriterion::Anaemia, &[minor]),
+            CriterionStatus::Minor
+        );
+        assert_eq!(
+            from_lab_results(ArcHbrCriterion::Anaemia, &lab_results[..1]),
+            CriterionStatus::Missing
+        );
+    }
+
+    #[test]
+    fn check_thrombocytopenia() {
+        let platelets = |patient_id, value| {
+            let lab_result = make_lab_result(patient_id, "Platelets", index_date(), value);
+            from_lab_results(ArcHbrCriterion::Thrombocytopenia, &[lab_result])
+        };
+        assert_eq!(
+            platelets("a", MeasurementValue::Integer(50)),
+            CriterionStatus::Major
+        );
+        assert_eq!(
+            platelets("a", MeasurementValue::Integer(150)),
+            CriterionStatus::NotMet
+        );
+        // An upper bound at the threshold is met
+        assert_eq!(
+            platelets("a", MeasurementValue::String(String::from("<100"))),
+            CriterionStatus::Major
+        );
+        assert_eq!(
+            platelets("a", MeasurementValue::String(String::from("<150"))),
+            CriterionStatus::NotMet
+        );
+        // A result for another patient is not used
+        assert_eq!(
+            platelets("b", MeasurementValue::Integer(50)),
+            CriterionStatus::Missing
+        );
+    }
+
+    #[test]
+    fn check_prior_bleeding() {
+        let bleed =
+            |start| from_diagnoses(ArcHbrCriterion::PriorBleeding, &[(start, &["D62"])], &[]);
+        assert_eq!(bleed(make_date(2022, 9, 1)), CriterionStatus::Major);
+        assert_eq!(bleed(make_date(2022, 3, 1)), CriterionStatus::Minor);
+        assert_eq!(bleed(make_date(2021, 3, 1)), CriterionStatus::NotMet);
+        // A bleed in the index spell is not prior bleeding
+        assert_eq!(
+            from_diagnoses(ArcHbrCriterion::PriorBleeding, &[], &["D62"]),
+            CriterionStatus::NotMet
+        );
+    }
+
+    #[test]
+    fn check_cancer() {
+        assert_eq!(
+            from_diagnoses(ArcHbrCriterion::Cancer, &[], &["C00.0"]),
+            CriterionStatus::Major
+        );
+        assert_eq!(
+            from_diagnoses(
+                ArcHbrCriterion::Cancer,
+                &[(make_date(2022, 6, 1), &["C00.0"])],
+                &[]
+            ),
+            CriterionStatus::Major
+        );
+        assert_eq!(
+            from_diagnoses(
+                ArcHbrCriterion::Cancer,
+                &[(make_date(2021, 6, 1), &["C00.0"])],
+                &[]
+            ),
+            CriterionStatus::NotMet
+        );
+    }
+
+    #[test]
+    fn check_liver_cirrhosis() {
+        assert_eq!(
+            from_diagnoses(
+                ArcHbrCriterion::LiverCirrhosis,
+                &[(make_date(2020, 1, 1), &["K74.6"])],
+                &["K76.6"]
+            ),
+            CriterionStatus::Major
+        );
+        // Cirrhosis without portal hypertension
+        assert_eq!(
+            from_diagnoses(ArcHbrCriterion::LiverCirrhosis, &[], &["K74.6"]),
+            CriterionStatus::NotMet
+        );
+    }
+
+    #[test]
+    fn check_ischaemic_stroke() {
+        assert_eq!(
+            from_diagnoses(
+                ArcHbrCriterion::IschaemicStroke,
+                &[(make_date(2015, 1, 1), &["I63.0"])],
+                &[]
+            ),
+            CriterionStatus::Minor
+        );
+        // A stroke in the index spell is not a prior stroke
+        assert_eq!(
+            from_diagnoses(ArcHbrCriterion::IschaemicStroke, &[], &["I63.0"]),
+            CriterionStatus::NotMet
+        );
+    }
+
+    #[test]
+    fn check_score_counts_major_and_minor_criteria() {
+        let episodes = make_episodes(&[(make_date(2022, 9, 1), &["I63.0"])], &[]);
+        let arc_hbr = evaluate(&episodes, &[], |patient| patient.age = Some(80));
+        assert_eq!(arc_hbr.criteria.len(), ArcHbrCriterion::ALL.len());
+        // Age and ischaemic stroke are both minor
+        assert_eq!(arc_hbr.score(), 1.0);
+        assert!(arc_hbr.is_hbr());
+
+        let arc_hbr = evaluate(&episodes, &[], |patient| patient.age = Some(60));
+        assert_eq!(arc_hbr.score(), 0.5);
+        assert!(!arc_hbr.is_hbr());
+    }
+
+    #[test]
+    fn check_criteria_from_features() {
+        let features = ArcHbrFeatures {
+            age: Some(80),
+            egfr: Some(45.0),
+            haemoglobin: Some(125.0),
+            platelets: Some(90.0),
+            oral_anticoagulant: Some(false),
+            bleeding_within_6_months: Some(false),
+            bleeding_within_12_months: Some(true),
+            cancer: Some(false),
+            ..Default::default()
+        };
+        let criteria = features.criteria();
+        let status = |criterion| {
+            criteria
+                .iter()
+                .find(|(c, _)| *c == criterion)
+                .map(|(_, status)| *status)
+                .unwrap()
+        };
+        assert_eq!(criteria.len(), ArcHbrCriterion::ALL.len());
+        assert_eq!(status(ArcHbrCriterion::Age), CriterionStatus::Minor);
+        assert_eq!(
+            status(ArcHbrCriterion::ChronicKidneyDisease),
+            CriterionStatus::Minor
+        );
+        assert_eq!(status(ArcHbrCriterion::Anaemia), CriterionStatus::NotMet);
+        assert_eq!(
+            status(ArcHbrCriterion::Thrombocytopenia),
+            CriterionStatus::Major
+        );
+        assert_eq!(
+            status(ArcHbrCriterion::OralAnticoagulant),
+            CriterionStatus::NotMet
+        );
+        assert_eq!(
+            status(ArcHbrCriterion::PriorBleeding),
+            CriterionStatus::Minor
+        );
+        assert_eq!(
+            status(ArcHbrCriterion::NsaidsOrSteroids),
+            CriterionStatus::Missing
+        );
+        assert_eq!(arc_hbr_score(&criteria), 2.5);
+
+        // Prior bleeding is missing unless it is known to be major, or
+        // both flags are known
+        let features = ArcHbrFeatures {
+            bleeding_within_6_months: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            features.criteria()[6],
+            (ArcHbrCriterion::PriorBleeding, CriterionStatus::Missing)
+        );
+        assert_eq!(arc_hbr_score(&features.criteria()), 0.0);
+    }
+
+    /// The synthetic pathology_blood results go through the same
+    /// preprocessing as real ones before the criteria are evaluated
+    #[cfg(all(feature = "synth", feature = "ingest"))]
+    #[test]
+    fn check_criteria_from_synthetic_pathology_blood() {
+        use crate::pathology_blood::{make_pathology_blood, DEFAULT_PROCESSING_TIME};
+        use crate::preprocess::{
+            measurement_from_pathology_blood, pathology_blood_measurements_lazy, strings, utc_dates,
+        };
+        use crate::synth_data::{DateWindow, EventTiming, GeneratorVersion, PatientRegistry};
+        use polars::prelude::IntoLazy;
+
+        // One patient (bristol_1), with all the samples collected in the
+        // index spell
+        let registry = PatientRegistry::new(0, 1);
+        let timing = EventTiming {
+            window: DateWindow {
+                start: index_date().naive_utc(),
+                end: (index_date() + Duration::hours(12)).naive_utc(),
+            },
+            duration: DEFAULT_PROCESSING_TIME,
+        };
+        let pathology_blood = make_pathology_blood(
+            "pathology_blood",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            30,
+        );
+        let table = pathology_blood_measurements_lazy(pathology_blood.lazy())
+            .collect()
+            .unwrap();
+        let column = |name| strings(table.column(name).unwrap()).unwrap();
+        let [subject, order_name, test_name, test_result, test_result_unit] = [
+            "subject",
+            "order_name",
+            "test_name",
+            "test_result",
+            "test_result_unit",
+        ]
+        .map(column);
+        let sample_collected =
+            utc_dates(table.column("sample_collected_date_time").unwrap()).unwrap();
+        let lab_results: Vec<_> = (0..table.height())
+            .map(|n| {
+                let mut measurement = measurement_from_pathology_blood(
+                    order_name[n].clone().unwrap(),
+                    test_name[n].clone().unwrap(),
+                    String::new(),
+                    String::new(),
+                    test_result[n].clone().unwrap(),
+                    test_result_unit[n].clone().unwrap(),
+                )
+                .unwrap();
+                measurement.measurement_date = sample_collected[n];
+                LabResult {
+                    patient_id: subject[n].clone().unwrap(),
+                    test_name: test_name[n].clone().unwrap(),
+                    measurement,
+                }
+            })
+            .collect();
+
+        let episodes: Vec<_> = make_episodes(&[], &[])
+            .into_iter()
+            .map(|episode| EpisodeRecord {
+                patient_id: String::from("bristol_1"),
+                ..episode
+            })
+            .collect();
+        let arc_hbr = evaluate(&episodes, &lab_results, |_| {});
+        for criterion in [
+            ArcHbrCriterion::ChronicKidneyDisease,
+            ArcHbrCriterion::Anaemia,
+            ArcHbrCriterion::Thrombocytopenia,
+        ] {
+            assert_ne!(arc_hbr.status(criterion), CriterionStatus::Missing);
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! Extracting code group features from a lookback window before
+//! index events
+//!
+//! The predictors of the bleeding and ischaemia models include the
+//! patient's history of conditions before the index event (e.g. prior
+//! bleeding, or a previous ACS). For each index event (see index_event)
+//! and code group, the extractor counts the episodes in the lookback
+//! window that contain a diagnosis or procedure code in the group, and
+//! finds the date of the most recent one.
+//!
+//! As in the HES prototype (get_episodes_before_index), the window is
+//! defined by a minimum and maximum time before the index event, so
+//! that a short period before the index event can be left out (e.g.
+//! if the features must be known some time in advance). The index
+//! spell itself is never counted.
+//!
+//! The result has one row per index event and one count and date for
+//! each group, in the same order as the groups. With the ingest
+//! feature, it can be converted into a polars DataFrame for modelling
+//! (see features_to_polars).
+
+use chrono::{DateTime, Duration, Utc};
+#[cfg(feature = "ingest")]
+use polars::prelude::*;
+
+use super::index_event::IndexEvent;
+use super::spell::code_groups;
+use super::PatientStore;
+use crate::clinical_code::ClinicalCodeStore;
+#[cfg(feature = "ingest")]
+use crate::error::HbrError;
+
+/// The window before an index event in which the code groups are
+/// counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LookbackWindow {
+    /// Episodes starting this long (or less) before the index event
+    /// are not counted
+    pub min_period_before: Duration,
+    /// Episodes starting more than this long before the index event
+    /// are not counted
+    pub max_period_before: Duration,
+}
+
+impl LookbackWindow {
+    /// Make a window that covers the lookback period up to (but not
+    /// including) the index event. An episode starting exactly the
+    /// lookback period before the index event is in the window.
+    pub fn new(lookback: Duration) -> Self {
+        Self {
+            min_period_before: Duration::zero(),
+            max_period_before: lookback,
+        }
+    }
+
+    /// Whether a date is in the window before an index event on the
+    /// index_date
+    pub fn contains(&self, index_date: DateTime<Utc>, date: DateTime<Utc>) -> bool {
+        let time_before_index = index_date - date;
+        self.min_period_before < time_before_index && time_before_index <= self.max_period_before
+    }
+}
+
+/// The history of one code group before an index event
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupFeature {
+    /// The number of episodes in the lookback window with a code in
+    /// the group
+    pub count: usize,
+    /// The start of the most recent of these episodes (None if the
+    /// count is zero)
+    pub most_recent: Option<DateTime<Utc>>,
+}
+
+/// The code group features of one index event
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexFeatures {
+    /// The patient id of the index event
+    pub patient_id: String,
+    /// The position of the index spell in the patient's spells
+    pub spell_index: usize,
+    /// The date of the index event
+    pub index_date: DateTime<Utc>,
+    /// One feature for each group, in the same order as the groups
+    pub features: Vec<GroupFeature>,
+}
+
+/// Count the episodes with a code in each group in the lookback window
+/// before each index event, in all the spells except the index spell.
+///
+/// The index events are matched to the patients by patient id (see
+/// PatientStore::index_events), and the diagnosis_store and
+/// procedure_store must be the code stores of the parsers used to make
+/// the patients. An index event whose patient is not in the store has
+/// a count of zero for every group. The features are in the same order
+/// as the index events.
+pub fn extract_lookback_features(
+    patient_store: &PatientStore,
+    index_events: &[IndexEvent],
+    groups: &[String],
+    window: &LookbackWindow,
+    diagnosis_store: &ClinicalCodeStore,
+    procedure_store: &ClinicalCodeStore,
+) -> Vec<IndexFeatures> {
+    let patients = patient_store.patients_by_id();
+    index_events
+        .iter()
+        .map(|index_event| {
+            let spells = patients
+                .get(index_event.patient_id.as_str())
+                .and_then(|patient| patient.spells.as_deref())
+                .unwrap_or_default();
+            let mut features = vec![
+                GroupFeature {
+                    count: 0,
+                    most_recent: None,
+                };
+                groups.len()
+            ];
+            let episodes = spells
+                .iter()
+                .enumerate()
+                .filter(|(spell_index, _)| *spell_index != index_event.spell_index)
+                .flat_map(|(_, spell)| spell.episodes.iter().flatten())
+                .filter(|episode| window.contains(index_event.date, episode.start));
+            for episode in episodes {
+                let episode_groups = code_groups(
+                    episode.diagnoses(),
+                    episode.procedures(),
+                    diagnosis_store,
+                    procedure_store,
+                );
+                for (group, feature) in groups.iter().zip(features.iter_mut()) {
+                    if episode_groups.contains(group) {
+                        feature.count += 1;
+                        feature.most_recent = feature.most_recent.max(Some(episode.start));
+                    }
+                }
+            }
+            IndexFeatures {
+                patient_id: index_event.patient_id.clone(),
+                spell_index: index_event.spell_index,
+                index_date: index_event.date,
+                features,
+            }
+        })
+        .collect()
+}
+
+/// Convert the features into a table with one row per index event, for
+/// modelling. The columns are patient_id, spell_index and index_date,
+/// followed by two columns for each group: {group}_before (the count)
+/// and {group}_most_recent (the date, null if the count is zero). The
+/// dates are naive datetimes in UTC. The groups must be the ones passed
+/// to extract_lookback_features. Returns an error if an index event
+/// does not have one feature for each group, or if a group appears more
+/// than once (the column names would be duplicated).
+#[cfg(feature = "ingest")]
+pub fn features_to_polars(
+    index_features: &[IndexFeatures],
+    groups: &[String],
+) -> Result<DataFrame, HbrError> {
+    if let Some(features) = index_features
+        .iter()
+        .find(|features| features.features.len() != groups.len())
+    {
+        return Err(HbrError::UnexpectedValue {
+            field: String::from("number of features"),
+            expected: format!("{} (one for each group)", groups.len()),
+            found: features.features.len().to_string(),
+        });
+    }
+    let patient_id: Vec<_> = index_features
+        .iter()
+        .map(|features| features.patient_id.as_str())
+        .collect();
+    let spell_index: Vec<_> = index_features
+        .iter()
+        .map(|features| features.spell_index as u64)
+        .collect();
+    let index_date: Vec<_> = index_features
+        .iter()
+        .map(|features| features.index_date.naive_utc())
+        .collect();
+    let mut columns = vec![
+        Series::new("patient_id", patient_id),
+        Series::new("spell_index", spell_index),
+        Series::new("index_date", index_date),
+    ];
+    for (n, group) in groups.iter().enumerate() {
+        let count: Vec<_> = index_features
+            .iter()
+            .map(|features| features.features[n].count as u64)
+            .collect();
+        let most_recent: Vec<_> = index_features
+            .iter()
+            .map(|features| {
+                features.features[n]
+                    .most_recent
+                    .map(|date| date.naive_utc())
+            })
+            .collect();
+        columns.push(Series::new(&format!("{group}_before"), count));
+        columns.push(Series::new(&format!("{group}_most_recent"), most_recent));
+    }
+    Ok(DataFrame::new(columns)?)
+}
+
+/// Tests for extracting lookback features
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::patient::index_event::IndexEventSpec;
+    use crate::patient::test_utils::{make_date, make_episode, make_parser};
+
+    #[test]
+    fn check_group_counts_in_lookback_window() {
+        let mut diagnosis_parser = make_parser("icd10_example.yaml");
+        let mut procedure_parser = make_parser("opcs4_example.yaml");
+        let episodes = vec![
+            // Before the lookback window
+            make_episode("a", "1", 1, &["D62"], &[]),
+            // Two episodes with a bleed in the same spell
+            make_episode("a", "2", 5, &["D62", "I48.0"], &[]),
+            make_episode("a", "2", 6, &["D62"], &["K49.1"]),
+            // Too close to the index event to count
+            make_episode("a", "3", 19, &["D62"], &[]),
+            // The index spell, with a bleed at the index event
+            make_episode("a", "4", 20, &["I21.0", "D62"], &[]),
+        ];
+        let patient_store =
+            PatientStore::from_episodes(&episodes, &mut diagnosis_parser, &mut procedure_parser);
+        let diagnosis_store = diagnosis_parser.code_store();
+        let procedure_store = procedure_parser.code_store();
+
+        let spec = IndexEventSpec::new(vec![String::from("acs_stemi_schnier")]);
+        let index_events = patient_store.index_events(&spec, diagnosis_store, procedure_store);
+        let groups = vec![
+            String::from("bleeding"),
+            String::from("atrial_fib"),
+            String::from("pci"),
+            String::from("cabg"),
+        ];
+        let window = LookbackWindow {
+            min_period_before: Duration::days(2),
+            max_period_before: Duration::days(18),
+        };
+        let index_features = extract_lookback_features(
+            &patient_store,
+            &index_events,
+            &groups,
+            &window,
+            diagnosis_store,
+            procedure_store,
+        );
+        assert_eq!(index_features.len(), 1);
+        let features = &index_features[0].features;
+        assert_eq!(index_features[0].index_date, make_date(20));
+        assert_eq!(features[0].count, 2);
+        assert_eq!(features[0].most_recent, Some(make_date(6)));
+        assert_eq!(features[1].count, 1);
+        assert_eq!(features[1].most_recent, Some(make_date(5)));
+        assert_eq!(features[2].count, 1);
+        assert_eq!(features[3].count, 0);
+        assert_eq!(features[3].most_recent, None);
+
+        // The minimum period before the index event is excluded
+        assert!(!window.contains(make_date(20), make_date(18)));
+        assert!(window.contains(make_date(20), make_date(17)));
+        // The maximum period before the index event is included
+        assert!(window.contains(make_date(20), make_date(2)));
+        assert!(!window.contains(make_date(20), make_date(1)));
+
+        #[cfg(feature = "ingest")]
+        {
+            let df = features_to_polars(&index_features, &groups).unwrap();
+            assert_eq!(df.shape(), (1, 3 + 2 * groups.len()));
+            let bleeding = df.column("bleeding_before").unwrap().u64().unwrap();
+            assert_eq!(bleeding.get(0), Some(2));
+            assert_eq!(df.column("cabg_most_recent").unwrap().null_count(), 1);
+
+            let duplicate_groups = vec![String::from("pci"), String::from("pci")];
+            assert!(matches!(
+                features_to_polars(&index_features[..0], &duplicate_groups),
+                Err(HbrError::Polars(_))
+            ));
+
+            // The groups must be the ones the features were extracted for
+            assert!(matches!(
+                features_to_polars(&index_features, &groups[..2]),
+                Err(HbrError::UnexpectedValue { .. })
+            ));
+            let extra_groups: Vec<_> = groups
+                .iter()
+                .cloned()
+                .chain([String::from("ischaemic_stroke")])
+                .collect();
+            assert!(matches!(
+                features_to_polars(&index_features, &extra_groups),
+                Err(HbrError::UnexpectedValue { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Finding the index events in patients' spells
+//!
+//! An index event is the hospital visit that a prediction is made at
+//! (e.g. an ACS or PCI admission). The features of the model are
+//! computed over a lookback window before the index event, and the
+//! outcomes over a follow-up window after it, so index events are the
+//! anchor for both.
+//!
+//! An index event is defined by a list of code groups: an episode is
+//! an index episode if it has a diagnosis or procedure code in any of
+//! the groups. As in the HES prototype (get_index_episodes), by default
+//! only the first episode of each spell is considered, so that there
+//! is at most one index event per spell, and the codes are looked up
+//! in the code stores of the parsers that made the patients (see
+//! PatientStore::from_episodes).
+
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Utc};
+
+use super::{Patient, PatientStore};
+use crate::clinical_code::{ClinicalCodeRef, ClinicalCodeStore, DiagnosisCode, ProcedureCode};
+
+/// Which of the index events of a patient to keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexEventSelection {
+    /// Keep every index event (at most one per spell)
+    #[default]
+    All,
+    /// Keep only the earliest index event
+    First,
+}
+
+/// The definition of an index event
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEventSpec {
+    /// An episode is an index episode if it contains a diagnosis or
+    /// procedure code in any of these groups
+    pub groups: Vec<String>,
+    /// If present, only episodes starting at or after this date are
+    /// index episodes
+    pub after: Option<DateTime<Utc>>,
+    /// If true, only the first episode of each spell can be an index
+    /// episode. Otherwise, the first matching episode of the spell is
+    /// used.
+    pub first_episode_only: bool,
+    /// Which index events to keep for each patient
+    pub selection: IndexEventSelection,
+}
+
+impl IndexEventSpec {
+    /// Make the definition of an index event from the groups, keeping
+    /// every spell whose first episode has a code in any of the groups
+    pub fn new(groups: Vec<String>) -> Self {
+        Self {
+            groups,
+            after: None,
+            first_episode_only: true,
+            selection: IndexEventSelection::All,
+        }
+    }
+}
+
+/// One index event, found by Patient::index_events
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEvent {
+    /// The patient id (the nhs_number of the patient)
+    pub patient_id: String,
+    /// The position of the index spell in the patient's spells
+    pub spell_index: usize,
+    /// The position of the index episode in the spell's episodes
+    pub episode_index: usize,
+    /// The date of the index event (the start of the index episode)
+    pub date: DateTime<Utc>,
+    /// The diagnosis codes in the index episode that are in an index
+    /// group
+    pub diagnoses: Vec<DiagnosisCode>,
+    /// The procedure codes in the index episode that are in an index
+    /// group
+    pub procedures: Vec<ProcedureCode>,
+    /// The index groups that the codes are in (e.g. to tell whether
+    /// a PCI was performed)
+    pub groups: BTreeSet<String>,
+}
+
+/// Get the groups in the list that contain the code, according to
+/// the code store that the code was parsed into
+fn index_groups_of<'a>(
+    code: ClinicalCodeRef,
+    code_store: &ClinicalCodeStore,
+    groups: &'a [String],
+) -> Vec<&'a String> {
+    match code_store.groups_of(&code) {
+        Some(code_groups) => groups
+            .iter()
+            .filter(|group| code_groups.contains(*group))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+impl Patient {
+    /// Find the index events in the patient's spells, in order of date.
+    ///
+    /// The diagnosis_store and procedure_store must be the code stores
+    /// of the parsers used to make the patient (see
+    /// PatientStore::from_episodes). Patients without spells have no
+    /// index events.
+    pub fn index_events(
+        &self,
+        spec: &IndexEventSpec,
+        diagnosis_store: &ClinicalCodeStore,
+        procedure_store: &ClinicalCodeStore,
+    ) -> Vec<IndexEvent> {
+        let patient_id = self.nhs_number.clone().unwrap_or_default();
+        let mut index_events = Vec::new();
+        for (spell_index, spell) in self.spells.iter().flatten().enumerate() {
+            let episodes = spell.episodes.as_deref().unwrap_or_default();
+            let num_candidates = if spec.first_episode_only {
+                1
+            } else {
+                episodes.len()
+            };
+            for (episode_index, episode) in episodes.iter().enumerate().take(num_candidates) {
+                if spec.after.is_some_and(|after| episode.start < after) {
+                    continue;
+                }
+                let mut groups = BTreeSet::new();
+                let mut in_index_groups = |code, code_store| {
+                    let code_groups = index_groups_of(code, code_store, &spec.groups);
+                    groups.extend(code_groups.iter().map(|group| group.to_string()));
+                    !code_groups.is_empty()
+                };
+                let diagnoses: Vec<_> = episode
+                    .diagnoses()
+                    .filter(|code| in_index_groups(code.clinical_code_ref(), diagnosis_store))
+                    .collect();
+                let procedures: Vec<_> = episode
+                    .procedures()
+                    .filter(|code| in_index_groups(code.clinical_code_ref(), procedure_store))
+                    .collect();
+                if diagnoses.is_empty() && procedures.is_empty() {
+                    continue;
+                }
+                index_events.push(IndexEvent {
+                    patient_id: patient_id.clone(),
+                    spell_index,
+                    episode_index,
+                    date: episode.start,
+                    diagnoses,
+                    procedures,
+                    groups,
+                });
+                // There is at most one index event per spell
+                break;
+            }
+        }
+        index_events.sort_by_key(|index_event| index_event.date);
+        if spec.selection == IndexEventSelection::First {
+            index_events.truncate(1);
+        }
+        index_events
+    }
+}
+
+impl PatientStore {
+    /// Find the index events of all the patients (see
+    /// Patient::index_events), in the same order as the patients
+    pub fn index_events(
+        &self,
+        spec: &IndexEventSpec,
+        diagnosis_store: &ClinicalCodeStore,
+        procedure_store: &ClinicalCodeStore,
+    ) -> Vec<IndexEvent> {
+        self.patients()
+            .iter()
+            .flat_map(|patient| patient.index_events(spec, diagnosis_store, procedure_store))
+            .collect()
+    }
+}
+
+/// Tests for finding index events
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::patient::test_utils::{make_date, make_episode, make_parser};
+
+    #[test]
+    fn check_index_events_from_code_groups() {
+        let mut diagnosis_parser = make_parser("icd10_example.yaml");
+        let mut procedure_parser = make_parser("opcs4_example.yaml");
+        let episodes = vec![
+            // ACS in the first episode
+            make_episode("a", "1", 2, &["I21.0", "I48.0"], &[]),
+            make_episode("a", "1", 3, &[], &["K49.1"]),
+            // PCI in the first episode, with an ACS later in the spell
+            make_episode("a", "2", 10, &["I48.0"], &["K49.1"]),
+            make_episode("a", "2", 11, &["I21.4"], &[]),
+            // ACS in the second episode only
+            make_episode("a", "3", 20, &["I48.0"], &[]),
+            make_episode("a", "3", 21, &["I21.0"], &[]),
+        ];
+        let patient_store =
+            PatientStore::from_episodes(&episodes, &mut diagnosis_parser, &mut procedure_parser);
+        let diagnosis_store = diagnosis_parser.code_store();
+        let procedure_store = procedure_parser.code_store();
+
+        let groups = vec![
+            String::from("acs_stemi_schnier"),
+            String::from("acs_nstemi"),
+            String::from("pci"),
+        ];
+        let mut spec = IndexEventSpec::new(groups);
+        let index_events = patient_store.index_events(&spec, diagnosis_store, procedure_store);
+        assert_eq!(index_events.len(), 2);
+        let index_event = &index_events[0];
+        assert_eq!(index_event.patient_id, "a");
+        assert_eq!(index_event.spell_index, 0);
+        assert_eq!(index_event.episode_index, 0);
+        assert_eq!(index_event.date, make_date(2));
+        // Only the triggering codes are kept
+        assert_eq!(index_event.diagnoses.len(), 1);
+        assert!(index_event.procedures.is_empty());
+        assert_eq!(
+            index_event.groups,
+            BTreeSet::from([String::from("acs_stemi_schnier")])
+        );
+        assert_eq!(index_events[1].spell_index, 1);
+        assert_eq!(
+            index_events[1].groups,
+            BTreeSet::from([String::from("pci")])
+        );
+
+        // Any episode of the spell, after a date
+        spec.first_episode_only = false;
+        spec.after = Some(make_date(5));
+        let index_events = patient_store.index_events(&spec, diagnosis_store, procedure_store);
+        let index_episodes: Vec<_> = index_events
+            .iter()
+            .map(|index_event| (index_event.spell_index, index_event.episode_index))
+            .collect();
+        assert_eq!(index_episodes, vec![(1, 0), (2, 1)]);
+
+        // Only the first index event
+        spec.selection = IndexEventSelection::First;
+        let index_events = patient_store.index_events(&spec, diagnosis_store, procedure_store);
+        assert_eq!(index_events.len(), 1);
+        assert_eq!(index_events[0].date, make_date(10));
+    }
+}
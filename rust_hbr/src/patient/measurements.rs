@@ -1,15 +1,18 @@
 use chrono::Utc;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::error::HbrError;
+
 /// Used as a tag to indicate whether a piece of
 /// information originated in primary or secondary care.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DataSource {
     PrimaryCare,
     SecondaryCare,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub enum MeasurementValue {
     Integer(i64),
     Real(f64),
@@ -20,20 +23,34 @@ impl MeasurementValue {
 
     /// Convert a string containing an integer measurement to a 
     /// MeasurementValue. Returns error if the parse fails.
-    pub fn from_integer_string(int_as_string: String) -> Result<Self, String> {
+    pub fn from_integer_string(int_as_string: String) -> Result<Self, HbrError> {
         if let Ok(value) = int_as_string.parse() {
             Ok(Self::Integer(value))
         } else {
-            Err(format!(
-                "Failed to convert {} to integer measurement value",
-                int_as_string,
-            ))
+            Err(HbrError::InvalidMeasurementValue(int_as_string))
+        }
+    }
+
+    /// Convert a string containing a test result to a MeasurementValue.
+    /// The result can be an integer (e.g. 145), a real number (e.g.
+    /// 11.5), or a bound on the value (e.g. >90), which is kept as a
+    /// string. Returns error if the string is none of these.
+    pub fn from_result_string(result: String) -> Result<Self, HbrError> {
+        let number = |value: &str| value.parse::<f64>().ok().filter(|value| value.is_finite());
+        if let Ok(value) = result.parse() {
+            Ok(Self::Integer(value))
+        } else if let Some(value) = number(&result) {
+            Ok(Self::Real(value))
+        } else if result.strip_prefix(['<', '>']).and_then(number).is_some() {
+            Ok(Self::String(result))
+        } else {
+            Err(HbrError::InvalidMeasurementValue(result))
         }
     }
 }
 
 #[serde_with::serde_as]
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub struct Measurement {
     /// The value of the measurement at this time point
     pub value: MeasurementValue,
@@ -41,22 +58,24 @@ pub struct Measurement {
     /// sample was collected from the patient -- i.e. the time
     /// that the value applied to).
     #[serde_as(as = "Option<bson::DateTime>")]
+    #[schemars(with = "Option<chrono::DateTime<Utc>>")]
     pub measurement_date: Option<chrono::DateTime<Utc>>,
     /// When the measurement was made available, if the result
     /// required a test that takes time to perform
     #[serde_as(as = "Option<bson::DateTime>")]
+    #[schemars(with = "Option<chrono::DateTime<Utc>>")]
     pub measurement_available: Option<chrono::DateTime<Utc>>,
     /// Whether the measurement came from a primary or secondary
     /// care data source.
     pub data_source: Option<DataSource>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub struct MeasurementHistory {
     /// The name of the measurement
-    measurement_name: String,
+    pub measurement_name: String,
     /// The unit of measurement
-    measurement_unit: String,
+    pub measurement_unit: String,
     /// The list of measurements results with times
-    timeseries: Vec<Measurement>,
+    pub timeseries: Vec<Measurement>,
 }
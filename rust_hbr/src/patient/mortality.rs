@@ -0,0 +1,67 @@
+//! Mortality struct
+//!
+//! Information about whether the patient has died, and if so when and
+//! why. The causes of death are ICD-10 codes, as they appear on the
+//! death certificate (e.g. in the civil registration mortality data):
+//! the underlying cause of death, followed by any other causes
+//! mentioned on the certificate.
+//!
+//! A missing Mortality on a Patient means that no mortality data is
+//! available, not that the patient is alive.
+
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::clinical_code::DiagnosisCode;
+
+/// Where the record of the patient's death came from
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MortalitySource {
+    /// The civil registration of deaths (the death certificate)
+    CivilRegistration,
+    /// A hospital record (e.g. a spell ending with the patient's death)
+    SecondaryCare,
+    /// A primary care record
+    PrimaryCare,
+}
+
+#[serde_with::serde_as]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct Mortality {
+    /// True if there is no record of the patient's death in the
+    /// mortality data
+    pub alive: bool,
+    /// When the patient died
+    #[serde_as(as = "Option<bson::DateTime>")]
+    #[schemars(with = "Option<chrono::DateTime<Utc>>")]
+    pub date_of_death: Option<chrono::DateTime<Utc>>,
+    /// The underlying cause of death, ICD-10
+    pub cause_of_death: Option<DiagnosisCode>,
+    /// The other causes of death on the death certificate, ICD-10
+    pub secondary_causes_of_death: Option<Vec<DiagnosisCode>>,
+    /// Where the record of death came from
+    pub source: Option<MortalitySource>,
+}
+
+impl Mortality {
+    /// The mortality of a patient with no record of death
+    pub fn alive() -> Self {
+        Self {
+            alive: true,
+            date_of_death: None,
+            cause_of_death: None,
+            secondary_causes_of_death: None,
+            source: None,
+        }
+    }
+
+    /// Get all the causes of death (the underlying cause first,
+    /// followed by the secondary causes)
+    pub fn causes_of_death(&self) -> impl Iterator<Item = DiagnosisCode> + '_ {
+        self.cause_of_death
+            .iter()
+            .chain(self.secondary_causes_of_death.iter().flatten())
+            .copied()
+    }
+}
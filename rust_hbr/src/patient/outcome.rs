@@ -0,0 +1,233 @@
+//! Labelling the outcomes that follow index events
+//!
+//! After an index event (see index_event), the patient is followed up
+//! for a period of time, and an outcome (e.g. a bleeding event) occurs
+//! if a later spell contains a diagnosis or procedure code in one of
+//! the outcome's code groups. For each index event and outcome, the
+//! labeller gives whether the outcome occurred in the follow-up window,
+//! and the time from the index event to the first occurrence (for
+//! survival models).
+//!
+//! As in the HES prototype (get_episodes_after_index), a short period
+//! straight after the index event can be left out of the window, so
+//! that codes recorded for the index presentation in a following spell
+//! (e.g. a transfer to another hospital) are not counted as outcomes.
+//! The index spell itself is never searched.
+
+use chrono::{DateTime, Duration, Utc};
+
+use super::index_event::IndexEvent;
+use super::spell::code_groups;
+use super::PatientStore;
+use crate::clinical_code::ClinicalCodeStore;
+
+/// The window after an index event in which outcomes are counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FollowUpWindow {
+    /// Spells starting this soon (or sooner) after the index event
+    /// are not counted
+    pub min_period_after: Duration,
+    /// Spells starting this long (or longer) after the index event are
+    /// not counted
+    pub follow_up: Duration,
+}
+
+impl FollowUpWindow {
+    /// Make a window that starts straight after the index event and
+    /// lasts for the follow_up period
+    pub fn new(follow_up: Duration) -> Self {
+        Self {
+            min_period_after: Duration::zero(),
+            follow_up,
+        }
+    }
+
+    /// Whether a date is in the window after an index event on the
+    /// index_date
+    pub fn contains(&self, index_date: DateTime<Utc>, date: DateTime<Utc>) -> bool {
+        let time_after_index = date - index_date;
+        self.min_period_after < time_after_index && time_after_index < self.follow_up
+    }
+}
+
+/// The definition of an outcome
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutcomeSpec {
+    /// The name of the outcome (e.g. bleeding)
+    pub name: String,
+    /// The outcome occurs if a spell contains a diagnosis or procedure
+    /// code in any of these groups
+    pub groups: Vec<String>,
+}
+
+/// Whether one outcome followed an index event
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutcomeLabel {
+    /// The name of the outcome
+    pub name: String,
+    /// Whether the outcome occurred in the follow-up window
+    pub occurred: bool,
+    /// The time from the index event to the start of the first spell
+    /// with the outcome (None if it did not occur)
+    pub time_to_event: Option<Duration>,
+}
+
+/// The outcomes following one index event
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutcomeLabels {
+    /// The patient id of the index event
+    pub patient_id: String,
+    /// The position of the index spell in the patient's spells
+    pub spell_index: usize,
+    /// The date of the index event
+    pub index_date: DateTime<Utc>,
+    /// One label for each outcome, in the same order as the outcome
+    /// definitions
+    pub labels: Vec<OutcomeLabel>,
+}
+
+/// Label the outcomes following each index event, searching the spells
+/// (other than the index spell) that start in the follow-up window.
+///
+/// The index events are matched to the patients by patient id (see
+/// PatientStore::index_events), and the diagnosis_store and
+/// procedure_store must be the code stores of the parsers used to make
+/// the patients. An index event whose patient is not in the store has
+/// no outcomes. The labels are in the same order as the index events.
+pub fn label_outcomes(
+    patient_store: &PatientStore,
+    index_events: &[IndexEvent],
+    outcomes: &[OutcomeSpec],
+    window: &FollowUpWindow,
+    diagnosis_store: &ClinicalCodeStore,
+    procedure_store: &ClinicalCodeStore,
+) -> Vec<OutcomeLabels> {
+    let patients = patient_store.patients_by_id();
+
+    index_events
+        .iter()
+        .map(|index_event| {
+            let spells = patients
+                .get(index_event.patient_id.as_str())
+                .and_then(|patient| patient.spells.as_deref())
+                .unwrap_or_default();
+            // The follow-up spells, in order of start date, with the
+            // groups of the codes in each spell
+            let mut follow_up: Vec<_> = spells
+                .iter()
+                .enumerate()
+                .filter(|(spell_index, spell)| {
+                    *spell_index != index_event.spell_index
+                        && window.contains(index_event.date, spell.start)
+                })
+                .map(|(_, spell)| {
+                    let groups = code_groups(
+                        spell.diagnoses(),
+                        spell.procedures(),
+                        diagnosis_store,
+                        procedure_store,
+                    );
+                    (spell.start, groups)
+                })
+                .collect();
+            follow_up.sort_by_key(|(start, _)| *start);
+
+            let labels = outcomes
+                .iter()
+                .map(|outcome| {
+                    let first_start = follow_up
+                        .iter()
+                        .find(|(_, groups)| {
+                            outcome.groups.iter().any(|group| groups.contains(group))
+                        })
+                        .map(|(start, _)| *start);
+                    OutcomeLabel {
+                        name: outcome.name.clone(),
+                        occurred: first_start.is_some(),
+                        time_to_event: first_start.map(|start| start - index_event.date),
+                    }
+                })
+                .collect();
+            OutcomeLabels {
+                patient_id: index_event.patient_id.clone(),
+                spell_index: index_event.spell_index,
+                index_date: index_event.date,
+                labels,
+            }
+        })
+        .collect()
+}
+
+/// Tests for labelling outcomes
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::patient::index_event::IndexEventSpec;
+    use crate::patient::test_utils::{make_episode, make_parser};
+
+    #[test]
+    fn check_outcomes_in_follow_up_window() {
+        let mut diagnosis_parser = make_parser("icd10_example.yaml");
+        let mut procedure_parser = make_parser("opcs4_example.yaml");
+        let episodes = vec![
+            // Index event with a bleed recorded in the index spell
+            make_episode("a", "1", 1, &["I21.0", "D62"], &[]),
+            // Too soon after the index event to count
+            make_episode("a", "2", 2, &["D62"], &[]),
+            make_episode("a", "3", 5, &["I63.0"], &[]),
+            make_episode("a", "4", 8, &["D62"], &[]),
+            // After the end of the follow-up window
+            make_episode("a", "5", 25, &["D62"], &[]),
+            // An index event with no outcomes
+            make_episode("b", "1", 1, &["I21.0"], &[]),
+        ];
+        let patient_store =
+            PatientStore::from_episodes(&episodes, &mut diagnosis_parser, &mut procedure_parser);
+        let diagnosis_store = diagnosis_parser.code_store();
+        let procedure_store = procedure_parser.code_store();
+
+        let spec = IndexEventSpec::new(vec![String::from("acs_stemi_schnier")]);
+        let index_events = patient_store.index_events(&spec, diagnosis_store, procedure_store);
+        assert_eq!(index_events.len(), 2);
+
+        let outcomes = vec![
+            OutcomeSpec {
+                name: String::from("bleeding"),
+                groups: vec![String::from("bleeding")],
+            },
+            OutcomeSpec {
+                name: String::from("ischaemia"),
+                groups: vec![
+                    String::from("acs_stemi_schnier"),
+                    String::from("ischaemic_stroke"),
+                ],
+            },
+        ];
+        let window = FollowUpWindow {
+            min_period_after: Duration::days(1),
+            follow_up: Duration::days(20),
+        };
+        let outcome_labels = label_outcomes(
+            &patient_store,
+            &index_events,
+            &outcomes,
+            &window,
+            diagnosis_store,
+            procedure_store,
+        );
+
+        let labels = &outcome_labels[0].labels;
+        assert_eq!(outcome_labels[0].patient_id, "a");
+        assert_eq!(labels[0].name, "bleeding");
+        assert!(labels[0].occurred);
+        assert_eq!(labels[0].time_to_event, Some(Duration::days(7)));
+        assert_eq!(labels[1].name, "ischaemia");
+        assert_eq!(labels[1].time_to_event, Some(Duration::days(4)));
+
+        let labels = &outcome_labels[1].labels;
+        assert_eq!(outcome_labels[1].patient_id, "b");
+        assert!(labels.iter().all(|label| !label.occurred));
+        assert!(labels.iter().all(|label| label.time_to_event.is_none()));
+    }
+}
@@ -0,0 +1,81 @@
+//! Prescriptions structs
+//!
+//! A PrescriptionsHistory holds all the known prescriptions of one
+//! patient, from any data source. Each Prescription is one drug at one
+//! dose, with the dates the prescription started and stopped. The drug
+//! is identified by its name, and by its dm+d code if the data source
+//! provides one.
+//!
+//! Medication-based criteria (e.g. whether the patient is taking an
+//! oral anticoagulant at the index date) are computed by finding the
+//! prescriptions of the drugs of interest that were active at a given
+//! time (see PrescriptionsHistory::active_at).
+
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::measurements::DataSource;
+
+#[serde_with::serde_as]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct Prescription {
+    /// The name of the drug (e.g. Apixaban)
+    pub drug_name: String,
+    /// The dm+d (SNOMED CT) code of the drug, if known
+    pub dmd_code: Option<String>,
+    /// The dose, as written in the data source (e.g. 5 mg)
+    pub dose: Option<String>,
+    /// The route of administration (e.g. oral)
+    pub route: Option<String>,
+    /// When the prescription started
+    #[serde_as(as = "Option<bson::DateTime>")]
+    #[schemars(with = "Option<chrono::DateTime<Utc>>")]
+    pub prescription_start: Option<chrono::DateTime<Utc>>,
+    /// When the prescription stopped (None if it has not stopped, or
+    /// the stop date is not known)
+    #[serde_as(as = "Option<bson::DateTime>")]
+    #[schemars(with = "Option<chrono::DateTime<Utc>>")]
+    pub prescription_stop: Option<chrono::DateTime<Utc>>,
+    /// Whether the prescription came from a primary or secondary
+    /// care data source.
+    pub data_source: Option<DataSource>,
+}
+
+impl Prescription {
+    /// Whether the prescription was active at the given time, meaning
+    /// that it started at or before the time and had not stopped.
+    /// Prescriptions with no start date are never active.
+    pub fn is_active_at(&self, time: chrono::DateTime<Utc>) -> bool {
+        let started = self.prescription_start.is_some_and(|start| start <= time);
+        let stopped = self.prescription_stop.is_some_and(|stop| stop <= time);
+        started && !stopped
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default)]
+pub struct PrescriptionsHistory {
+    /// The prescriptions, sorted by start date (prescriptions with no
+    /// start date first)
+    pub prescriptions: Vec<Prescription>,
+}
+
+impl PrescriptionsHistory {
+    /// Get the prescriptions of any of the drugs with the given names
+    pub fn of_drugs<'a>(
+        &'a self,
+        drug_names: &'a [&str],
+    ) -> impl Iterator<Item = &'a Prescription> + 'a {
+        self.prescriptions
+            .iter()
+            .filter(|prescription| drug_names.contains(&prescription.drug_name.as_str()))
+    }
+
+    /// Get the prescriptions that were active at the given time (see
+    /// Prescription::is_active_at)
+    pub fn active_at(&self, time: chrono::DateTime<Utc>) -> impl Iterator<Item = &Prescription> {
+        self.prescriptions
+            .iter()
+            .filter(move |prescription| prescription.is_active_at(time))
+    }
+}
@@ -0,0 +1,906 @@
+//! Conversion between a PatientStore and an Arrow RecordBatch
+//!
+//! Each patient is one row of the record batch. The patient's spells,
+//! episodes, mortality, measurements and prescriptions are stored as nested columns
+//! (lists of structs, or structs), instead of being flattened into one row per episode. This
+//! means the patient collection can be saved to parquet (save_record_batch)
+//! and queried with DataFusion directly, without going via BSON/MongoDB.
+//!
+//! The layout of the record batch is:
+//! * id, nhs_number, trust_number: Utf8
+//! * age: UInt32
+//! * spells: List<Struct<start, end, episodes: List<Struct<start, end,
+//!   primary_diagnosis, secondary_diagnoses, primary_procedure,
+//!   secondary_procedures>>>>
+//! * mortality: Struct<alive, date_of_death, cause_of_death,
+//!   secondary_causes_of_death: List<UInt32>, source>
+//! * measurements: Struct<measurement_name, measurement_unit,
+//!   timeseries: List<Struct<value, measurement_date, measurement_available,
+//!   data_source>>>
+//! * prescriptions: List<Struct<drug_name, dmd_code, dose, route,
+//!   prescription_start, prescription_stop, data_source>>
+//!
+//! Dates are millisecond UTC timestamps. Clinical codes are stored as the
+//! raw id of the ClinicalCodeRef, so the ClinicalCodeStore used to make
+//! the patients is needed to interpret them.
+//!
+
+use std::sync::Arc;
+
+use chrono::{DateTime, TimeZone, Utc};
+use datafusion::arrow::array::{
+    Array, ArrayRef, BooleanArray, BooleanBufferBuilder, Float64Array, Int64Array, ListArray,
+    StringArray, StructArray, TimestampMillisecondArray, UInt32Array,
+};
+use datafusion::arrow::buffer::{NullBuffer, OffsetBuffer, ScalarBuffer};
+use datafusion::arrow::datatypes::{DataType, Field, FieldRef, Fields, Schema, TimeUnit};
+use datafusion::arrow::record_batch::RecordBatch;
+use mongodb::bson::oid::ObjectId;
+
+use crate::clinical_code::{ClinicalCodeRef, DiagnosisCode, ProcedureCode};
+
+use super::measurements::{DataSource, Measurement, MeasurementHistory, MeasurementValue};
+use super::mortality::{Mortality, MortalitySource};
+use super::prescriptions::{Prescription, PrescriptionsHistory};
+use super::spell::{episode::Episode, Spell};
+use super::{Patient, PatientStore};
+
+fn timestamp_type() -> DataType {
+    DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()))
+}
+
+fn list_type(item: DataType, nullable: bool) -> DataType {
+    DataType::List(Arc::new(Field::new("item", item, nullable)))
+}
+
+fn episode_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("start", timestamp_type(), false),
+        Field::new("end", timestamp_type(), false),
+        Field::new("primary_diagnosis", DataType::UInt32, true),
+        Field::new("secondary_diagnoses", list_type(DataType::UInt32, false), true),
+        Field::new("primary_procedure", DataType::UInt32, true),
+        Field::new("secondary_procedures", list_type(DataType::UInt32, false), true),
+    ])
+}
+
+fn spell_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("start", timestamp_type(), false),
+        Field::new("end", timestamp_type(), false),
+        Field::new(
+            "episodes",
+            list_type(DataType::Struct(episode_fields()), false),
+            true,
+        ),
+    ])
+}
+
+fn mortality_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("alive", DataType::Boolean, false),
+        Field::new("date_of_death", timestamp_type(), true),
+        Field::new("cause_of_death", DataType::UInt32, true),
+        Field::new("secondary_causes_of_death", list_type(DataType::UInt32, false), true),
+        Field::new("source", DataType::Utf8, true),
+    ])
+}
+
+/// A measurement value is stored as a struct with one field for
+/// each variant of MeasurementValue, only one of which is non-null.
+fn measurement_value_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("integer", DataType::Int64, true),
+        Field::new("real", DataType::Float64, true),
+        Field::new("string", DataType::Utf8, true),
+    ])
+}
+
+fn measurement_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("value", DataType::Struct(measurement_value_fields()), false),
+        Field::new("measurement_date", timestamp_type(), true),
+        Field::new("measurement_available", timestamp_type(), true),
+        Field::new("data_source", DataType::Utf8, true),
+    ])
+}
+
+fn measurement_history_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("measurement_name", DataType::Utf8, false),
+        Field::new("measurement_unit", DataType::Utf8, false),
+        Field::new(
+            "timeseries",
+            list_type(DataType::Struct(measurement_fields()), false),
+            false,
+        ),
+    ])
+}
+
+fn prescription_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("drug_name", DataType::Utf8, false),
+        Field::new("dmd_code", DataType::Utf8, true),
+        Field::new("dose", DataType::Utf8, true),
+        Field::new("route", DataType::Utf8, true),
+        Field::new("prescription_start", timestamp_type(), true),
+        Field::new("prescription_stop", timestamp_type(), true),
+        Field::new("data_source", DataType::Utf8, true),
+    ])
+}
+
+/// The schema of the record batch made by PatientStore::to_record_batch
+pub fn patient_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("id", DataType::Utf8, true),
+        Field::new("nhs_number", DataType::Utf8, true),
+        Field::new("trust_number", DataType::Utf8, true),
+        Field::new("age", DataType::UInt32, true),
+        Field::new(
+            "spells",
+            list_type(DataType::Struct(spell_fields()), false),
+            true,
+        ),
+        Field::new("mortality", DataType::Struct(mortality_fields()), true),
+        Field::new(
+            "measurements",
+            DataType::Struct(measurement_history_fields()),
+            true,
+        ),
+        Field::new(
+            "prescriptions",
+            list_type(DataType::Struct(prescription_fields()), false),
+            true,
+        ),
+    ])
+}
+
+/// Make a null buffer from a list of validity flags (true means
+/// the value is present)
+fn make_null_buffer(validity: &[bool]) -> NullBuffer {
+    let mut builder = BooleanBufferBuilder::new(validity.len());
+    builder.append_slice(validity);
+    NullBuffer::new(builder.finish())
+}
+
+/// Make a list array from the length of each list (None for
+/// a null list) and the flattened values of all the lists
+fn make_list_array(item_type: DataType, lengths: &[Option<usize>], values: ArrayRef) -> ArrayRef {
+    let mut offsets = vec![0i32];
+    let mut validity = Vec::new();
+    for length in lengths {
+        let last = *offsets.last().expect("Offsets are never empty");
+        let offset: i32 = length
+            .unwrap_or(0)
+            .try_into()
+            .expect("List length too large for arrow list");
+        offsets.push(last + offset);
+        validity.push(length.is_some());
+    }
+    let item: FieldRef = Arc::new(Field::new("item", item_type, false));
+    Arc::new(ListArray::new(
+        item,
+        OffsetBuffer::new(ScalarBuffer::from(offsets)),
+        values,
+        Some(make_null_buffer(&validity)),
+    ))
+}
+
+fn make_timestamp_array(dates: Vec<Option<i64>>) -> ArrayRef {
+    Arc::new(TimestampMillisecondArray::from(dates).with_timezone("UTC"))
+}
+
+fn make_episodes_array(episodes: &[&Episode]) -> ArrayRef {
+    let mut start = Vec::new();
+    let mut end = Vec::new();
+    let mut primary_diagnosis = Vec::new();
+    let mut secondary_diagnoses_lengths = Vec::new();
+    let mut secondary_diagnoses = Vec::new();
+    let mut primary_procedure = Vec::new();
+    let mut secondary_procedures_lengths = Vec::new();
+    let mut secondary_procedures = Vec::new();
+
+    for episode in episodes {
+        start.push(Some(episode.start.timestamp_millis()));
+        end.push(Some(episode.end.timestamp_millis()));
+        primary_diagnosis.push(
+            episode
+                .primary_diagnosis
+                .as_ref()
+                .map(|code| code.clinical_code_ref().id()),
+        );
+        secondary_diagnoses_lengths.push(episode.secondary_diagnoses.as_ref().map(Vec::len));
+        for code in episode.secondary_diagnoses.iter().flatten() {
+            secondary_diagnoses.push(code.clinical_code_ref().id());
+        }
+        primary_procedure.push(
+            episode
+                .primary_procedure
+                .as_ref()
+                .map(|code| code.clinical_code_ref().id()),
+        );
+        secondary_procedures_lengths.push(episode.secondary_procedures.as_ref().map(Vec::len));
+        for code in episode.secondary_procedures.iter().flatten() {
+            secondary_procedures.push(code.clinical_code_ref().id());
+        }
+    }
+
+    let columns: Vec<ArrayRef> = vec![
+        make_timestamp_array(start),
+        make_timestamp_array(end),
+        Arc::new(UInt32Array::from(primary_diagnosis)),
+        make_list_array(
+            DataType::UInt32,
+            &secondary_diagnoses_lengths,
+            Arc::new(UInt32Array::from(secondary_diagnoses)),
+        ),
+        Arc::new(UInt32Array::from(primary_procedure)),
+        make_list_array(
+            DataType::UInt32,
+            &secondary_procedures_lengths,
+            Arc::new(UInt32Array::from(secondary_procedures)),
+        ),
+    ];
+    Arc::new(StructArray::new(episode_fields(), columns, None))
+}
+
+fn make_spells_array(spells: &[&Spell]) -> ArrayRef {
+    let mut start = Vec::new();
+    let mut end = Vec::new();
+    let mut episodes_lengths = Vec::new();
+    let mut episodes = Vec::new();
+    for spell in spells {
+        start.push(Some(spell.start.timestamp_millis()));
+        end.push(Some(spell.end.timestamp_millis()));
+        episodes_lengths.push(spell.episodes.as_ref().map(Vec::len));
+        episodes.extend(spell.episodes.iter().flatten());
+    }
+    let columns: Vec<ArrayRef> = vec![
+        make_timestamp_array(start),
+        make_timestamp_array(end),
+        make_list_array(
+            DataType::Struct(episode_fields()),
+            &episodes_lengths,
+            make_episodes_array(&episodes),
+        ),
+    ];
+    Arc::new(StructArray::new(spell_fields(), columns, None))
+}
+
+fn make_mortality_array(mortalities: &[Option<&Mortality>]) -> ArrayRef {
+    let mut alive = Vec::new();
+    let mut date_of_death = Vec::new();
+    let mut cause_of_death = Vec::new();
+    let mut secondary_causes_lengths = Vec::new();
+    let mut secondary_causes = Vec::new();
+    let mut source = Vec::new();
+    let mut validity = Vec::new();
+    for mortality in mortalities {
+        validity.push(mortality.is_some());
+        // Null mortalities still need a (hidden) value in the alive column
+        alive.push(mortality.is_none_or(|m| m.alive));
+        date_of_death.push(mortality.and_then(|m| m.date_of_death).map(|date| date.timestamp_millis()));
+        cause_of_death.push(
+            mortality
+                .and_then(|m| m.cause_of_death.as_ref())
+                .map(|code| code.clinical_code_ref().id()),
+        );
+        let secondary = mortality.and_then(|m| m.secondary_causes_of_death.as_ref());
+        secondary_causes_lengths.push(secondary.map(Vec::len));
+        for code in secondary.into_iter().flatten() {
+            secondary_causes.push(code.clinical_code_ref().id());
+        }
+        source.push(mortality.and_then(|m| m.source).map(|source| match source {
+            MortalitySource::CivilRegistration => "CivilRegistration",
+            MortalitySource::SecondaryCare => "SecondaryCare",
+            MortalitySource::PrimaryCare => "PrimaryCare",
+        }));
+    }
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(BooleanArray::from(alive)),
+        make_timestamp_array(date_of_death),
+        Arc::new(UInt32Array::from(cause_of_death)),
+        make_list_array(
+            DataType::UInt32,
+            &secondary_causes_lengths,
+            Arc::new(UInt32Array::from(secondary_causes)),
+        ),
+        Arc::new(StringArray::from(source)),
+    ];
+    Arc::new(StructArray::new(
+        mortality_fields(),
+        columns,
+        Some(make_null_buffer(&validity)),
+    ))
+}
+
+fn data_source_name(data_source: &DataSource) -> &'static str {
+    match data_source {
+        DataSource::PrimaryCare => "PrimaryCare",
+        DataSource::SecondaryCare => "SecondaryCare",
+    }
+}
+
+fn make_prescriptions_array(prescriptions: &[&Prescription]) -> ArrayRef {
+    let mut drug_name = Vec::new();
+    let mut dmd_code = Vec::new();
+    let mut dose = Vec::new();
+    let mut route = Vec::new();
+    let mut prescription_start = Vec::new();
+    let mut prescription_stop = Vec::new();
+    let mut data_source = Vec::new();
+    for prescription in prescriptions {
+        drug_name.push(prescription.drug_name.clone());
+        dmd_code.push(prescription.dmd_code.clone());
+        dose.push(prescription.dose.clone());
+        route.push(prescription.route.clone());
+        prescription_start.push(prescription.prescription_start.map(|date| date.timestamp_millis()));
+        prescription_stop.push(prescription.prescription_stop.map(|date| date.timestamp_millis()));
+        data_source.push(prescription.data_source.as_ref().map(data_source_name));
+    }
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(drug_name)),
+        Arc::new(StringArray::from(dmd_code)),
+        Arc::new(StringArray::from(dose)),
+        Arc::new(StringArray::from(route)),
+        make_timestamp_array(prescription_start),
+        make_timestamp_array(prescription_stop),
+        Arc::new(StringArray::from(data_source)),
+    ];
+    Arc::new(StructArray::new(prescription_fields(), columns, None))
+}
+
+fn make_measurements_array(timeseries: &[&Measurement]) -> ArrayRef {
+    let mut integer = Vec::new();
+    let mut real = Vec::new();
+    let mut string = Vec::new();
+    let mut measurement_date = Vec::new();
+    let mut measurement_available = Vec::new();
+    let mut data_source = Vec::new();
+    for measurement in timeseries {
+        let (i, r, s) = match &measurement.value {
+            MeasurementValue::Integer(value) => (Some(*value), None, None),
+            MeasurementValue::Real(value) => (None, Some(*value), None),
+            MeasurementValue::String(value) => (None, None, Some(value.clone())),
+        };
+        integer.push(i);
+        real.push(r);
+        string.push(s);
+        measurement_date.push(measurement.measurement_date.map(|date| date.timestamp_millis()));
+        measurement_available.push(
+            measurement
+                .measurement_available
+                .map(|date| date.timestamp_millis()),
+        );
+        data_source.push(measurement.data_source.as_ref().map(data_source_name));
+    }
+    let value_columns: Vec<ArrayRef> = vec![
+        Arc::new(Int64Array::from(integer)),
+        Arc::new(Float64Array::from(real)),
+        Arc::new(StringArray::from(string)),
+    ];
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StructArray::new(
+            measurement_value_fields(),
+            value_columns,
+            None,
+        )),
+        make_timestamp_array(measurement_date),
+        make_timestamp_array(measurement_available),
+        Arc::new(StringArray::from(data_source)),
+    ];
+    Arc::new(StructArray::new(measurement_fields(), columns, None))
+}
+
+fn make_measurement_histories_array(histories: &[Option<&MeasurementHistory>]) -> ArrayRef {
+    let mut measurement_name = Vec::new();
+    let mut measurement_unit = Vec::new();
+    let mut timeseries_lengths = Vec::new();
+    let mut timeseries = Vec::new();
+    let mut validity = Vec::new();
+    for history in histories {
+        validity.push(history.is_some());
+        // Null histories still need a (hidden) value in each child column
+        measurement_name.push(history.map(|h| h.measurement_name.clone()).unwrap_or_default());
+        measurement_unit.push(history.map(|h| h.measurement_unit.clone()).unwrap_or_default());
+        timeseries_lengths.push(Some(history.map_or(0, |h| h.timeseries.len())));
+        timeseries.extend(history.iter().flat_map(|h| h.timeseries.iter()));
+    }
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(measurement_name)),
+        Arc::new(StringArray::from(measurement_unit)),
+        make_list_array(
+            DataType::Struct(measurement_fields()),
+            &timeseries_lengths,
+            make_measurements_array(&timeseries),
+        ),
+    ];
+    Arc::new(StructArray::new(
+        measurement_history_fields(),
+        columns,
+        Some(make_null_buffer(&validity)),
+    ))
+}
+
+/// Get a column from a struct array and downcast it to the expected
+/// array type, or return an error if the column is missing or has the
+/// wrong type.
+fn struct_column<'a, T: 'static>(array: &'a StructArray, name: &str) -> Result<&'a T, String> {
+    array
+        .column_by_name(name)
+        .ok_or(format!("Missing column {name}"))?
+        .as_any()
+        .downcast_ref::<T>()
+        .ok_or(format!("Unexpected type for column {name}"))
+}
+
+/// Downcast an array to the expected type, or return an error
+fn downcast<'a, T: 'static>(array: &'a ArrayRef, name: &str) -> Result<&'a T, String> {
+    array
+        .as_any()
+        .downcast_ref::<T>()
+        .ok_or(format!("Unexpected type for column {name}"))
+}
+
+fn date_from_millis(millis: i64) -> Result<DateTime<Utc>, String> {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .ok_or(format!("Invalid timestamp {millis}"))
+}
+
+fn optional_date(array: &TimestampMillisecondArray, n: usize) -> Result<Option<DateTime<Utc>>, String> {
+    if array.is_null(n) {
+        Ok(None)
+    } else {
+        Ok(Some(date_from_millis(array.value(n))?))
+    }
+}
+
+fn optional_string(array: &StringArray, n: usize) -> Option<String> {
+    if array.is_null(n) {
+        None
+    } else {
+        Some(array.value(n).to_string())
+    }
+}
+
+/// Get the range of child rows for list n, or None if list n is null
+fn list_range(list: &ListArray, n: usize) -> Option<std::ops::Range<usize>> {
+    if list.is_null(n) {
+        None
+    } else {
+        let offsets = list.value_offsets();
+        Some(offsets[n] as usize..offsets[n + 1] as usize)
+    }
+}
+
+fn code_refs_from_list(list: &ListArray, n: usize) -> Result<Option<Vec<ClinicalCodeRef>>, String> {
+    let values = downcast::<UInt32Array>(list.values(), "code list")?;
+    Ok(list_range(list, n).map(|range| {
+        range
+            .map(|m| ClinicalCodeRef::from(values.value(m)))
+            .collect()
+    }))
+}
+
+fn episodes_from_array(array: &StructArray, range: std::ops::Range<usize>) -> Result<Vec<Episode>, String> {
+    let start = struct_column::<TimestampMillisecondArray>(array, "start")?;
+    let end = struct_column::<TimestampMillisecondArray>(array, "end")?;
+    let primary_diagnosis = struct_column::<UInt32Array>(array, "primary_diagnosis")?;
+    let secondary_diagnoses = struct_column::<ListArray>(array, "secondary_diagnoses")?;
+    let primary_procedure = struct_column::<UInt32Array>(array, "primary_procedure")?;
+    let secondary_procedures = struct_column::<ListArray>(array, "secondary_procedures")?;
+
+    let mut episodes = Vec::new();
+    for n in range {
+        let primary_diagnosis = if primary_diagnosis.is_null(n) {
+            None
+        } else {
+            Some(DiagnosisCode::from(ClinicalCodeRef::from(primary_diagnosis.value(n))))
+        };
+        let primary_procedure = if primary_procedure.is_null(n) {
+            None
+        } else {
+            Some(ProcedureCode::from(ClinicalCodeRef::from(primary_procedure.value(n))))
+        };
+        episodes.push(Episode {
+            start: date_from_millis(start.value(n))?,
+            end: date_from_millis(end.value(n))?,
+            primary_diagnosis,
+            secondary_diagnoses: code_refs_from_list(secondary_diagnoses, n)?
+                .map(|refs| refs.into_iter().map(DiagnosisCode::from).collect()),
+            primary_procedure,
+            secondary_procedures: code_refs_from_list(secondary_procedures, n)?
+                .map(|refs| refs.into_iter().map(ProcedureCode::from).collect()),
+        });
+    }
+    Ok(episodes)
+}
+
+fn spells_from_array(array: &StructArray, range: std::ops::Range<usize>) -> Result<Vec<Spell>, String> {
+    let start = struct_column::<TimestampMillisecondArray>(array, "start")?;
+    let end = struct_column::<TimestampMillisecondArray>(array, "end")?;
+    let episodes = struct_column::<ListArray>(array, "episodes")?;
+    let episode_values = downcast::<StructArray>(episodes.values(), "episodes")?;
+
+    let mut spells = Vec::new();
+    for n in range {
+        let spell_episodes = match list_range(episodes, n) {
+            Some(range) => Some(episodes_from_array(episode_values, range)?),
+            None => None,
+        };
+        spells.push(Spell {
+            start: date_from_millis(start.value(n))?,
+            end: date_from_millis(end.value(n))?,
+            episodes: spell_episodes,
+        });
+    }
+    Ok(spells)
+}
+
+fn mortality_from_array(array: &StructArray, n: usize) -> Result<Option<Mortality>, String> {
+    if array.is_null(n) {
+        return Ok(None);
+    }
+    let alive = struct_column::<BooleanArray>(array, "alive")?;
+    let date_of_death = struct_column::<TimestampMillisecondArray>(array, "date_of_death")?;
+    let cause_of_death = struct_column::<UInt32Array>(array, "cause_of_death")?;
+    let secondary_causes_of_death = struct_column::<ListArray>(array, "secondary_causes_of_death")?;
+    let source = struct_column::<StringArray>(array, "source")?;
+
+    let cause_of_death = if cause_of_death.is_null(n) {
+        None
+    } else {
+        Some(DiagnosisCode::from(ClinicalCodeRef::from(cause_of_death.value(n))))
+    };
+    let source = match optional_string(source, n).as_deref() {
+        Some("CivilRegistration") => Some(MortalitySource::CivilRegistration),
+        Some("SecondaryCare") => Some(MortalitySource::SecondaryCare),
+        Some("PrimaryCare") => Some(MortalitySource::PrimaryCare),
+        Some(other) => return Err(format!("Unrecognised mortality source {other}")),
+        None => None,
+    };
+    Ok(Some(Mortality {
+        alive: alive.value(n),
+        date_of_death: optional_date(date_of_death, n)?,
+        cause_of_death,
+        secondary_causes_of_death: code_refs_from_list(secondary_causes_of_death, n)?
+            .map(|refs| refs.into_iter().map(DiagnosisCode::from).collect()),
+        source,
+    }))
+}
+
+/// Read a data_source column, written by data_source_name
+fn data_source_from_array(array: &StringArray, n: usize) -> Result<Option<DataSource>, String> {
+    match optional_string(array, n).as_deref() {
+        Some("PrimaryCare") => Ok(Some(DataSource::PrimaryCare)),
+        Some("SecondaryCare") => Ok(Some(DataSource::SecondaryCare)),
+        Some(other) => Err(format!("Unrecognised data_source {other}")),
+        None => Ok(None),
+    }
+}
+
+fn prescriptions_from_array(
+    array: &StructArray,
+    range: std::ops::Range<usize>,
+) -> Result<Vec<Prescription>, String> {
+    let drug_name = struct_column::<StringArray>(array, "drug_name")?;
+    let dmd_code = struct_column::<StringArray>(array, "dmd_code")?;
+    let dose = struct_column::<StringArray>(array, "dose")?;
+    let route = struct_column::<StringArray>(array, "route")?;
+    let prescription_start = struct_column::<TimestampMillisecondArray>(array, "prescription_start")?;
+    let prescription_stop = struct_column::<TimestampMillisecondArray>(array, "prescription_stop")?;
+    let data_source = struct_column::<StringArray>(array, "data_source")?;
+
+    let mut prescriptions = Vec::new();
+    for n in range {
+        prescriptions.push(Prescription {
+            drug_name: drug_name.value(n).to_string(),
+            dmd_code: optional_string(dmd_code, n),
+            dose: optional_string(dose, n),
+            route: optional_string(route, n),
+            prescription_start: optional_date(prescription_start, n)?,
+            prescription_stop: optional_date(prescription_stop, n)?,
+            data_source: data_source_from_array(data_source, n)?,
+        });
+    }
+    Ok(prescriptions)
+}
+
+fn measurements_from_array(
+    array: &StructArray,
+    range: std::ops::Range<usize>,
+) -> Result<Vec<Measurement>, String> {
+    let value = struct_column::<StructArray>(array, "value")?;
+    let integer = struct_column::<Int64Array>(value, "integer")?;
+    let real = struct_column::<Float64Array>(value, "real")?;
+    let string = struct_column::<StringArray>(value, "string")?;
+    let measurement_date = struct_column::<TimestampMillisecondArray>(array, "measurement_date")?;
+    let measurement_available =
+        struct_column::<TimestampMillisecondArray>(array, "measurement_available")?;
+    let data_source = struct_column::<StringArray>(array, "data_source")?;
+
+    let mut measurements = Vec::new();
+    for n in range {
+        let value = if !integer.is_null(n) {
+            MeasurementValue::Integer(integer.value(n))
+        } else if !real.is_null(n) {
+            MeasurementValue::Real(real.value(n))
+        } else if !string.is_null(n) {
+            MeasurementValue::String(string.value(n).to_string())
+        } else {
+            return Err(String::from("Measurement value is missing"));
+        };
+        measurements.push(Measurement {
+            value,
+            measurement_date: optional_date(measurement_date, n)?,
+            measurement_available: optional_date(measurement_available, n)?,
+            data_source: data_source_from_array(data_source, n)?,
+        });
+    }
+    Ok(measurements)
+}
+
+impl PatientStore {
+    /// Convert the patients into an Arrow record batch, with one row per
+    /// patient and nested columns for spells and measurements. See the
+    /// module documentation for the layout.
+    pub fn to_record_batch(&self) -> RecordBatch {
+        let patients = self.patients();
+
+        let id: Vec<_> = patients
+            .iter()
+            .map(|patient| patient.id.map(|id| id.to_hex()))
+            .collect();
+        let nhs_number: Vec<_> = patients.iter().map(|p| p.nhs_number.clone()).collect();
+        let trust_number: Vec<_> = patients.iter().map(|p| p.trust_number.clone()).collect();
+        let age: Vec<_> = patients.iter().map(|p| p.age).collect();
+
+        let spells_lengths: Vec<_> = patients
+            .iter()
+            .map(|p| p.spells.as_ref().map(Vec::len))
+            .collect();
+        let spells: Vec<_> = patients.iter().flat_map(|p| p.spells.iter().flatten()).collect();
+        let mortalities: Vec<_> = patients.iter().map(|p| p.mortality.as_ref()).collect();
+        let measurements: Vec<_> = patients.iter().map(|p| p.measurements.as_ref()).collect();
+        let prescriptions_lengths: Vec<_> = patients
+            .iter()
+            .map(|p| p.prescriptions.as_ref().map(|h| h.prescriptions.len()))
+            .collect();
+        let prescriptions: Vec<_> = patients
+            .iter()
+            .flat_map(|p| p.prescriptions.iter().flat_map(|h| h.prescriptions.iter()))
+            .collect();
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(id)),
+            Arc::new(StringArray::from(nhs_number)),
+            Arc::new(StringArray::from(trust_number)),
+            Arc::new(UInt32Array::from(age)),
+            make_list_array(
+                DataType::Struct(spell_fields()),
+                &spells_lengths,
+                make_spells_array(&spells),
+            ),
+            make_mortality_array(&mortalities),
+            make_measurement_histories_array(&measurements),
+            make_list_array(
+                DataType::Struct(prescription_fields()),
+                &prescriptions_lengths,
+                make_prescriptions_array(&prescriptions),
+            ),
+        ];
+        RecordBatch::try_new(Arc::new(patient_schema()), columns)
+            .expect("Patient columns should match the patient schema")
+    }
+
+    /// Read patients from an Arrow record batch in the format produced
+    /// by to_record_batch (for example, after loading it from a parquet
+    /// file). Returns an error if a column is missing or has the wrong
+    /// type.
+    pub fn from_record_batch(batch: &RecordBatch) -> Result<Self, String> {
+        let column = |name: &str| {
+            batch
+                .column_by_name(name)
+                .ok_or(format!("Missing column {name}"))
+        };
+        let id = downcast::<StringArray>(column("id")?, "id")?;
+        let nhs_number = downcast::<StringArray>(column("nhs_number")?, "nhs_number")?;
+        let trust_number = downcast::<StringArray>(column("trust_number")?, "trust_number")?;
+        let age = downcast::<UInt32Array>(column("age")?, "age")?;
+        let spells = downcast::<ListArray>(column("spells")?, "spells")?;
+        let spell_values = downcast::<StructArray>(spells.values(), "spells")?;
+        let mortality = downcast::<StructArray>(column("mortality")?, "mortality")?;
+        let measurements = downcast::<StructArray>(column("measurements")?, "measurements")?;
+        let measurement_name = struct_column::<StringArray>(measurements, "measurement_name")?;
+        let measurement_unit = struct_column::<StringArray>(measurements, "measurement_unit")?;
+        let timeseries = struct_column::<ListArray>(measurements, "timeseries")?;
+        let timeseries_values = downcast::<StructArray>(timeseries.values(), "timeseries")?;
+        let prescriptions = downcast::<ListArray>(column("prescriptions")?, "prescriptions")?;
+        let prescription_values = downcast::<StructArray>(prescriptions.values(), "prescriptions")?;
+
+        let mut patient_store = PatientStore::new();
+        for n in 0..batch.num_rows() {
+            let patient_id = match optional_string(id, n) {
+                Some(hex) => Some(
+                    ObjectId::parse_str(&hex).map_err(|_| format!("Invalid patient id {hex}"))?,
+                ),
+                None => None,
+            };
+            let patient_spells = match list_range(spells, n) {
+                Some(range) => Some(spells_from_array(spell_values, range)?),
+                None => None,
+            };
+            let patient_measurements = if measurements.is_null(n) {
+                None
+            } else {
+                let range = list_range(timeseries, n).unwrap_or(0..0);
+                Some(MeasurementHistory {
+                    measurement_name: measurement_name.value(n).to_string(),
+                    measurement_unit: measurement_unit.value(n).to_string(),
+                    timeseries: measurements_from_array(timeseries_values, range)?,
+                })
+            };
+            let patient_prescriptions = match list_range(prescriptions, n) {
+                Some(range) => Some(PrescriptionsHistory {
+                    prescriptions: prescriptions_from_array(prescription_values, range)?,
+                }),
+                None => None,
+            };
+            patient_store.push(Patient {
+                id: patient_id,
+                nhs_number: optional_string(nhs_number, n),
+                trust_number: optional_string(trust_number, n),
+                age: if age.is_null(n) { None } else { Some(age.value(n)) },
+                spells: patient_spells,
+                mortality: mortality_from_array(mortality, n)?,
+                measurements: patient_measurements,
+                prescriptions: patient_prescriptions,
+            });
+        }
+        Ok(patient_store)
+    }
+}
+
+/// Tests for the record batch conversion
+///
+/// The main thing to check is that converting patients to a record
+/// batch and back again gives the same patients, including the
+/// edge cases where optional (nullable) fields are missing.
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn make_date(millis: i64) -> DateTime<Utc> {
+        date_from_millis(millis).unwrap()
+    }
+
+    fn make_episode(start: i64, with_codes: bool) -> Episode {
+        let diagnosis = |id| DiagnosisCode::from(ClinicalCodeRef::from(id));
+        let procedure = |id| ProcedureCode::from(ClinicalCodeRef::from(id));
+        if with_codes {
+            Episode {
+                start: make_date(start),
+                end: make_date(start + 1000),
+                primary_diagnosis: Some(diagnosis(1)),
+                secondary_diagnoses: Some(vec![diagnosis(2), diagnosis(3)]),
+                primary_procedure: Some(procedure(4)),
+                secondary_procedures: Some(vec![]),
+            }
+        } else {
+            Episode {
+                start: make_date(start),
+                end: make_date(start + 1000),
+                primary_diagnosis: None,
+                secondary_diagnoses: None,
+                primary_procedure: None,
+                secondary_procedures: None,
+            }
+        }
+    }
+
+    fn make_patient_store() -> PatientStore {
+        let mut patient_store = PatientStore::new();
+
+        // Patient with everything present
+        patient_store.push(Patient {
+            id: Some(ObjectId::new()),
+            nhs_number: Some(String::from("123456789")),
+            trust_number: None,
+            age: Some(65),
+            spells: Some(vec![
+                Spell {
+                    start: make_date(0),
+                    end: make_date(5000),
+                    episodes: Some(vec![make_episode(0, true), make_episode(2000, false)]),
+                },
+                Spell {
+                    start: make_date(10000),
+                    end: make_date(20000),
+                    episodes: None,
+                },
+            ]),
+            mortality: Some(Mortality {
+                alive: false,
+                date_of_death: Some(make_date(30000)),
+                cause_of_death: Some(DiagnosisCode::from(ClinicalCodeRef::from(5))),
+                secondary_causes_of_death: Some(vec![DiagnosisCode::from(ClinicalCodeRef::from(6))]),
+                source: Some(MortalitySource::CivilRegistration),
+            }),
+            measurements: Some(MeasurementHistory {
+                measurement_name: String::from("Platelets"),
+                measurement_unit: String::from("10*9/L"),
+                timeseries: vec![
+                    Measurement {
+                        value: MeasurementValue::Integer(250),
+                        measurement_date: Some(make_date(3000)),
+                        measurement_available: None,
+                        data_source: Some(DataSource::SecondaryCare),
+                    },
+                    Measurement {
+                        value: MeasurementValue::String(String::from(">90")),
+                        measurement_date: None,
+                        measurement_available: None,
+                        data_source: None,
+                    },
+                ],
+            }),
+            prescriptions: Some(PrescriptionsHistory {
+                prescriptions: vec![
+                    Prescription {
+                        drug_name: String::from("Apixaban"),
+                        dmd_code: Some(String::from("698090000")),
+                        dose: Some(String::from("5 mg")),
+                        route: Some(String::from("oral")),
+                        prescription_start: Some(make_date(4000)),
+                        prescription_stop: None,
+                        data_source: Some(DataSource::SecondaryCare),
+                    },
+                    Prescription {
+                        drug_name: String::from("Aspirin"),
+                        dmd_code: None,
+                        dose: None,
+                        route: None,
+                        prescription_start: None,
+                        prescription_stop: Some(make_date(6000)),
+                        data_source: None,
+                    },
+                ],
+            }),
+        });
+
+        // Patient with nothing present
+        patient_store.push(Patient::default());
+
+        // Patient with an empty list of spells, who is alive
+        patient_store.push(Patient {
+            spells: Some(vec![]),
+            mortality: Some(Mortality::alive()),
+            prescriptions: Some(PrescriptionsHistory::default()),
+            ..Default::default()
+        });
+
+        patient_store
+    }
+
+    #[test]
+    fn check_record_batch_has_one_row_per_patient() {
+        let patient_store = make_patient_store();
+        let batch = patient_store.to_record_batch();
+        assert_eq!(batch.num_rows(), 3);
+        assert_eq!(batch.schema().as_ref(), &patient_schema());
+    }
+
+    #[test]
+    fn check_record_batch_roundtrip() {
+        let patient_store = make_patient_store();
+        let batch = patient_store.to_record_batch();
+        let patient_store_read =
+            PatientStore::from_record_batch(&batch).expect("Conversion should succeed");
+        assert_eq!(
+            format!("{:?}", patient_store_read),
+            format!("{:?}", patient_store)
+        );
+    }
+}
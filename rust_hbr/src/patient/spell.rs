@@ -22,24 +22,60 @@
 //! constraints that the episodes occur within the spell timeframe.
 //! 
 
+use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
 use episode::Episode;
 use chrono::Utc;
+use std::collections::HashSet;
+use crate::clinical_code::{ClinicalCodeStore, DiagnosisCode, ProcedureCode};
 use rand_chacha::ChaCha8Rng;
 
-mod episode;
+pub mod episode;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub struct Spell {
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
-    start: chrono::DateTime<Utc>,
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub start: chrono::DateTime<Utc>,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
-    end: chrono::DateTime<Utc>,
-    episodes: Option<Vec<Episode>>,
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub end: chrono::DateTime<Utc>,
+    pub episodes: Option<Vec<Episode>>,
 }
 
 impl Spell {
-    // TODO: put useful spell-level functions here
+    /// Get the number of episodes in the spell
+    pub fn num_episodes(&self) -> usize {
+        self.episodes.as_ref().map_or(0, Vec::len)
+    }
+
+    /// Get all the diagnosis codes (primary and secondary) in all
+    /// the episodes of the spell
+    pub fn diagnoses(&self) -> impl Iterator<Item = DiagnosisCode> + '_ {
+        self.episodes.iter().flatten().flat_map(Episode::diagnoses)
+    }
+
+    /// Get all the procedure codes (primary and secondary) in all
+    /// the episodes of the spell
+    pub fn procedures(&self) -> impl Iterator<Item = ProcedureCode> + '_ {
+        self.episodes.iter().flatten().flat_map(Episode::procedures)
+    }
+}
+
+/// Get the groups containing any of the diagnosis or procedure codes
+/// (e.g. the codes of a spell or an episode), using the code stores that
+/// the codes were parsed into (see PatientStore::from_episodes)
+pub(super) fn code_groups<'a>(
+    diagnoses: impl Iterator<Item = DiagnosisCode>,
+    procedures: impl Iterator<Item = ProcedureCode>,
+    diagnosis_store: &'a ClinicalCodeStore,
+    procedure_store: &'a ClinicalCodeStore,
+) -> HashSet<&'a String> {
+    let diagnosis_groups =
+        diagnoses.filter_map(|code| diagnosis_store.groups_of(&code.clinical_code_ref()));
+    let procedure_groups =
+        procedures.filter_map(|code| procedure_store.groups_of(&code.clinical_code_ref()));
+    diagnosis_groups.chain(procedure_groups).flatten().collect()
 }
 
 /// Create a random spell
@@ -1,17 +1,39 @@
+use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
 use crate::clinical_code::{DiagnosisCode, ProcedureCode};
 use chrono::Utc;
 
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub struct Episode {
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
-    start: chrono::DateTime<Utc>,
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub start: chrono::DateTime<Utc>,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
-    end: chrono::DateTime<Utc>,
-    primary_diagnosis: Option<DiagnosisCode>,
-    secondary_diagnoses: Option<Vec<DiagnosisCode>>,
-    primary_procedure: Option<ProcedureCode>,
-    secondary_procedures: Option<Vec<ProcedureCode>>,    
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub end: chrono::DateTime<Utc>,
+    pub primary_diagnosis: Option<DiagnosisCode>,
+    pub secondary_diagnoses: Option<Vec<DiagnosisCode>>,
+    pub primary_procedure: Option<ProcedureCode>,
+    pub secondary_procedures: Option<Vec<ProcedureCode>>,    
 }
 
+impl Episode {
+    /// Get all the diagnosis codes (primary first, then secondary)
+    /// in the episode
+    pub fn diagnoses(&self) -> impl Iterator<Item = DiagnosisCode> + '_ {
+        self.primary_diagnosis
+            .iter()
+            .chain(self.secondary_diagnoses.iter().flatten())
+            .copied()
+    }
+
+    /// Get all the procedure codes (primary first, then secondary)
+    /// in the episode
+    pub fn procedures(&self) -> impl Iterator<Item = ProcedureCode> + '_ {
+        self.primary_procedure
+            .iter()
+            .chain(self.secondary_procedures.iter().flatten())
+            .copied()
+    }
+}
@@ -0,0 +1,600 @@
+//! Building patients from tables of episodes, and summarising them
+//!
+//! Raw episode data (e.g. HES) has one row per episode, with the
+//! patient, the spell and the diagnosis and procedure codes as columns.
+//! PatientStore::from_episodes groups the rows into patients and spells,
+//! parsing the codes as it goes, and PatientStore::add_mortality and
+//! PatientStore::add_prescriptions add the deaths and prescriptions from
+//! tables of death and prescription records. The patients can then be summarised
+//! over a lookback window before an index date (summarise_patients),
+//! giving the patient-level counts used as model inputs (e.g. the
+//! number of spells with a code in each group).
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Duration, Utc};
+
+use super::features::LookbackWindow;
+use super::measurements::{DataSource, Measurement, MeasurementValue};
+use super::mortality::{Mortality, MortalitySource};
+use super::prescriptions::{Prescription, PrescriptionsHistory};
+use super::spell::{code_groups, episode::Episode, Spell};
+use super::{Patient, PatientStore};
+use crate::clinical_code::{ClinicalCodeRef, ClinicalCodeStore, DiagnosisCode, ProcedureCode};
+use crate::clinical_code_tree::CodeParserCache;
+
+/// One episode from a table of episodes (e.g. one row of HES), with
+/// the codes as they appear in the raw data
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpisodeRecord {
+    /// The patient the episode belongs to (e.g. the NHS number)
+    pub patient_id: String,
+    /// The spell containing the episode. Episodes with the same
+    /// patient and spell id are put in the same spell.
+    pub spell_id: String,
+    /// When the episode started
+    pub start: DateTime<Utc>,
+    /// When the episode ended
+    pub end: DateTime<Utc>,
+    /// The diagnosis codes, primary diagnosis first
+    pub diagnoses: Vec<String>,
+    /// The procedure codes, primary procedure first
+    pub procedures: Vec<String>,
+}
+
+/// One death record from a table of deaths (e.g. one row of the civil
+/// registration mortality data), with the codes as they appear in the
+/// raw data
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeathRecord {
+    /// The patient who died (e.g. the NHS number)
+    pub patient_id: String,
+    /// When the patient died, if known
+    pub date_of_death: Option<DateTime<Utc>>,
+    /// The causes of death (ICD-10), underlying cause first
+    pub causes_of_death: Vec<String>,
+}
+
+/// One prescription from a table of prescriptions (e.g. one row of the
+/// prescriptions table)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrescriptionRecord {
+    /// The patient the drug was prescribed to
+    pub patient_id: String,
+    /// The name of the drug
+    pub drug_name: String,
+    /// The dm+d code of the drug, if present in the data
+    pub dmd_code: Option<String>,
+    /// The dose (e.g. 75 mg)
+    pub dose: Option<String>,
+    /// The route of administration (e.g. oral)
+    pub route: Option<String>,
+    /// When the prescription started
+    pub prescription_start: Option<DateTime<Utc>>,
+    /// When the prescription stopped (None if it has not stopped)
+    pub prescription_stop: Option<DateTime<Utc>>,
+}
+
+/// One blood test result for a patient (e.g. converted from the
+/// pathology_blood table by measurement_from_pathology_blood)
+#[derive(Debug)]
+pub struct LabResult {
+    /// The patient the result belongs to
+    pub patient_id: String,
+    /// The name of the test (e.g. Platelets)
+    pub test_name: String,
+    /// The result of the test. Results with no measurement_date
+    /// are not included in the summaries.
+    pub measurement: Measurement,
+}
+
+/// Patient-level counts over a lookback window before an index date
+/// (see summarise_patients)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatientSummary {
+    /// The patient id (the nhs_number of the patient)
+    pub patient_id: String,
+    /// The total number of spells for the patient
+    pub n_spells: usize,
+    /// The number of spells that started in the lookback window
+    pub n_spells_in_lookback: usize,
+    /// For each group, the number of spells in the lookback window
+    /// with at least one diagnosis or procedure code in the group
+    pub group_counts: HashMap<String, usize>,
+    /// For each test, the value of the most recent numerical result
+    /// in the lookback window (tests with no results are missing)
+    pub latest_measurements: HashMap<String, f64>,
+}
+
+/// Parse the raw codes for one kind of code, keeping the codes that
+/// have an exact match in the tree. The first element is the primary
+/// code (None if it is missing or not valid), and the rest are the
+/// valid secondary codes.
+fn parse_codes<T>(
+    codes: &[String],
+    parser: &mut CodeParserCache,
+    wrap: impl Fn(ClinicalCodeRef) -> T,
+) -> (Option<T>, Vec<T>) {
+    let mut parse = |code: &String| {
+        parser
+            .find_exact(code)
+            .ok()
+            .map(|code_match| wrap(code_match.code()))
+    };
+    match codes.split_first() {
+        Some((primary, secondary)) => {
+            (parse(primary), secondary.iter().filter_map(parse).collect())
+        }
+        None => (None, Vec::new()),
+    }
+}
+
+impl PatientStore {
+    /// Make patients from a table of episodes, with one patient for each
+    /// patient id and one spell for each spell id (within a patient).
+    ///
+    /// Diagnosis codes are parsed with the diagnosis_parser and procedure
+    /// codes with the procedure_parser, so the code stores of the parsers
+    /// are needed to interpret the codes in the patients. Codes that do
+    /// not have an exact match are left out. The start and end of each
+    /// spell are the earliest start and the latest end of its episodes.
+    /// Patients are sorted by id, and spells and episodes by start date.
+    pub fn from_episodes(
+        episodes: &[EpisodeRecord],
+        diagnosis_parser: &mut CodeParserCache,
+        procedure_parser: &mut CodeParserCache,
+    ) -> Self {
+        let mut records: BTreeMap<&str, BTreeMap<&str, Vec<&EpisodeRecord>>> = BTreeMap::new();
+        for episode in episodes {
+            records
+                .entry(&episode.patient_id)
+                .or_default()
+                .entry(&episode.spell_id)
+                .or_default()
+                .push(episode);
+        }
+
+        let mut patient_store = Self::new();
+        for (patient_id, spell_records) in records {
+            let mut spells: Vec<Spell> = spell_records
+                .into_values()
+                .map(|mut episode_records| {
+                    episode_records.sort_by_key(|episode| episode.start);
+                    let episodes: Vec<Episode> = episode_records
+                        .iter()
+                        .map(|record| {
+                            let (primary_diagnosis, secondary_diagnoses) = parse_codes(
+                                &record.diagnoses,
+                                diagnosis_parser,
+                                DiagnosisCode::from,
+                            );
+                            let (primary_procedure, secondary_procedures) = parse_codes(
+                                &record.procedures,
+                                procedure_parser,
+                                ProcedureCode::from,
+                            );
+                            Episode {
+                                start: record.start,
+                                end: record.end,
+                                primary_diagnosis,
+                                secondary_diagnoses: Some(secondary_diagnoses),
+                                primary_procedure,
+                                secondary_procedures: Some(secondary_procedures),
+                            }
+                        })
+                        .collect();
+                    // There is at least one episode in every spell
+                    let start = episode_records[0].start;
+                    let end = episode_records
+                        .iter()
+                        .map(|record| record.end)
+                        .max()
+                        .unwrap_or(start);
+                    Spell {
+                        start,
+                        end,
+                        episodes: Some(episodes),
+                    }
+                })
+                .collect();
+            spells.sort_by_key(|spell| spell.start);
+            patient_store.push(Patient {
+                nhs_number: Some(patient_id.to_string()),
+                spells: Some(spells),
+                ..Default::default()
+            });
+        }
+        patient_store
+    }
+}
+
+impl PatientStore {
+    /// Add the mortality data from a table of death records, matching
+    /// the records to the patients by patient id (the nhs_number).
+    ///
+    /// Patients with a death record are marked as dead, with the date
+    /// and causes of death from the record (parsed with the
+    /// diagnosis_parser, leaving out codes that do not have an exact
+    /// match) and the given source. If a patient has more than one
+    /// record, the earliest is used. All the other patients are marked
+    /// as alive, because the table is assumed to contain all the deaths
+    /// in the data source. Records for patients not in the store are
+    /// ignored.
+    pub fn add_mortality(
+        &mut self,
+        deaths: &[DeathRecord],
+        source: MortalitySource,
+        diagnosis_parser: &mut CodeParserCache,
+    ) {
+        let mut records: HashMap<&str, &DeathRecord> = HashMap::new();
+        for death in deaths {
+            records
+                .entry(&death.patient_id)
+                .and_modify(|record| {
+                    // Records with no date of death count as the latest
+                    let key =
+                        |death: &DeathRecord| (death.date_of_death.is_none(), death.date_of_death);
+                    if key(death) < key(record) {
+                        *record = death;
+                    }
+                })
+                .or_insert(death);
+        }
+
+        for patient in self.patients.iter_mut() {
+            let record = patient
+                .nhs_number
+                .as_deref()
+                .and_then(|patient_id| records.get(patient_id));
+            let mortality = match record {
+                Some(record) => {
+                    let (cause_of_death, secondary_causes_of_death) = parse_codes(
+                        &record.causes_of_death,
+                        diagnosis_parser,
+                        DiagnosisCode::from,
+                    );
+                    Mortality {
+                        alive: false,
+                        date_of_death: record.date_of_death,
+                        cause_of_death,
+                        secondary_causes_of_death: Some(secondary_causes_of_death),
+                        source: Some(source),
+                    }
+                }
+                None => Mortality::alive(),
+            };
+            patient.mortality = Some(mortality);
+        }
+    }
+}
+
+impl PatientStore {
+    /// Add the prescriptions from a table of prescription records,
+    /// matching the records to the patients by patient id (the
+    /// nhs_number), and tagging each prescription with the data_source.
+    ///
+    /// The prescriptions are added to any existing prescriptions of the
+    /// patient (so that tables from primary and secondary care can both
+    /// be added), and are kept sorted by start date. Every patient gets
+    /// a PrescriptionsHistory, which is empty if the patient has no
+    /// prescriptions. Records for patients not in the store are ignored.
+    pub fn add_prescriptions(
+        &mut self,
+        prescriptions: &[PrescriptionRecord],
+        data_source: DataSource,
+    ) {
+        let mut records: HashMap<&str, Vec<&PrescriptionRecord>> = HashMap::new();
+        for prescription in prescriptions {
+            records
+                .entry(&prescription.patient_id)
+                .or_default()
+                .push(prescription);
+        }
+
+        for patient in self.patients.iter_mut() {
+            let history = patient
+                .prescriptions
+                .get_or_insert_with(PrescriptionsHistory::default);
+            let patient_records = patient
+                .nhs_number
+                .as_deref()
+                .and_then(|patient_id| records.get(patient_id));
+            for record in patient_records.into_iter().flatten() {
+                history.prescriptions.push(Prescription {
+                    drug_name: record.drug_name.clone(),
+                    dmd_code: record.dmd_code.clone(),
+                    dose: record.dose.clone(),
+                    route: record.route.clone(),
+                    prescription_start: record.prescription_start,
+                    prescription_stop: record.prescription_stop,
+                    data_source: Some(data_source),
+                });
+            }
+            history
+                .prescriptions
+                .sort_by_key(|prescription| prescription.prescription_start);
+        }
+    }
+}
+
+/// Summarise each patient over the lookback window before the index
+/// date (from index_date - lookback, up to but not including the
+/// index date, see LookbackWindow::new).
+///
+/// A spell is in the window if it started in the window. The group
+/// counts are returned for every group in groups (including groups
+/// with a count of zero), and the diagnosis_store and procedure_store
+/// must be the code stores of the parsers used to make the patients
+/// (see PatientStore::from_episodes). The lab results are matched to
+/// the patients by patient id. The summaries are in the same order as
+/// the patients.
+pub fn summarise_patients(
+    patient_store: &PatientStore,
+    lab_results: &[LabResult],
+    index_date: DateTime<Utc>,
+    lookback: Duration,
+    groups: &[String],
+    diagnosis_store: &ClinicalCodeStore,
+    procedure_store: &ClinicalCodeStore,
+) -> Vec<PatientSummary> {
+    let window = LookbackWindow::new(lookback);
+    let in_window = |date: DateTime<Utc>| window.contains(index_date, date);
+
+    // The most recent numerical result for each test, for each patient
+    let mut latest: HashMap<&str, HashMap<&str, (DateTime<Utc>, f64)>> = HashMap::new();
+    for lab_result in lab_results {
+        let Some(date) = lab_result.measurement.measurement_date else {
+            continue;
+        };
+        let value = match lab_result.measurement.value {
+            MeasurementValue::Integer(value) => value as f64,
+            MeasurementValue::Real(value) => value,
+            MeasurementValue::String(_) => continue,
+        };
+        if !in_window(date) {
+            continue;
+        }
+        let patient_latest = latest.entry(&lab_result.patient_id).or_default();
+        match patient_latest.get(lab_result.test_name.as_str()) {
+            Some((latest_date, _)) if *latest_date >= date => (),
+            _ => {
+                patient_latest.insert(&lab_result.test_name, (date, value));
+            }
+        }
+    }
+
+    patient_store
+        .patients()
+        .iter()
+        .map(|patient| {
+            let patient_id = patient.nhs_number.clone().unwrap_or_default();
+            let spells = patient.spells.as_deref().unwrap_or_default();
+            let mut group_counts: HashMap<String, usize> =
+                groups.iter().map(|group| (group.clone(), 0)).collect();
+            let mut n_spells_in_lookback = 0;
+            for spell in spells.iter().filter(|spell| in_window(spell.start)) {
+                n_spells_in_lookback += 1;
+                for group in code_groups(
+                    spell.diagnoses(),
+                    spell.procedures(),
+                    diagnosis_store,
+                    procedure_store,
+                ) {
+                    if let Some(count) = group_counts.get_mut(group) {
+                        *count += 1;
+                    }
+                }
+            }
+            let latest_measurements = latest
+                .get(patient_id.as_str())
+                .into_iter()
+                .flatten()
+                .map(|(test_name, (_, value))| (test_name.to_string(), *value))
+                .collect();
+            PatientSummary {
+                patient_id,
+                n_spells: spells.len(),
+                n_spells_in_lookback,
+                group_counts,
+                latest_measurements,
+            }
+        })
+        .collect()
+}
+
+/// Tests for building and summarising patients
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::patient::test_utils::{make_date, make_episode, make_parser};
+
+    fn make_lab_result(patient_id: &str, day: u32, value: i64) -> LabResult {
+        LabResult {
+            patient_id: patient_id.to_string(),
+            test_name: String::from("Platelets"),
+            measurement: Measurement {
+                value: MeasurementValue::Integer(value),
+                measurement_date: Some(make_date(day)),
+                measurement_available: None,
+                data_source: None,
+            },
+        }
+    }
+
+    #[test]
+    fn check_patients_from_episodes_and_summaries() {
+        let mut diagnosis_parser = make_parser("icd10_example.yaml");
+        let mut procedure_parser = make_parser("opcs4_example.yaml");
+
+        let episodes = vec![
+            // Two episodes in the same spell, one with an invalid code
+            make_episode("b", "1", 12, &["I21.0", "not a code"], &[]),
+            make_episode("b", "1", 10, &["I48.0"], &["K75.1"]),
+            // A spell before the lookback window
+            make_episode("b", "2", 1, &["I21.0"], &[]),
+            make_episode("a", "1", 15, &[], &[]),
+            // A spell starting exactly at the start of the lookback window
+            make_episode("a", "2", 6, &[], &[]),
+        ];
+        let patient_store =
+            PatientStore::from_episodes(&episodes, &mut diagnosis_parser, &mut procedure_parser);
+        assert_eq!(patient_store.len(), 2);
+
+        let patient = &patient_store.patients()[1];
+        assert_eq!(patient.nhs_number.as_deref(), Some("b"));
+        let spells = patient.spells.as_ref().unwrap();
+        assert_eq!(spells.len(), 2);
+        assert_eq!(spells[1].start, make_date(10));
+        assert_eq!(spells[1].end, make_date(13));
+        assert_eq!(spells[1].num_episodes(), 2);
+        assert_eq!(spells[1].diagnoses().count(), 2);
+        assert_eq!(spells[1].procedures().count(), 1);
+
+        let lab_results = vec![
+            make_lab_result("b", 11, 150),
+            make_lab_result("b", 14, 90),
+            make_lab_result("b", 25, 300),
+        ];
+        let groups = vec![
+            String::from("acs_stemi_schnier"),
+            String::from("atrial_fib"),
+            String::from("pci"),
+            String::from("cabg"),
+        ];
+        let summaries = summarise_patients(
+            &patient_store,
+            &lab_results,
+            make_date(20),
+            Duration::days(14),
+            &groups,
+            diagnosis_parser.code_store(),
+            procedure_parser.code_store(),
+        );
+
+        assert_eq!(summaries[0].patient_id, "a");
+        assert_eq!(summaries[0].n_spells_in_lookback, 2);
+        assert!(summaries[0].group_counts.values().all(|count| *count == 0));
+        assert!(summaries[0].latest_measurements.is_empty());
+
+        let summary = &summaries[1];
+        assert_eq!(summary.n_spells, 2);
+        assert_eq!(summary.n_spells_in_lookback, 1);
+        assert_eq!(summary.group_counts["acs_stemi_schnier"], 1);
+        assert_eq!(summary.group_counts["atrial_fib"], 1);
+        assert_eq!(summary.group_counts["pci"], 1);
+        assert_eq!(summary.group_counts["cabg"], 0);
+        assert_eq!(summary.latest_measurements["Platelets"], 90.0);
+    }
+
+    #[test]
+    fn check_mortality_from_death_records() {
+        let mut diagnosis_parser = make_parser("icd10_example.yaml");
+        let mut procedure_parser = make_parser("opcs4_example.yaml");
+        let episodes = vec![
+            make_episode("a", "1", 1, &["I21.0"], &[]),
+            make_episode("b", "1", 1, &["I21.0"], &[]),
+        ];
+        let mut patient_store =
+            PatientStore::from_episodes(&episodes, &mut diagnosis_parser, &mut procedure_parser);
+
+        let make_death = |patient_id: &str, day: Option<u32>, causes: &[&str]| DeathRecord {
+            patient_id: patient_id.to_string(),
+            date_of_death: day.map(make_date),
+            causes_of_death: causes.iter().map(|code| code.to_string()).collect(),
+        };
+        let deaths = vec![
+            make_death("b", None, &[]),
+            make_death("b", Some(20), &["I48.0"]),
+            make_death("b", Some(10), &["I21.0", "not a code", "I48.0"]),
+            // A patient who is not in the store
+            make_death("c", Some(5), &["I21.0"]),
+        ];
+        patient_store.add_mortality(
+            &deaths,
+            MortalitySource::CivilRegistration,
+            &mut diagnosis_parser,
+        );
+        assert_eq!(patient_store.len(), 2);
+
+        let mortality = patient_store.patients()[0].mortality.as_ref().unwrap();
+        assert!(mortality.alive);
+        assert_eq!(mortality.date_of_death, None);
+        assert_eq!(mortality.causes_of_death().count(), 0);
+
+        // The earliest record is used, with the invalid code left out
+        let mortality = patient_store.patients()[1].mortality.as_ref().unwrap();
+        assert!(!mortality.alive);
+        assert_eq!(mortality.date_of_death, Some(make_date(10)));
+        assert_eq!(mortality.source, Some(MortalitySource::CivilRegistration));
+        let causes: Vec<_> = mortality
+            .causes_of_death()
+            .map(|code| {
+                let code_store = diagnosis_parser.code_store();
+                code_store
+                    .clinical_code_from(&code.clinical_code_ref())
+                    .unwrap()
+                    .name()
+                    .clone()
+            })
+            .collect();
+        assert_eq!(causes, vec!["I21.0", "I48.0"]);
+    }
+
+    #[test]
+    fn check_prescriptions_from_prescription_records() {
+        let mut diagnosis_parser = make_parser("icd10_example.yaml");
+        let mut procedure_parser = make_parser("opcs4_example.yaml");
+        let episodes = vec![
+            make_episode("a", "1", 1, &[], &[]),
+            make_episode("b", "1", 1, &[], &[]),
+        ];
+        let mut patient_store =
+            PatientStore::from_episodes(&episodes, &mut diagnosis_parser, &mut procedure_parser);
+
+        let make_prescription =
+            |drug_name: &str, start: u32, stop: Option<u32>| PrescriptionRecord {
+                patient_id: String::from("b"),
+                drug_name: drug_name.to_string(),
+                dmd_code: None,
+                dose: Some(String::from("5 mg")),
+                route: None,
+                prescription_start: Some(make_date(start)),
+                prescription_stop: stop.map(make_date),
+            };
+        let secondary_care = vec![
+            make_prescription("Apixaban", 10, None),
+            make_prescription("Aspirin", 5, Some(12)),
+        ];
+        let primary_care = vec![make_prescription("Warfarin", 1, Some(8))];
+        patient_store.add_prescriptions(&secondary_care, DataSource::SecondaryCare);
+        patient_store.add_prescriptions(&primary_care, DataSource::PrimaryCare);
+
+        let history = patient_store.patients()[0].prescriptions.as_ref().unwrap();
+        assert!(history.prescriptions.is_empty());
+
+        // All the prescriptions are kept, sorted by start date
+        let history = patient_store.patients()[1].prescriptions.as_ref().unwrap();
+        let drug_names: Vec<_> = history
+            .prescriptions
+            .iter()
+            .map(|prescription| prescription.drug_name.as_str())
+            .collect();
+        assert_eq!(drug_names, vec!["Warfarin", "Aspirin", "Apixaban"]);
+        assert_eq!(
+            history.prescriptions[0].data_source,
+            Some(DataSource::PrimaryCare)
+        );
+
+        let active: Vec<_> = history
+            .active_at(make_date(11))
+            .map(|prescription| prescription.drug_name.as_str())
+            .collect();
+        assert_eq!(active, vec!["Aspirin", "Apixaban"]);
+        let oral_anticoagulants = ["Warfarin", "Apixaban", "Rivaroxaban"];
+        assert_eq!(history.of_drugs(&oral_anticoagulants).count(), 2);
+        assert!(!history
+            .of_drugs(&oral_anticoagulants)
+            .any(|prescription| prescription.is_active_at(make_date(9))));
+    }
+}
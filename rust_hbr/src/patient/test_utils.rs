@@ -0,0 +1,56 @@
+//! Fixtures shared by the tests of the patient modules
+//!
+//! The patients in the tests are built from episode records (see
+//! PatientStore::from_episodes), with the codes parsed using the
+//! example codes files in resources/test.
+
+use std::path::PathBuf;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+use super::summary::EpisodeRecord;
+use crate::clinical_code_tree::{ClinicalCodeTree, CodeParserCache};
+
+/// Make a parser for one of the codes files in resources/test
+pub(crate) fn make_parser(file_name: &str) -> CodeParserCache {
+    let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    file_path.push("resources");
+    file_path.push("test");
+    file_path.push(file_name);
+    let f = std::fs::File::open(file_path).expect("Failed to open codes file");
+    CodeParserCache::new(ClinicalCodeTree::from_reader(f).expect("Failed to parse codes file"))
+}
+
+/// Get midnight at the start of a day in January 2023
+pub(crate) fn make_date(day: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2023, 1, day, 0, 0, 0).unwrap()
+}
+
+/// Make an episode lasting one day, starting at the start date
+pub(crate) fn make_episode_at(
+    patient_id: &str,
+    spell_id: &str,
+    start: DateTime<Utc>,
+    diagnoses: &[&str],
+    procedures: &[&str],
+) -> EpisodeRecord {
+    EpisodeRecord {
+        patient_id: patient_id.to_string(),
+        spell_id: spell_id.to_string(),
+        start,
+        end: start + Duration::days(1),
+        diagnoses: diagnoses.iter().map(|code| code.to_string()).collect(),
+        procedures: procedures.iter().map(|code| code.to_string()).collect(),
+    }
+}
+
+/// Make an episode lasting one day, starting on a day in January 2023
+pub(crate) fn make_episode(
+    patient_id: &str,
+    spell_id: &str,
+    day: u32,
+    diagnoses: &[&str],
+    procedures: &[&str],
+) -> EpisodeRecord {
+    make_episode_at(patient_id, spell_id, make_date(day), diagnoses, procedures)
+}
@@ -1,18 +1,51 @@
 //! Preprocessing code for interpreting data from data sources
 //!
+//! The functions that convert one raw record (e.g.
+//! measurement_from_pathology_blood) only need the patient feature,
+//! so that they can be used by the language bindings; the functions
+//! that operate on whole tables need the ingest feature.
 
+#[cfg(feature = "ingest")]
+use polars::prelude::*;
+
+#[cfg(feature = "ingest")]
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::error::HbrError;
 use crate::patient::measurements::{Measurement, MeasurementValue};
+use crate::units::{to_canonical_unit, Analyte};
+#[cfg(feature = "ingest")]
+use crate::patient::summary::PrescriptionRecord;
+
+/// Make the error for a field that does not have the expected value
+fn unexpected_value(field: &str, expected: &str, found: String) -> HbrError {
+    HbrError::UnexpectedValue {
+        field: field.to_string(),
+        expected: expected.to_string(),
+        found,
+    }
+}
 
-/// Simple error string helper
-fn field_error_string(field_name: &str, expected: &str, found: String) -> String {
-    format!("Unexpected {} {} for {}", field_name, found, expected)
+/// The order_name of each analyte's test in the pathology_blood table
+fn pathology_blood_order_name(analyte: Analyte) -> &'static str {
+    match analyte {
+        Analyte::Haemoglobin | Analyte::Platelets => "FULL BLOOD COUNT",
+        Analyte::Creatinine | Analyte::Egfr => "UREACREAT + ELECTROLYTES",
+    }
 }
 
 /// Read a measurement from the corresponding columns of the
 /// HIC (Hospital Information Collaborative) pathology_blood
 /// table.
 ///
-///
+/// The test_name must be one of the tests in units (see
+/// Analyte::from_test_name), and the order_name must be the order that
+/// the test belongs to (FULL BLOOD COUNT for haemoglobin and platelets,
+/// UREACREAT + ELECTROLYTES for creatinine and eGFR). The test result
+/// can be an integer, a real number or a bound (e.g. >90), and is
+/// converted into the canonical unit of the test (see units), so
+/// results reported in any unit in the conversion table can be
+/// compared. An unknown unit is an error.
 pub fn measurement_from_pathology_blood(
     order_name: String,
     test_name: String,
@@ -20,35 +53,224 @@ pub fn measurement_from_pathology_blood(
     result_available: String,
     test_result: String,
     test_result_unit: String,
-) -> Result<Measurement, String> {
-    match test_name.as_ref() {
-        "Platelets" => {
-            if order_name != "FULL BLOOD COUNT" {
-                Err(field_error_string(
-                    "order_name",
-                    "FULL_BLOOD_COUNT",
-                    order_name,
-                ))
-            } else if test_result_unit != "10*9/L" {
-                Err(field_error_string(
-                    "test_result_unit",
-                    "10*9/L",
-                    test_result_unit,
-                ))
-            } else {
-                let value = MeasurementValue::from_integer_string(test_result)?;
-                let measurement_date = None;
-                let measurement_available = None;
-                let data_source = None;
-                Ok(Measurement {
-                    value,
-                    measurement_date,
-                    measurement_available,
-                    data_source,
-                })
-            }
-
-        }
-        &_ => Err(format!("Unrecognised test_name {}", test_name)),
+) -> Result<Measurement, HbrError> {
+    let Some(analyte) = Analyte::from_test_name(&test_name) else {
+        return Err(HbrError::UnrecognisedTestName(test_name));
+    };
+    let expected_order_name = pathology_blood_order_name(analyte);
+    if order_name != expected_order_name {
+        return Err(unexpected_value(
+            "order_name",
+            expected_order_name,
+            order_name,
+        ));
+    }
+    let value = MeasurementValue::from_result_string(test_result)?;
+    let value = to_canonical_unit(analyte, value, &test_result_unit)?;
+    let measurement_date = None;
+    let measurement_available = None;
+    let data_source = None;
+    Ok(Measurement {
+        value,
+        measurement_date,
+        measurement_available,
+        data_source,
+    })
+}
+
+/// Select the columns of the HIC pathology_blood table that are
+/// needed by measurement_from_pathology_blood, and keep only the rows
+/// for blood tests that can be converted into measurements.
+///
+/// The result is lazy, so when the input is a lazy synthetic table
+/// (e.g. from make_pathology_blood_lazy) or a scanned file, the selection
+/// and filter are fused with reading or generating the data instead of
+/// materialising the full table first.
+#[cfg(feature = "ingest")]
+pub fn pathology_blood_measurements_lazy(pathology_blood: LazyFrame) -> LazyFrame {
+    pathology_blood
+        .select([
+            col("subject"),
+            col("order_name"),
+            col("test_name"),
+            col("test_result"),
+            col("test_result_unit"),
+            col("sample_collected_date_time"),
+            col("result_available_date_time"),
+        ])
+        .filter(Analyte::test_names().fold(lit(false), |known_test, test_name| {
+            known_test.or(col("test_name").eq(lit(test_name)))
+        }))
+}
+
+/// Convert a datetime column into UTC dates. Naive datetimes are
+/// assumed to already be in UTC (convert local times with
+/// to_timezone_aware first).
+#[cfg(feature = "ingest")]
+pub(crate) fn utc_dates(series: &Series) -> Result<Vec<Option<DateTime<Utc>>>, HbrError> {
+    let DataType::Datetime(time_unit, _) = series.dtype() else {
+        return Err(unexpected_value(
+            &format!("type of column {}", series.name()),
+            "a datetime column",
+            series.dtype().to_string(),
+        ));
+    };
+    let time_unit = *time_unit;
+    let timestamps = series.cast(&DataType::Int64)?;
+    let dates = timestamps
+        .i64()?
+        .into_iter()
+        .map(|timestamp| {
+            timestamp.and_then(|timestamp| match time_unit {
+                TimeUnit::Milliseconds => Utc.timestamp_millis_opt(timestamp).single(),
+                TimeUnit::Microseconds => Utc.timestamp_micros(timestamp).single(),
+                TimeUnit::Nanoseconds => Some(Utc.timestamp_nanos(timestamp)),
+            })
+        })
+        .collect();
+    Ok(dates)
+}
+
+/// Convert a string column into owned strings
+#[cfg(feature = "ingest")]
+pub(crate) fn strings(series: &Series) -> Result<Vec<Option<String>>, HbrError> {
+    Ok(series
+        .utf8()?
+        .into_iter()
+        .map(|value| value.map(String::from))
+        .collect())
+}
+
+/// Read the rows of a prescriptions table (e.g. the synthetic
+/// prescriptions table) as prescription records, which can be added
+/// to patients with PatientStore::add_prescriptions.
+///
+/// The table must have the columns subject, drug_name, dose,
+/// prescription_start and prescription_stop, and can also have the
+/// columns dmd_code and route. Rows with a null subject or drug_name
+/// are left out. Returns an error if a column is missing or has the
+/// wrong type.
+#[cfg(feature = "ingest")]
+pub fn prescriptions_from_table(
+    prescriptions: &DataFrame,
+) -> Result<Vec<PrescriptionRecord>, HbrError> {
+    let optional_strings = |name: &str| match prescriptions.column(name) {
+        Ok(column) => strings(column),
+        Err(_) => Ok(vec![None; prescriptions.height()]),
+    };
+    let subject = strings(prescriptions.column("subject")?)?;
+    let drug_name = strings(prescriptions.column("drug_name")?)?;
+    let dmd_code = optional_strings("dmd_code")?;
+    let dose = strings(prescriptions.column("dose")?)?;
+    let route = optional_strings("route")?;
+    let prescription_start = utc_dates(prescriptions.column("prescription_start")?)?;
+    let prescription_stop = utc_dates(prescriptions.column("prescription_stop")?)?;
+
+    let mut records = Vec::new();
+    for n in 0..prescriptions.height() {
+        let (Some(patient_id), Some(drug_name)) = (&subject[n], &drug_name[n]) else {
+            continue;
+        };
+        records.push(PrescriptionRecord {
+            patient_id: patient_id.clone(),
+            drug_name: drug_name.clone(),
+            dmd_code: dmd_code[n].clone(),
+            dose: dose[n].clone(),
+            route: route[n].clone(),
+            prescription_start: prescription_start[n],
+            prescription_stop: prescription_stop[n],
+        });
+    }
+    Ok(records)
+}
+
+/// Tests for preprocessing raw tables
+#[cfg(all(test, feature = "ingest"))]
+mod tests {
+
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn check_pathology_blood_rows_are_selected() {
+        let date = NaiveDate::from_ymd_opt(2023, 1, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let pathology_blood = df!(
+            "subject" => ["bristol_1"; 4],
+            "order_name" => [
+                "FULL BLOOD COUNT",
+                "FULL BLOOD COUNT",
+                "UREACREAT + ELECTROLYTES",
+                "FULL BLOOD COUNT",
+            ],
+            "test_name" => ["Haemoglobin", "Basophils", "eGFR/1.73m2 (CKD-EPI)", "Platelets"],
+            "test_result" => ["130", "0.1", ">90", "250"],
+            "test_result_unit" => ["g/L", "10*9/L", "mL/min", "10*9/L"],
+            "sample_collected_date_time" => [date; 4],
+            "result_available_date_time" => [date; 4],
+            "sample_id" => ["1", "2", "3", "4"],
+        )
+        .unwrap();
+
+        let measurements = pathology_blood_measurements_lazy(pathology_blood.lazy())
+            .collect()
+            .unwrap();
+        assert_eq!(measurements.width(), 7);
+        let test_names = strings(measurements.column("test_name").unwrap()).unwrap();
+        assert_eq!(
+            test_names,
+            vec![
+                Some(String::from("Haemoglobin")),
+                Some(String::from("eGFR/1.73m2 (CKD-EPI)")),
+                Some(String::from("Platelets")),
+            ]
+        );
+    }
+
+    #[test]
+    fn check_prescriptions_from_table() {
+        let date = |day| {
+            NaiveDate::from_ymd_opt(2023, 1, day)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap()
+        };
+        let prescriptions = df!(
+            "subject" => [Some("bristol_1"), None, Some("bristol_2")],
+            "drug_name" => ["Apixaban", "Aspirin", "Warfarin"],
+            "dmd_code" => ["698090000", "387458008", "372756006"],
+            "dose" => ["5 mg", "75 mg", "3 mg"],
+            "prescription_start" => [date(1), date(2), date(3)],
+            "prescription_stop" => [Some(date(10)), None, None],
+        )
+        .unwrap();
+
+        let records = prescriptions_from_table(&prescriptions).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].patient_id, "bristol_1");
+        assert_eq!(records[0].dmd_code.as_deref(), Some("698090000"));
+        assert_eq!(records[0].route, None);
+        assert_eq!(
+            records[0].prescription_start,
+            Some(Utc.with_ymd_and_hms(2023, 1, 1, 12, 0, 0).unwrap())
+        );
+        assert_eq!(
+            records[0].prescription_stop,
+            Some(Utc.with_ymd_and_hms(2023, 1, 10, 12, 0, 0).unwrap())
+        );
+        assert_eq!(records[1].prescription_stop, None);
+
+        // The prescription dates must be datetimes
+        let wrong_type = prescriptions
+            .lazy()
+            .with_column(col("prescription_start").cast(DataType::Utf8))
+            .collect()
+            .unwrap();
+        assert!(matches!(
+            prescriptions_from_table(&wrong_type),
+            Err(HbrError::UnexpectedValue { .. })
+        ));
     }
 }
@@ -0,0 +1,399 @@
+//! The prescriptions table contains the medicines prescribed in secondary
+//! care. Each row is one prescription made during a hospital admission,
+//! with the drug (name and dm+d code), the dose, and the dates the
+//! prescription started and stopped.
+//!
+//! The drugs include the classes that are relevant to bleeding risk
+//! (antiplatelets used in dual antiplatelet therapy, oral anticoagulants
+//! and NSAIDs), along with some other common cardiovascular drugs, so
+//! that medication features can be developed against synthetic data.
+
+use crate::seeded_column_block::{make_chunks, to_polars, SeededColumnBlock, SyntheticRows};
+use crate::seeded_rng::make_rng;
+use crate::synth_data::{DurationRange, EventTiming, GeneratorVersion, PatientRegistry};
+use chrono::NaiveDateTime;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+use polars::prelude::*;
+
+/// The default length of a prescription (up to 90 days)
+pub const DEFAULT_PRESCRIPTION_DURATION: DurationRange = DurationRange {
+    min_minutes: 0,
+    max_minutes: 129600,
+};
+
+/// A drug that can appear in the prescriptions table
+struct Drug {
+    /// The name of the drug (as it appears in the drug_name column)
+    name: &'static str,
+    /// The dm+d (SNOMED CT) code of the drug substance. These are
+    /// only intended to look realistic for testing, and should not be
+    /// used as a reference for real dm+d codes.
+    dmd_code: &'static str,
+    /// The doses the drug can be prescribed at
+    doses: &'static [&'static str],
+}
+
+/// The drugs that are picked from when making a prescription. The first
+/// four are the antiplatelets (aspirin is combined with one of the other
+/// three for DAPT), followed by the oral anticoagulants, the NSAIDs, and
+/// some other common drugs.
+const DRUGS: [Drug; 14] = [
+    Drug {
+        name: "Aspirin",
+        dmd_code: "387458008",
+        doses: &["75 mg"],
+    },
+    Drug {
+        name: "Clopidogrel",
+        dmd_code: "386952008",
+        doses: &["75 mg", "300 mg", "600 mg"],
+    },
+    Drug {
+        name: "Ticagrelor",
+        dmd_code: "698805004",
+        doses: &["60 mg", "90 mg", "180 mg"],
+    },
+    Drug {
+        name: "Prasugrel",
+        dmd_code: "443129001",
+        doses: &["5 mg", "10 mg", "60 mg"],
+    },
+    Drug {
+        name: "Warfarin",
+        dmd_code: "372756006",
+        doses: &["1 mg", "3 mg", "5 mg"],
+    },
+    Drug {
+        name: "Apixaban",
+        dmd_code: "698090000",
+        doses: &["2.5 mg", "5 mg"],
+    },
+    Drug {
+        name: "Rivaroxaban",
+        dmd_code: "442031002",
+        doses: &["15 mg", "20 mg"],
+    },
+    Drug {
+        name: "Ibuprofen",
+        dmd_code: "387207008",
+        doses: &["200 mg", "400 mg"],
+    },
+    Drug {
+        name: "Naproxen",
+        dmd_code: "372588000",
+        doses: &["250 mg", "500 mg"],
+    },
+    Drug {
+        name: "Diclofenac",
+        dmd_code: "7034005",
+        doses: &["50 mg", "75 mg"],
+    },
+    Drug {
+        name: "Atorvastatin",
+        dmd_code: "373444002",
+        doses: &["20 mg", "40 mg", "80 mg"],
+    },
+    Drug {
+        name: "Bisoprolol",
+        dmd_code: "386868003",
+        doses: &["1.25 mg", "2.5 mg", "5 mg"],
+    },
+    Drug {
+        name: "Ramipril",
+        dmd_code: "386872004",
+        doses: &["2.5 mg", "5 mg", "10 mg"],
+    },
+    Drug {
+        name: "Omeprazole",
+        dmd_code: "387137007",
+        doses: &["20 mg", "40 mg"],
+    },
+];
+
+/// Make an admission id (format "admission_nnnnnnn")
+fn make_admission_id(rng: &mut ChaCha8Rng) -> String {
+    let admission_id = rng.gen_range(1..=5000000);
+    format!("admission_{admission_id}")
+}
+
+/// Pick a drug uniformly at random, and one of its doses
+fn make_drug_and_dose(rng: &mut ChaCha8Rng) -> (&'static Drug, &'static str) {
+    let drug = DRUGS.choose(rng).expect("List of drugs is not empty");
+    let dose = drug.doses.choose(rng).expect("Every drug has a dose");
+    (drug, dose)
+}
+
+/// Make the prescription start and stop times, using the window and
+/// duration range in the timing. A fifth of prescriptions have not
+/// stopped (the stop date is None).
+fn make_prescription_times(
+    rng: &mut ChaCha8Rng,
+    timing: &EventTiming,
+) -> (NaiveDateTime, Option<NaiveDateTime>) {
+    let (prescription_start, prescription_stop) = timing.pick(rng);
+    let prescription_stop = if rng.gen_range(0..5) == 0 {
+        None
+    } else {
+        Some(prescription_stop)
+    };
+    (prescription_start, prescription_stop)
+}
+
+/// One row of the prescriptions table
+#[derive(Debug)]
+pub struct PrescriptionsRow {
+    pub subject: String,
+    pub admission_id: String,
+    pub drug_name: String,
+    pub dmd_code: String,
+    pub dose: String,
+    pub prescription_start: NaiveDateTime,
+    pub prescription_stop: Option<NaiveDateTime>,
+}
+
+/// Iterator over the rows of the prescriptions table (see
+/// PathologyBloodRows for how the column blocks are seeded)
+pub struct PrescriptionsRows {
+    registry: PatientRegistry,
+    timing: EventTiming,
+    subject_rng: ChaCha8Rng,
+    admission_rng: ChaCha8Rng,
+    drug_rng: ChaCha8Rng,
+    prescription_time_rng: ChaCha8Rng,
+    rows_left: usize,
+}
+
+impl PrescriptionsRows {
+    /// Make an iterator that will return num_rows rows. The arguments
+    /// have the same meaning as in make_prescriptions.
+    pub fn new(
+        block_id: &str,
+        registry: &PatientRegistry,
+        timing: EventTiming,
+        num_rows: usize,
+    ) -> Self {
+        let global_seed = registry.global_seed();
+        let subject_block_id = format!("{block_id}subject");
+        let admission_block_id = format!("{block_id}admission");
+        let drug_block_id = format!("{block_id}drug");
+        let prescription_time_block_id = format!("{block_id}prescription_time");
+        Self {
+            registry: registry.clone(),
+            timing,
+            subject_rng: make_rng(global_seed, subject_block_id.as_ref()),
+            admission_rng: make_rng(global_seed, admission_block_id.as_ref()),
+            drug_rng: make_rng(global_seed, drug_block_id.as_ref()),
+            prescription_time_rng: make_rng(global_seed, prescription_time_block_id.as_ref()),
+            rows_left: num_rows,
+        }
+    }
+}
+
+impl Iterator for PrescriptionsRows {
+    type Item = PrescriptionsRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rows_left == 0 {
+            return None;
+        }
+        self.rows_left -= 1;
+
+        let (drug, dose) = make_drug_and_dose(&mut self.drug_rng);
+        let (prescription_start, prescription_stop) =
+            make_prescription_times(&mut self.prescription_time_rng, &self.timing);
+        Some(PrescriptionsRow {
+            subject: self.registry.sample(&mut self.subject_rng).subject.clone(),
+            admission_id: make_admission_id(&mut self.admission_rng),
+            drug_name: String::from(drug.name),
+            dmd_code: String::from(drug.dmd_code),
+            dose: String::from(dose),
+            prescription_start,
+            prescription_stop,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.rows_left, Some(self.rows_left))
+    }
+}
+
+impl SyntheticRows for PrescriptionsRows {
+    fn take_chunk(&mut self, chunk_size: usize) -> DataFrame {
+        let num_rows = chunk_size.min(self.rows_left);
+        self.rows_left -= num_rows;
+        let Self {
+            registry,
+            timing,
+            subject_rng,
+            admission_rng,
+            drug_rng,
+            prescription_time_rng,
+            ..
+        } = self;
+
+        let ((subject, admission_id), (drugs, prescription_times)) = rayon::join(
+            || {
+                rayon::join(
+                    || {
+                        let subject: Vec<_> = (0..num_rows)
+                            .map(|_| registry.sample(subject_rng).subject.as_str())
+                            .collect();
+                        Series::new("subject", subject)
+                    },
+                    || {
+                        let admission_id: Vec<_> = (0..num_rows)
+                            .map(|_| make_admission_id(admission_rng))
+                            .collect();
+                        Series::new("admission_id", admission_id)
+                    },
+                )
+            },
+            || {
+                rayon::join(
+                    || {
+                        (0..num_rows)
+                            .map(|_| make_drug_and_dose(drug_rng))
+                            .collect::<Vec<_>>()
+                    },
+                    || {
+                        (0..num_rows)
+                            .map(|_| make_prescription_times(prescription_time_rng, timing))
+                            .collect::<Vec<_>>()
+                    },
+                )
+            },
+        );
+
+        let drug_name: Vec<_> = drugs.iter().map(|(drug, _)| drug.name).collect();
+        let dmd_code: Vec<_> = drugs.iter().map(|(drug, _)| drug.dmd_code).collect();
+        let dose: Vec<_> = drugs.iter().map(|(_, dose)| *dose).collect();
+        let (prescription_start, prescription_stop): (Vec<_>, Vec<_>) =
+            prescription_times.into_iter().unzip();
+
+        let seeded_column_blocks = vec![
+            SeededColumnBlock {
+                columns: vec![subject],
+            },
+            SeededColumnBlock {
+                columns: vec![admission_id],
+            },
+            SeededColumnBlock {
+                columns: vec![
+                    Series::new("drug_name", drug_name),
+                    Series::new("dmd_code", dmd_code),
+                    Series::new("dose", dose),
+                ],
+            },
+            SeededColumnBlock {
+                columns: vec![
+                    Series::new("prescription_start", prescription_start),
+                    Series::new("prescription_stop", prescription_stop),
+                ],
+            },
+        ];
+        to_polars(seeded_column_blocks)
+    }
+}
+
+/// Create the secondary-care prescriptions table. The drugs include:
+///
+/// * antiplatelets (aspirin, clopidogrel, ticagrelor, prasugrel)
+/// * oral anticoagulants (warfarin, apixaban, rivaroxaban)
+/// * NSAIDs (ibuprofen, naproxen, diclofenac)
+/// * other common drugs (atorvastatin, bisoprolol, ramipril, omeprazole)
+///
+/// The prescriptions start at times picked from the window in the
+/// timing, and last for a duration picked from its duration range (by
+/// default, up to DEFAULT_PRESCRIPTION_DURATION).
+pub fn make_prescriptions(
+    block_id: &str,
+    registry: &PatientRegistry,
+    version: GeneratorVersion,
+    timing: EventTiming,
+    num_rows: usize,
+) -> DataFrame {
+    let global_seed = registry.global_seed();
+    let _span =
+        tracing::debug_span!("make_prescriptions", block_id, global_seed, num_rows).entered();
+    match version {
+        GeneratorVersion::V1 => {
+            PrescriptionsRows::new(block_id, registry, timing, num_rows).take_chunk(num_rows)
+        }
+    }
+}
+
+/// Create the prescriptions table in chunks of (at most) chunk_size rows
+/// (see the synth_data module documentation).
+pub fn make_prescriptions_chunks(
+    block_id: &str,
+    registry: &PatientRegistry,
+    version: GeneratorVersion,
+    timing: EventTiming,
+    num_rows: usize,
+    chunk_size: usize,
+) -> impl Iterator<Item = DataFrame> {
+    let global_seed = registry.global_seed();
+    let span = tracing::debug_span!(
+        "make_prescriptions_chunks",
+        block_id,
+        global_seed,
+        num_rows,
+        chunk_size
+    );
+    let rows = match version {
+        GeneratorVersion::V1 => PrescriptionsRows::new(block_id, registry, timing, num_rows),
+    };
+    make_chunks(rows, chunk_size, span)
+}
+
+/// Tests for the prescriptions table
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn check_prescriptions_are_reproducible() {
+        let registry = PatientRegistry::new(0, 100);
+        let timing = EventTiming::with_duration(DEFAULT_PRESCRIPTION_DURATION);
+        let prescriptions = make_prescriptions(
+            "prescriptions",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            1000,
+        );
+        assert_eq!(prescriptions.height(), 1000);
+        assert!(prescriptions.frame_equal_missing(&make_prescriptions(
+            "prescriptions",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            1000
+        )));
+
+        // The rows do not depend on how many rows are generated
+        let first_rows =
+            make_prescriptions("prescriptions", &registry, GeneratorVersion::V1, timing, 10);
+        assert!(first_rows.frame_equal_missing(&prescriptions.head(Some(10))));
+
+        // The dataframe contains the same rows as the iterator
+        let rows: Vec<_> = PrescriptionsRows::new("prescriptions", &registry, timing, 10).collect();
+        let subject = first_rows.column("subject").unwrap().utf8().unwrap();
+        let dose = first_rows.column("dose").unwrap().utf8().unwrap();
+        for (n, row) in rows.iter().enumerate() {
+            assert_eq!(subject.get(n), Some(row.subject.as_str()));
+            assert_eq!(dose.get(n), Some(row.dose.as_str()));
+        }
+
+        // All the drugs appear in a table this size
+        let drug_names = prescriptions
+            .column("drug_name")
+            .unwrap()
+            .n_unique()
+            .unwrap();
+        assert_eq!(drug_names, DRUGS.len());
+    }
+}
@@ -0,0 +1,51 @@
+//! JSON Schema export for the main data structures
+//!
+//! The schemas are generated from the Rust types (using schemars), so
+//! they always match what the Rust code serializes and deserializes.
+//! They are intended for the Python and R sides (and anyone else) to
+//! validate documents against, without having to read the Rust code.
+//!
+//! Note that dates inside Patient are stored as BSON dates in MongoDB;
+//! in the JSON schema they are described as RFC 3339 date-time strings.
+
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+
+use crate::clinical_code_tree::ClinicalCodeTree;
+#[cfg(feature = "patient")]
+use crate::patient::{measurements::Measurement, spell::episode::Episode, spell::Spell, Patient};
+
+/// The names of the types that have a JSON schema, as accepted
+/// by json_schema(). The codes_file schema describes the format
+/// of the codes files (e.g. icd10.yaml).
+#[cfg(feature = "patient")]
+pub const SCHEMA_NAMES: [&str; 5] = ["patient", "spell", "episode", "measurement", "codes_file"];
+
+/// Without the patient feature, only the codes file schema is available
+#[cfg(not(feature = "patient"))]
+pub const SCHEMA_NAMES: [&str; 1] = ["codes_file"];
+
+/// Get the JSON schema for one of the types in SCHEMA_NAMES, or
+/// None if the name is not recognised.
+pub fn json_schema(name: &str) -> Option<RootSchema> {
+    match name {
+        #[cfg(feature = "patient")]
+        "patient" => Some(schema_for!(Patient)),
+        #[cfg(feature = "patient")]
+        "spell" => Some(schema_for!(Spell)),
+        #[cfg(feature = "patient")]
+        "episode" => Some(schema_for!(Episode)),
+        #[cfg(feature = "patient")]
+        "measurement" => Some(schema_for!(Measurement)),
+        "codes_file" => Some(schema_for!(ClinicalCodeTree)),
+        _ => None,
+    }
+}
+
+/// Get the JSON schema for one of the types in SCHEMA_NAMES
+/// as a pretty-printed JSON string.
+pub fn json_schema_string(name: &str) -> Option<String> {
+    let schema = json_schema(name)?;
+    let string = serde_json::to_string_pretty(&schema).expect("Failed to convert schema to JSON");
+    Some(string)
+}
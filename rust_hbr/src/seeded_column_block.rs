@@ -33,3 +33,45 @@ pub fn to_polars(
         .collect();
     DataFrame::new(columns).expect("Failed to create dataframe")
 }
+
+/// The rows of a synthetic table, which can also be generated a chunk
+/// at a time as a dataframe. The size hint of the iterator must be
+/// exact.
+pub trait SyntheticRows: Iterator {
+    /// Generate the next chunk_size rows (or all the rows left, if
+    /// there are fewer) as a dataframe. The rows are the same as the
+    /// ones the iterator would return, but each seeded column block is
+    /// generated in full on its own thread (from the rayon thread pool),
+    /// which is possible because each block only uses its own generator.
+    fn take_chunk(&mut self, chunk_size: usize) -> DataFrame;
+
+    /// The number of rows that have not been generated yet
+    fn rows_left(&self) -> usize {
+        self.size_hint().0
+    }
+}
+
+/// Generate all the rows in chunks of (at most) chunk_size rows, inside
+/// the span (which records the table and its arguments). This is how
+/// all the make_*_chunks functions are implemented (see the synth_data
+/// module documentation). A chunk_size of zero means no limit, so all
+/// the rows are generated in one chunk.
+pub fn make_chunks<R: SyntheticRows>(
+    mut rows: R,
+    chunk_size: usize,
+    span: tracing::Span,
+) -> impl Iterator<Item = DataFrame> {
+    let chunk_size = match chunk_size {
+        0 => rows.rows_left(),
+        chunk_size => chunk_size,
+    };
+    std::iter::from_fn(move || {
+        let _entered = span.enter();
+        if rows.rows_left() == 0 {
+            None
+        } else {
+            tracing::trace!(rows_left = rows.rows_left(), "generating chunk");
+            Some(rows.take_chunk(chunk_size))
+        }
+    })
+}
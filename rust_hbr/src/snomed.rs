@@ -0,0 +1,399 @@
+//! SNOMED CT concept hierarchy
+//!
+//! SNOMED CT is used for coding in primary care. Unlike ICD-10 and
+//! OPCS-4, concepts are not arranged in a tree: each concept can have
+//! several parents (is-a relationships), so the hierarchy is a directed
+//! acyclic graph. For this reason SNOMED CT is stored in its own structure,
+//! SnomedHierarchy, instead of a ClinicalCodeTree.
+//!
+//! The hierarchy is loaded from an RF2 snapshot release (the concept,
+//! description and relationship files). Only active concepts and active
+//! is-a relationships are kept. The description of each concept is its
+//! fully specified name.
+//!
+//! Code groups are defined by tagging one or more concepts with a group
+//! name: the group then contains those concepts and all their descendants
+//! (less the descendants of any excluded concepts). Matching a concept
+//! with find_exact returns the groups that contain it, in the same way as
+//! for a ClinicalCodeTree.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::clinical_code::{ClinicalCodeRef, ClinicalCodeStore};
+use crate::clinical_code_tree::CodeMatch;
+use crate::error::HbrError;
+
+/// The concept id of the is-a relationship type
+const IS_A: u64 = 116680003;
+
+/// The concept id of the fully specified name description type
+const FULLY_SPECIFIED_NAME: u64 = 900000000000003001;
+
+/// A hierarchy of SNOMED CT concepts loaded from an RF2 snapshot
+#[derive(Debug, Default)]
+pub struct SnomedHierarchy {
+    /// Map from the active concept ids to their fully specified name
+    concepts: HashMap<u64, String>,
+    /// Map from concept id to the ids of its children
+    children: HashMap<u64, Vec<u64>>,
+    /// Map from concept id to the ids of its parents
+    parents: HashMap<u64, Vec<u64>>,
+    /// Map from group name to the set of concepts in the group
+    groups: HashMap<String, HashSet<u64>>,
+}
+
+/// Read the rows of an RF2 file (tab-separated with a header),
+/// keeping only the active rows, and passing the fields of each
+/// row to the function f.
+fn read_active_rows<R, F>(reader: R, mut f: F) -> Result<(), HbrError>
+where
+    R: Read,
+    F: FnMut(&Vec<&str>) -> Result<(), HbrError>,
+{
+    let mut lines = BufReader::new(reader).lines();
+    let header = lines
+        .next()
+        .ok_or(HbrError::InvalidCodeList(String::from("missing header row")))??;
+    let active_column = header
+        .split('\t')
+        .position(|column| column == "active")
+        .ok_or(HbrError::InvalidCodeList(String::from("missing active column")))?;
+    for line in lines {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.get(active_column) == Some(&"1") {
+            f(&fields)?;
+        }
+    }
+    Ok(())
+}
+
+/// Parse the field at position n of an RF2 row as a SNOMED CT id
+fn id_field(fields: &Vec<&str>, n: usize) -> Result<u64, HbrError> {
+    fields
+        .get(n)
+        .and_then(|field| field.parse().ok())
+        .ok_or(HbrError::InvalidCodeList(format!(
+            "expected a SNOMED CT id in column {n}: {}",
+            fields.join("\t")
+        )))
+}
+
+/// Find the file in a directory whose name starts with prefix
+fn find_file_with_prefix(dir: &Path, prefix: &str) -> Result<File, HbrError> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_match = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(prefix));
+        if is_match {
+            return Ok(File::open(path)?);
+        }
+    }
+    Err(HbrError::InvalidCodeList(format!(
+        "no file starting with {prefix} in {}",
+        dir.display()
+    )))
+}
+
+impl SnomedHierarchy {
+    /// Load the hierarchy from the concept, description and relationship
+    /// files of an RF2 snapshot
+    ///
+    /// Relationships between inactive concepts, and concepts without a
+    /// fully specified name, are ignored. Returns an error if any of
+    /// the files cannot be read or are not in RF2 format.
+    pub fn from_rf2_readers<C, D, R>(
+        concepts: C,
+        descriptions: D,
+        relationships: R,
+    ) -> Result<Self, HbrError>
+    where
+        C: Read,
+        D: Read,
+        R: Read,
+    {
+        // Concept file: id, effectiveTime, active, moduleId, definitionStatusId
+        let mut active = HashSet::new();
+        read_active_rows(concepts, |fields| {
+            active.insert(id_field(fields, 0)?);
+            Ok(())
+        })?;
+
+        // Description file: id, effectiveTime, active, moduleId, conceptId,
+        // languageCode, typeId, term, caseSignificanceId
+        let mut hierarchy = Self::default();
+        read_active_rows(descriptions, |fields| {
+            let concept_id = id_field(fields, 4)?;
+            if id_field(fields, 6)? == FULLY_SPECIFIED_NAME && active.contains(&concept_id) {
+                let term = fields.get(7).copied().unwrap_or_default();
+                hierarchy.concepts.insert(concept_id, term.to_string());
+            }
+            Ok(())
+        })?;
+
+        // Relationship file: id, effectiveTime, active, moduleId, sourceId,
+        // destinationId, relationshipGroup, typeId, ...
+        read_active_rows(relationships, |fields| {
+            if id_field(fields, 7)? == IS_A {
+                let child = id_field(fields, 4)?;
+                let parent = id_field(fields, 5)?;
+                if hierarchy.concepts.contains_key(&child)
+                    && hierarchy.concepts.contains_key(&parent)
+                {
+                    hierarchy.children.entry(parent).or_default().push(child);
+                    hierarchy.parents.entry(child).or_default().push(parent);
+                }
+            }
+            Ok(())
+        })?;
+
+        Ok(hierarchy)
+    }
+
+    /// Load the hierarchy from the folder containing the RF2 snapshot
+    /// files (the Snapshot/Terminology folder of a release), which are
+    /// found by the standard file name prefixes.
+    pub fn from_rf2_snapshot<P: AsRef<Path>>(dir: P) -> Result<Self, HbrError> {
+        let dir = dir.as_ref();
+        Self::from_rf2_readers(
+            find_file_with_prefix(dir, "sct2_Concept_Snapshot")?,
+            find_file_with_prefix(dir, "sct2_Description_Snapshot")?,
+            find_file_with_prefix(dir, "sct2_Relationship_Snapshot")?,
+        )
+    }
+
+    /// Get the number of (active) concepts in the hierarchy
+    pub fn num_concepts(&self) -> usize {
+        self.concepts.len()
+    }
+
+    /// Get the fully specified name of a concept, or None if the
+    /// concept is not in the hierarchy
+    pub fn docs(&self, concept_id: u64) -> Option<&String> {
+        self.concepts.get(&concept_id)
+    }
+
+    /// Get the direct parents of a concept
+    pub fn parents(&self, concept_id: u64) -> &[u64] {
+        self.parents.get(&concept_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Get the direct children of a concept
+    pub fn children(&self, concept_id: u64) -> &[u64] {
+        self.children.get(&concept_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Get all the descendants of a concept (its children, their
+    /// children, and so on), not including the concept itself. Each
+    /// descendant appears once, even if it can be reached by more
+    /// than one path. The result is sorted by concept id.
+    pub fn descendants(&self, concept_id: u64) -> Vec<u64> {
+        let mut found = HashSet::new();
+        let mut to_visit = self.children(concept_id).to_vec();
+        while let Some(next) = to_visit.pop() {
+            if found.insert(next) {
+                to_visit.extend_from_slice(self.children(next));
+            }
+        }
+        let mut descendants: Vec<u64> = found.into_iter().collect();
+        descendants.sort();
+        descendants
+    }
+
+    /// Define a code group containing the included concepts and all
+    /// their descendants, apart from the excluded concepts and their
+    /// descendants. Tagging the same group again replaces it.
+    ///
+    /// Returns an error if any of the concepts are not in the hierarchy.
+    pub fn tag_group(
+        &mut self,
+        group: &str,
+        include: &[u64],
+        exclude: &[u64],
+    ) -> Result<(), HbrError> {
+        let expand = |roots: &[u64]| -> Result<HashSet<u64>, HbrError> {
+            let mut concepts = HashSet::new();
+            for root in roots {
+                if !self.concepts.contains_key(root) {
+                    return Err(HbrError::CodeNotFound(root.to_string()));
+                }
+                concepts.insert(*root);
+                concepts.extend(self.descendants(*root));
+            }
+            Ok(concepts)
+        };
+        let excluded = expand(exclude)?;
+        let members = expand(include)?
+            .into_iter()
+            .filter(|concept| !excluded.contains(concept))
+            .collect();
+        self.groups.insert(group.to_string(), members);
+        Ok(())
+    }
+
+    /// Get the names of the groups defined in the hierarchy
+    pub fn groups(&self) -> HashSet<String> {
+        self.groups.keys().cloned().collect()
+    }
+
+    /// Get a reference to a concept (stored in the code store with
+    /// the id as the name and the fully specified name as the docs)
+    fn concept_ref(&self, concept_id: u64, code_store: &mut ClinicalCodeStore) -> ClinicalCodeRef {
+        let docs = &self.concepts[&concept_id];
+        code_store.clinical_code_ref_from_parts(&concept_id.to_string(), docs)
+    }
+
+    /// Get all the concepts in a group, sorted by concept id, or
+    /// return an error if the group is not defined.
+    pub fn codes_in_group(
+        &self,
+        group: &str,
+        code_store: &mut ClinicalCodeStore,
+    ) -> Result<Vec<ClinicalCodeRef>, HbrError> {
+        let members = self
+            .groups
+            .get(group)
+            .ok_or_else(|| HbrError::UnknownGroup(group.to_string()))?;
+        let mut concept_ids: Vec<u64> = members.iter().copied().collect();
+        concept_ids.sort();
+        Ok(concept_ids
+            .into_iter()
+            .map(|concept_id| self.concept_ref(concept_id, code_store))
+            .collect())
+    }
+
+    /// Find a concept by its id (surrounding whitespace is ignored),
+    /// returning the concept and the groups that contain it, or an
+    /// error if the concept is not an active concept in the hierarchy.
+    pub fn find_exact(
+        &self,
+        code: &str,
+        code_store: &mut ClinicalCodeStore,
+    ) -> Result<CodeMatch, HbrError> {
+        let concept_id: u64 = code
+            .trim()
+            .parse()
+            .ok()
+            .filter(|concept_id| self.concepts.contains_key(concept_id))
+            .ok_or_else(|| HbrError::CodeNotFound(code.to_string()))?;
+        let groups = self
+            .groups
+            .iter()
+            .filter(|(_, members)| members.contains(&concept_id))
+            .map(|(group, _)| group.clone())
+            .collect();
+        let code = self.concept_ref(concept_id, code_store);
+        code_store.record_groups(&code, &groups);
+        Ok(CodeMatch::new(code, groups, true))
+    }
+}
+
+/// Tests for the SNOMED CT hierarchy
+///
+/// The RF2 files are small extracts with the same columns as
+/// a real snapshot.
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::name;
+
+    const CONCEPTS: &str = "id\teffectiveTime\tactive\tmoduleId\tdefinitionStatusId\n\
+        22298006\t20020131\t1\t900000000000207008\t900000000000074008\n\
+        57054005\t20020131\t1\t900000000000207008\t900000000000073002\n\
+        401303003\t20020131\t1\t900000000000207008\t900000000000073002\n\
+        401314000\t20020131\t1\t900000000000207008\t900000000000073002\n\
+        304914007\t20020131\t1\t900000000000207008\t900000000000073002\n\
+        1000001\t20020131\t0\t900000000000207008\t900000000000074008\n";
+
+    const DESCRIPTIONS: &str = "id\teffectiveTime\tactive\tmoduleId\tconceptId\tlanguageCode\ttypeId\tterm\tcaseSignificanceId\n\
+        1\t20020131\t1\t900000000000207008\t22298006\ten\t900000000000003001\tMyocardial infarction (disorder)\t900000000000448009\n\
+        2\t20020131\t1\t900000000000207008\t22298006\ten\t900000000000013009\tHeart attack\t900000000000448009\n\
+        3\t20020131\t1\t900000000000207008\t57054005\ten\t900000000000003001\tAcute myocardial infarction (disorder)\t900000000000448009\n\
+        4\t20020131\t1\t900000000000207008\t401303003\ten\t900000000000003001\tAcute ST segment elevation myocardial infarction (disorder)\t900000000000448009\n\
+        5\t20020131\t1\t900000000000207008\t401314000\ten\t900000000000003001\tAcute non-ST segment elevation myocardial infarction (disorder)\t900000000000448009\n\
+        6\t20020131\t1\t900000000000207008\t304914007\ten\t900000000000003001\tAcute Q wave myocardial infarction (disorder)\t900000000000448009\n\
+        7\t20020131\t1\t900000000000207008\t1000001\ten\t900000000000003001\tInactive concept (disorder)\t900000000000448009\n";
+
+    const RELATIONSHIPS: &str = "id\teffectiveTime\tactive\tmoduleId\tsourceId\tdestinationId\trelationshipGroup\ttypeId\tcharacteristicTypeId\tmodifierId\n\
+        11\t20020131\t1\t900000000000207008\t57054005\t22298006\t0\t116680003\t900000000000011006\t900000000000451002\n\
+        12\t20020131\t1\t900000000000207008\t401303003\t57054005\t0\t116680003\t900000000000011006\t900000000000451002\n\
+        13\t20020131\t1\t900000000000207008\t401314000\t57054005\t0\t116680003\t900000000000011006\t900000000000451002\n\
+        14\t20020131\t1\t900000000000207008\t304914007\t57054005\t0\t116680003\t900000000000011006\t900000000000451002\n\
+        15\t20020131\t1\t900000000000207008\t304914007\t401303003\t0\t116680003\t900000000000011006\t900000000000451002\n\
+        16\t20020131\t0\t900000000000207008\t401314000\t22298006\t0\t116680003\t900000000000011006\t900000000000451002\n";
+
+    fn example_hierarchy() -> SnomedHierarchy {
+        SnomedHierarchy::from_rf2_readers(
+            CONCEPTS.as_bytes(),
+            DESCRIPTIONS.as_bytes(),
+            RELATIONSHIPS.as_bytes(),
+        )
+        .expect("Should load hierarchy")
+    }
+
+    #[test]
+    fn check_rf2_snapshot_loads() {
+        let hierarchy = example_hierarchy();
+        assert_eq!(hierarchy.num_concepts(), 5);
+        assert_eq!(
+            hierarchy.docs(22298006).expect("Concept should be present"),
+            "Myocardial infarction (disorder)"
+        );
+        assert!(hierarchy.docs(1000001).is_none());
+
+        // Q wave MI has two parents; the inactive relationship is ignored
+        assert_eq!(hierarchy.parents(304914007).len(), 2);
+        assert_eq!(hierarchy.parents(401314000), &[57054005]);
+    }
+
+    #[test]
+    fn check_descendants_are_unique() {
+        let hierarchy = example_hierarchy();
+        assert_eq!(
+            hierarchy.descendants(22298006),
+            vec![57054005, 304914007, 401303003, 401314000]
+        );
+        assert!(hierarchy.descendants(304914007).is_empty());
+    }
+
+    #[test]
+    fn check_group_tagging() {
+        let mut hierarchy = example_hierarchy();
+        let mut code_store = ClinicalCodeStore::new();
+        hierarchy
+            .tag_group("acute_mi", &[57054005], &[])
+            .expect("Concepts should be present");
+        hierarchy
+            .tag_group("stemi", &[401303003], &[])
+            .expect("Concepts should be present");
+        hierarchy
+            .tag_group("acute_mi_not_stemi", &[57054005], &[401303003])
+            .expect("Concepts should be present");
+
+        let code_match = hierarchy
+            .find_exact(" 304914007 ", &mut code_store)
+            .expect("Concept should be present");
+        assert_eq!(name!(code_match.code(), code_store), "304914007");
+        assert!(code_match.in_group("acute_mi"));
+        assert!(code_match.in_group("stemi"));
+        assert!(!code_match.in_group("acute_mi_not_stemi"));
+
+        let codes = hierarchy
+            .codes_in_group("acute_mi_not_stemi", &mut code_store)
+            .expect("Group should exist");
+        assert_eq!(codes.len(), 2);
+
+        assert!(hierarchy.find_exact("1000001", &mut code_store).is_err());
+        assert!(hierarchy.find_exact("I21.9", &mut code_store).is_err());
+        assert!(hierarchy.tag_group("unknown", &[1000001], &[]).is_err());
+    }
+}
@@ -0,0 +1,412 @@
+//! The attributes table is modelled on the system-wide dataset (SWD)
+//! primary-care attributes, which record the state of each patient
+//! once per month. Each row is one patient in one month, with their
+//! smoking status, blood pressure (if it was measured that month), and
+//! flags for the QOF conditions they have been diagnosed with.
+//!
+//! Unlike the other synthetic tables, the subject is not random: the
+//! rows contain MONTHS_PER_PATIENT consecutive months for each patient
+//! in the registry in turn (bristol_1, bristol_2, ...), so that each
+//! patient appears exactly once per month. The smoking status and
+//! conditions carry over from one month to the next for the same
+//! patient. Patients with CKD in the registry (see PatientConditions)
+//! always have the CKD flag set.
+
+use crate::seeded_column_block::{make_chunks, to_polars, SeededColumnBlock, SyntheticRows};
+use crate::seeded_rng::make_rng;
+use crate::synth_data::{GeneratorVersion, PatientRegistry, SyntheticPatient};
+use chrono::{Months, NaiveDate, NaiveDateTime};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+use polars::prelude::*;
+
+/// The number of months of attributes for each patient
+pub const MONTHS_PER_PATIENT: usize = 12;
+
+/// The month of the first row for each patient (the following
+/// months are consecutive)
+fn first_attribute_period() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2023, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+}
+
+/// The QOF condition flags recorded in the table (in column order)
+const QOF_CONDITIONS: [&str; 5] = [
+    "hypertension",
+    "diabetes",
+    "atrial_fibrillation",
+    "ckd",
+    "heart_failure",
+];
+
+/// Smoking status of a patient
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SmokingStatus {
+    Never,
+    Ex,
+    Current,
+}
+
+impl SmokingStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Never => "never",
+            Self::Ex => "ex",
+            Self::Current => "current",
+        }
+    }
+}
+
+/// Pick the smoking status of a new patient (roughly 55% never,
+/// 30% ex and 15% current smokers)
+fn make_smoking_status(rng: &mut ChaCha8Rng) -> SmokingStatus {
+    match rng.gen_range(0..20) {
+        0..=10 => SmokingStatus::Never,
+        11..=16 => SmokingStatus::Ex,
+        _ => SmokingStatus::Current,
+    }
+}
+
+/// Update the smoking status from one month to the next. Current
+/// smokers occasionally quit, and ex-smokers occasionally restart.
+fn next_smoking_status(rng: &mut ChaCha8Rng, status: SmokingStatus) -> SmokingStatus {
+    let change = rng.gen_range(0..100) == 0;
+    match (status, change) {
+        (SmokingStatus::Current, true) => SmokingStatus::Ex,
+        (SmokingStatus::Ex, true) => SmokingStatus::Current,
+        (status, _) => status,
+    }
+}
+
+/// Update the smoking status for a row of the table. A new patient
+/// starts every MONTHS_PER_PATIENT rows, and otherwise the status
+/// carries over from the previous month.
+fn update_smoking_status(rng: &mut ChaCha8Rng, status: &mut SmokingStatus, row: usize) {
+    let month = row % MONTHS_PER_PATIENT;
+    *status = if month == 0 {
+        make_smoking_status(rng)
+    } else {
+        next_smoking_status(rng, *status)
+    };
+}
+
+/// Update the QOF condition flags for a row of the table. For a new
+/// patient, each condition is already present at the start with
+/// probability 1/10. For the same patient, conditions are never
+/// resolved, and each one is diagnosed with probability 1/200 per
+/// month. The CKD flag is always set if the patient has CKD.
+fn update_qof_conditions(
+    rng: &mut ChaCha8Rng,
+    conditions: &mut [bool; 5],
+    patient: &SyntheticPatient,
+    row: usize,
+) {
+    let month = row % MONTHS_PER_PATIENT;
+    for condition in conditions.iter_mut() {
+        if month == 0 {
+            *condition = rng.gen_range(0..10) == 0;
+        } else {
+            *condition |= rng.gen_range(0..200) == 0;
+        }
+    }
+    // CKD is the fourth flag in QOF_CONDITIONS
+    conditions[3] |= patient.conditions.ckd;
+}
+
+/// The month that a row of the table applies to
+fn attribute_period(row: usize) -> NaiveDateTime {
+    let month = row % MONTHS_PER_PATIENT;
+    first_attribute_period()
+        .checked_add_months(Months::new(month as u32))
+        .unwrap()
+}
+
+/// Make the systolic and diastolic blood pressure (in mmHg), which
+/// is only measured in a quarter of months. Blood pressure is higher
+/// for patients with hypertension.
+fn make_blood_pressure(rng: &mut ChaCha8Rng, hypertension: bool) -> (Option<i64>, Option<i64>) {
+    let offset = if hypertension { 20 } else { 0 };
+    let systolic = rng.gen_range(100..150) + offset;
+    let diastolic = rng.gen_range(60..90) + offset / 2;
+    if rng.gen_range(0..4) == 0 {
+        (Some(systolic), Some(diastolic))
+    } else {
+        (None, None)
+    }
+}
+
+/// One row of the attributes table
+#[derive(Debug)]
+pub struct SwdAttributesRow {
+    pub subject: String,
+    pub attribute_period: NaiveDateTime,
+    pub smoking_status: String,
+    pub systolic_bp: Option<i64>,
+    pub diastolic_bp: Option<i64>,
+    /// The QOF condition flags, in the order of QOF_CONDITIONS
+    pub qof_conditions: [bool; 5],
+}
+
+/// Iterator over the rows of the attributes table. The state of the
+/// current patient is kept between rows, and a new patient is started
+/// every MONTHS_PER_PATIENT rows.
+pub struct SwdAttributesRows {
+    registry: PatientRegistry,
+    smoking_rng: ChaCha8Rng,
+    blood_pressure_rng: ChaCha8Rng,
+    qof_rng: ChaCha8Rng,
+    row: usize,
+    num_rows: usize,
+    smoking_status: SmokingStatus,
+    qof_conditions: [bool; 5],
+}
+
+impl SwdAttributesRows {
+    /// Make an iterator that will return num_rows rows (or fewer if
+    /// there are not enough patients in the registry). The block_id and
+    /// registry have the same meaning as in make_swd_attributes.
+    pub fn new(block_id: &str, registry: &PatientRegistry, num_rows: usize) -> Self {
+        let global_seed = registry.global_seed();
+        let num_rows = num_rows.min(MONTHS_PER_PATIENT * registry.num_patients());
+        let smoking_block_id = format!("{block_id}smoking");
+        let blood_pressure_block_id = format!("{block_id}blood_pressure");
+        let qof_block_id = format!("{block_id}qof");
+        Self {
+            registry: registry.clone(),
+            smoking_rng: make_rng(global_seed, smoking_block_id.as_ref()),
+            blood_pressure_rng: make_rng(global_seed, blood_pressure_block_id.as_ref()),
+            qof_rng: make_rng(global_seed, qof_block_id.as_ref()),
+            row: 0,
+            num_rows,
+            smoking_status: SmokingStatus::Never,
+            qof_conditions: [false; 5],
+        }
+    }
+}
+
+impl Iterator for SwdAttributesRows {
+    type Item = SwdAttributesRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.row == self.num_rows {
+            return None;
+        }
+        let row = self.row;
+        self.row += 1;
+
+        update_smoking_status(&mut self.smoking_rng, &mut self.smoking_status, row);
+        let patient = &self.registry.patients()[row / MONTHS_PER_PATIENT];
+        update_qof_conditions(&mut self.qof_rng, &mut self.qof_conditions, patient, row);
+        let (systolic_bp, diastolic_bp) =
+            make_blood_pressure(&mut self.blood_pressure_rng, self.qof_conditions[0]);
+        Some(SwdAttributesRow {
+            subject: patient.subject.clone(),
+            attribute_period: attribute_period(row),
+            smoking_status: String::from(self.smoking_status.name()),
+            systolic_bp,
+            diastolic_bp,
+            qof_conditions: self.qof_conditions,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let rows_left = self.num_rows - self.row;
+        (rows_left, Some(rows_left))
+    }
+}
+
+impl SyntheticRows for SwdAttributesRows {
+    /// The blood pressure depends on the hypertension flag, so it is
+    /// generated in the same block as the QOF conditions.
+    fn take_chunk(&mut self, chunk_size: usize) -> DataFrame {
+        let num_rows = chunk_size.min(self.num_rows - self.row);
+        let rows = self.row..self.row + num_rows;
+        self.row += num_rows;
+        let Self {
+            registry,
+            smoking_rng,
+            blood_pressure_rng,
+            qof_rng,
+            smoking_status,
+            qof_conditions,
+            ..
+        } = self;
+
+        let (smoking, (blood_pressure, qof)) = rayon::join(
+            || {
+                let smoking: Vec<_> = rows
+                    .clone()
+                    .map(|row| {
+                        update_smoking_status(smoking_rng, smoking_status, row);
+                        smoking_status.name()
+                    })
+                    .collect();
+                Series::new("smoking_status", smoking)
+            },
+            || {
+                let mut blood_pressure = Vec::with_capacity(num_rows);
+                let mut qof: [Vec<bool>; 5] = Default::default();
+                for row in rows.clone() {
+                    let patient = &registry.patients()[row / MONTHS_PER_PATIENT];
+                    update_qof_conditions(qof_rng, qof_conditions, patient, row);
+                    blood_pressure.push(make_blood_pressure(blood_pressure_rng, qof_conditions[0]));
+                    for (column, flag) in qof.iter_mut().zip(*qof_conditions) {
+                        column.push(flag);
+                    }
+                }
+                (blood_pressure, qof)
+            },
+        );
+
+        let subject: Vec<_> = rows
+            .clone()
+            .map(|row| {
+                registry.patients()[row / MONTHS_PER_PATIENT]
+                    .subject
+                    .as_str()
+            })
+            .collect();
+        let attribute_period: Vec<_> = rows.map(attribute_period).collect();
+        let (systolic_bp, diastolic_bp): (Vec<_>, Vec<_>) = blood_pressure.into_iter().unzip();
+
+        let mut qof_columns = Vec::new();
+        for (name, column) in QOF_CONDITIONS.iter().zip(qof) {
+            qof_columns.push(Series::new(name, column));
+        }
+        let seeded_column_blocks = vec![
+            SeededColumnBlock {
+                columns: vec![
+                    Series::new("subject", subject),
+                    Series::new("attribute_period", attribute_period),
+                ],
+            },
+            SeededColumnBlock {
+                columns: vec![smoking],
+            },
+            SeededColumnBlock {
+                columns: vec![
+                    Series::new("systolic_bp", systolic_bp),
+                    Series::new("diastolic_bp", diastolic_bp),
+                ],
+            },
+            SeededColumnBlock {
+                columns: qof_columns,
+            },
+        ];
+        to_polars(seeded_column_blocks)
+    }
+}
+
+/// Create the primary-care attributes table, with one row per patient
+/// per month (see the module documentation). num_rows does not need to
+/// be a multiple of MONTHS_PER_PATIENT, in which case the last patient
+/// has fewer months. The table is truncated to MONTHS_PER_PATIENT rows
+/// per patient in the registry if num_rows is larger. The columns are:
+///
+/// * subject: the patient id (bristol_1, bristol_2, ...)
+/// * attribute_period: the first day of the month the row applies to
+/// * smoking_status: never, ex or current
+/// * systolic_bp, diastolic_bp: blood pressure in mmHg (null if not
+///   measured that month)
+/// * hypertension, diabetes, atrial_fibrillation, ckd, heart_failure:
+///   whether the patient has been diagnosed with the QOF condition
+pub fn make_swd_attributes(
+    block_id: &str,
+    registry: &PatientRegistry,
+    version: GeneratorVersion,
+    num_rows: usize,
+) -> DataFrame {
+    let global_seed = registry.global_seed();
+    let _span =
+        tracing::debug_span!("make_swd_attributes", block_id, global_seed, num_rows).entered();
+    match version {
+        GeneratorVersion::V1 => {
+            SwdAttributesRows::new(block_id, registry, num_rows).take_chunk(num_rows)
+        }
+    }
+}
+
+/// Create the attributes table in chunks of (at most) chunk_size rows
+/// (see the synth_data module documentation). A patient's months can be
+/// split across two chunks.
+pub fn make_swd_attributes_chunks(
+    block_id: &str,
+    registry: &PatientRegistry,
+    version: GeneratorVersion,
+    num_rows: usize,
+    chunk_size: usize,
+) -> impl Iterator<Item = DataFrame> {
+    let global_seed = registry.global_seed();
+    let span = tracing::debug_span!(
+        "make_swd_attributes_chunks",
+        block_id,
+        global_seed,
+        num_rows,
+        chunk_size
+    );
+    let rows = match version {
+        GeneratorVersion::V1 => SwdAttributesRows::new(block_id, registry, num_rows),
+    };
+    make_chunks(rows, chunk_size, span)
+}
+
+/// Tests for the attributes table
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn check_one_row_per_patient_per_month() {
+        let registry = PatientRegistry::new(0, 2);
+        let rows: Vec<_> = SwdAttributesRows::new("swd_attributes", &registry, 30).collect();
+        assert_eq!(rows.len(), 2 * MONTHS_PER_PATIENT);
+        let rows: Vec<_> = SwdAttributesRows::new("swd_attributes", &registry, 20).collect();
+        assert_eq!(rows.len(), 20);
+        assert_eq!(rows[0].subject, "bristol_1");
+        assert_eq!(rows[11].subject, "bristol_1");
+        assert_eq!(rows[12].subject, "bristol_2");
+        assert_eq!(rows[12].attribute_period, first_attribute_period());
+        assert_eq!(
+            rows[11].attribute_period,
+            NaiveDate::from_ymd_opt(2023, 12, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        );
+
+        // Conditions are never resolved within a patient
+        for pair in rows[..MONTHS_PER_PATIENT].windows(2) {
+            for (before, after) in pair[0].qof_conditions.iter().zip(pair[1].qof_conditions) {
+                assert!(!before || after);
+            }
+        }
+
+        let attributes = make_swd_attributes("swd_attributes", &registry, GeneratorVersion::V1, 20);
+        assert_eq!(attributes.shape(), (20, 10));
+
+        // The dataframe contains the same rows as the iterator
+        let smoking_status = attributes.column("smoking_status").unwrap().utf8().unwrap();
+        let systolic_bp = attributes.column("systolic_bp").unwrap().i64().unwrap();
+        let ckd = attributes.column("ckd").unwrap().bool().unwrap();
+        for (n, row) in rows.iter().enumerate() {
+            assert_eq!(smoking_status.get(n), Some(row.smoking_status.as_str()));
+            assert_eq!(systolic_bp.get(n), row.systolic_bp);
+            assert_eq!(ckd.get(n), Some(row.qof_conditions[3]));
+        }
+
+        // Chunks carry the patient state over from one chunk to the next
+        let chunks: Vec<_> =
+            make_swd_attributes_chunks("swd_attributes", &registry, GeneratorVersion::V1, 20, 7)
+                .collect();
+        assert_eq!(chunks.len(), 3);
+        let mut joined = chunks[0].clone();
+        for chunk in &chunks[1..] {
+            joined.vstack_mut(chunk).unwrap();
+        }
+        assert!(joined.frame_equal_missing(&attributes));
+    }
+}
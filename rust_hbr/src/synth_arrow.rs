@@ -0,0 +1,144 @@
+//! Synthetic tables as Arrow record batches
+//!
+//! The synthetic tables are generated as polars DataFrames, but the
+//! ingestion code (DataFusion, save_record_batch and PatientStore)
+//! works with Arrow RecordBatches. Instead of a second implementation
+//! of each table that builds Arrow arrays, to_record_batch converts
+//! any generated table (or chunk of a table) into a record batch with
+//! the same columns and values, so each table is only generated in one
+//! place whichever form is needed (see TableSpec::make_record_batch).
+//!
+//! String, boolean, integer, float and timestamp columns are converted.
+//! Naive timestamps (the default) become Arrow timestamps without a
+//! timezone, and timezone-aware timestamps keep their timezone. Every
+//! field in the schema is nullable, so that all the chunks of a table
+//! have the same schema whether or not a chunk contains any nulls.
+
+use std::sync::Arc;
+
+use datafusion::arrow::array::{
+    ArrayRef, BooleanArray, Float64Array, Int32Array, Int64Array, StringArray,
+    TimestampMicrosecondArray, TimestampMillisecondArray, TimestampNanosecondArray, UInt32Array,
+};
+use datafusion::arrow::datatypes::{Field, Schema};
+use datafusion::arrow::record_batch::RecordBatch;
+use polars::prelude::{DataFrame, DataType, Series, TimeUnit};
+
+use crate::error::HbrError;
+
+/// Convert one column of a table into an Arrow array
+fn to_arrow_array(series: &Series) -> Result<ArrayRef, HbrError> {
+    let array: ArrayRef = match series.dtype() {
+        DataType::Utf8 => Arc::new(StringArray::from_iter(series.utf8()?)),
+        DataType::Boolean => Arc::new(BooleanArray::from_iter(series.bool()?)),
+        DataType::Int32 => Arc::new(Int32Array::from_iter(series.i32()?)),
+        DataType::Int64 => Arc::new(Int64Array::from_iter(series.i64()?)),
+        DataType::UInt32 => Arc::new(UInt32Array::from_iter(series.u32()?)),
+        DataType::Float64 => Arc::new(Float64Array::from_iter(series.f64()?)),
+        DataType::Datetime(time_unit, time_zone) => {
+            let timestamps = series.cast(&DataType::Int64)?;
+            let timestamps = timestamps.i64()?;
+            let time_zone = time_zone.clone();
+            match time_unit {
+                TimeUnit::Milliseconds => Arc::new(
+                    TimestampMillisecondArray::from_iter(timestamps).with_timezone_opt(time_zone),
+                ),
+                TimeUnit::Microseconds => Arc::new(
+                    TimestampMicrosecondArray::from_iter(timestamps).with_timezone_opt(time_zone),
+                ),
+                TimeUnit::Nanoseconds => Arc::new(
+                    TimestampNanosecondArray::from_iter(timestamps).with_timezone_opt(time_zone),
+                ),
+            }
+        }
+        other => {
+            return Err(HbrError::UnexpectedValue {
+                field: format!("type of column {}", series.name()),
+                expected: String::from("a string, boolean, integer, float or timestamp column"),
+                found: other.to_string(),
+            })
+        }
+    };
+    Ok(array)
+}
+
+/// Convert a synthetic table (or any DataFrame with the column types
+/// listed in the module documentation) into an Arrow record batch with
+/// the same column names and values. Returns HbrError::UnexpectedValue
+/// if a column has a type that cannot be converted.
+pub fn to_record_batch(table: &DataFrame) -> Result<RecordBatch, HbrError> {
+    let mut fields = Vec::new();
+    let mut columns = Vec::new();
+    for series in table.get_columns() {
+        let column = to_arrow_array(series)?;
+        fields.push(Field::new(series.name(), column.data_type().clone(), true));
+        columns.push(column);
+    }
+    let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+        .expect("Columns of a DataFrame have the same length");
+    Ok(batch)
+}
+
+/// Tests for converting synthetic tables to record batches
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::synth_data::{
+        to_timezone_aware, GeneratorVersion, PatientRegistry, LOCAL_TIME_ZONE,
+    };
+    use crate::synth_dataset::{SyntheticTable, TableSpec};
+    use datafusion::arrow::array::{Array, AsArray};
+    use datafusion::arrow::datatypes::{DataType as ArrowDataType, TimeUnit as ArrowTimeUnit};
+
+    #[test]
+    fn check_every_table_converts_to_a_record_batch() {
+        let registry = PatientRegistry::new(0, 100);
+        for table in SyntheticTable::ALL {
+            let df = TableSpec::new(table, 50).make(&registry, GeneratorVersion::V1);
+            let batch = to_record_batch(&df).expect("Synthetic tables can be converted");
+            assert_eq!(batch.num_rows(), df.height());
+            let names: Vec<_> = batch
+                .schema()
+                .fields()
+                .iter()
+                .map(|f| f.name().clone())
+                .collect();
+            assert_eq!(names, df.get_column_names());
+        }
+
+        // The values (including nulls) and timestamps are unchanged
+        let df = TableSpec::new(SyntheticTable::Prescriptions, 200)
+            .make(&registry, GeneratorVersion::V1);
+        let batch = to_record_batch(&df).unwrap();
+        let drug_name: Vec<_> = df
+            .column("drug_name")
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .collect();
+        let batch_drug_name = batch
+            .column_by_name("drug_name")
+            .unwrap()
+            .as_string::<i32>();
+        assert_eq!(batch_drug_name.iter().collect::<Vec<_>>(), drug_name);
+        let stop = df.column("prescription_stop").unwrap();
+        let stop_batch = batch.column_by_name("prescription_stop").unwrap();
+        assert_eq!(
+            stop_batch.data_type(),
+            &ArrowDataType::Timestamp(ArrowTimeUnit::Millisecond, None)
+        );
+        assert_eq!(stop_batch.null_count(), stop.null_count());
+
+        let aware = to_timezone_aware(df, LOCAL_TIME_ZONE);
+        let batch = to_record_batch(&aware).unwrap();
+        assert_eq!(
+            batch
+                .column_by_name("prescription_stop")
+                .unwrap()
+                .data_type(),
+            &ArrowDataType::Timestamp(ArrowTimeUnit::Millisecond, Some("Europe/London".into()))
+        );
+    }
+}
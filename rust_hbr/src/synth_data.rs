@@ -1,18 +1,129 @@
 //! Generic information used across the different synthetic data, such as the
 //! subject (patient id), which is present in all tables.
+//!
+//! All the synthetic tables pick their subjects from a PatientRegistry,
+//! which is generated from the global seed. Tables generated from the
+//! same registry therefore refer to the same pool of patients (with the
+//! same demographics), so they can be joined on the subject column.
+//!
+//! Each patient also has underlying conditions (PatientConditions),
+//! which are not written to any table, but which make the values in
+//! different tables consistent with each other. For example, patients
+//! with anaemia have anaemia diagnosis codes in the episodes table and
+//! low haemoglobin results in the pathology_blood table, so that
+//! preprocessing checks and toy models can find real signal in the
+//! synthetic data.
+//!
+//! The times of the events in the tables (e.g. blood samples being
+//! collected) are picked from a DateWindow, and the time until the end
+//! of each event (e.g. the result being available) from a
+//! DurationRange. The defaults depend on the table, and can be changed
+//! in the dataset spec. Like the times in the real extracts, they are
+//! naive local times in LOCAL_TIME_ZONE, but some extracts contain
+//! timezone-aware timestamps instead, so a table can also be converted
+//! to use them (see to_timezone_aware).
+//!
+//! Every table is randomly generated from the global seed of the
+//! registry, with no particular statistical characteristics apart from
+//! the ones that follow from the patient conditions, and the subjects
+//! are picked from the patients in the registry. The columns are made
+//! in seeded column blocks (see seeded_column_block), each with its own
+//! generator seeded from the global seed and the block id, so adding
+//! a column or a table does not change the data in the others.
+//!
+//! Each table also has a make_*_chunks function, which generates the
+//! same rows in chunks of (at most) chunk_size rows (see make_chunks).
+//! The concatenation of all the chunks is the same as the table
+//! returned by the make_* function with the same arguments, and only
+//! one chunk is held in memory at a time, so this can be used to stream
+//! a large table into a file.
+//!
+//! The make_* functions for the tables take the GeneratorVersion to
+//! use, so that the generators can change (e.g. to add columns or fix
+//! the distribution of a value) without changing the data generated
+//! for existing tests and stored datasets, which keep using the version
+//! they were written with.
 
+use chrono::{Datelike, Duration, DurationRound, LocalResult, NaiveDate, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use polars::prelude::*;
 use rand::prelude::*;
 use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
+use crate::error::HbrError;
+use crate::seeded_column_block::{to_polars, SeededColumnBlock};
+use crate::seeded_rng::make_rng;
+
+/// The number of patients in the registry used when the size is not
+/// given explicitly
+pub const DEFAULT_NUM_PATIENTS: usize = 50000;
+
+/// The version of the table generators. The data generated by a
+/// version (from the same registry and arguments) never changes, so
+/// any change to a generator that would change its output is made in a
+/// new version, leaving the older versions reproducible bit-for-bit.
+/// Versions are written as numbers in dataset specs (e.g.
+/// generator_version: 1).
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(try_from = "u32", into = "u32")]
+pub enum GeneratorVersion {
+    /// The first version, which is used when no version is given
+    #[default]
+    V1,
+}
+
+impl GeneratorVersion {
+    /// The most recent version, used for new datasets
+    pub const LATEST: Self = Self::V1;
+
+    /// The version number (starting from 1)
+    pub fn number(&self) -> u32 {
+        match self {
+            Self::V1 => 1,
+        }
+    }
+}
+
+impl TryFrom<u32> for GeneratorVersion {
+    type Error = HbrError;
+
+    /// Find the version with the given number (returns
+    /// HbrError::InvalidDatasetSpec if there is no such version)
+    fn try_from(number: u32) -> Result<Self, HbrError> {
+        match number {
+            1 => Ok(Self::V1),
+            _ => Err(HbrError::InvalidDatasetSpec(format!(
+                "there is no generator version {number} (the latest is {})",
+                Self::LATEST.number()
+            ))),
+        }
+    }
+}
+
+impl From<GeneratorVersion> for u32 {
+    fn from(version: GeneratorVersion) -> Self {
+        version.number()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Gender {
     Female,
     Male,
 }
 
-/// Generate a patient id for the pathology_blood table (format "bristol_nnnn")
-pub fn make_subject(rng: &mut ChaCha8Rng) -> String {
-    let patient_id = rng.gen_range(1..=50000);
-    format! {"bristol_{patient_id}"}
+impl Gender {
+    /// The name of the gender (as it appears in the patients table)
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Female => "female",
+            Self::Male => "male",
+        }
+    }
 }
 
 /// Pick gender uniform randomly (only male or female)
@@ -22,4 +133,390 @@ pub fn make_gender(rng: &mut ChaCha8Rng) -> Gender {
     } else {
         Gender::Male
     }
-}
\ No newline at end of file
+}
+
+/// Pick a date of birth uniformly between 1920 and the end of 2005
+/// (so that all patients are adults by the end of the synthetic data)
+fn make_date_of_birth(rng: &mut ChaCha8Rng) -> NaiveDateTime {
+    let earliest = NaiveDate::from_ymd_opt(1920, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    earliest + Duration::days(rng.gen_range(0..31411))
+}
+
+/// The window of time that the events in a synthetic table start in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DateWindow {
+    /// The earliest time an event can start
+    pub start: NaiveDateTime,
+    /// The end of the window (events start before this time)
+    pub end: NaiveDateTime,
+}
+
+impl Default for DateWindow {
+    /// From the start of 1970 to roughly the middle of 2023
+    fn default() -> Self {
+        let start = NaiveDateTime::from_timestamp_opt(0, 0).unwrap();
+        Self {
+            start,
+            end: start + Duration::minutes(28150015),
+        }
+    }
+}
+
+impl DateWindow {
+    /// Check that the window is not empty (returns
+    /// HbrError::InvalidDatasetSpec otherwise)
+    pub fn validate(&self) -> Result<(), HbrError> {
+        if self.start >= self.end {
+            return Err(HbrError::InvalidDatasetSpec(format!(
+                "date window start {} must be before end {}",
+                self.start, self.end
+            )));
+        }
+        Ok(())
+    }
+
+    /// Pick a time uniformly from the window (to the minute)
+    pub fn pick(&self, rng: &mut ChaCha8Rng) -> NaiveDateTime {
+        let num_minutes = (self.end - self.start).num_minutes().max(1);
+        self.start + Duration::minutes(rng.gen_range(0..num_minutes))
+    }
+}
+
+/// A range of durations, from min_minutes up to (but not including)
+/// max_minutes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DurationRange {
+    pub min_minutes: i64,
+    pub max_minutes: i64,
+}
+
+impl DurationRange {
+    /// Check that the range is not empty and the durations are not
+    /// negative (returns HbrError::InvalidDatasetSpec otherwise)
+    pub fn validate(&self) -> Result<(), HbrError> {
+        if self.min_minutes < 0 || self.min_minutes >= self.max_minutes {
+            return Err(HbrError::InvalidDatasetSpec(format!(
+                "duration range {} - {} minutes must be non-empty and non-negative",
+                self.min_minutes, self.max_minutes
+            )));
+        }
+        Ok(())
+    }
+
+    /// Pick a duration uniformly from the range (to the minute)
+    pub fn pick(&self, rng: &mut ChaCha8Rng) -> Duration {
+        Duration::minutes(rng.gen_range(self.min_minutes..self.max_minutes))
+    }
+}
+
+/// When the events in a synthetic table happen. Each event starts at
+/// a time picked from the window, and ends after a duration picked from
+/// the duration range (for example, a blood sample is collected, and
+/// the result is available after the processing time).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventTiming {
+    pub window: DateWindow,
+    pub duration: DurationRange,
+}
+
+impl EventTiming {
+    /// Events in the default window, with durations in the given range
+    pub fn with_duration(duration: DurationRange) -> Self {
+        Self {
+            window: DateWindow::default(),
+            duration,
+        }
+    }
+
+    /// Check that the window and the duration range are valid
+    pub fn validate(&self) -> Result<(), HbrError> {
+        self.window.validate()?;
+        self.duration.validate()
+    }
+
+    /// Pick the start and end times of an event
+    pub fn pick(&self, rng: &mut ChaCha8Rng) -> (NaiveDateTime, NaiveDateTime) {
+        let start = self.window.pick(rng);
+        (start, start + self.duration.pick(rng))
+    }
+}
+
+/// The time zone that the naive timestamps in the synthetic (and real)
+/// tables are local times in
+pub const LOCAL_TIME_ZONE: Tz = chrono_tz::Europe::London;
+
+/// Convert a local (wall clock) time in a time zone to UTC. When the
+/// clocks go back, a local time that happens twice is taken to be the
+/// first one. When the clocks go forward, a local time in the gap (which
+/// does not exist) is moved to the end of the gap. This means that a
+/// later local time is never converted to an earlier UTC time, so the
+/// end of an event is never before its start.
+pub fn local_to_utc(local: NaiveDateTime, time_zone: Tz) -> NaiveDateTime {
+    let mut local = local;
+    loop {
+        match time_zone.from_local_datetime(&local) {
+            LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => return time.naive_utc(),
+            LocalResult::None => {
+                local = local
+                    .duration_trunc(Duration::minutes(1))
+                    .expect("Local time can be rounded to the minute")
+                    + Duration::minutes(1);
+            }
+        }
+    }
+}
+
+/// Convert a time to a timestamp (the number of time units since the
+/// start of 1970) of a polars datetime column
+pub(crate) fn to_timestamp(time: NaiveDateTime, time_unit: TimeUnit) -> i64 {
+    match time_unit {
+        TimeUnit::Nanoseconds => time.timestamp_nanos(),
+        TimeUnit::Microseconds => time.timestamp_micros(),
+        TimeUnit::Milliseconds => time.timestamp_millis(),
+    }
+}
+
+/// Convert the naive timestamp columns of a table, which hold local
+/// times in time_zone, to timezone-aware timestamps in the same time
+/// zone (see local_to_utc for how the daylight saving changes are
+/// handled). The other columns are not changed.
+pub fn to_timezone_aware(table: DataFrame, time_zone: Tz) -> DataFrame {
+    let columns = table
+        .get_columns()
+        .iter()
+        .map(|series| match series.dtype() {
+            DataType::Datetime(time_unit, None) => {
+                let time_unit = *time_unit;
+                let timestamps: Int64Chunked = series
+                    .datetime()
+                    .expect("Column is a timestamp column")
+                    .as_datetime_iter()
+                    .map(|time| {
+                        time.map(|time| to_timestamp(local_to_utc(time, time_zone), time_unit))
+                    })
+                    .collect();
+                let mut converted = timestamps
+                    .into_datetime(time_unit, Some(String::from(time_zone.name())))
+                    .into_series();
+                converted.rename(series.name());
+                converted
+            }
+            _ => series.clone(),
+        })
+        .collect();
+    DataFrame::new(columns).expect("Converted columns have the same length")
+}
+
+/// The conditions that a synthetic patient has, which are used to
+/// correlate the values generated in different tables
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PatientConditions {
+    /// Prior bleeding (bleeding diagnoses and lower haemoglobin)
+    pub bleeding: bool,
+    /// Anaemia (anaemia diagnoses and lower haemoglobin)
+    pub anaemia: bool,
+    /// Chronic kidney disease (CKD diagnoses, the QOF CKD flag, and
+    /// lower eGFR)
+    pub ckd: bool,
+    /// An acute coronary syndrome index event (ACS diagnoses, and
+    /// coronary procedures such as PCI)
+    pub acs: bool,
+}
+
+/// Pick the conditions of a patient (5% of patients have prior
+/// bleeding, 10% have anaemia, 10% have CKD and 20% have an ACS,
+/// independently)
+fn make_conditions(rng: &mut ChaCha8Rng) -> PatientConditions {
+    PatientConditions {
+        bleeding: rng.gen_bool(0.05),
+        anaemia: rng.gen_bool(0.1),
+        ckd: rng.gen_bool(0.1),
+        acs: rng.gen_bool(0.2),
+    }
+}
+
+/// One patient in the registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntheticPatient {
+    /// The patient id (format "bristol_nnnn")
+    pub subject: String,
+    pub gender: Gender,
+    /// The date of birth (at midnight)
+    pub date_of_birth: NaiveDateTime,
+    pub conditions: PatientConditions,
+}
+
+impl SyntheticPatient {
+    /// The age of the patient in whole years at the given time (which
+    /// is negative if the time is before the patient was born)
+    pub fn age_at(&self, time: NaiveDateTime) -> i32 {
+        let date_of_birth = self.date_of_birth.date();
+        let date = time.date();
+        let age = date.year() - date_of_birth.year();
+        if (date.month(), date.day()) < (date_of_birth.month(), date_of_birth.day()) {
+            age - 1
+        } else {
+            age
+        }
+    }
+}
+
+/// The pool of patients that the synthetic tables sample from
+///
+/// The registry is a function of the global seed and the number of
+/// patients only, and patient n is the same in any registry that has
+/// at least n patients. Cloning the registry is cheap (the patients
+/// are shared), so each table generator keeps its own copy.
+#[derive(Debug, Clone)]
+pub struct PatientRegistry {
+    global_seed: u64,
+    patients: Arc<[SyntheticPatient]>,
+}
+
+impl PatientRegistry {
+    /// Make the registry of num_patients patients (bristol_1 to
+    /// bristol_{num_patients}) from the global seed. Panics if
+    /// num_patients is zero.
+    pub fn new(global_seed: u64, num_patients: usize) -> Self {
+        assert!(num_patients > 0, "Patient registry must not be empty");
+        let _span =
+            tracing::debug_span!("make_patient_registry", global_seed, num_patients).entered();
+        let mut gender_rng = make_rng(global_seed, "patient_registrygender");
+        let mut date_of_birth_rng = make_rng(global_seed, "patient_registrydate_of_birth");
+        let mut conditions_rng = make_rng(global_seed, "patient_registryconditions");
+        let patients = (1..=num_patients)
+            .map(|n| SyntheticPatient {
+                subject: format!("bristol_{n}"),
+                gender: make_gender(&mut gender_rng),
+                date_of_birth: make_date_of_birth(&mut date_of_birth_rng),
+                conditions: make_conditions(&mut conditions_rng),
+            })
+            .collect();
+        Self {
+            global_seed,
+            patients,
+        }
+    }
+
+    /// The global seed used to make the registry, which is also used
+    /// to seed the tables generated from it
+    pub fn global_seed(&self) -> u64 {
+        self.global_seed
+    }
+
+    /// The number of patients in the registry
+    pub fn num_patients(&self) -> usize {
+        self.patients.len()
+    }
+
+    /// All the patients, in order of subject number
+    pub fn patients(&self) -> &[SyntheticPatient] {
+        &self.patients
+    }
+
+    /// Pick a patient uniformly at random
+    pub fn sample(&self, rng: &mut ChaCha8Rng) -> &SyntheticPatient {
+        self.patients
+            .choose(rng)
+            .expect("Patient registry is not empty")
+    }
+
+    /// Make the patients table, containing the first num_rows patients
+    /// (or all the patients if there are fewer than num_rows). The
+    /// columns are subject, gender and date_of_birth.
+    pub fn to_polars(&self, num_rows: usize) -> DataFrame {
+        let _span = tracing::debug_span!("make_patients", global_seed = self.global_seed, num_rows)
+            .entered();
+        let patients = &self.patients[..num_rows.min(self.patients.len())];
+        let subject: Vec<_> = patients.iter().map(|p| p.subject.as_str()).collect();
+        let gender: Vec<_> = patients.iter().map(|p| p.gender.name()).collect();
+        let date_of_birth: Vec<_> = patients.iter().map(|p| p.date_of_birth).collect();
+        to_polars(vec![SeededColumnBlock {
+            columns: vec![
+                Series::new("subject", subject),
+                Series::new("gender", gender),
+                Series::new("date_of_birth", date_of_birth),
+            ],
+        }])
+    }
+}
+
+/// Tests for the patient registry
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::pathology_blood::{make_pathology_blood, DEFAULT_PROCESSING_TIME};
+
+    #[test]
+    fn check_registry_is_consistent_across_sizes() {
+        let small = PatientRegistry::new(0, 10);
+        let large = PatientRegistry::new(0, 1000);
+        assert_eq!(small.patients(), &large.patients()[..10]);
+        assert_eq!(large.patients()[999].subject, "bristol_1000");
+        assert_ne!(PatientRegistry::new(1, 10).patients(), small.patients());
+
+        let patients = large.to_polars(2000);
+        assert_eq!(patients.shape(), (1000, 3));
+    }
+
+    #[test]
+    fn check_local_times_are_converted_across_daylight_saving() {
+        let time = |month, day, hour, minute| {
+            NaiveDate::from_ymd_opt(2021, month, day)
+                .unwrap()
+                .and_hms_opt(hour, minute, 0)
+                .unwrap()
+        };
+        let to_utc = |local| local_to_utc(local, LOCAL_TIME_ZONE);
+        assert_eq!(to_utc(time(1, 15, 12, 0)), time(1, 15, 12, 0));
+        assert_eq!(to_utc(time(7, 1, 12, 0)), time(7, 1, 11, 0));
+
+        // The clocks go forward at 01:00 on 28 March 2021, so times in
+        // the next hour do not exist
+        assert_eq!(to_utc(time(3, 28, 0, 59)), time(3, 28, 0, 59));
+        assert_eq!(to_utc(time(3, 28, 1, 30)), time(3, 28, 1, 0));
+        assert_eq!(to_utc(time(3, 28, 2, 30)), time(3, 28, 1, 30));
+
+        // The clocks go back at 02:00 on 31 October 2021, so times in
+        // the hour before happen twice
+        assert_eq!(to_utc(time(10, 31, 1, 30)), time(10, 31, 0, 30));
+        assert_eq!(to_utc(time(10, 31, 2, 30)), time(10, 31, 2, 30));
+    }
+
+    #[test]
+    fn check_haemoglobin_depends_on_conditions() {
+        let registry = PatientRegistry::new(0, 1000);
+        let timing = EventTiming::with_duration(DEFAULT_PROCESSING_TIME);
+        let table = make_pathology_blood(
+            "pathology_blood",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            3000,
+        );
+        let subject = table.column("subject").unwrap().utf8().unwrap();
+        let test_name = table.column("test_name").unwrap().utf8().unwrap();
+        let test_result = table.column("test_result").unwrap().utf8().unwrap();
+
+        // Mean haemoglobin for patients with and without anaemia or bleeding
+        let mut sums = [(0.0, 0); 2];
+        for ((subject, test_name), test_result) in
+            subject.into_iter().zip(test_name).zip(test_result)
+        {
+            if test_name != Some("Haemoglobin") {
+                continue;
+            }
+            let n: usize = subject.unwrap()["bristol_".len()..].parse().unwrap();
+            let conditions = registry.patients()[n - 1].conditions;
+            let sum = &mut sums[usize::from(conditions.anaemia || conditions.bleeding)];
+            sum.0 += test_result.unwrap().parse::<f64>().unwrap();
+            sum.1 += 1;
+        }
+        let [without, with] = sums.map(|(sum, count)| sum / count as f64);
+        assert!(with < without - 30.0);
+    }
+}
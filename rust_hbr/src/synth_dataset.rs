@@ -0,0 +1,774 @@
+//! Generating whole synthetic datasets
+//!
+//! A DatasetSpec lists the synthetic tables to generate, and how many
+//! rows each one should have. All the tables are generated from the
+//! same global seed, using the name of the table as the block id, so
+//! adding a table to the spec does not change the data in the others.
+//! The generator_version in the spec picks the version of the table
+//! generators (see GeneratorVersion). It is version 1 if not given, so
+//! a spec always generates the same data even after the generators
+//! have changed.
+//! The subjects in every table are picked from one PatientRegistry
+//! (with num_patients patients), so the tables can be joined.
+//!
+//! The spec is normally read from a YAML (or JSON) file, for example:
+//!
+//! ```yaml
+//! seed: 42
+//! num_patients: 10000
+//! tables:
+//! - table: pathology_blood
+//!   num_rows: 100000
+//! - table: episodes
+//!   num_rows: 20000
+//!   diagnosis_frequencies:
+//!     I21.0: 1250
+//!     K92.2: 430
+//!   date_window:
+//!     start: 2015-01-01T00:00:00
+//!     end: 2020-01-01T00:00:00
+//!   duration:
+//!     min_minutes: 60
+//!     max_minutes: 20160
+//!   timezone_aware: true
+//! format: csv
+//! generator_version: 1
+//! ```
+//!
+//! generate_dataset writes each table to a file in an output directory
+//! (parquet by default, or CSV or NDJSON if format is given), so that
+//! large tables can be produced from the language bindings without
+//! passing all the data through Python or R. If chunk_size is given,
+//! each table is generated and written chunk_size rows at a time (one
+//! parquet row group per chunk), so the memory used does not depend
+//! on the number of rows. If corruption is given, defects such as
+//! trailing whitespace, placeholder dates and duplicate rows are
+//! injected into the tables (see the corruption module). A manifest is written with the
+//! tables, which manifest::verify_dataset uses to check that the files
+//! have not changed and can still be reproduced from the spec.
+
+use std::io::Read;
+#[cfg(feature = "parquet")]
+use std::io::Write;
+#[cfg(feature = "parquet")]
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "ingest")]
+use datafusion::arrow::record_batch::RecordBatch;
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::coronary_procedures::{
+    make_coronary_procedures, make_coronary_procedures_chunks, DEFAULT_PROCEDURE_DURATION,
+};
+#[cfg(feature = "parquet")]
+use crate::corruption::Corrupter;
+use crate::corruption::CorruptionSpec;
+use crate::echocardiography::{
+    make_echocardiography, make_echocardiography_chunks, DEFAULT_REPORT_TIME,
+};
+use crate::episodes::{
+    make_episodes, make_episodes_chunks, CodeFrequencies, DEFAULT_EPISODE_DURATION,
+};
+use crate::error::HbrError;
+#[cfg(feature = "parquet")]
+use crate::manifest::{DatasetManifest, HashingWriter, ManifestFile};
+use crate::pathology_blood::{
+    make_pathology_blood, make_pathology_blood_chunks, DEFAULT_PROCESSING_TIME,
+};
+use crate::prescriptions::{
+    make_prescriptions, make_prescriptions_chunks, DEFAULT_PRESCRIPTION_DURATION,
+};
+use crate::swd_attributes::{make_swd_attributes, make_swd_attributes_chunks};
+#[cfg(feature = "ingest")]
+use crate::synth_arrow::to_record_batch;
+use crate::synth_data::{
+    to_timezone_aware, DateWindow, DurationRange, EventTiming, GeneratorVersion, PatientRegistry,
+    DEFAULT_NUM_PATIENTS, LOCAL_TIME_ZONE,
+};
+use crate::ward_transfers::{
+    make_ward_transfers, make_ward_transfers_chunks, DEFAULT_SPELL_DURATION,
+};
+
+/// The synthetic tables that can be generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyntheticTable {
+    /// Blood test results (see make_pathology_blood)
+    PathologyBlood,
+    /// Secondary-care prescriptions (see make_prescriptions)
+    Prescriptions,
+    /// Primary-care attributes, one row per patient per month (see
+    /// make_swd_attributes)
+    SwdAttributes,
+    /// Hospital episodes with their primary diagnosis (see
+    /// make_episodes)
+    Episodes,
+    /// Admission, discharge and transfer events within hospital spells
+    /// (see make_ward_transfers)
+    WardTransfers,
+    /// Coronary angiograms and PCI procedures for patients with an
+    /// acute coronary syndrome (see make_coronary_procedures)
+    CoronaryProcedures,
+    /// Echocardiogram findings (LVEF and valve disease) (see
+    /// make_echocardiography)
+    Echocardiography,
+    /// The patients in the registry, with their demographics (see
+    /// PatientRegistry::to_polars)
+    Patients,
+}
+
+impl SyntheticTable {
+    /// All the tables, in the order they are listed by the CLI
+    pub const ALL: [Self; 8] = [
+        Self::PathologyBlood,
+        Self::Prescriptions,
+        Self::SwdAttributes,
+        Self::Episodes,
+        Self::WardTransfers,
+        Self::CoronaryProcedures,
+        Self::Echocardiography,
+        Self::Patients,
+    ];
+
+    /// The name of the table (as used in the spec), which is also
+    /// the block id used to seed the table and the output file name
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PathologyBlood => "pathology_blood",
+            Self::Prescriptions => "prescriptions",
+            Self::SwdAttributes => "swd_attributes",
+            Self::Episodes => "episodes",
+            Self::WardTransfers => "ward_transfers",
+            Self::CoronaryProcedures => "coronary_procedures",
+            Self::Echocardiography => "echocardiography",
+            Self::Patients => "patients",
+        }
+    }
+
+    /// Find the table with the given name (as returned by name())
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|table| table.name() == name)
+    }
+
+    /// The default timing of the events in the table (None if the table
+    /// does not have random event times)
+    pub fn default_timing(&self) -> Option<EventTiming> {
+        let duration = match self {
+            Self::PathologyBlood => DEFAULT_PROCESSING_TIME,
+            Self::Prescriptions => DEFAULT_PRESCRIPTION_DURATION,
+            Self::Episodes => DEFAULT_EPISODE_DURATION,
+            Self::WardTransfers => DEFAULT_SPELL_DURATION,
+            Self::CoronaryProcedures => DEFAULT_PROCEDURE_DURATION,
+            Self::Echocardiography => DEFAULT_REPORT_TIME,
+            Self::SwdAttributes | Self::Patients => return None,
+        };
+        Some(EventTiming::with_duration(duration))
+    }
+
+    /// The column names and types of the table (in the latest version
+    /// of the generators)
+    pub fn schema(&self) -> Schema {
+        TableSpec::new(*self, 0)
+            .make(&PatientRegistry::new(0, 1), GeneratorVersion::LATEST)
+            .schema()
+    }
+}
+
+/// The file format the tables in a dataset are written in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// Parquet files (the column types are kept)
+    #[default]
+    Parquet,
+    /// CSV files with a header row
+    Csv,
+    /// Newline-delimited JSON, with one object per row
+    Ndjson,
+}
+
+impl OutputFormat {
+    /// All the formats
+    pub const ALL: [Self; 3] = [Self::Parquet, Self::Csv, Self::Ndjson];
+
+    /// The name of the format (as used in the spec), which is also
+    /// the extension of the files written in this format
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Parquet => "parquet",
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+        }
+    }
+
+    /// Find the format with the given name (as returned by name())
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.name() == name)
+    }
+}
+
+/// One table to generate in a synthetic dataset
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TableSpec {
+    /// Which table to generate
+    pub table: SyntheticTable,
+    /// The number of rows in the table
+    pub num_rows: usize,
+    /// The frequencies used to pick the primary diagnosis codes (only
+    /// allowed for the episodes table, where the codes are picked
+    /// uniformly from a default list if not given)
+    #[serde(default)]
+    pub diagnosis_frequencies: Option<CodeFrequencies>,
+    /// The window that the events in the table start in (only allowed
+    /// for tables with event times, see SyntheticTable::default_timing)
+    #[serde(default)]
+    pub date_window: Option<DateWindow>,
+    /// The range of durations of the events in the table, such as the
+    /// processing time of the blood tests (only allowed for tables with
+    /// event times)
+    #[serde(default)]
+    pub duration: Option<DurationRange>,
+    /// Write the event times as timezone-aware timestamps in
+    /// LOCAL_TIME_ZONE, instead of naive local times (only allowed for
+    /// tables with event times)
+    #[serde(default)]
+    pub timezone_aware: bool,
+}
+
+impl TableSpec {
+    /// Generate num_rows rows of table, with the default options
+    pub fn new(table: SyntheticTable, num_rows: usize) -> Self {
+        Self {
+            table,
+            num_rows,
+            diagnosis_frequencies: None,
+            date_window: None,
+            duration: None,
+            timezone_aware: false,
+        }
+    }
+
+    /// Generate the table using the given version of the generators,
+    /// picking subjects from the registry (whose global seed is used for
+    /// the table)
+    pub fn make(&self, registry: &PatientRegistry, version: GeneratorVersion) -> DataFrame {
+        let name = self.table.name();
+        let num_rows = self.num_rows;
+        let table = match self.table {
+            SyntheticTable::PathologyBlood => {
+                make_pathology_blood(name, registry, version, self.timing(), num_rows)
+            }
+            SyntheticTable::Prescriptions => {
+                make_prescriptions(name, registry, version, self.timing(), num_rows)
+            }
+            SyntheticTable::SwdAttributes => make_swd_attributes(name, registry, version, num_rows),
+            SyntheticTable::Episodes => make_episodes(
+                name,
+                registry,
+                version,
+                &self.diagnosis_frequencies(),
+                self.timing(),
+                num_rows,
+            ),
+            SyntheticTable::WardTransfers => {
+                make_ward_transfers(name, registry, version, self.timing(), num_rows)
+            }
+            SyntheticTable::CoronaryProcedures => {
+                make_coronary_procedures(name, registry, version, self.timing(), num_rows)
+            }
+            SyntheticTable::Echocardiography => {
+                make_echocardiography(name, registry, version, self.timing(), num_rows)
+            }
+            SyntheticTable::Patients => registry.to_polars(num_rows),
+        };
+        if self.timezone_aware {
+            to_timezone_aware(table, LOCAL_TIME_ZONE)
+        } else {
+            table
+        }
+    }
+
+    /// Generate the table in chunks of (at most) chunk_size rows. The
+    /// concatenation of the chunks is the same as the table returned by
+    /// make. The patients table is always one chunk, because the
+    /// patients are already held in memory by the registry.
+    pub fn make_chunks(
+        &self,
+        registry: &PatientRegistry,
+        version: GeneratorVersion,
+        chunk_size: usize,
+    ) -> Box<dyn Iterator<Item = DataFrame>> {
+        let name = self.table.name();
+        let num_rows = self.num_rows;
+        let chunks: Box<dyn Iterator<Item = DataFrame>> = match self.table {
+            SyntheticTable::PathologyBlood => Box::new(make_pathology_blood_chunks(
+                name,
+                registry,
+                version,
+                self.timing(),
+                num_rows,
+                chunk_size,
+            )),
+            SyntheticTable::Prescriptions => Box::new(make_prescriptions_chunks(
+                name,
+                registry,
+                version,
+                self.timing(),
+                num_rows,
+                chunk_size,
+            )),
+            SyntheticTable::SwdAttributes => Box::new(make_swd_attributes_chunks(
+                name, registry, version, num_rows, chunk_size,
+            )),
+            SyntheticTable::Episodes => Box::new(make_episodes_chunks(
+                name,
+                registry,
+                version,
+                &self.diagnosis_frequencies(),
+                self.timing(),
+                num_rows,
+                chunk_size,
+            )),
+            SyntheticTable::WardTransfers => Box::new(make_ward_transfers_chunks(
+                name,
+                registry,
+                version,
+                self.timing(),
+                num_rows,
+                chunk_size,
+            )),
+            SyntheticTable::CoronaryProcedures => Box::new(make_coronary_procedures_chunks(
+                name,
+                registry,
+                version,
+                self.timing(),
+                num_rows,
+                chunk_size,
+            )),
+            SyntheticTable::Echocardiography => Box::new(make_echocardiography_chunks(
+                name,
+                registry,
+                version,
+                self.timing(),
+                num_rows,
+                chunk_size,
+            )),
+            SyntheticTable::Patients => Box::new(std::iter::once(registry.to_polars(num_rows))),
+        };
+        if self.timezone_aware {
+            Box::new(chunks.map(|chunk| to_timezone_aware(chunk, LOCAL_TIME_ZONE)))
+        } else {
+            chunks
+        }
+    }
+
+    /// Generate the table as an Arrow record batch instead of a polars
+    /// DataFrame (see to_record_batch)
+    #[cfg(feature = "ingest")]
+    pub fn make_record_batch(
+        &self,
+        registry: &PatientRegistry,
+        version: GeneratorVersion,
+    ) -> RecordBatch {
+        to_record_batch(&self.make(registry, version))
+            .expect("Synthetic tables only contain columns that can be converted to Arrow")
+    }
+
+    /// Generate the table as Arrow record batches of (at most)
+    /// chunk_size rows (see make_chunks)
+    #[cfg(feature = "ingest")]
+    pub fn make_record_batch_chunks(
+        &self,
+        registry: &PatientRegistry,
+        version: GeneratorVersion,
+        chunk_size: usize,
+    ) -> impl Iterator<Item = RecordBatch> {
+        self.make_chunks(registry, version, chunk_size)
+            .map(|chunk| {
+                to_record_batch(&chunk)
+                    .expect("Synthetic tables only contain columns that can be converted to Arrow")
+            })
+    }
+
+    /// The diagnosis frequencies, or the default ones if not given
+    fn diagnosis_frequencies(&self) -> CodeFrequencies {
+        self.diagnosis_frequencies.clone().unwrap_or_default()
+    }
+
+    /// The timing of the events in the table, using the defaults for the
+    /// table for anything not given (only for tables with event times)
+    fn timing(&self) -> EventTiming {
+        let default = self.table.default_timing().expect("Table has event times");
+        EventTiming {
+            window: self.date_window.unwrap_or(default.window),
+            duration: self.duration.unwrap_or(default.duration),
+        }
+    }
+
+    /// Check that the options given are allowed for the table, and
+    /// are valid
+    fn validate(&self) -> Result<(), HbrError> {
+        if self.date_window.is_some() || self.duration.is_some() || self.timezone_aware {
+            if self.table.default_timing().is_none() {
+                return Err(HbrError::InvalidDatasetSpec(format!(
+                    "date_window, duration and timezone_aware cannot be given for table {}",
+                    self.table.name()
+                )));
+            }
+            self.timing().validate()?;
+        }
+        if let Some(diagnosis_frequencies) = &self.diagnosis_frequencies {
+            if self.table != SyntheticTable::Episodes {
+                return Err(HbrError::InvalidDatasetSpec(format!(
+                    "diagnosis_frequencies cannot be given for table {}",
+                    self.table.name()
+                )));
+            }
+            diagnosis_frequencies
+                .validate()
+                .map_err(|err| HbrError::InvalidDatasetSpec(err.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+/// The tables to generate in a synthetic dataset, and the global
+/// seed used to generate them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatasetSpec {
+    /// The global seed for all the tables
+    pub seed: u64,
+    /// The number of patients in the registry shared by the tables
+    /// (DEFAULT_NUM_PATIENTS if not given)
+    #[serde(default = "default_num_patients")]
+    pub num_patients: usize,
+    /// The tables to generate (each table can only appear once)
+    pub tables: Vec<TableSpec>,
+    /// The format of the files written by generate_dataset (parquet
+    /// if not given)
+    #[serde(default)]
+    pub format: OutputFormat,
+    /// If given, generate_dataset generates and writes each table in
+    /// chunks of this many rows, instead of all at once
+    #[serde(default)]
+    pub chunk_size: Option<usize>,
+    /// If given, generate_dataset injects defects into the tables at
+    /// the rates in the spec (the tables are clean by default)
+    #[serde(default)]
+    pub corruption: Option<CorruptionSpec>,
+    /// The version of the generators used to make the tables (version
+    /// 1 if not given)
+    #[serde(default)]
+    pub generator_version: GeneratorVersion,
+}
+
+fn default_num_patients() -> usize {
+    DEFAULT_NUM_PATIENTS
+}
+
+impl DatasetSpec {
+    /// Read a dataset spec from a YAML (or JSON) file. Returns
+    /// HbrError::InvalidDatasetSpec if the spec cannot be parsed,
+    /// has no patients, or contains the same table more than once.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, HbrError> {
+        let spec: Self = serde_yaml::from_reader(reader)
+            .map_err(|err| HbrError::InvalidDatasetSpec(err.to_string()))?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    /// Check that the spec has at least one patient, a non-zero chunk
+    /// size (if given), valid corruption rates (if given), valid options
+    /// for each table, and contains each table at most once (returns
+    /// HbrError::InvalidDatasetSpec otherwise). This is done by
+    /// from_reader, but needs calling for specs that are made in code.
+    pub fn validate(&self) -> Result<(), HbrError> {
+        if self.num_patients == 0 {
+            return Err(HbrError::InvalidDatasetSpec(String::from(
+                "num_patients must be greater than zero",
+            )));
+        }
+        if self.chunk_size == Some(0) {
+            return Err(HbrError::InvalidDatasetSpec(String::from(
+                "chunk_size must be greater than zero",
+            )));
+        }
+        if let Some(corruption) = &self.corruption {
+            corruption.validate()?;
+        }
+        for (n, table_spec) in self.tables.iter().enumerate() {
+            table_spec.validate()?;
+            if self.tables[..n]
+                .iter()
+                .any(|other| other.table == table_spec.table)
+            {
+                return Err(HbrError::InvalidDatasetSpec(format!(
+                    "table {} appears more than once",
+                    table_spec.table.name()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Make the registry of patients that the tables are generated from
+    pub fn patient_registry(&self) -> PatientRegistry {
+        PatientRegistry::new(self.seed, self.num_patients)
+    }
+}
+
+/// Write the chunks of a table to a file in the given format, one
+/// chunk at a time. The empty table is used for the schema (and CSV
+/// header), so that a file is still written if there are no chunks.
+#[cfg(feature = "parquet")]
+fn write_table_chunks<I, W>(
+    chunks: I,
+    empty_table: &mut DataFrame,
+    mut file: W,
+    format: OutputFormat,
+) -> Result<(), HbrError>
+where
+    I: Iterator<Item = DataFrame>,
+    W: Write,
+{
+    match format {
+        OutputFormat::Parquet => {
+            let mut writer = ParquetWriter::new(file).batched(&empty_table.schema())?;
+            for chunk in chunks {
+                writer.write_batch(&chunk)?;
+            }
+            writer.finish()?;
+        }
+        OutputFormat::Csv => {
+            CsvWriter::new(&mut file)
+                .has_header(true)
+                .finish(empty_table)?;
+            for mut chunk in chunks {
+                CsvWriter::new(&mut file)
+                    .has_header(false)
+                    .finish(&mut chunk)?;
+            }
+        }
+        OutputFormat::Ndjson => {
+            for mut chunk in chunks {
+                JsonWriter::new(&mut file)
+                    .with_json_format(JsonFormat::JsonLines)
+                    .finish(&mut chunk)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Generate one of the tables in a dataset spec (with the chunk size
+/// and corruption in the spec), and write it to a file in the format
+/// of the spec. Returns the number of rows written.
+#[cfg(feature = "parquet")]
+pub(crate) fn write_table<W: Write>(
+    spec: &DatasetSpec,
+    table_spec: &TableSpec,
+    registry: &PatientRegistry,
+    file: W,
+) -> Result<usize, HbrError> {
+    let name = table_spec.table.name();
+    let chunk_size = spec.chunk_size.unwrap_or(table_spec.num_rows.max(1));
+    let chunks = table_spec.make_chunks(registry, spec.generator_version, chunk_size);
+    let chunks: Box<dyn Iterator<Item = DataFrame>> = match &spec.corruption {
+        Some(corruption) => {
+            let mut corrupter = Corrupter::new(corruption.clone(), spec.seed, name);
+            Box::new(chunks.map(move |chunk| corrupter.corrupt(chunk)))
+        }
+        None => chunks,
+    };
+    let mut num_rows = 0;
+    let chunks = chunks.inspect(|chunk| num_rows += chunk.height());
+    let empty_table_spec = TableSpec {
+        num_rows: 0,
+        ..table_spec.clone()
+    };
+    let mut empty_table = empty_table_spec.make(registry, spec.generator_version);
+    write_table_chunks(chunks, &mut empty_table, file, spec.format)?;
+    Ok(num_rows)
+}
+
+/// The name of the file that a table is written to (the name of the
+/// table, with the extension of the format)
+#[cfg(feature = "parquet")]
+fn table_file_name(table: SyntheticTable, format: OutputFormat) -> String {
+    format!("{}.{}", table.name(), format.name())
+}
+
+/// Generate the tables in the spec, writing each one to a file named
+/// after the table in output_dir, with the extension of the format in
+/// the spec (e.g. pathology_blood.parquet or pathology_blood.csv). A
+/// manifest of the files, with their row counts and content hashes, is
+/// also written to output_dir (see the manifest module), so that the
+/// dataset can be checked later using manifest::verify_dataset.
+///
+/// The whole table is generated before it is written, unless the spec
+/// has a chunk_size. The output directory is created if it does not
+/// exist, and existing files are overwritten. Returns the paths of the
+/// tables written (not including the manifest), in the same order as
+/// the tables in the spec.
+#[cfg(feature = "parquet")]
+pub fn generate_dataset<P: AsRef<Path>>(
+    spec: &DatasetSpec,
+    output_dir: P,
+) -> Result<Vec<PathBuf>, HbrError> {
+    let _span = tracing::info_span!("generate_dataset", seed = spec.seed).entered();
+    std::fs::create_dir_all(&output_dir)?;
+    let registry = spec.patient_registry();
+    let mut paths = Vec::new();
+    let mut files = Vec::new();
+    for table_spec in &spec.tables {
+        let file_name = table_file_name(table_spec.table, spec.format);
+        let path = output_dir.as_ref().join(&file_name);
+        tracing::debug!(table = table_spec.table.name(), num_rows = table_spec.num_rows, path = %path.display(), "writing table");
+        let mut writer = HashingWriter::new(std::fs::File::create(&path)?);
+        let num_rows = write_table(spec, table_spec, &registry, &mut writer)?;
+        files.push(ManifestFile {
+            table: table_spec.table,
+            file_name,
+            num_rows,
+            hash: writer.hash(),
+        });
+        paths.push(path);
+    }
+    DatasetManifest::new(spec.clone(), files).write(output_dir)?;
+    Ok(paths)
+}
+
+/// Tests for reading dataset specs
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn check_dataset_spec_from_yaml_and_json() {
+        let yaml = "seed: 42\ntables:\n- table: pathology_blood\n  num_rows: 10\n";
+        let spec = DatasetSpec::from_reader(yaml.as_bytes()).expect("Spec should be valid");
+        assert_eq!(
+            spec,
+            DatasetSpec {
+                seed: 42,
+                num_patients: DEFAULT_NUM_PATIENTS,
+                format: OutputFormat::Parquet,
+                chunk_size: None,
+                corruption: None,
+                generator_version: GeneratorVersion::V1,
+                tables: vec![TableSpec::new(SyntheticTable::PathologyBlood, 10)],
+            }
+        );
+
+        let json = r#"{"seed": 42, "tables": [{"table": "pathology_blood", "num_rows": 10}]}"#;
+        let json_spec = DatasetSpec::from_reader(json.as_bytes()).expect("Spec should be valid");
+        assert_eq!(spec, json_spec);
+
+        let table = spec.tables[0].make(&spec.patient_registry(), spec.generator_version);
+        assert_eq!(table.height(), 10);
+        let chunks: Vec<_> = spec.tables[0]
+            .make_chunks(&spec.patient_registry(), spec.generator_version, 4)
+            .map(|chunk| chunk.height())
+            .collect();
+        assert_eq!(chunks, vec![4, 4, 2]);
+
+        let latest = "seed: 42\ngenerator_version: 1\ntables: []\n";
+        let spec = DatasetSpec::from_reader(latest.as_bytes()).expect("Spec should be valid");
+        assert_eq!(spec.generator_version, GeneratorVersion::LATEST);
+        let future = "seed: 42\ngenerator_version: 1000\ntables: []\n";
+        assert!(matches!(
+            DatasetSpec::from_reader(future.as_bytes()),
+            Err(HbrError::InvalidDatasetSpec(_))
+        ));
+
+        let no_patients = "seed: 42\nnum_patients: 0\ntables: []\n";
+        assert!(matches!(
+            DatasetSpec::from_reader(no_patients.as_bytes()),
+            Err(HbrError::InvalidDatasetSpec(_))
+        ));
+
+        let unknown = "seed: 42\ntables:\n- table: pathology_bloods\n  num_rows: 10\n";
+        assert!(matches!(
+            DatasetSpec::from_reader(unknown.as_bytes()),
+            Err(HbrError::InvalidDatasetSpec(_))
+        ));
+        let repeated = "seed: 42\ntables:\n- table: pathology_blood\n  num_rows: 10\n- table: pathology_blood\n  num_rows: 5\n";
+        assert!(matches!(
+            DatasetSpec::from_reader(repeated.as_bytes()),
+            Err(HbrError::InvalidDatasetSpec(_))
+        ));
+
+        let frequencies = "seed: 42\ntables:\n- table: episodes\n  num_rows: 10\n  diagnosis_frequencies:\n    I21.0: 2\n    K92.2: 1\n";
+        let spec = DatasetSpec::from_reader(frequencies.as_bytes()).expect("Spec should be valid");
+        let expected = CodeFrequencies::from_reader("I21.0: 2\nK92.2: 1\n".as_bytes()).unwrap();
+        assert_eq!(spec.tables[0].diagnosis_frequencies, Some(expected));
+        assert_eq!(
+            spec.tables[0]
+                .make(&spec.patient_registry(), spec.generator_version)
+                .height(),
+            10
+        );
+        let window = "seed: 42\ntables:\n- table: pathology_blood\n  num_rows: 100\n  date_window:\n    start: 2015-01-01T00:00:00\n    end: 2016-01-01T00:00:00\n  duration:\n    min_minutes: 10\n    max_minutes: 60\n";
+        let spec = DatasetSpec::from_reader(window.as_bytes()).expect("Spec should be valid");
+        let table = spec.tables[0].make(&spec.patient_registry(), spec.generator_version);
+        let start = NaiveDate::from_ymd_opt(2015, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let end = NaiveDate::from_ymd_opt(2016, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let times = |name| -> Vec<_> {
+            table
+                .column(name)
+                .unwrap()
+                .datetime()
+                .unwrap()
+                .as_datetime_iter()
+                .map(Option::unwrap)
+                .collect()
+        };
+        let collected = times("sample_collected_date_time");
+        let available = times("result_available_date_time");
+        for (collected, available) in collected.iter().zip(&available) {
+            assert!((start..end).contains(collected));
+            assert!((10..60).contains(&(*available - *collected).num_minutes()));
+        }
+        let aware = "seed: 42\ntables:\n- table: pathology_blood\n  num_rows: 100\n  date_window:\n    start: 2021-03-28T00:00:00\n    end: 2021-03-28T03:00:00\n  duration:\n    min_minutes: 0\n    max_minutes: 60\n  timezone_aware: true\n";
+        let spec = DatasetSpec::from_reader(aware.as_bytes()).expect("Spec should be valid");
+        let table = spec.tables[0].make(&spec.patient_registry(), spec.generator_version);
+        let timestamps = |name| -> Vec<_> {
+            let column = table.column(name).unwrap();
+            assert_eq!(
+                column.dtype(),
+                &DataType::Datetime(TimeUnit::Milliseconds, Some(String::from("Europe/London")))
+            );
+            let timestamps = column.cast(&DataType::Int64).unwrap();
+            timestamps.i64().unwrap().into_no_null_iter().collect()
+        };
+        let collected = timestamps("sample_collected_date_time");
+        let available = timestamps("result_available_date_time");
+        assert!(collected.iter().zip(&available).all(|(c, a)| c <= a));
+        let naive_table =
+            "seed: 42\ntables:\n- table: patients\n  num_rows: 10\n  timezone_aware: true\n";
+        assert!(matches!(
+            DatasetSpec::from_reader(naive_table.as_bytes()),
+            Err(HbrError::InvalidDatasetSpec(_))
+        ));
+        let backwards = window.replace("2016", "2014");
+        assert!(matches!(
+            DatasetSpec::from_reader(backwards.as_bytes()),
+            Err(HbrError::InvalidDatasetSpec(_))
+        ));
+        let wrong_table = "seed: 42\ntables:\n- table: prescriptions\n  num_rows: 10\n  diagnosis_frequencies:\n    I21.0: 1\n";
+        assert!(matches!(
+            DatasetSpec::from_reader(wrong_table.as_bytes()),
+            Err(HbrError::InvalidDatasetSpec(_))
+        ));
+    }
+}
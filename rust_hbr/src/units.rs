@@ -0,0 +1,347 @@
+//! Normalising lab results to canonical units
+//!
+//! The same blood test can be reported in different units depending on
+//! the laboratory (e.g. haemoglobin in g/dL or g/L, creatinine in mg/dL
+//! or µmol/L). Criteria such as ARC-HBR are defined using fixed
+//! thresholds, so every result is converted into one canonical unit
+//! per analyte before it is stored in a Measurement:
+//!
+//! | Analyte     | Canonical unit | Other units                  |
+//! |-------------|----------------|------------------------------|
+//! | Haemoglobin | g/L            | g/dL, mmol/L                 |
+//! | Platelets   | 10*9/L         | 10^9/L, x10*9/L, 10*3/uL     |
+//! | Creatinine  | umol/L         | µmol/L, mg/dL                |
+//! | eGFR        | mL/min/1.73m2  | mL/min                       |
+//!
+//! Units are matched ignoring case and surrounding whitespace. A unit
+//! that is not in the table is an error (HbrError::UnknownUnit),
+//! rather than being passed through, because a result in the wrong
+//! unit would silently give the wrong answer.
+
+use crate::error::HbrError;
+use crate::patient::measurements::MeasurementValue;
+
+/// The blood tests whose units can be normalised
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Analyte {
+    Haemoglobin,
+    Platelets,
+    Creatinine,
+    Egfr,
+}
+
+/// One row of the conversion table: a value in the unit is multiplied
+/// by the factor to convert it into the analyte's canonical unit
+struct UnitConversion {
+    analyte: Analyte,
+    unit: &'static str,
+    factor: f64,
+}
+
+/// The conversion table. The first row for each analyte is its
+/// canonical unit.
+const CONVERSIONS: [UnitConversion; 13] = [
+    UnitConversion {
+        analyte: Analyte::Haemoglobin,
+        unit: "g/L",
+        factor: 1.0,
+    },
+    UnitConversion {
+        analyte: Analyte::Haemoglobin,
+        unit: "g/dL",
+        factor: 10.0,
+    },
+    // Haemoglobin (monomer) has a molar mass of about 16,114 g/mol,
+    // which is 16.114 g/mmol
+    UnitConversion {
+        analyte: Analyte::Haemoglobin,
+        unit: "mmol/L",
+        factor: 16.114,
+    },
+    UnitConversion {
+        analyte: Analyte::Platelets,
+        unit: "10*9/L",
+        factor: 1.0,
+    },
+    UnitConversion {
+        analyte: Analyte::Platelets,
+        unit: "10^9/L",
+        factor: 1.0,
+    },
+    UnitConversion {
+        analyte: Analyte::Platelets,
+        unit: "x10*9/L",
+        factor: 1.0,
+    },
+    UnitConversion {
+        analyte: Analyte::Platelets,
+        unit: "10*3/uL",
+        factor: 1.0,
+    },
+    UnitConversion {
+        analyte: Analyte::Creatinine,
+        unit: "umol/L",
+        factor: 1.0,
+    },
+    UnitConversion {
+        analyte: Analyte::Creatinine,
+        unit: "µmol/L",
+        factor: 1.0,
+    },
+    // Creatinine has a molar mass of 113.12 g/mol
+    UnitConversion {
+        analyte: Analyte::Creatinine,
+        unit: "mg/dL",
+        factor: 88.42,
+    },
+    UnitConversion {
+        analyte: Analyte::Egfr,
+        unit: "mL/min/1.73m2",
+        factor: 1.0,
+    },
+    UnitConversion {
+        analyte: Analyte::Egfr,
+        unit: "mL/min/1.73m^2",
+        factor: 1.0,
+    },
+    // The HIC pathology_blood table records the (already normalised)
+    // eGFR in mL/min
+    UnitConversion {
+        analyte: Analyte::Egfr,
+        unit: "mL/min",
+        factor: 1.0,
+    },
+];
+
+/// The names of the tests in the pathology_blood table whose units
+/// can be normalised, and the analyte that each one measures
+const TEST_NAMES: [(&str, Analyte); 5] = [
+    ("Haemoglobin", Analyte::Haemoglobin),
+    ("Platelets", Analyte::Platelets),
+    ("Creatinine", Analyte::Creatinine),
+    ("eGFR", Analyte::Egfr),
+    ("eGFR/1.73m2 (CKD-EPI)", Analyte::Egfr),
+];
+
+impl Analyte {
+    /// Get the analyte measured by a test in the pathology_blood table
+    /// (None if the test is not one whose units can be normalised)
+    pub fn from_test_name(test_name: &str) -> Option<Self> {
+        TEST_NAMES
+            .iter()
+            .find(|(name, _)| *name == test_name)
+            .map(|(_, analyte)| *analyte)
+    }
+
+    /// Get the names of all the tests in the pathology_blood table
+    /// that measure an analyte (see from_test_name)
+    pub fn test_names() -> impl Iterator<Item = &'static str> {
+        TEST_NAMES.iter().map(|(name, _)| *name)
+    }
+
+    /// The unit that results are converted into
+    pub fn canonical_unit(&self) -> &'static str {
+        CONVERSIONS
+            .iter()
+            .find(|conversion| conversion.analyte == *self)
+            .map(|conversion| conversion.unit)
+            .expect("Every analyte has a canonical unit")
+    }
+
+    /// Get the factor that converts a value in the unit into the
+    /// canonical unit
+    fn factor(&self, unit: &str) -> Result<f64, HbrError> {
+        let unit = unit.trim();
+        CONVERSIONS
+            .iter()
+            .find(|conversion| {
+                conversion.analyte == *self && conversion.unit.eq_ignore_ascii_case(unit)
+            })
+            .map(|conversion| conversion.factor)
+            .ok_or_else(|| HbrError::UnknownUnit {
+                analyte: format!("{self:?}"),
+                unit: unit.to_string(),
+            })
+    }
+}
+
+/// Convert a value of the analyte from one unit to another (e.g.
+/// creatinine from µmol/L to mg/dL). Returns an error if either unit
+/// is not in the conversion table.
+pub fn convert(analyte: Analyte, value: f64, from: &str, to: &str) -> Result<f64, HbrError> {
+    Ok(value * analyte.factor(from)? / analyte.factor(to)?)
+}
+
+/// Convert a measurement value in the unit into the analyte's
+/// canonical unit.
+///
+/// Values that are already in the canonical unit (or a unit with a
+/// factor of one) are returned unchanged, so integers stay integers.
+/// Otherwise, the value becomes Real. String values that are a bound
+/// (e.g. <5) have the number converted and the bound kept.
+pub fn to_canonical_unit(
+    analyte: Analyte,
+    value: MeasurementValue,
+    unit: &str,
+) -> Result<MeasurementValue, HbrError> {
+    let factor = analyte.factor(unit)?;
+    if factor == 1.0 {
+        return Ok(value);
+    }
+    match value {
+        MeasurementValue::Integer(value) => Ok(MeasurementValue::Real(value as f64 * factor)),
+        MeasurementValue::Real(value) => Ok(MeasurementValue::Real(value * factor)),
+        MeasurementValue::String(value) => {
+            let number = value.trim_start_matches(['<', '>']);
+            let bound = &value[..value.len() - number.len()];
+            match number.parse::<f64>() {
+                Ok(number) => Ok(MeasurementValue::String(format!(
+                    "{bound}{}",
+                    number * factor
+                ))),
+                Err(_) => Err(HbrError::InvalidMeasurementValue(value)),
+            }
+        }
+    }
+}
+
+/// Tests for unit normalisation
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::preprocess::measurement_from_pathology_blood;
+
+    #[test]
+    fn check_unit_conversions() {
+        let haemoglobin =
+            to_canonical_unit(Analyte::Haemoglobin, MeasurementValue::Real(11.5), " g/dl ")
+                .unwrap();
+        assert!(matches!(haemoglobin, MeasurementValue::Real(value) if value == 115.0));
+        let haemoglobin =
+            to_canonical_unit(Analyte::Haemoglobin, MeasurementValue::Integer(8), "mmol/L")
+                .unwrap();
+        assert!(matches!(haemoglobin, MeasurementValue::Real(value) if value == 8.0 * 16.114));
+
+        // Creatinine in both directions
+        let creatinine = convert(Analyte::Creatinine, 1.0, "mg/dL", "µmol/L").unwrap();
+        assert!((creatinine - 88.42).abs() < 1e-9);
+        let creatinine = convert(Analyte::Creatinine, 88.42, "umol/L", "mg/dL").unwrap();
+        assert!((creatinine - 1.0).abs() < 1e-9);
+        assert_eq!(Analyte::Creatinine.canonical_unit(), "umol/L");
+    }
+
+    #[test]
+    fn check_canonical_units_are_passed_through() {
+        let platelets =
+            to_canonical_unit(Analyte::Platelets, MeasurementValue::Integer(150), "10^9/L")
+                .unwrap();
+        assert!(matches!(platelets, MeasurementValue::Integer(150)));
+        let egfr = to_canonical_unit(
+            Analyte::Egfr,
+            MeasurementValue::String(String::from(">90")),
+            "mL/min",
+        )
+        .unwrap();
+        assert!(matches!(egfr, MeasurementValue::String(value) if value == ">90"));
+    }
+
+    #[test]
+    fn check_bounds_are_kept_when_converting() {
+        let haemoglobin = to_canonical_unit(
+            Analyte::Haemoglobin,
+            MeasurementValue::String(String::from("<5")),
+            "g/dL",
+        )
+        .unwrap();
+        assert!(matches!(haemoglobin, MeasurementValue::String(value) if value == "<50"));
+        assert!(matches!(
+            to_canonical_unit(
+                Analyte::Haemoglobin,
+                MeasurementValue::String(String::from("low")),
+                "g/dL",
+            ),
+            Err(HbrError::InvalidMeasurementValue(_))
+        ));
+    }
+
+    #[test]
+    fn check_unknown_units_are_errors() {
+        assert!(matches!(
+            to_canonical_unit(Analyte::Platelets, MeasurementValue::Integer(150), "g/L"),
+            Err(HbrError::UnknownUnit { .. })
+        ));
+        assert!(convert(Analyte::Haemoglobin, 1.0, "g/L", "mg/dL").is_err());
+    }
+
+    #[test]
+    fn check_pathology_blood_results_are_normalised() {
+        let platelets = measurement_from_pathology_blood(
+            String::from("FULL BLOOD COUNT"),
+            String::from("Platelets"),
+            String::new(),
+            String::new(),
+            String::from("250"),
+            String::from("x10*9/L"),
+        )
+        .unwrap();
+        assert!(matches!(platelets.value, MeasurementValue::Integer(250)));
+
+        let haemoglobin = measurement_from_pathology_blood(
+            String::from("FULL BLOOD COUNT"),
+            String::from("Haemoglobin"),
+            String::new(),
+            String::new(),
+            String::from("10.5"),
+            String::from("g/dL"),
+        )
+        .unwrap();
+        assert!(matches!(haemoglobin.value, MeasurementValue::Real(value) if value == 105.0));
+
+        let egfr = measurement_from_pathology_blood(
+            String::from("UREACREAT + ELECTROLYTES"),
+            String::from("eGFR/1.73m2 (CKD-EPI)"),
+            String::new(),
+            String::new(),
+            String::from(">90"),
+            String::from("mL/min"),
+        )
+        .unwrap();
+        assert!(matches!(egfr.value, MeasurementValue::String(value) if value == ">90"));
+
+        assert!(matches!(
+            measurement_from_pathology_blood(
+                String::from("FULL BLOOD COUNT"),
+                String::from("Platelets"),
+                String::new(),
+                String::new(),
+                String::from("250"),
+                String::from("mmol/L"),
+            ),
+            Err(HbrError::UnknownUnit { .. })
+        ));
+        // The order_name must match the test
+        assert!(matches!(
+            measurement_from_pathology_blood(
+                String::from("FULL BLOOD COUNT"),
+                String::from("eGFR/1.73m2 (CKD-EPI)"),
+                String::new(),
+                String::new(),
+                String::from("60"),
+                String::from("mL/min"),
+            ),
+            Err(HbrError::UnexpectedValue { .. })
+        ));
+        assert!(matches!(
+            measurement_from_pathology_blood(
+                String::from("FULL BLOOD COUNT"),
+                String::from("Basophils"),
+                String::new(),
+                String::new(),
+                String::from("0.1"),
+                String::from("10*9/L"),
+            ),
+            Err(HbrError::UnrecognisedTestName(_))
+        ));
+    }
+}
@@ -0,0 +1,438 @@
+//! The ward transfers table contains admission, discharge and transfer
+//! (ADT) events, which record the movements of patients between wards
+//! during hospital spells. Each row is one event, and the events of a
+//! spell are in consecutive rows in time order:
+//!
+//! * an admission to the first ward at the start of the spell
+//! * a transfer to each of the following wards (if any)
+//! * a discharge from the last ward at the end of the spell
+//!
+//! Unlike the episodes table, where every row is independent, the rows
+//! of a spell belong together (like the months of a patient in the
+//! swd_attributes table). The spells start at times picked from a
+//! DateWindow, and last for a time picked from a DurationRange (see
+//! EventTiming), and the transfers happen at random times within the
+//! spell. The table stops after num_rows rows even if that is in the
+//! middle of a spell, so the last spell may have no discharge (like a
+//! patient who is still in hospital when the data is extracted).
+
+use std::collections::VecDeque;
+
+use crate::seeded_column_block::{make_chunks, to_polars, SeededColumnBlock, SyntheticRows};
+use crate::seeded_rng::make_rng;
+use crate::synth_data::{DurationRange, EventTiming, GeneratorVersion, PatientRegistry};
+use chrono::{Duration, NaiveDateTime};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+use polars::prelude::*;
+
+/// The default length of a spell (up to 30 days)
+pub const DEFAULT_SPELL_DURATION: DurationRange = DurationRange {
+    min_minutes: 0,
+    max_minutes: 43200,
+};
+
+/// The wards that patients are admitted to (acute medical unit, coronary
+/// care unit and surgical assessment unit)
+const ADMISSION_WARDS: [&str; 3] = ["AMU", "CCU", "SAU"];
+
+/// The wards that patients are transferred to
+const WARDS: [&str; 7] = ["CCU", "CARD", "ICU", "GMED", "ELDC", "SURG", "DLNG"];
+
+/// The wards a patient stays on during a spell, and when they move
+/// from one to the next (as fractions of the length of the spell, in
+/// increasing order)
+struct WardStays {
+    wards: Vec<&'static str>,
+    transfers: Vec<f64>,
+}
+
+/// Pick the wards for a spell. Half the spells stay on the admission
+/// ward, and the others have up to three transfers (each to a different
+/// ward from the one before).
+fn make_ward_stays(rng: &mut ChaCha8Rng) -> WardStays {
+    let num_wards = match rng.gen_range(0..20) {
+        0..=9 => 1,
+        10..=15 => 2,
+        16..=18 => 3,
+        _ => 4,
+    };
+    let mut wards = vec![*ADMISSION_WARDS.choose(rng).unwrap()];
+    while wards.len() < num_wards {
+        let previous = wards[wards.len() - 1];
+        let ward = *WARDS
+            .iter()
+            .filter(|ward| **ward != previous)
+            .collect::<Vec<_>>()
+            .choose(rng)
+            .unwrap();
+        wards.push(ward);
+    }
+    let mut transfers: Vec<f64> = (1..num_wards).map(|_| rng.gen()).collect();
+    transfers.sort_by(f64::total_cmp);
+    WardStays { wards, transfers }
+}
+
+/// Make a spell id (format "spell_nnnnnnn")
+fn make_spell_identifier(rng: &mut ChaCha8Rng) -> String {
+    let spell_id = rng.gen_range(1..=5000000);
+    format!("spell_{spell_id}")
+}
+
+/// One row of the ward transfers table
+#[derive(Debug, Clone, PartialEq)]
+pub struct WardTransfersRow {
+    pub subject: String,
+    pub spell_identifier: String,
+    /// The position of the event in the spell (starting at 1)
+    pub event_number: i64,
+    /// One of admission, transfer or discharge
+    pub event_type: String,
+    pub event_time: NaiveDateTime,
+    /// The ward the patient is on after the event (or the ward they
+    /// are discharged from)
+    pub ward_code: String,
+}
+
+/// Make the rows of one spell
+fn make_spell_rows(
+    subject: &str,
+    spell_identifier: &str,
+    (spell_start, spell_end): (NaiveDateTime, NaiveDateTime),
+    ward_stays: &WardStays,
+) -> Vec<WardTransfersRow> {
+    let spell_minutes = (spell_end - spell_start).num_minutes() as f64;
+    let transfer_times = ward_stays
+        .transfers
+        .iter()
+        .map(|fraction| spell_start + Duration::minutes((fraction * spell_minutes) as i64));
+    let last_ward = ward_stays.wards[ward_stays.wards.len() - 1];
+    let events = std::iter::once(("admission", spell_start))
+        .chain(transfer_times.map(|time| ("transfer", time)))
+        .zip(&ward_stays.wards)
+        .map(|((event_type, time), ward)| (event_type, time, *ward))
+        .chain(std::iter::once(("discharge", spell_end, last_ward)));
+    events
+        .enumerate()
+        .map(|(n, (event_type, time, ward))| WardTransfersRow {
+            subject: String::from(subject),
+            spell_identifier: String::from(spell_identifier),
+            event_number: n as i64 + 1,
+            event_type: String::from(event_type),
+            event_time: time,
+            ward_code: String::from(ward),
+        })
+        .collect()
+}
+
+/// Iterator over the rows of the ward transfers table
+///
+/// Each seeded column block has its own generator (see
+/// PathologyBloodRows), which is advanced once per spell instead of
+/// once per row. The rows of the current spell that have not been
+/// returned yet are kept between calls (and between chunks).
+pub struct WardTransfersRows {
+    registry: PatientRegistry,
+    /// When the spells start, and how long they last
+    timing: EventTiming,
+    subject_rng: ChaCha8Rng,
+    identifier_rng: ChaCha8Rng,
+    spell_time_rng: ChaCha8Rng,
+    ward_rng: ChaCha8Rng,
+    /// The rows left in the current spell
+    spell_rows: VecDeque<WardTransfersRow>,
+    rows_left: usize,
+}
+
+impl WardTransfersRows {
+    /// Make an iterator that will return num_rows rows. The arguments
+    /// have the same meaning as in make_ward_transfers.
+    pub fn new(
+        block_id: &str,
+        registry: &PatientRegistry,
+        timing: EventTiming,
+        num_rows: usize,
+    ) -> Self {
+        let global_seed = registry.global_seed();
+        let subject_block_id = format!("{block_id}subject");
+        let identifier_block_id = format!("{block_id}identifier");
+        let spell_time_block_id = format!("{block_id}spell_time");
+        let ward_block_id = format!("{block_id}ward");
+        Self {
+            registry: registry.clone(),
+            timing,
+            subject_rng: make_rng(global_seed, subject_block_id.as_ref()),
+            identifier_rng: make_rng(global_seed, identifier_block_id.as_ref()),
+            spell_time_rng: make_rng(global_seed, spell_time_block_id.as_ref()),
+            ward_rng: make_rng(global_seed, ward_block_id.as_ref()),
+            spell_rows: VecDeque::new(),
+            rows_left: num_rows,
+        }
+    }
+}
+
+impl Iterator for WardTransfersRows {
+    type Item = WardTransfersRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rows_left == 0 {
+            return None;
+        }
+        self.rows_left -= 1;
+
+        if self.spell_rows.is_empty() {
+            let ward_stays = make_ward_stays(&mut self.ward_rng);
+            let patient = self.registry.sample(&mut self.subject_rng);
+            let spell_identifier = make_spell_identifier(&mut self.identifier_rng);
+            let spell_times = self.timing.pick(&mut self.spell_time_rng);
+            self.spell_rows = make_spell_rows(
+                &patient.subject,
+                &spell_identifier,
+                spell_times,
+                &ward_stays,
+            )
+            .into();
+        }
+        self.spell_rows.pop_front()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.rows_left, Some(self.rows_left))
+    }
+}
+
+impl SyntheticRows for WardTransfersRows {
+    /// The number of events in each spell depends on the ward block, so the
+    /// ward stays of all the new spells needed for the chunk are picked
+    /// first, and then the other seeded column blocks are generated in
+    /// parallel.
+    fn take_chunk(&mut self, chunk_size: usize) -> DataFrame {
+        let num_rows = chunk_size.min(self.rows_left);
+        self.rows_left -= num_rows;
+        let Self {
+            registry,
+            timing,
+            subject_rng,
+            identifier_rng,
+            spell_time_rng,
+            ward_rng,
+            spell_rows,
+            ..
+        } = self;
+
+        // Finish the current spell, and then start new ones
+        let mut rows: Vec<_> = spell_rows.drain(..num_rows.min(spell_rows.len())).collect();
+        let mut ward_stays = Vec::new();
+        let mut num_planned_rows = rows.len();
+        while num_planned_rows < num_rows {
+            let stays = make_ward_stays(ward_rng);
+            num_planned_rows += stays.wards.len() + 1;
+            ward_stays.push(stays);
+        }
+        let num_spells = ward_stays.len();
+        let (patients, (spell_identifiers, spell_times)) = rayon::join(
+            || {
+                (0..num_spells)
+                    .map(|_| registry.sample(subject_rng))
+                    .collect::<Vec<_>>()
+            },
+            || {
+                rayon::join(
+                    || {
+                        (0..num_spells)
+                            .map(|_| make_spell_identifier(identifier_rng))
+                            .collect::<Vec<_>>()
+                    },
+                    || {
+                        (0..num_spells)
+                            .map(|_| timing.pick(spell_time_rng))
+                            .collect::<Vec<_>>()
+                    },
+                )
+            },
+        );
+        for (((patient, spell_identifier), spell_times), stays) in patients
+            .iter()
+            .zip(&spell_identifiers)
+            .zip(spell_times)
+            .zip(&ward_stays)
+        {
+            rows.extend(make_spell_rows(
+                &patient.subject,
+                spell_identifier,
+                spell_times,
+                stays,
+            ));
+        }
+        // Keep the end of the last spell for the next chunk
+        spell_rows.extend(rows.drain(num_rows..));
+
+        let mut subject = Vec::with_capacity(num_rows);
+        let mut spell_identifier = Vec::with_capacity(num_rows);
+        let mut event_number = Vec::with_capacity(num_rows);
+        let mut event_type = Vec::with_capacity(num_rows);
+        let mut event_time = Vec::with_capacity(num_rows);
+        let mut ward_code = Vec::with_capacity(num_rows);
+        for row in rows {
+            subject.push(row.subject);
+            spell_identifier.push(row.spell_identifier);
+            event_number.push(row.event_number);
+            event_type.push(row.event_type);
+            event_time.push(row.event_time);
+            ward_code.push(row.ward_code);
+        }
+
+        let seeded_column_blocks = vec![
+            SeededColumnBlock {
+                columns: vec![Series::new("subject", subject)],
+            },
+            SeededColumnBlock {
+                columns: vec![Series::new("spell_identifier", spell_identifier)],
+            },
+            SeededColumnBlock {
+                columns: vec![
+                    Series::new("event_number", event_number),
+                    Series::new("event_type", event_type),
+                ],
+            },
+            SeededColumnBlock {
+                columns: vec![Series::new("event_time", event_time)],
+            },
+            SeededColumnBlock {
+                columns: vec![Series::new("ward_code", ward_code)],
+            },
+        ];
+        to_polars(seeded_column_blocks)
+    }
+}
+
+/// Create the ward transfers (ADT) table, with the events of each spell
+/// in consecutive rows (see the module documentation). The columns are:
+///
+/// * subject: the patient id
+/// * spell_identifier: the spell the event belongs to
+/// * event_number: the position of the event in the spell (from 1)
+/// * event_type: admission, transfer or discharge
+/// * event_time: when the event happened
+/// * ward_code: the ward the patient is on after the event (or the
+///   ward they are discharged from)
+///
+/// The spells start at times picked from the window in the timing, and
+/// last for a duration picked from its duration range (by default, up
+/// to DEFAULT_SPELL_DURATION).
+pub fn make_ward_transfers(
+    block_id: &str,
+    registry: &PatientRegistry,
+    version: GeneratorVersion,
+    timing: EventTiming,
+    num_rows: usize,
+) -> DataFrame {
+    let global_seed = registry.global_seed();
+    let _span =
+        tracing::debug_span!("make_ward_transfers", block_id, global_seed, num_rows).entered();
+    match version {
+        GeneratorVersion::V1 => {
+            WardTransfersRows::new(block_id, registry, timing, num_rows).take_chunk(num_rows)
+        }
+    }
+}
+
+/// Create the ward transfers table in chunks of (at most) chunk_size rows
+/// (see the synth_data module documentation). A spell can be split across
+/// two chunks.
+pub fn make_ward_transfers_chunks(
+    block_id: &str,
+    registry: &PatientRegistry,
+    version: GeneratorVersion,
+    timing: EventTiming,
+    num_rows: usize,
+    chunk_size: usize,
+) -> impl Iterator<Item = DataFrame> {
+    let global_seed = registry.global_seed();
+    let span = tracing::debug_span!(
+        "make_ward_transfers_chunks",
+        block_id,
+        global_seed,
+        num_rows,
+        chunk_size
+    );
+    let rows = match version {
+        GeneratorVersion::V1 => WardTransfersRows::new(block_id, registry, timing, num_rows),
+    };
+    make_chunks(rows, chunk_size, span)
+}
+
+/// Tests for the ward transfers table
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn check_events_are_nested_in_spells() {
+        let registry = PatientRegistry::new(0, 100);
+        let timing = EventTiming::with_duration(DEFAULT_SPELL_DURATION);
+        let rows: Vec<_> =
+            WardTransfersRows::new("ward_transfers", &registry, timing, 500).collect();
+        assert_eq!(rows.len(), 500);
+
+        // Each spell starts with an admission and (apart from the last
+        // one, which may be cut off) ends with a discharge, with the
+        // events in time order on the same subject
+        let mut spells = Vec::new();
+        for row in &rows {
+            if row.event_number == 1 {
+                assert_eq!(row.event_type, "admission");
+                spells.push(vec![row]);
+            } else {
+                spells.last_mut().unwrap().push(row);
+            }
+        }
+        for (n, spell) in spells.iter().enumerate() {
+            for (event, pair) in spell.iter().zip(spell.windows(2)) {
+                assert_ne!(event.event_type, "discharge");
+                assert_eq!(pair[0].subject, pair[1].subject);
+                assert_eq!(pair[0].spell_identifier, pair[1].spell_identifier);
+                assert_eq!(pair[0].event_number + 1, pair[1].event_number);
+                assert!(pair[0].event_time <= pair[1].event_time);
+            }
+            if n < spells.len() - 1 {
+                let discharge = spell[spell.len() - 1];
+                assert_eq!(discharge.event_type, "discharge");
+                assert_eq!(discharge.ward_code, spell[spell.len() - 2].ward_code);
+            }
+        }
+        assert!(spells.iter().any(|spell| spell.len() > 2));
+
+        // The chunks (which split spells) contain the same rows as the
+        // iterator
+        let table = make_ward_transfers(
+            "ward_transfers",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            500,
+        );
+        let chunks: Vec<_> = make_ward_transfers_chunks(
+            "ward_transfers",
+            &registry,
+            GeneratorVersion::V1,
+            timing,
+            500,
+            7,
+        )
+        .collect();
+        let mut joined = chunks[0].clone();
+        for chunk in &chunks[1..] {
+            joined.vstack_mut(chunk).unwrap();
+        }
+        assert!(joined.frame_equal_missing(&table));
+        let ward_code = table.column("ward_code").unwrap().utf8().unwrap();
+        let event_number = table.column("event_number").unwrap().i64().unwrap();
+        for (n, row) in rows.iter().enumerate() {
+            assert_eq!(ward_code.get(n), Some(row.ward_code.as_str()));
+            assert_eq!(event_number.get(n), Some(row.event_number));
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! The main Rust-language interface layer between rust_hbr (the Rust crate)
+//! and JavaScript (via WebAssembly), for use in the codes editor frontend.
+//!
+//! Only the clinical code parsing parts of rust_hbr are included, so that
+//! codes are parsed client-side with exactly the same logic as the backend.
+
+use rust_hbr::{
+    clinical_code::{ClinicalCodeRef, ClinicalCodeStore},
+    clinical_code_tree::ClinicalCodeTree,
+};
+use wasm_bindgen::prelude::*;
+
+/// A clinical code (name and description) returned to JavaScript
+#[wasm_bindgen(getter_with_clone)]
+pub struct WasmClinicalCode {
+    /// The name of the code, e.g. I21.0
+    pub name: String,
+    /// The description of the code
+    pub docs: String,
+}
+
+/// Class for parsing codes by searching for them in a code tree
+/// (the contents of a codes file, such as icd10.yaml).
+#[wasm_bindgen]
+pub struct WasmClinicalCodeParser {
+    code_store: ClinicalCodeStore,
+    code_tree: ClinicalCodeTree,
+}
+
+impl WasmClinicalCodeParser {
+    /// Convert a list of code references into codes for JavaScript
+    fn to_wasm_codes(&self, code_refs: Vec<ClinicalCodeRef>) -> Vec<WasmClinicalCode> {
+        code_refs
+            .iter()
+            .map(|code_ref| {
+                let clinical_code = self
+                    .code_store
+                    .clinical_code_from(code_ref)
+                    .expect("Clinical code should be present");
+                WasmClinicalCode {
+                    name: clinical_code.name().clone(),
+                    docs: clinical_code.docs().clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[wasm_bindgen]
+impl WasmClinicalCodeParser {
+    /// Make a parser from the contents of a codes file (a YAML string),
+    /// or throw an error if the contents are not a valid codes file.
+    #[wasm_bindgen(constructor)]
+    pub fn new(codes_file_contents: &str) -> Result<WasmClinicalCodeParser, JsError> {
+        Ok(Self {
+            code_store: ClinicalCodeStore::new(),
+            code_tree: ClinicalCodeTree::from_reader(codes_file_contents.as_bytes())?,
+        })
+    }
+
+    /// Find an exact match for the code, or return undefined if the code
+    /// does not match anything in the code tree.
+    pub fn find_exact(&mut self, code: &str) -> Option<WasmClinicalCode> {
+        let code_ref = self
+            .code_tree
+            .find_exact(code.to_string(), &mut self.code_store)
+            .ok()?
+            .code();
+        self.to_wasm_codes(vec![code_ref]).pop()
+    }
+
+    /// Get all the codes whose name or description contains the search
+    /// text (ignoring case).
+    pub fn search(&mut self, text: &str) -> Vec<WasmClinicalCode> {
+        let code_refs = self.code_tree.search(text, &mut self.code_store);
+        self.to_wasm_codes(code_refs)
+    }
+
+    /// Get the names of the code groups defined in the code tree
+    pub fn groups(&self) -> Vec<String> {
+        let mut groups: Vec<String> = self.code_tree.groups().iter().cloned().collect();
+        groups.sort();
+        groups
+    }
+
+    /// Get all the codes in a code group, or throw an error if
+    /// the group is not defined in the code tree.
+    pub fn codes_in_group(&mut self, group: &str) -> Result<Vec<WasmClinicalCode>, JsError> {
+        let code_refs = self
+            .code_tree
+            .codes_in_group(&group.to_string(), &mut self.code_store)?;
+        Ok(self.to_wasm_codes(code_refs))
+    }
+}